@@ -3,31 +3,52 @@ use std::vec;
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    from_binary, to_binary, Addr, Binary, CosmosMsg, Deps, DepsMut, Empty, Env, IbcEndpoint,
-    IbcQuery, MessageInfo, Order, PortIdResponse, Response, StdError, StdResult, Storage, Uint128,
+    attr, from_binary, to_binary, Addr, Attribute, Binary, Decimal, Deps, DepsMut, Env,
+    IbcEndpoint, IbcQuery, IbcTimeout, IbcTimeoutBlock, MessageInfo, Order, PortIdResponse,
+    Response, StdError, StdResult, Storage, SubMsg, SubMsgResult, Timestamp, Uint128,
 };
 use cw2::set_contract_version;
 use cw20::{Cw20Coin, Cw20ReceiveMsg};
-use cw20_ics20_msg::helper::parse_ibc_wasm_port_id;
-use cw_storage_plus::Bound;
+use cw20_ics20_msg::helper::{denom_to_asset_info, parse_asset_info_denom, parse_ibc_wasm_port_id};
+use cw20_ics20_msg::receiver::MAX_SLIPPAGE_BPS;
+use cw_storage_plus::{Bound, Map};
 use oraiswap::asset::AssetInfo;
-use oraiswap::router::RouterController;
+use oraiswap::router::{RouterController, SwapOperation};
 
 use crate::error::ContractError;
-use crate::ibc::{build_ibc_send_packet, parse_voucher_denom, process_deduct_fee};
+use crate::ibc::{
+    apply_slippage, build_ibc_send_packet, collect_fee_msgs, compute_receive_fees, encode_reply_id,
+    load_swap_route, parse_swap_return_amount, parse_voucher_denom, process_deduct_fee,
+    record_fee_denom_price, Ics20Packet, ReceiveFees, SEND_PACKET_ID, SWAP_TRANSFER_ID,
+};
 use crate::msg::{
-    AllowMsg, AllowedInfo, AllowedResponse, ChannelResponse, ChannelWithKeyResponse,
-    ConfigResponse, DeletePairMsg, ExecuteMsg, InitMsg, ListAllowedResponse, ListChannelsResponse,
-    ListMappingResponse, MigrateMsg, PairQuery, PortResponse, QueryMsg, RelayerFeeResponse,
-    TransferBackMsg, UpdatePairMsg,
+    AccumulatedFeesResponse, AllowMsg, AllowedInfo, AllowedResponse, BatchTransferBackMsg,
+    ChannelResponse, ChannelWithKeyResponse, ConfigResponse, DeletePairMsg, ExecuteMsg, InitMsg,
+    ListAllowedResponse, ListChannelsResponse, ListFeeExemptionsResponse, ListFeeHistoryResponse,
+    ListMappingResponse, ListPendingTransfersResponse, ListQueuedChangesResponse,
+    ListRateLimitsResponse, ListRecoverableFundsResponse, ListRelayersResponse,
+    ListStuckFundsResponse, MigrateMsg, PacketTimeout, PairQuery, PendingTransferResponse,
+    PortResponse, PriceResponse, QueryMsg, QueuedChangeResponse, RateLimitResponse,
+    RecoverableFundResponse, RelayerBalancesResponse, RelayerFeeResponse, RelayerResponse,
+    SimulateReceiveResponse, SimulateTransferResponse, StuckFundResponse, SwapRouteResponse,
+    TransferBackMsg, TransferMsg, UpdatePairMsg,
 };
 use crate::state::{
-    get_key_ics20_ibc_denom, ics20_denoms, increase_channel_balance, override_channel_balance,
-    reduce_channel_balance, AllowInfo, Config, MappingMetadata, RelayerFee, ReplyArgs, TokenFee,
-    ADMIN, ALLOW_LIST, CHANNEL_INFO, CHANNEL_REVERSE_STATE, CONFIG, RELAYER_FEE, REPLY_ARGS,
-    SINGLE_STEP_REPLY_ARGS, TOKEN_FEE,
+    accumulate_fee, assert_not_paused, channel_default_timeout, get_key_ics20_ibc_denom,
+    ics20_denoms, increase_channel_forward_balance, next_reply_nonce, override_channel_balance,
+    record_rate_limited_flow, reduce_channel_balance, reduce_channel_forward_balance,
+    save_relayer_fee, save_token_fee, AllowInfo, Config, FeeExemption, FeeExemptionSubject, FeeKey,
+    FlowDirection, MappingMetadata, PauseScope, PendingTransfer, PriceFallback, PriceSource,
+    QueuedChange, RateLimit, Ratio, RelayerFee, RelayerFeeSchedule, RelayerInfo, SensitiveChange,
+    StuckFund, SwapTransfer, TokenFee, TokenFeeSchedule, UnregisteredRelayerPolicy, ADMIN,
+    ALLOW_LIST, CHANNEL_FORWARD_STATE, CHANNEL_INFO, CHANNEL_RECOVERABLE_FUNDS,
+    CHANNEL_REVERSE_STATE, CHANNEL_TIMEOUTS, CONFIG, FEE_EXEMPTIONS, FEE_HISTORY, FIXED_PRICES,
+    GUARDIAN, LAST_KNOWN_PRICES, PAUSED_SCOPES, PENDING_ADMIN, PENDING_TRANSFERS, QUEUED_CHANGES,
+    QUEUED_CHANGE_COUNT, RATE_LIMITS, RATE_LIMIT_USAGE, RECEIVE_LOCK, RELAYERS, RELAYER_BALANCES,
+    RELAYER_FEE, RELAYER_FEE_ACCUMULATOR, STUCK_FUNDS, SWAP_ROUTES, SWAP_TRANSFERS, TIMELOCK_DELAY,
+    TOKEN_FEE, TOKEN_FEE_ACCUMULATOR, UNSEQUENCED_TRANSFERS,
 };
-use cw20_ics20_msg::amount::{convert_local_to_remote, Amount};
+use cw20_ics20_msg::amount::{convert_local_to_remote, convert_remote_to_local, Amount};
 use cw_utils::{maybe_addr, nonpayable, one_coin};
 
 // version info for migration info
@@ -51,6 +72,13 @@ pub fn instantiate(
         swap_router_contract: RouterController(msg.swap_router_contract),
         token_fee_receiver: admin.clone(),
         relayer_fee_receiver: admin,
+        price_source: PriceSource::default(),
+        price_fallback: PriceFallback::default(),
+        unregistered_relayer_policy: UnregisteredRelayerPolicy::default(),
+        default_slippage_bps: 0,
+        max_slippage_bps: 0,
+        min_timeout: 0,
+        max_timeout: 0,
     };
     CONFIG.save(deps.storage, &cfg)?;
 
@@ -72,16 +100,24 @@ pub fn execute(
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
+    // the payouts and hooks of a packet being received must not call back into this contract, its replies may still undo the accounting.
+    // Pausing moves nothing, so it stays reachable
+    if !matches!(msg, ExecuteMsg::Pause { .. }) && RECEIVE_LOCK.exists(deps.storage) {
+        return Err(ContractError::Reentrancy);
+    }
     match msg {
         ExecuteMsg::Receive(msg) => execute_receive(deps, env, info, msg),
-        // ExecuteMsg::Transfer(msg) => {
-        //     let coin = one_coin(&info)?;
-        //     execute_transfer(deps, env, msg, Amount::Native(coin), info.sender)
-        // }
+        ExecuteMsg::Transfer(msg) => {
+            let coin = one_coin(&info)?;
+            execute_transfer(deps, env, msg, Amount::Native(coin), info.sender)
+        }
         ExecuteMsg::TransferToRemote(msg) => {
+            execute_transfer_native_to_remote_chain(deps, env, info, msg)
+        }
+        ExecuteMsg::BatchTransferToRemote(msg) => {
             let coin = one_coin(&info)?;
             let amount = Amount::from_parts(coin.denom, coin.amount);
-            execute_transfer_back_to_remote_chain(deps, env, msg, amount, info.sender)
+            execute_batch_transfer_back_to_remote_chain(deps, env, msg, amount, info.sender)
         }
         ExecuteMsg::UpdateMappingPair(msg) => execute_update_mapping_pair(deps, env, info, msg),
         ExecuteMsg::DeleteMappingPair(msg) => execute_delete_mapping_pair(deps, env, info, msg),
@@ -98,6 +134,7 @@ pub fn execute(
             relayer_fee,
         } => update_config(
             deps,
+            env,
             info,
             default_timeout,
             default_gas_limit,
@@ -109,1983 +146,7392 @@ pub fn execute(
             relayer_fee_receiver,
             relayer_fee,
         ),
-        // self-called msgs for ibc_packet_receive
-        ExecuteMsg::IncreaseChannelBalanceIbcReceive {
-            dest_channel_id,
+        ExecuteMsg::OverrideChannelBalance {
+            channel_id,
             ibc_denom,
-            amount,
-            local_receiver,
-        } => handle_increase_channel_balance_ibc_receive(
+            outstanding,
+            total_sent,
+        } => handle_override_channel_balance(
             deps,
-            info.sender,
-            env.contract.address,
-            dest_channel_id,
+            env,
+            info,
+            channel_id,
             ibc_denom,
-            amount,
-            local_receiver,
+            outstanding,
+            total_sent,
         ),
-        ExecuteMsg::ReduceChannelBalanceIbcReceive {
-            src_channel_id,
+        ExecuteMsg::RecoverChannelFunds {
+            channel_id,
             ibc_denom,
             amount,
-            local_receiver,
-        } => handle_reduce_channel_balance_ibc_receive(
-            deps.storage,
-            info.sender,
-            env.contract.address,
-            src_channel_id,
+            recipient,
+        } => handle_recover_channel_funds(deps, info, channel_id, ibc_denom, amount, recipient),
+        ExecuteMsg::SetRateLimit {
+            channel_id,
             ibc_denom,
-            amount,
-            local_receiver,
+            window_seconds,
+            max_inflow,
+            max_outflow,
+        } => handle_set_rate_limit(
+            deps,
+            info,
+            channel_id,
+            ibc_denom,
+            RateLimit {
+                window_seconds,
+                max_inflow,
+                max_outflow,
+            },
         ),
-        ExecuteMsg::OverrideChannelBalance {
+        ExecuteMsg::RemoveRateLimit {
             channel_id,
             ibc_denom,
-            outstanding,
-            total_sent,
-        } => handle_override_channel_balance(
+        } => handle_remove_rate_limit(deps, info, channel_id, ibc_denom),
+        ExecuteMsg::SetFeeExemption { subject, discount } => {
+            handle_set_fee_exemption(deps, info, subject, discount)
+        }
+        ExecuteMsg::RemoveFeeExemption { subject } => {
+            handle_remove_fee_exemption(deps, info, subject)
+        }
+        ExecuteMsg::SetFees {
+            token_fees,
+            relayer_fees,
+        } => handle_set_fees(deps, env, info, token_fees, relayer_fees),
+        ExecuteMsg::RemoveFees { fees } => handle_remove_fees(deps, env, info, fees),
+        ExecuteMsg::ReplaceFees {
+            token_fees,
+            relayer_fees,
+        } => handle_replace_fees(deps, env, info, token_fees, relayer_fees),
+        ExecuteMsg::SetPriceSource {
+            price_source,
+            price_fallback,
+        } => handle_set_price_source(deps, env, info, price_source, price_fallback),
+        ExecuteMsg::SetFixedPrice { asset_info, price } => {
+            handle_set_fixed_price(deps, env, info, asset_info, price)
+        }
+        ExecuteMsg::SetSwapRoute {
+            offer_asset_info,
+            ask_asset_info,
+            operations,
+        } => handle_set_swap_route(
             deps,
+            env,
             info,
+            offer_asset_info,
+            ask_asset_info,
+            operations,
+        ),
+        ExecuteMsg::SetTimeoutBounds {
+            min_timeout,
+            max_timeout,
+        } => handle_set_timeout_bounds(deps, info, min_timeout, max_timeout),
+        ExecuteMsg::SetChannelTimeout {
+            channel_id,
+            timeout,
+        } => handle_set_channel_timeout(deps, info, channel_id, timeout),
+        ExecuteMsg::SetSlippage {
+            default_bps,
+            max_bps,
+        } => handle_set_slippage(deps, info, default_bps, max_bps),
+        ExecuteMsg::ProposeAdmin { admin } => handle_propose_admin(deps, info, admin),
+        ExecuteMsg::AcceptAdmin {} => handle_accept_admin(deps, info),
+        ExecuteMsg::CancelAdminProposal {} => handle_cancel_admin_proposal(deps, info),
+        ExecuteMsg::SetTimelockDelay { delay } => {
+            ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+            let attributes =
+                schedule_sensitive_change(deps, &env, SensitiveChange::TimelockDelay { delay })?;
+            Ok(Response::new()
+                .add_attribute("action", "set_timelock_delay")
+                .add_attributes(attributes))
+        }
+        ExecuteMsg::ExecuteQueuedChange { id } => handle_execute_queued_change(deps, env, info, id),
+        ExecuteMsg::CancelQueuedChange { id } => handle_cancel_queued_change(deps, info, id),
+        ExecuteMsg::Pause { scope } => handle_pause(deps, info, scope),
+        ExecuteMsg::Unpause { scope } => handle_unpause(deps, info, scope),
+        ExecuteMsg::SetGuardian { guardian } => handle_set_guardian(deps, info, guardian),
+        ExecuteMsg::CollectFees {} => handle_collect_fees(deps, info),
+        ExecuteMsg::RegisterRelayer { relayer, payout } => {
+            handle_register_relayer(deps, info, relayer, payout)
+        }
+        ExecuteMsg::RemoveRelayer { relayer } => handle_remove_relayer(deps, info, relayer),
+        ExecuteMsg::SetUnregisteredRelayerPolicy { policy } => {
+            ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+            let mut config = CONFIG.load(deps.storage)?;
+            config.unregistered_relayer_policy = policy;
+            CONFIG.save(deps.storage, &config)?;
+            Ok(Response::new().add_attributes(vec![
+                ("action", "set_unregistered_relayer_policy"),
+                (
+                    "policy",
+                    &format!("{:?}", config.unregistered_relayer_policy),
+                ),
+            ]))
+        }
+        ExecuteMsg::ClaimRelayerFees {} => handle_claim_relayer_fees(deps, info),
+        ExecuteMsg::ClaimStuckFund { id } => handle_claim_stuck_fund(deps, env, info, id),
+        ExecuteMsg::ResolveStuckFund { id, recipient } => {
+            handle_resolve_stuck_fund(deps, env, info, id, recipient)
+        }
+    }
+}
+
+pub fn handle_override_channel_balance(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    channel_id: String,
+    ibc_denom: String,
+    outstanding: Uint128,
+    total_sent: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    let attributes = vec![
+        attr("action", "override_channel_balance"),
+        attr("channel_id", &channel_id),
+        attr("ibc_denom", &ibc_denom),
+        attr("new_outstanding", outstanding),
+        attr("total_sent", total_sent.unwrap_or_default()),
+    ];
+    let queued = schedule_sensitive_change(
+        deps,
+        &env,
+        SensitiveChange::OverrideChannelBalance {
             channel_id,
             ibc_denom,
             outstanding,
             total_sent,
-        ),
+        },
+    )?;
+    Ok(Response::new()
+        .add_attributes(attributes)
+        .add_attributes(queued))
+}
+
+// applies the change right away without a timelock, otherwise queues it until the delay has passed
+fn schedule_sensitive_change(
+    deps: DepsMut,
+    env: &Env,
+    change: SensitiveChange,
+) -> Result<Vec<Attribute>, ContractError> {
+    let delay = TIMELOCK_DELAY.may_load(deps.storage)?.unwrap_or_default();
+    if delay == 0 {
+        apply_sensitive_change(deps, env, change)?;
+        return Ok(vec![]);
     }
+    let id = QUEUED_CHANGE_COUNT
+        .may_load(deps.storage)?
+        .unwrap_or_default()
+        + 1;
+    QUEUED_CHANGE_COUNT.save(deps.storage, &id)?;
+    let eta = env.block.time.plus_seconds(delay);
+    QUEUED_CHANGES.save(deps.storage, id, &QueuedChange { change, eta })?;
+    Ok(vec![
+        attr("queued_change_id", id.to_string()),
+        attr("eta", eta.seconds().to_string()),
+    ])
 }
 
-pub fn is_caller_contract(caller: Addr, contract_addr: Addr) -> StdResult<()> {
-    if caller.ne(&contract_addr) {
-        return Err(cosmwasm_std::StdError::generic_err(
-            "Caller is not the contract itself!",
-        ));
+fn apply_sensitive_change(
+    deps: DepsMut,
+    env: &Env,
+    change: SensitiveChange,
+) -> Result<(), ContractError> {
+    match change {
+        SensitiveChange::SwapRouterContract {
+            swap_router_contract,
+        } => {
+            CONFIG.update(deps.storage, |mut config| -> StdResult<Config> {
+                config.swap_router_contract = RouterController(swap_router_contract);
+                Ok(config)
+            })?;
+        }
+        SensitiveChange::TokenFeeReceiver { fee_receiver } => {
+            CONFIG.update(deps.storage, |mut config| -> StdResult<Config> {
+                config.token_fee_receiver = fee_receiver;
+                Ok(config)
+            })?;
+        }
+        SensitiveChange::RelayerFeeReceiver {
+            relayer_fee_receiver,
+        } => {
+            CONFIG.update(deps.storage, |mut config| -> StdResult<Config> {
+                config.relayer_fee_receiver = relayer_fee_receiver;
+                Ok(config)
+            })?;
+        }
+        SensitiveChange::OverrideChannelBalance {
+            channel_id,
+            ibc_denom,
+            outstanding,
+            total_sent,
+        } => override_channel_balance(
+            deps.storage,
+            &channel_id,
+            &ibc_denom,
+            outstanding,
+            total_sent,
+        )?,
+        SensitiveChange::TimelockDelay { delay } => TIMELOCK_DELAY.save(deps.storage, &delay)?,
+        SensitiveChange::PriceSource {
+            price_source,
+            price_fallback,
+        } => {
+            CONFIG.update(deps.storage, |mut config| -> StdResult<Config> {
+                config.price_source = price_source;
+                config.price_fallback = price_fallback;
+                Ok(config)
+            })?;
+        }
+        SensitiveChange::FixedPrice { asset_info, price } => match price {
+            Some(price) => FIXED_PRICES.save(deps.storage, &asset_info.to_string(), &price)?,
+            None => FIXED_PRICES.remove(deps.storage, &asset_info.to_string()),
+        },
+        SensitiveChange::SwapRoute {
+            offer_asset_info,
+            ask_asset_info,
+            operations,
+        } => {
+            let (offer, ask) = (offer_asset_info.to_string(), ask_asset_info.to_string());
+            match operations {
+                Some(operations) => SWAP_ROUTES.save(deps.storage, (&offer, &ask), &operations)?,
+                None => SWAP_ROUTES.remove(deps.storage, (&offer, &ask)),
+            }
+        }
+        SensitiveChange::SetFees {
+            token_fees,
+            relayer_fees,
+        } => set_fees(deps.storage, env.block.height, &token_fees, &relayer_fees)?,
+        SensitiveChange::RemoveFees { fees } => remove_fees(deps.storage, env.block.height, &fees)?,
+        SensitiveChange::ReplaceFees {
+            token_fees,
+            relayer_fees,
+        } => replace_fees(deps.storage, env.block.height, token_fees, relayer_fees)?,
     }
     Ok(())
 }
 
-pub fn handle_override_channel_balance(
+pub fn handle_execute_queued_change(
     deps: DepsMut,
+    env: Env,
     info: MessageInfo,
-    channel_id: String,
-    ibc_denom: String,
-    outstanding: Uint128,
-    total_sent: Option<Uint128>,
+    id: u64,
 ) -> Result<Response, ContractError> {
     ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
-    override_channel_balance(
-        deps.storage,
-        &channel_id,
-        &ibc_denom,
-        outstanding,
-        total_sent,
-    )?;
+    let queued = QUEUED_CHANGES.load(deps.storage, id)?;
+    if env.block.time < queued.eta {
+        return Err(ContractError::TimelockNotExpired {
+            id,
+            eta: queued.eta.seconds(),
+        });
+    }
+    QUEUED_CHANGES.remove(deps.storage, id);
+    apply_sensitive_change(deps, &env, queued.change)?;
     Ok(Response::new().add_attributes(vec![
-        ("action", "override_channel_balance"),
-        ("channel_id", &channel_id),
-        ("ibc_denom", &ibc_denom),
-        ("new_outstanding", &outstanding.to_string()),
-        ("total_sent", &total_sent.unwrap_or_default().to_string()),
+        ("action", "execute_queued_change"),
+        ("id", &id.to_string()),
     ]))
 }
 
-pub fn handle_increase_channel_balance_ibc_receive(
+pub fn handle_cancel_queued_change(
     deps: DepsMut,
-    caller: Addr,
-    contract_addr: Addr,
-    dst_channel_id: String,
-    ibc_denom: String,
-    remote_amount: Uint128,
-    local_receiver: String,
+    info: MessageInfo,
+    id: u64,
 ) -> Result<Response, ContractError> {
-    is_caller_contract(caller, contract_addr)?;
-    // will have to increase balance here because if this tx fails then it will be reverted, and the balance on the remote chain will also be reverted
-    increase_channel_balance(
-        deps.storage,
-        &dst_channel_id,
-        &ibc_denom,
-        remote_amount.clone(),
-    )?;
-    // we need to save the data to update the balances in reply
-    let reply_args = ReplyArgs {
-        channel: dst_channel_id.clone(),
-        denom: ibc_denom.clone(),
-        amount: remote_amount.clone(),
-        local_receiver: local_receiver.clone(),
-    };
-    REPLY_ARGS.save(deps.storage, &reply_args)?;
-    Ok(Response::default().add_attributes(vec![
-        ("action", "increase_channel_balance_ibc_receive"),
-        ("channel_id", dst_channel_id.as_str()),
-        ("ibc_denom", ibc_denom.as_str()),
-        ("amount", remote_amount.to_string().as_str()),
-        ("local_receiver", local_receiver.as_str()),
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    QUEUED_CHANGES.load(deps.storage, id)?;
+    QUEUED_CHANGES.remove(deps.storage, id);
+    Ok(Response::new().add_attributes(vec![
+        ("action", "cancel_queued_change"),
+        ("id", &id.to_string()),
     ]))
 }
 
-pub fn handle_reduce_channel_balance_ibc_receive(
-    storage: &mut dyn Storage,
-    caller: Addr,
-    contract_addr: Addr,
-    src_channel_id: String,
-    ibc_denom: String,
-    remote_amount: Uint128,
-    local_receiver: String,
+pub fn handle_propose_admin(
+    deps: DepsMut,
+    info: MessageInfo,
+    admin: String,
 ) -> Result<Response, ContractError> {
-    is_caller_contract(caller, contract_addr)?;
-    // because we are transferring back, we reduce the channel's balance
-    reduce_channel_balance(storage, src_channel_id.as_str(), &ibc_denom, remote_amount)
-        .map_err(|err| StdError::generic_err(err.to_string()))?;
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    let admin = deps.api.addr_validate(&admin)?;
+    PENDING_ADMIN.save(deps.storage, &admin)?;
+    Ok(Response::new().add_attributes(vec![
+        ("action", "propose_admin"),
+        ("pending_admin", admin.as_str()),
+    ]))
+}
 
-    // keep track of the single-step reply since we need ibc data to undo reducing channel balance and local data for refunding.
-    // we use a different item to not override REPLY_ARGS
-    SINGLE_STEP_REPLY_ARGS.save(
-        storage,
-        &ReplyArgs {
-            channel: src_channel_id.to_string(),
-            denom: ibc_denom.clone(),
-            amount: remote_amount,
-            local_receiver: local_receiver.to_string(),
-        },
-    )?;
-    Ok(Response::default().add_attributes(vec![
-        ("action", "reduce_channel_balance_ibc_receive"),
-        ("channel_id", src_channel_id.as_str()),
-        ("ibc_denom", ibc_denom.as_str()),
-        ("amount", remote_amount.to_string().as_str()),
-        ("local_receiver", local_receiver.as_str()),
+pub fn handle_accept_admin(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let pending_admin = PENDING_ADMIN
+        .may_load(deps.storage)?
+        .ok_or(ContractError::NoPendingAdmin)?;
+    if pending_admin != info.sender {
+        return Err(ContractError::NotPendingAdmin);
+    }
+    PENDING_ADMIN.remove(deps.storage);
+    ADMIN.set(deps, Some(pending_admin))?;
+    Ok(Response::new().add_attributes(vec![
+        ("action", "accept_admin"),
+        ("admin", info.sender.as_str()),
     ]))
 }
 
-pub fn update_config(
+pub fn handle_cancel_admin_proposal(
     deps: DepsMut,
     info: MessageInfo,
-    default_timeout: Option<u64>,
-    default_gas_limit: Option<u64>,
-    fee_denom: Option<String>,
-    swap_router_contract: Option<String>,
-    admin: Option<String>,
-    token_fee: Option<Vec<TokenFee>>,
-    fee_receiver: Option<String>,
-    relayer_fee_receiver: Option<String>,
-    relayer_fee: Option<Vec<RelayerFee>>,
 ) -> Result<Response, ContractError> {
     ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
-    if let Some(token_fee) = token_fee {
-        for fee in token_fee {
-            TOKEN_FEE.save(deps.storage, &fee.token_denom, &fee.ratio)?;
-        }
+    PENDING_ADMIN.remove(deps.storage);
+    Ok(Response::new().add_attribute("action", "cancel_admin_proposal"))
+}
+
+pub fn handle_pause(
+    deps: DepsMut,
+    info: MessageInfo,
+    scope: PauseScope,
+) -> Result<Response, ContractError> {
+    let is_guardian = GUARDIAN.may_load(deps.storage)?.as_ref() == Some(&info.sender);
+    if !is_guardian {
+        ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
     }
-    if let Some(relayer_fee) = relayer_fee {
-        for fee in relayer_fee {
-            RELAYER_FEE.save(deps.storage, &fee.prefix, &fee.fee)?;
-        }
+    let mut paused = PAUSED_SCOPES.may_load(deps.storage)?.unwrap_or_default();
+    if !paused.contains(&scope) {
+        paused.push(scope.clone());
+        PAUSED_SCOPES.save(deps.storage, &paused)?;
     }
-    CONFIG.update(deps.storage, |mut config| -> StdResult<Config> {
-        if let Some(default_timeout) = default_timeout {
-            config.default_timeout = default_timeout;
-        }
-        if let Some(fee_denom) = fee_denom {
-            config.fee_denom = fee_denom;
-        }
-        if let Some(swap_router_contract) = swap_router_contract {
-            config.swap_router_contract = RouterController(swap_router_contract);
+    Ok(Response::new().add_attributes(vec![
+        ("action", "pause"),
+        ("scope", &scope.to_string()),
+        ("sender", info.sender.as_str()),
+    ]))
+}
+
+pub fn handle_unpause(
+    deps: DepsMut,
+    info: MessageInfo,
+    scope: PauseScope,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    let mut paused = PAUSED_SCOPES.may_load(deps.storage)?.unwrap_or_default();
+    paused.retain(|paused_scope| paused_scope != &scope);
+    PAUSED_SCOPES.save(deps.storage, &paused)?;
+    Ok(Response::new().add_attributes(vec![("action", "unpause"), ("scope", &scope.to_string())]))
+}
+
+pub fn handle_set_guardian(
+    deps: DepsMut,
+    info: MessageInfo,
+    guardian: Option<String>,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    match guardian {
+        Some(guardian) => {
+            let guardian = deps.api.addr_validate(&guardian)?;
+            GUARDIAN.save(deps.storage, &guardian)?;
+            Ok(Response::new().add_attributes(vec![
+                ("action", "set_guardian"),
+                ("guardian", guardian.as_str()),
+            ]))
         }
-        if let Some(fee_receiver) = fee_receiver {
-            config.token_fee_receiver = deps.api.addr_validate(&fee_receiver)?;
+        None => {
+            GUARDIAN.remove(deps.storage);
+            Ok(Response::new()
+                .add_attributes(vec![("action", "set_guardian"), ("guardian", "None")]))
         }
-        if let Some(relayer_fee_receiver) = relayer_fee_receiver {
-            config.relayer_fee_receiver = deps.api.addr_validate(&relayer_fee_receiver)?;
+    }
+}
+
+pub fn handle_set_rate_limit(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel_id: String,
+    ibc_denom: String,
+    limit: RateLimit,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    if limit.window_seconds == 0 {
+        return Err(ContractError::InvalidRateLimitWindow);
+    }
+    // the sub-windows of the usage only line up with the window they were recorded in
+    if let Some(previous) = RATE_LIMITS.may_load(deps.storage, (&channel_id, &ibc_denom))? {
+        if previous.window_seconds != limit.window_seconds {
+            RATE_LIMIT_USAGE.remove(deps.storage, (&channel_id, &ibc_denom));
         }
-        config.default_gas_limit = default_gas_limit;
-        Ok(config)
-    })?;
-    if let Some(admin) = admin {
-        let admin = deps.api.addr_validate(&admin)?;
-        ADMIN.execute_update_admin::<Empty, Empty>(deps, info, Some(admin))?;
     }
-    Ok(Response::default().add_attribute("action", "update_config"))
+    RATE_LIMITS.save(deps.storage, (&channel_id, &ibc_denom), &limit)?;
+    let none = || "None".to_string();
+    Ok(Response::new().add_attributes(vec![
+        ("action", "set_rate_limit"),
+        ("channel_id", &channel_id),
+        ("ibc_denom", &ibc_denom),
+        ("window_seconds", &limit.window_seconds.to_string()),
+        (
+            "max_inflow",
+            &limit.max_inflow.map_or_else(none, |max| max.to_string()),
+        ),
+        (
+            "max_outflow",
+            &limit.max_outflow.map_or_else(none, |max| max.to_string()),
+        ),
+    ]))
 }
 
-pub fn execute_receive(
+pub fn handle_remove_rate_limit(
     deps: DepsMut,
-    env: Env,
     info: MessageInfo,
-    wrapper: Cw20ReceiveMsg,
+    channel_id: String,
+    ibc_denom: String,
 ) -> Result<Response, ContractError> {
-    nonpayable(&info)?;
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    RATE_LIMITS.remove(deps.storage, (&channel_id, &ibc_denom));
+    RATE_LIMIT_USAGE.remove(deps.storage, (&channel_id, &ibc_denom));
+    Ok(Response::new().add_attributes(vec![
+        ("action", "remove_rate_limit"),
+        ("channel_id", &channel_id),
+        ("ibc_denom", &ibc_denom),
+    ]))
+}
 
-    let amount = Amount::Cw20(Cw20Coin {
-        address: info.sender.to_string(),
-        amount: wrapper.amount,
-    });
-    let api = deps.api;
+pub fn handle_set_fee_exemption(
+    deps: DepsMut,
+    info: MessageInfo,
+    subject: FeeExemptionSubject,
+    discount: Option<Ratio>,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    if let Some(ratio) = &discount {
+        if ratio.denominator == 0 || ratio.nominator > ratio.denominator {
+            return Err(ContractError::InvalidFeeDiscount);
+        }
+    }
+    let subject = match subject {
+        FeeExemptionSubject::LocalAddress { address } => FeeExemptionSubject::LocalAddress {
+            address: deps.api.addr_validate(&address)?.into_string(),
+        },
+        subject => subject,
+    };
+    let exemption = FeeExemption { subject, discount };
+    FEE_EXEMPTIONS.save(deps.storage, &exemption.subject.to_string(), &exemption)?;
+    Ok(Response::new().add_attributes(vec![
+        ("action", "set_fee_exemption"),
+        ("subject", &exemption.subject.to_string()),
+        ("discount", &exemption.discount().to_string()),
+    ]))
+}
 
-    // let msg_result: StdResult<TransferMsg> = from_binary(&wrapper.msg);
-    // if msg_result.is_ok() {
-    //     let msg: TransferMsg = msg_result.unwrap();
-    //     return execute_transfer(deps, env, msg, amount, api.addr_validate(&wrapper.sender)?);
-    // }
+pub fn handle_remove_fee_exemption(
+    deps: DepsMut,
+    info: MessageInfo,
+    subject: FeeExemptionSubject,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    FEE_EXEMPTIONS.remove(deps.storage, &subject.to_string());
+    Ok(Response::new().add_attributes(vec![
+        ("action", "remove_fee_exemption"),
+        ("subject", &subject.to_string()),
+    ]))
+}
 
-    let msg: TransferBackMsg = from_binary(&wrapper.msg)?;
-    execute_transfer_back_to_remote_chain(
+pub fn handle_set_fees(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_fees: Vec<TokenFee>,
+    relayer_fees: Vec<RelayerFee>,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    validate_token_fees(&token_fees)?;
+    let attributes = vec![
+        attr("action", "set_fees"),
+        attr("token_fees", token_fees.len().to_string()),
+        attr("relayer_fees", relayer_fees.len().to_string()),
+    ];
+    let queued = schedule_sensitive_change(
         deps,
-        env,
-        msg,
-        amount,
-        api.addr_validate(&wrapper.sender)?,
-    )
+        &env,
+        SensitiveChange::SetFees {
+            token_fees,
+            relayer_fees,
+        },
+    )?;
+    Ok(Response::new()
+        .add_attributes(attributes)
+        .add_attributes(queued))
+}
+
+// an invalid schedule is rejected when the change is made, not when a queued change is executed
+fn validate_token_fees(token_fees: &[TokenFee]) -> Result<(), ContractError> {
+    for fee in token_fees {
+        fee.schedule().validate(&fee.token_denom)?;
+    }
+    Ok(())
 }
 
-// pub fn execute_transfer(
-//     deps: DepsMut,
-//     env: Env,
-//     msg: TransferMsg,
-//     amount: Amount,
-//     sender: Addr,
-// ) -> Result<Response, ContractError> {
-//     if amount.is_empty() {
-//         return Err(ContractError::NoFunds {});
-//     }
-//     // ensure the requested channel is registered
-//     if !CHANNEL_INFO.has(deps.storage, &msg.channel) {
-//         return Err(ContractError::NoSuchChannel { id: msg.channel });
-//     }
-//     let config = CONFIG.load(deps.storage)?;
-
-//     // if cw20 token, validate and ensure it is whitelisted, or we set default gas limit
-//     if let Amount::Cw20(coin) = &amount {
-//         let addr = deps.api.addr_validate(&coin.address)?;
-//         // if limit is set, then we always allow cw20
-//         if config.default_gas_limit.is_none() {
-//             ALLOW_LIST
-//                 .may_load(deps.storage, &addr)?
-//                 .ok_or(ContractError::NotOnAllowList)?;
-//         }
-//     };
-
-//     // delta from user is in seconds
-//     let timeout_delta = match msg.timeout {
-//         Some(t) => t,
-//         None => config.default_timeout,
-//     };
-//     // timeout is in nanoseconds
-//     let timeout = env.block.time.plus_seconds(timeout_delta);
-
-//     // build ics20 packet
-//     let packet = Ics20Packet::new(
-//         amount.amount(),
-//         amount.denom(),
-//         sender.as_ref(),
-//         &msg.remote_address,
-//         msg.memo,
-//     );
-//     packet.validate()?;
-
-//     // Update the balance now (optimistically) like ibctransfer modules.
-//     // In on_packet_failure (ack with error message or a timeout), we reduce the balance appropriately.
-//     // This means the channel works fine if success acks are not relayed.
-//     increase_channel_balance(
-//         deps.storage,
-//         &msg.channel,
-//         &amount.denom(),
-//         amount.amount(),
-//         true,
-//     )?;
-
-//     // prepare ibc message
-//     let msg = IbcMsg::SendPacket {
-//         channel_id: msg.channel,
-//         data: to_binary(&packet)?,
-//         timeout: timeout.into(),
-//     };
-
-//     // send response
-//     let res = Response::new()
-//         .add_message(msg)
-//         .add_attribute("action", "transfer")
-//         .add_attribute("sender", &packet.sender)
-//         .add_attribute("receiver", &packet.receiver)
-//         .add_attribute("denom", &packet.denom)
-//         .add_attribute("amount", &packet.amount.to_string());
-//     Ok(res)
-// }
+fn set_fees(
+    storage: &mut dyn Storage,
+    height: u64,
+    token_fees: &[TokenFee],
+    relayer_fees: &[RelayerFee],
+) -> Result<(), ContractError> {
+    for fee in token_fees {
+        save_token_fee(storage, height, &fee.token_denom, Some(fee.schedule()))?;
+    }
+    for fee in relayer_fees {
+        save_relayer_fee(
+            storage,
+            height,
+            &fee.prefix,
+            fee.remote_denom(),
+            Some(fee.schedule()),
+        )?;
+    }
+    Ok(())
+}
 
-pub fn execute_transfer_back_to_remote_chain(
+pub fn handle_remove_fees(
     deps: DepsMut,
     env: Env,
-    msg: TransferBackMsg,
-    amount: Amount,
-    sender: Addr,
+    info: MessageInfo,
+    fees: Vec<FeeKey>,
 ) -> Result<Response, ContractError> {
-    if amount.is_empty() {
-        return Err(ContractError::NoFunds {});
-    }
-    let config = CONFIG.load(deps.storage)?;
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    let attributes = vec![
+        attr("action", "remove_fees"),
+        attr(
+            "fees",
+            fees.iter()
+                .map(|fee| fee.to_string())
+                .collect::<Vec<String>>()
+                .join(","),
+        ),
+    ];
+    let queued = schedule_sensitive_change(deps, &env, SensitiveChange::RemoveFees { fees })?;
+    Ok(Response::new()
+        .add_attributes(attributes)
+        .add_attributes(queued))
+}
 
-    // should be in form port/channel/denom
-    let mappings = get_mappings_from_asset_info(
-        deps.as_ref().storage,
-        match amount.clone() {
-            Amount::Native(coin) => AssetInfo::NativeToken { denom: coin.denom },
-            Amount::Cw20(cw20_coin) => AssetInfo::Token {
-                contract_addr: deps.api.addr_validate(cw20_coin.address.as_str())?,
-            },
-        },
-    )?;
-
-    // parse denom & compare with user input. Should not use string.includes() because hacker can fake a port that has the same remote denom to return true
-    let mapping = mappings
-        .into_iter()
-        .find(|pair| -> bool {
-            let (denom, is_native) = parse_voucher_denom(
-                pair.key.as_str(),
-                &IbcEndpoint {
-                    port_id: parse_ibc_wasm_port_id(env.contract.address.clone().into_string()),
-                    channel_id: msg.local_channel_id.clone(), // also verify local channel id
-                },
-            )
-            .unwrap_or_else(|_| ("", true)); // if there's an error, change is_native to true so it automatically returns false
-            if is_native {
-                return false;
-            }
-            if denom.eq(&msg.remote_denom) {
-                return true;
-            }
-            return false;
-        })
-        .ok_or(ContractError::MappingPairNotFound {})?;
-
-    // if found mapping, then deduct fee based on mapping
-    let fee_data = process_deduct_fee(
-        deps.storage,
-        &deps.querier,
-        deps.api,
-        &msg.remote_address,
-        &msg.remote_denom,
-        amount,
-        &config.swap_router_contract,
-    )?;
+fn remove_fees(
+    storage: &mut dyn Storage,
+    height: u64,
+    fees: &[FeeKey],
+) -> Result<(), ContractError> {
+    for fee in fees {
+        match fee {
+            FeeKey::TokenFee { token_denom } => save_token_fee(storage, height, token_denom, None)?,
+            FeeKey::RelayerFee {
+                prefix,
+                remote_denom,
+            } => save_relayer_fee(
+                storage,
+                height,
+                prefix,
+                remote_denom.as_deref().unwrap_or_default(),
+                None,
+            )?,
+        }
+    }
+    Ok(())
+}
 
-    let mut cosmos_msgs: Vec<CosmosMsg> = vec![];
-    if !fee_data.token_fee.is_empty() {
-        cosmos_msgs.push(
-            fee_data
-                .token_fee
-                .send_amount(config.token_fee_receiver.into_string(), None),
-        )
+pub fn handle_replace_fees(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_fees: Option<Vec<TokenFee>>,
+    relayer_fees: Option<Vec<RelayerFee>>,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    validate_token_fees(token_fees.as_deref().unwrap_or_default())?;
+    let mut res = Response::new().add_attribute("action", "replace_fees");
+    if let Some(token_fees) = &token_fees {
+        res = res.add_attribute("token_fees", token_fees.len().to_string());
     }
-    if !fee_data.relayer_fee.is_empty() {
-        cosmos_msgs.push(
-            fee_data
-                .relayer_fee
-                .send_amount(config.relayer_fee_receiver.into_string(), None),
-        )
+    if let Some(relayer_fees) = &relayer_fees {
+        res = res.add_attribute("relayer_fees", relayer_fees.len().to_string());
     }
+    let queued = schedule_sensitive_change(
+        deps,
+        &env,
+        SensitiveChange::ReplaceFees {
+            token_fees,
+            relayer_fees,
+        },
+    )?;
+    Ok(res.add_attributes(queued))
+}
 
-    // send response
-    let token_fee_str = fee_data.token_fee.amount().to_string();
-    let relayer_fee_str = fee_data.relayer_fee.amount().to_string();
-    let attributes = vec![
-        ("action", "transfer_back_to_remote_chain"),
-        ("sender", sender.as_str()),
-        ("receiver", &msg.remote_address),
-        ("token_fee", &token_fee_str),
-        ("relayer_fee", &relayer_fee_str),
-    ];
-
-    // if our fees have drained the initial amount entirely, then we just get all the fees and that's it
-    if fee_data.deducted_amount.is_zero() {
-        return Ok(Response::new()
-            .add_messages(cosmos_msgs)
-            .add_attributes(attributes));
+fn replace_fees(
+    storage: &mut dyn Storage,
+    height: u64,
+    token_fees: Option<Vec<TokenFee>>,
+    relayer_fees: Option<Vec<RelayerFee>>,
+) -> Result<(), ContractError> {
+    if let Some(token_fees) = token_fees {
+        // entries missing from the new list are removed, so the history shows them as deleted
+        let stale = TOKEN_FEE
+            .keys(storage, None, None, Order::Ascending)
+            .filter(|key| match key {
+                Ok(key) => !token_fees.iter().any(|fee| &fee.token_denom == key),
+                Err(_) => true,
+            })
+            .collect::<StdResult<Vec<String>>>()?;
+        for token_denom in stale {
+            save_token_fee(storage, height, &token_denom, None)?;
+        }
+        set_fees(storage, height, &token_fees, &[])?;
     }
-
-    let ibc_denom = mapping.key;
-    // ensure the requested channel is registered
-    if !CHANNEL_INFO.has(deps.storage, &msg.local_channel_id) {
-        return Err(ContractError::NoSuchChannel {
-            id: msg.local_channel_id,
-        });
+    if let Some(relayer_fees) = relayer_fees {
+        let stale = RELAYER_FEE
+            .keys(storage, None, None, Order::Ascending)
+            .filter(|key| match key {
+                Ok((prefix, remote_denom)) => !relayer_fees
+                    .iter()
+                    .any(|fee| &fee.prefix == prefix && fee.remote_denom() == remote_denom),
+                Err(_) => true,
+            })
+            .collect::<StdResult<Vec<(String, String)>>>()?;
+        for (prefix, remote_denom) in stale {
+            save_relayer_fee(storage, height, &prefix, &remote_denom, None)?;
+        }
+        set_fees(storage, height, &[], &relayer_fees)?;
     }
+    Ok(())
+}
 
-    // delta from user is in seconds
-    let timeout_delta = match msg.timeout {
-        Some(t) => t,
-        None => config.default_timeout,
+pub fn handle_set_swap_route(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    offer_asset_info: AssetInfo,
+    ask_asset_info: AssetInfo,
+    operations: Option<Vec<SwapOperation>>,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    let offer = offer_asset_info.to_string();
+    let ask = ask_asset_info.to_string();
+    let res = match &operations {
+        Some(operations) => {
+            validate_swap_route(&offer_asset_info, &ask_asset_info, operations)?;
+            Response::new().add_attributes(vec![
+                ("action", "set_swap_route"),
+                ("offer_asset_info", &offer),
+                ("ask_asset_info", &ask),
+                ("operations", &operations.len().to_string()),
+            ])
+        }
+        None => Response::new().add_attributes(vec![
+            ("action", "remove_swap_route"),
+            ("offer_asset_info", &offer),
+            ("ask_asset_info", &ask),
+        ]),
     };
-    // timeout is in nanoseconds
-    let timeout = env.block.time.plus_seconds(timeout_delta);
-    // need to convert decimal of cw20 to remote decimal before transferring
-    let amount_remote = convert_local_to_remote(
-        fee_data.deducted_amount,
-        mapping.pair_mapping.remote_decimals,
-        mapping.pair_mapping.asset_info_decimals,
+    let queued = schedule_sensitive_change(
+        deps,
+        &env,
+        SensitiveChange::SwapRoute {
+            offer_asset_info,
+            ask_asset_info,
+            operations,
+        },
     )?;
+    Ok(res.add_attributes(queued))
+}
 
-    // now this is processed in ack
-    // // because we are transferring back, we reduce the channel's balance
-    reduce_channel_balance(
-        deps.storage,
-        &msg.local_channel_id,
-        &ibc_denom,
-        amount_remote,
-    )?;
+fn validate_swap_route(
+    offer_asset_info: &AssetInfo,
+    ask_asset_info: &AssetInfo,
+    operations: &[SwapOperation],
+) -> Result<(), ContractError> {
+    // each operation must offer what the previous one asked for, ending with the ask asset
+    let mut current = Some(offer_asset_info.clone());
+    for operation in operations.iter() {
+        let SwapOperation::OraiSwap {
+            offer_asset_info: operation_offer,
+            ask_asset_info: operation_ask,
+        } = operation;
+        current = current
+            .filter(|current| current.eq(operation_offer) && operation_ask.ne(operation_offer))
+            .map(|_| operation_ask.clone());
+    }
+    if operations.is_empty()
+        || offer_asset_info.eq(ask_asset_info)
+        || current.as_ref() != Some(ask_asset_info)
+    {
+        return Err(ContractError::InvalidSwapRoute {
+            offer: offer_asset_info.to_string(),
+            ask: ask_asset_info.to_string(),
+        });
+    }
+    Ok(())
+}
 
-    // prepare ibc message
-    let ibc_msg = build_ibc_send_packet(
-        amount_remote,
-        &ibc_denom, // we use ibc denom in form <transfer>/<channel>/<denom> so that when it is sent back to remote chain, it gets parsed correctly and burned
-        sender.as_str(),
-        &msg.remote_address,
-        msg.memo,
-        &msg.local_channel_id,
-        timeout.into(),
-    )?;
-    Ok(Response::new()
-        .add_messages(cosmos_msgs)
-        .add_message(ibc_msg)
-        .add_attributes(attributes)
-        .add_attributes(vec![
-            ("denom", &ibc_denom),
-            ("amount", &amount_remote.to_string()),
-        ]))
+pub fn handle_set_timeout_bounds(
+    deps: DepsMut,
+    info: MessageInfo,
+    min_timeout: u64,
+    max_timeout: u64,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    if max_timeout != 0 && max_timeout < min_timeout {
+        return Err(ContractError::InvalidTimeout {
+            reason: format!("max {} is below min {}", max_timeout, min_timeout),
+        });
+    }
+    let mut config = CONFIG.load(deps.storage)?;
+    config.min_timeout = min_timeout;
+    config.max_timeout = max_timeout;
+    CONFIG.save(deps.storage, &config)?;
+    Ok(Response::new().add_attributes(vec![
+        ("action", "set_timeout_bounds"),
+        ("min_timeout", &min_timeout.to_string()),
+        ("max_timeout", &max_timeout.to_string()),
+    ]))
 }
 
-/// The gov contract can allow new contracts, or increase the gas limit on existing contracts.
-/// It cannot block or reduce the limit to avoid forcible sticking tokens in the channel.
-pub fn execute_allow(
+pub fn handle_set_channel_timeout(
     deps: DepsMut,
-    _env: Env,
     info: MessageInfo,
-    allow: AllowMsg,
+    channel_id: String,
+    timeout: Option<u64>,
 ) -> Result<Response, ContractError> {
     ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    if !CHANNEL_INFO.has(deps.storage, &channel_id) {
+        return Err(ContractError::NoSuchChannel { id: channel_id });
+    }
+    match timeout {
+        Some(timeout) => CHANNEL_TIMEOUTS.save(deps.storage, &channel_id, &timeout)?,
+        None => CHANNEL_TIMEOUTS.remove(deps.storage, &channel_id),
+    }
+    Ok(Response::new().add_attributes(vec![
+        ("action", "set_channel_timeout"),
+        ("channel_id", &channel_id),
+        (
+            "timeout",
+            &timeout
+                .map(|timeout| timeout.to_string())
+                .unwrap_or_default(),
+        ),
+    ]))
+}
 
-    let contract = deps.api.addr_validate(&allow.contract)?;
-    let set = AllowInfo {
-        gas_limit: allow.gas_limit,
+// timeout of an outgoing packet on the channel. Without one from the sender, the default of the channel is used
+fn resolve_packet_timeout(
+    storage: &dyn Storage,
+    env: &Env,
+    config: &Config,
+    channel: &str,
+    timeout: Option<u64>,
+    packet_timeout: Option<PacketTimeout>,
+) -> Result<IbcTimeout, ContractError> {
+    let invalid = |reason: String| ContractError::InvalidTimeout { reason };
+    let packet_timeout = match (timeout, packet_timeout) {
+        (Some(_), Some(_)) => {
+            return Err(invalid(
+                "timeout and packet timeout cannot both be set".to_string(),
+            ))
+        }
+        (Some(seconds), None) => PacketTimeout::Relative { seconds },
+        (None, Some(packet_timeout)) => packet_timeout,
+        // the defaults are set by the admin, so they are not checked against the bounds
+        (None, None) => {
+            let seconds = channel_default_timeout(storage, channel, config.default_timeout)?;
+            return Ok(IbcTimeout::with_timestamp(
+                env.block.time.plus_seconds(seconds),
+            ));
+        }
     };
-    ALLOW_LIST.update(deps.storage, &contract, |old| {
-        if let Some(old) = old {
-            // we must ensure it increases the limit
-            match (old.gas_limit, set.gas_limit) {
-                (None, Some(_)) => return Err(ContractError::CannotLowerGas),
-                (Some(old), Some(new)) if new < old => return Err(ContractError::CannotLowerGas),
-                _ => {}
-            };
+    // the block time timeout must leave the packet between the min and max seconds to live
+    let check_timestamp = |timestamp: Timestamp| -> Result<Timestamp, ContractError> {
+        let seconds = timestamp
+            .seconds()
+            .checked_sub(env.block.time.seconds())
+            .ok_or_else(|| invalid(format!("{} is in the past", timestamp)))?;
+        if seconds < config.min_timeout {
+            return Err(invalid(format!(
+                "{} seconds is below the min of {}",
+                seconds, config.min_timeout
+            )));
         }
-        Ok(AllowInfo {
-            gas_limit: allow.gas_limit,
-        })
-    })?;
-
-    let gas = if let Some(gas) = allow.gas_limit {
-        gas.to_string()
-    } else {
-        "None".to_string()
+        if config.max_timeout != 0 && seconds > config.max_timeout {
+            return Err(invalid(format!(
+                "{} seconds is above the max of {}",
+                seconds, config.max_timeout
+            )));
+        }
+        Ok(timestamp)
     };
-
-    let res = Response::new()
-        .add_attribute("action", "allow")
-        .add_attribute("contract", allow.contract)
-        .add_attribute("gas_limit", gas);
-    Ok(res)
+    let check_block = |block: IbcTimeoutBlock| -> Result<IbcTimeoutBlock, ContractError> {
+        if block.height == 0 {
+            return Err(invalid("block height cannot be zero".to_string()));
+        }
+        Ok(block)
+    };
+    Ok(match packet_timeout {
+        PacketTimeout::Relative { seconds } => {
+            IbcTimeout::with_timestamp(check_timestamp(env.block.time.plus_seconds(seconds))?)
+        }
+        PacketTimeout::Absolute { timestamp } => {
+            IbcTimeout::with_timestamp(check_timestamp(timestamp)?)
+        }
+        // a block height alone could keep the packet in flight past the max, the timestamp is what the bounds check
+        PacketTimeout::Height { .. } => {
+            return Err(invalid(
+                "a block height needs a timestamp, use height or timestamp".to_string(),
+            ))
+        }
+        PacketTimeout::HeightOrTimestamp { block, timestamp } => {
+            IbcTimeout::with_both(check_block(block)?, check_timestamp(timestamp)?)
+        }
+    })
 }
 
-/// The gov contract can allow new contracts, or increase the gas limit on existing contracts.
-/// It cannot block or reduce the limit to avoid forcible sticking tokens in the channel.
-pub fn execute_update_mapping_pair(
+pub fn handle_set_slippage(
     deps: DepsMut,
-    env: Env,
     info: MessageInfo,
-    mapping_pair_msg: UpdatePairMsg,
+    default_bps: u16,
+    max_bps: u16,
 ) -> Result<Response, ContractError> {
     ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
-
-    let ibc_denom = get_key_ics20_ibc_denom(
-        &parse_ibc_wasm_port_id(env.contract.address.into_string()),
-        &mapping_pair_msg.local_channel_id,
-        &mapping_pair_msg.denom,
-    );
-
-    // if pair already exists in list, remove it and create a new one
-    if ics20_denoms().load(deps.storage, &ibc_denom).is_ok() {
-        ics20_denoms().remove(deps.storage, &ibc_denom)?;
+    if default_bps > max_bps || max_bps > MAX_SLIPPAGE_BPS {
+        return Err(ContractError::InvalidSlippage {
+            default_bps,
+            max_bps,
+        });
     }
+    let mut config = CONFIG.load(deps.storage)?;
+    config.default_slippage_bps = default_bps;
+    config.max_slippage_bps = max_bps;
+    CONFIG.save(deps.storage, &config)?;
+    Ok(Response::new().add_attributes(vec![
+        ("action", "set_slippage"),
+        ("default_slippage_bps", &default_bps.to_string()),
+        ("max_slippage_bps", &max_bps.to_string()),
+    ]))
+}
 
-    ics20_denoms().save(
-        deps.storage,
-        &ibc_denom,
-        &MappingMetadata {
-            asset_info: mapping_pair_msg.local_asset_info.clone(),
-            remote_decimals: mapping_pair_msg.remote_decimals,
-            asset_info_decimals: mapping_pair_msg.local_asset_info_decimals,
+pub fn handle_set_price_source(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    price_source: PriceSource,
+    price_fallback: PriceFallback,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    let price_source = match price_source {
+        PriceSource::Oracle { contract } => PriceSource::Oracle {
+            contract: deps.api.addr_validate(contract.as_str())?,
+        },
+        price_source => price_source,
+    };
+    let attributes = vec![
+        attr("action", "set_price_source"),
+        attr("price_source", format!("{:?}", price_source)),
+        attr("price_fallback", format!("{:?}", price_fallback)),
+    ];
+    let queued = schedule_sensitive_change(
+        deps,
+        &env,
+        SensitiveChange::PriceSource {
+            price_source,
+            price_fallback,
         },
     )?;
-
-    let res = Response::new()
-        .add_attribute("action", "execute_update_mapping_pair")
-        .add_attribute("denom", mapping_pair_msg.denom)
-        .add_attribute(
-            "new_asset_info",
-            mapping_pair_msg.local_asset_info.to_string(),
-        );
-    Ok(res)
+    Ok(Response::new()
+        .add_attributes(attributes)
+        .add_attributes(queued))
 }
 
-pub fn execute_delete_mapping_pair(
+pub fn handle_set_fixed_price(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
-    mapping_pair_msg: DeletePairMsg,
+    asset_info: AssetInfo,
+    price: Option<Decimal>,
 ) -> Result<Response, ContractError> {
     ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
-
-    let ibc_denom = get_key_ics20_ibc_denom(
-        &parse_ibc_wasm_port_id(env.contract.address.into_string()),
-        &mapping_pair_msg.local_channel_id,
-        &mapping_pair_msg.denom,
-    );
-
-    ics20_denoms().remove(deps.storage, &ibc_denom)?;
-
-    let res = Response::new()
-        .add_attribute("action", "execute_delete_mapping_pair")
-        .add_attribute("local_channel_id", mapping_pair_msg.local_channel_id)
-        .add_attribute("original_denom", mapping_pair_msg.denom);
-    Ok(res)
+    let attributes = vec![
+        attr("action", "set_fixed_price"),
+        attr("asset_info", asset_info.to_string()),
+        attr(
+            "price",
+            price.map(|price| price.to_string()).unwrap_or_default(),
+        ),
+    ];
+    let queued = schedule_sensitive_change(
+        deps,
+        &env,
+        SensitiveChange::FixedPrice { asset_info, price },
+    )?;
+    Ok(Response::new()
+        .add_attributes(attributes)
+        .add_attributes(queued))
 }
 
-#[entry_point]
-pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
-    // we don't need to save anything if migrating from the same version
-    // CONFIG.save(
-    //     deps.storage,
-    //     &Config {
-    //         default_timeout: msg.default_timeout,
-    //         default_gas_limit: msg.default_gas_limit,
-    //         fee_denom: msg.fee_denom,
-    //         swap_router_contract: RouterController(msg.swap_router_contract),
-    //         token_fee_receiver: deps.api.addr_validate(&msg.token_fee_receiver)?,
-    //         relayer_fee_receiver: deps.api.addr_validate(&msg.relayer_fee_receiver)?,
-    //     },
-    // )?;
-    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
-    Ok(Response::new())
+pub fn handle_recover_channel_funds(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel_id: String,
+    ibc_denom: String,
+    remote_amount: Uint128,
+    recipient: String,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    let recipient = deps.api.addr_validate(&recipient)?;
+    let channel_info = CHANNEL_INFO.load(deps.storage, &channel_id)?;
+    if !channel_info.closed {
+        return Err(ContractError::ChannelNotClosed { id: channel_id });
+    }
+
+    let fund = CHANNEL_RECOVERABLE_FUNDS.update(
+        deps.storage,
+        (&channel_id, &ibc_denom),
+        |fund| -> Result<_, ContractError> {
+            let mut fund = fund.ok_or(ContractError::NoSuchChannelState {
+                id: channel_id.clone(),
+                denom: ibc_denom.clone(),
+            })?;
+            fund.recovered = fund.recovered.checked_add(remote_amount)?;
+            if fund.recovered > fund.outstanding {
+                return Err(ContractError::InsufficientRecoverableFunds {
+                    id: channel_id.clone(),
+                    denom: ibc_denom.clone(),
+                });
+            }
+            Ok(fund)
+        },
+    )?;
+    let to_send = if fund.escrowed {
+        // escrowed tokens of this chain are unescrowed as they are, the denom is local
+        reduce_channel_forward_balance(deps.storage, &channel_id, &ibc_denom, remote_amount)?;
+        Amount::from_parts(ibc_denom.clone(), remote_amount)
+    } else {
+        // the recovered amount no longer backs anything on the remote chain
+        reduce_channel_balance(deps.storage, &channel_id, &ibc_denom, remote_amount)?;
+        let pair_mapping = ics20_denoms().load(deps.storage, &ibc_denom)?;
+        Amount::from_parts(
+            parse_asset_info_denom(pair_mapping.asset_info),
+            convert_remote_to_local(
+                remote_amount,
+                pair_mapping.remote_decimals,
+                pair_mapping.asset_info_decimals,
+            )?,
+        )
+    };
+
+    Ok(Response::new()
+        .add_message(to_send.send_amount(recipient.to_string(), None))
+        .add_attributes(vec![
+            ("action", "recover_channel_funds"),
+            ("channel_id", &channel_id),
+            ("ibc_denom", &ibc_denom),
+            ("amount", &remote_amount.to_string()),
+            ("local_amount", &to_send.amount().to_string()),
+            ("recipient", recipient.as_str()),
+        ]))
 }
 
-#[entry_point]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::Port {} => to_binary(&query_port(deps)?),
-        QueryMsg::ListChannels {} => to_binary(&query_list(deps)?),
-        QueryMsg::Channel { id } => to_binary(&query_channel(deps, id)?),
-        QueryMsg::ChannelWithKey { channel_id, denom } => {
-            to_binary(&query_channel_with_key(deps, channel_id, denom)?)
-        }
-        QueryMsg::Config {} => to_binary(&query_config(deps)?),
-        QueryMsg::Allowed { contract } => to_binary(&query_allowed(deps, contract)?),
-        QueryMsg::ListAllowed {
-            start_after,
-            limit,
-            order,
-        } => to_binary(&list_allowed(deps, start_after, limit, order)?),
-        QueryMsg::PairMappings {
-            start_after,
-            limit,
-            order,
-        } => to_binary(&list_cw20_mapping(deps, start_after, limit, order)?),
-        QueryMsg::PairMapping { key } => to_binary(&get_mapping_from_key(deps, key)?),
-        QueryMsg::PairMappingsFromAssetInfo { asset_info } => {
-            to_binary(&get_mappings_from_asset_info(deps.storage, asset_info)?)
-        }
-        QueryMsg::Admin {} => to_binary(&ADMIN.query_admin(deps)?),
-        QueryMsg::GetTransferTokenFee { remote_token_denom } => {
-            to_binary(&TOKEN_FEE.load(deps.storage, &remote_token_denom)?)
-        }
+pub fn handle_collect_fees(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.token_fee_receiver
+        && info.sender != config.relayer_fee_receiver
+        && !ADMIN.is_admin(deps.as_ref(), &info.sender)?
+    {
+        return Err(ContractError::NotFeeCollector);
     }
+    let mut cosmos_msgs = collect_fee_msgs(
+        deps.storage,
+        config.token_fee_receiver.into_string(),
+        TOKEN_FEE_ACCUMULATOR,
+    )?;
+    cosmos_msgs.extend(collect_fee_msgs(
+        deps.storage,
+        config.relayer_fee_receiver.into_string(),
+        RELAYER_FEE_ACCUMULATOR,
+    )?);
+    Ok(Response::new()
+        .add_messages(cosmos_msgs)
+        .add_attribute("action", "collect_fees"))
 }
 
-fn query_port(deps: Deps) -> StdResult<PortResponse> {
-    let query = IbcQuery::PortId {}.into();
-    let PortIdResponse { port_id } = deps.querier.query(&query)?;
-    Ok(PortResponse { port_id })
+pub fn handle_register_relayer(
+    deps: DepsMut,
+    info: MessageInfo,
+    relayer: String,
+    payout: Option<String>,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    let relayer = deps.api.addr_validate(&relayer)?;
+    let payout = match payout {
+        Some(payout) => deps.api.addr_validate(&payout)?,
+        None => relayer.clone(),
+    };
+    RELAYERS.save(
+        deps.storage,
+        &relayer,
+        &RelayerInfo {
+            payout: payout.clone(),
+        },
+    )?;
+    Ok(Response::new().add_attributes(vec![
+        ("action", "register_relayer"),
+        ("relayer", relayer.as_str()),
+        ("payout", payout.as_str()),
+    ]))
 }
 
-fn query_list(deps: Deps) -> StdResult<ListChannelsResponse> {
-    let channels = CHANNEL_INFO
-        .range_raw(deps.storage, None, None, Order::Ascending)
-        .map(|r| r.map(|(_, v)| v))
-        .collect::<StdResult<_>>()?;
-    Ok(ListChannelsResponse { channels })
+pub fn handle_remove_relayer(
+    deps: DepsMut,
+    info: MessageInfo,
+    relayer: String,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    RELAYERS.remove(deps.storage, &Addr::unchecked(&relayer));
+    Ok(Response::new().add_attributes(vec![("action", "remove_relayer"), ("relayer", &relayer)]))
 }
 
-// make public for ibc tests
-pub fn query_channel(deps: Deps, id: String) -> StdResult<ChannelResponse> {
-    let info = CHANNEL_INFO.load(deps.storage, &id)?;
-    // this returns Vec<(outstanding, total)>
-    let channel_state = CHANNEL_REVERSE_STATE;
-    let state = channel_state
-        .prefix(&id)
+pub fn handle_claim_relayer_fees(
+    deps: DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let relayer = info.sender;
+    let payout = RELAYERS
+        .may_load(deps.storage, &relayer)?
+        .map(|info| info.payout)
+        .unwrap_or_else(|| relayer.clone());
+    let balances = RELAYER_BALANCES
+        .prefix(&relayer)
         .range(deps.storage, None, None, Order::Ascending)
-        .map(|r| {
-            // this denom is
-            r.map(|(denom, v)| {
-                let outstanding = Amount::from_parts(denom.clone(), v.outstanding);
-                let total = Amount::from_parts(denom, v.total_sent);
-                (outstanding, total)
-            })
-        })
-        .collect::<StdResult<Vec<_>>>()?;
-    // we want (Vec<outstanding>, Vec<total>)
-    let (balances, total_sent): (Vec<Amount>, Vec<Amount>) = state.into_iter().unzip();
-
-    Ok(ChannelResponse {
-        info,
-        balances,
-        total_sent,
-    })
+        .collect::<StdResult<Vec<(String, Uint128)>>>()?;
+    let mut cosmos_msgs = vec![];
+    for (denom, amount) in balances {
+        RELAYER_BALANCES.remove(deps.storage, (&relayer, &denom));
+        if !amount.is_zero() {
+            cosmos_msgs
+                .push(Amount::from_parts(denom, amount).send_amount(payout.to_string(), None));
+        }
+    }
+    Ok(Response::new()
+        .add_messages(cosmos_msgs)
+        .add_attributes(vec![
+            ("action", "claim_relayer_fees"),
+            ("relayer", relayer.as_str()),
+            ("payout", payout.as_str()),
+        ]))
 }
 
-pub fn query_channel_with_key(
-    deps: Deps,
-    channel_id: String,
-    denom: String,
-) -> StdResult<ChannelWithKeyResponse> {
-    let info = CHANNEL_INFO.load(deps.storage, &channel_id)?;
-    // this returns Vec<(outstanding, total)>
-    let (balance, total_sent) = CHANNEL_REVERSE_STATE
-        .load(deps.storage, (&channel_id, &denom))
-        .map(|channel_state| {
-            let outstanding = Amount::from_parts(denom.clone(), channel_state.outstanding);
-            let total = Amount::from_parts(denom, channel_state.total_sent);
-            (outstanding, total)
-        })?;
+pub fn handle_claim_stuck_fund(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let fund = STUCK_FUNDS.load(deps.storage, id)?;
+    if info.sender != fund.receiver {
+        return Err(ContractError::NotStuckFundReceiver { id });
+    }
+    pay_out_stuck_fund(deps, env, id, fund, info.sender.into_string())
+}
 
-    Ok(ChannelWithKeyResponse {
-        info,
-        balance,
-        total_sent,
-    })
+pub fn handle_resolve_stuck_fund(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+    recipient: String,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    let recipient = deps.api.addr_validate(&recipient)?;
+    let fund = STUCK_FUNDS.load(deps.storage, id)?;
+    pay_out_stuck_fund(deps, env, id, fund, recipient.into_string())
 }
 
-fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
-    let cfg = CONFIG.load(deps.storage)?;
-    let admin = ADMIN.get(deps)?.unwrap_or_else(|| Addr::unchecked(""));
-    let res = ConfigResponse {
-        default_timeout: cfg.default_timeout,
-        default_gas_limit: cfg.default_gas_limit,
-        fee_denom: cfg.fee_denom,
-        swap_router_contract: cfg.swap_router_contract.addr(),
-        gov_contract: admin.into(),
-        relayer_fee_receiver: cfg.relayer_fee_receiver,
-        token_fee_receiver: cfg.token_fee_receiver,
-        token_fees: TOKEN_FEE
-            .range(deps.storage, None, None, Order::Ascending)
-            .map(|data_result| {
-                data_result.map(|data| TokenFee {
-                    token_denom: data.0,
-                    ratio: data.1,
-                })
-            })
-            .collect::<StdResult<Vec<TokenFee>>>()?,
-        relayer_fees: RELAYER_FEE
-            .range(deps.storage, None, None, Order::Ascending)
-            .map(|data_result| {
-                data_result.map(|data| RelayerFeeResponse {
-                    prefix: data.0,
-                    amount: data.1,
-                })
-            })
-            .collect::<StdResult<Vec<RelayerFeeResponse>>>()?,
-    };
-    Ok(res)
+// the record is kept after the payout so that manual recoveries stay auditable
+fn pay_out_stuck_fund(
+    deps: DepsMut,
+    env: Env,
+    id: u64,
+    mut fund: StuckFund,
+    recipient: String,
+) -> Result<Response, ContractError> {
+    if fund.paid_to.is_some() {
+        return Err(ContractError::StuckFundAlreadyPaid { id });
+    }
+    fund.paid_to = Some(recipient.clone());
+    fund.paid_height = Some(env.block.height);
+    STUCK_FUNDS.save(deps.storage, id, &fund)?;
+
+    let to_send = Amount::from_parts(fund.asset.clone(), fund.amount);
+    Ok(Response::new()
+        .add_message(to_send.send_amount(recipient.clone(), None))
+        .add_attributes(vec![
+            ("action", "pay_out_stuck_fund"),
+            ("id", &id.to_string()),
+            ("receiver", &fund.receiver),
+            ("recipient", &recipient),
+            ("asset", &fund.asset),
+            ("amount", &fund.amount.to_string()),
+        ]))
 }
 
-fn query_allowed(deps: Deps, contract: String) -> StdResult<AllowedResponse> {
-    let addr = deps.api.addr_validate(&contract)?;
-    let info = ALLOW_LIST.may_load(deps.storage, &addr)?;
-    let res = match info {
-        None => AllowedResponse {
-            is_allowed: false,
-            gas_limit: None,
-        },
-        Some(a) => AllowedResponse {
-            is_allowed: true,
-            gas_limit: a.gas_limit,
-        },
-    };
+#[allow(clippy::too_many_arguments)]
+pub fn update_config(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    default_timeout: Option<u64>,
+    default_gas_limit: Option<u64>,
+    fee_denom: Option<String>,
+    swap_router_contract: Option<String>,
+    admin: Option<String>,
+    token_fee: Option<Vec<TokenFee>>,
+    fee_receiver: Option<String>,
+    relayer_fee_receiver: Option<String>,
+    relayer_fee: Option<Vec<RelayerFee>>,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    CONFIG.update(deps.storage, |mut config| -> StdResult<Config> {
+        if let Some(default_timeout) = default_timeout {
+            config.default_timeout = default_timeout;
+        }
+        if let Some(fee_denom) = fee_denom {
+            config.fee_denom = fee_denom;
+        }
+        config.default_gas_limit = default_gas_limit;
+        Ok(config)
+    })?;
+
+    // the sensitive fields go through the timelock
+    let mut changes = vec![];
+    if token_fee.is_some() || relayer_fee.is_some() {
+        let token_fees = token_fee.unwrap_or_default();
+        validate_token_fees(&token_fees)?;
+        changes.push(SensitiveChange::SetFees {
+            token_fees,
+            relayer_fees: relayer_fee.unwrap_or_default(),
+        });
+    }
+    if let Some(swap_router_contract) = swap_router_contract {
+        changes.push(SensitiveChange::SwapRouterContract {
+            swap_router_contract,
+        });
+    }
+    if let Some(fee_receiver) = fee_receiver {
+        changes.push(SensitiveChange::TokenFeeReceiver {
+            fee_receiver: deps.api.addr_validate(&fee_receiver)?,
+        });
+    }
+    if let Some(relayer_fee_receiver) = relayer_fee_receiver {
+        changes.push(SensitiveChange::RelayerFeeReceiver {
+            relayer_fee_receiver: deps.api.addr_validate(&relayer_fee_receiver)?,
+        });
+    }
+    let mut res = Response::default().add_attribute("action", "update_config");
+    for change in changes {
+        res = res.add_attributes(schedule_sensitive_change(deps.branch(), &env, change)?);
+    }
+
+    // the new admin only takes over once it accepts
+    if let Some(admin) = admin {
+        let admin = deps.api.addr_validate(&admin)?;
+        PENDING_ADMIN.save(deps.storage, &admin)?;
+        res = res.add_attribute("pending_admin", admin);
+    }
     Ok(res)
 }
 
-// settings for pagination
-const MAX_LIMIT: u32 = 30;
-const DEFAULT_LIMIT: u32 = 10;
-
-fn list_allowed(
-    deps: Deps,
-    start_after: Option<String>,
-    limit: Option<u32>,
-    order: Option<u8>,
-) -> StdResult<ListAllowedResponse> {
-    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
-    let addr = maybe_addr(deps.api, start_after)?;
-    let start = addr.as_ref().map(Bound::exclusive);
+pub fn execute_receive(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    wrapper: Cw20ReceiveMsg,
+) -> Result<Response, ContractError> {
+    nonpayable(&info)?;
 
-    let allow = ALLOW_LIST
-        .range(deps.storage, start, None, map_order(order))
-        .take(limit)
-        .map(|item| {
-            item.map(|(addr, allow)| AllowedInfo {
-                contract: addr.into(),
-                gas_limit: allow.gas_limit,
-            })
-        })
-        .collect::<StdResult<_>>()?;
-    Ok(ListAllowedResponse { allow })
-}
+    let amount = Amount::Cw20(Cw20Coin {
+        address: info.sender.to_string(),
+        amount: wrapper.amount,
+    });
+    let api = deps.api;
 
-fn list_cw20_mapping(
-    deps: Deps,
-    start_after: Option<String>,
-    limit: Option<u32>,
-    order: Option<u8>,
-) -> StdResult<ListMappingResponse> {
-    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
-    let mut allow_range = ics20_denoms().range(deps.storage, None, None, map_order(order));
-    if let Some(start_after) = start_after {
-        let start = Some(Bound::exclusive::<&str>(&start_after));
-        allow_range = ics20_denoms().range(deps.storage, start, None, map_order(order));
+    // a plain transfer escrows tokens that originated on this chain
+    if let Ok(msg) = from_binary::<TransferMsg>(&wrapper.msg) {
+        return execute_transfer(deps, env, msg, amount, api.addr_validate(&wrapper.sender)?);
     }
-    let pairs = allow_range
-        .take(limit)
-        .map(|item| {
-            item.map(|(key, mapping)| PairQuery {
-                key,
-                pair_mapping: mapping,
-            })
-        })
-        .collect::<StdResult<_>>()?;
-    Ok(ListMappingResponse { pairs })
-}
 
-fn get_mapping_from_key(deps: Deps, ibc_denom: String) -> StdResult<PairQuery> {
-    let result = ics20_denoms().load(deps.storage, &ibc_denom)?;
-    Ok(PairQuery {
-        key: ibc_denom,
-        pair_mapping: result,
-    })
+    if let Ok(msg) = from_binary::<BatchTransferBackMsg>(&wrapper.msg) {
+        return execute_batch_transfer_back_to_remote_chain(
+            deps,
+            env,
+            msg,
+            amount,
+            api.addr_validate(&wrapper.sender)?,
+        );
+    }
+
+    let msg: TransferBackMsg = from_binary(&wrapper.msg)?;
+    execute_transfer_back_to_remote_chain(
+        deps,
+        env,
+        msg,
+        amount,
+        api.addr_validate(&wrapper.sender)?,
+    )
 }
 
-fn get_mappings_from_asset_info(
-    storage: &dyn Storage,
-    asset_info: AssetInfo,
-) -> StdResult<Vec<PairQuery>> {
-    let pair_mapping_result: StdResult<Vec<(String, MappingMetadata)>> = ics20_denoms()
-        .idx
-        .asset_info
-        .prefix(asset_info.to_string())
-        .range(storage, None, None, Order::Ascending)
-        .collect();
-    if pair_mapping_result.is_err() {
-        return Err(pair_mapping_result.unwrap_err());
+pub fn execute_transfer(
+    deps: DepsMut,
+    env: Env,
+    msg: TransferMsg,
+    amount: Amount,
+    sender: Addr,
+) -> Result<Response, ContractError> {
+    if amount.is_empty() {
+        return Err(ContractError::NoFunds {});
     }
-    let pair_mappings = pair_mapping_result.unwrap();
-    let pair_queries: Vec<PairQuery> = pair_mappings
-        .into_iter()
-        .map(|pair| PairQuery {
-            key: pair.0,
-            pair_mapping: pair.1,
-        })
-        .collect();
-    Ok(pair_queries)
+    // ensure the requested channel is registered and still open
+    let channel_info =
+        CHANNEL_INFO
+            .may_load(deps.storage, &msg.channel)?
+            .ok_or(ContractError::NoSuchChannel {
+                id: msg.channel.clone(),
+            })?;
+    if channel_info.closed {
+        return Err(ContractError::ChannelClosed { id: msg.channel });
+    }
+    let config = CONFIG.load(deps.storage)?;
+
+    // if cw20 token, validate and ensure it is whitelisted, or we set default gas limit
+    if let Amount::Cw20(coin) = &amount {
+        let addr = deps.api.addr_validate(&coin.address)?;
+        // if limit is set, then we always allow cw20
+        if config.default_gas_limit.is_none() {
+            ALLOW_LIST
+                .may_load(deps.storage, &addr)?
+                .ok_or(ContractError::NotOnAllowList)?;
+        }
+    };
+
+    let timeout = resolve_packet_timeout(
+        deps.storage,
+        &env,
+        &config,
+        &msg.channel,
+        msg.timeout,
+        msg.packet_timeout.clone(),
+    )?;
+
+    let denom = amount.denom();
+    assert_not_paused(deps.storage, &msg.channel, &denom, FlowDirection::Outflow)?;
+    // tokens that originate on this chain are rate limited under their local denom
+    record_rate_limited_flow(
+        deps.storage,
+        env.block.time,
+        &msg.channel,
+        &denom,
+        amount.amount(),
+        FlowDirection::Outflow,
+    )?;
+    // Update the balance now (optimistically) like ibctransfer modules.
+    // In on_packet_failure (ack with error message or a timeout), we reduce the balance appropriately.
+    // This means the channel works fine if success acks are not relayed.
+    increase_channel_forward_balance(deps.storage, &msg.channel, &denom, amount.amount())?;
+
+    // prepare ibc message
+    let ibc_msg = build_ibc_send_packet(
+        amount.amount(),
+        &denom,
+        sender.as_str(),
+        &msg.remote_address,
+        msg.memo,
+        &msg.channel,
+        timeout,
+    )?;
+
+    // snapshot the escrow so that a failed ack or a timeout refunds exactly what was sent
+    let nonce = next_reply_nonce(deps.storage)?;
+    UNSEQUENCED_TRANSFERS.save(
+        deps.storage,
+        nonce,
+        &PendingTransfer {
+            src_channel_id: msg.channel,
+            local_sender: sender.to_string(),
+            local_amount: amount.clone(),
+            token_fee: Uint128::zero(),
+            relayer_fee: Uint128::zero(),
+            relayer_fee_on_ack: false,
+            ibc_denom: denom.clone(),
+            remote_amount: amount.amount(),
+            mapping: None,
+            sent_at: Some(env.block.time),
+        },
+    )?;
+
+    // send response
+    Ok(Response::new()
+        .add_submessage(SubMsg::reply_on_success(
+            ibc_msg,
+            encode_reply_id(SEND_PACKET_ID, nonce),
+        ))
+        .add_attribute("action", "transfer")
+        .add_attribute("sender", sender)
+        .add_attribute("receiver", &msg.remote_address)
+        .add_attribute("denom", denom)
+        .add_attribute("amount", amount.amount().to_string()))
 }
 
-fn map_order(order: Option<u8>) -> Order {
-    if order.is_none() {
-        return Order::Ascending;
+/// Sends the attached native coins back to the remote chain, one packet per coin.
+/// With several coins, each coin uses msg.remote_denom if it is mapped to it on the channel,
+/// otherwise the only remote denom the coin is mapped to on the channel.
+pub fn execute_transfer_native_to_remote_chain(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: TransferBackMsg,
+) -> Result<Response, ContractError> {
+    if info.funds.len() <= 1 {
+        let coin = one_coin(&info)?;
+        let amount = Amount::from_parts(coin.denom, coin.amount);
+        return execute_transfer_back_to_remote_chain(deps, env, msg, amount, info.sender);
     }
-    let order_unwrap = order.unwrap();
-    if order_unwrap == 1 {
-        return Order::Ascending;
+    if msg.swap_to.is_some() {
+        return Err(ContractError::SwapWithSeveralCoins);
     }
-    Order::Descending
+
+    let mut res = Response::new();
+    for coin in info.funds {
+        let remote_denoms = get_channel_remote_denoms(
+            deps.storage,
+            &env,
+            &msg.local_channel_id,
+            AssetInfo::NativeToken {
+                denom: coin.denom.clone(),
+            },
+        )?;
+        let remote_denom = if remote_denoms.contains(&msg.remote_denom) {
+            msg.remote_denom.clone()
+        } else if remote_denoms.len() == 1 {
+            remote_denoms[0].clone()
+        } else {
+            return Err(ContractError::MappingPairNotFound {});
+        };
+        let coin_res = execute_transfer_back_to_remote_chain(
+            deps.branch(),
+            env.clone(),
+            TransferBackMsg {
+                remote_denom,
+                ..msg.clone()
+            },
+            Amount::from_parts(coin.denom, coin.amount),
+            info.sender.clone(),
+        )?;
+        res = res
+            .add_submessages(coin_res.messages)
+            .add_attributes(coin_res.attributes);
+    }
+    Ok(res)
 }
 
-#[cfg(test)]
-mod test {
-    use std::ops::Sub;
+/// Sends the amount back in several transfers, one packet per leg. If any leg fails, the whole batch fails
+pub fn execute_batch_transfer_back_to_remote_chain(
+    mut deps: DepsMut,
+    env: Env,
+    msg: BatchTransferBackMsg,
+    amount: Amount,
+    sender: Addr,
+) -> Result<Response, ContractError> {
+    if msg.legs.is_empty() || msg.legs.iter().any(|leg| leg.amount.is_zero()) {
+        return Err(ContractError::NoFunds {});
+    }
+    let legs_amount = msg
+        .legs
+        .iter()
+        .try_fold(Uint128::zero(), |total, leg| total.checked_add(leg.amount))?;
+    if legs_amount != amount.amount() {
+        return Err(ContractError::BatchAmountMismatch {
+            sent: amount.amount(),
+            legs: legs_amount,
+        });
+    }
 
-    use super::*;
-    use crate::ibc::{handle_packet_refund, ibc_packet_receive, Ics20Packet, REFUND_FAILURE_ID};
-    use crate::state::Ratio;
-    use crate::test_helpers::*;
+    let mut res = Response::new().add_attributes(vec![
+        ("action", "batch_transfer_back_to_remote_chain"),
+        ("legs", &msg.legs.len().to_string()),
+    ]);
+    for leg in msg.legs {
+        let leg_res = execute_transfer_back_to_remote_chain(
+            deps.branch(),
+            env.clone(),
+            leg.msg,
+            Amount::from_parts(amount.denom(), leg.amount),
+            sender.clone(),
+        )?;
+        res = res
+            .add_submessages(leg_res.messages)
+            .add_attributes(leg_res.attributes);
+    }
+    Ok(res)
+}
 
-    use cosmwasm_std::testing::{mock_env, mock_info};
-    use cosmwasm_std::{
-        coin, coins, BankMsg, CosmosMsg, Decimal, IbcEndpoint, IbcMsg, IbcPacket,
-        IbcPacketReceiveMsg, StdError, SubMsg, Timestamp, Uint128, WasmMsg,
+// remote denoms the asset is mapped to on the local channel
+fn get_channel_remote_denoms(
+    storage: &dyn Storage,
+    env: &Env,
+    local_channel_id: &str,
+    asset_info: AssetInfo,
+) -> StdResult<Vec<String>> {
+    let endpoint = IbcEndpoint {
+        port_id: parse_ibc_wasm_port_id(env.contract.address.clone().into_string()),
+        channel_id: local_channel_id.to_string(),
     };
-    use cw20::Cw20ExecuteMsg;
-    use cw_controllers::AdminError;
+    Ok(get_mappings_from_asset_info(storage, asset_info)?
+        .into_iter()
+        .filter_map(|pair| match parse_voucher_denom(&pair.key, &endpoint) {
+            Ok((denom, false)) => Some(denom.to_string()),
+            _ => None,
+        })
+        .collect())
+}
 
-    use oraiswap::asset::AssetInfo;
+// the mapping of the sent asset whose remote denom on the local channel is the requested one
+fn find_transfer_back_mapping(
+    deps: Deps,
+    env: &Env,
+    msg: &TransferBackMsg,
+    amount: &Amount,
+) -> Result<PairQuery, ContractError> {
+    // should be in form port/channel/denom
+    let mappings = get_mappings_from_asset_info(
+        deps.storage,
+        match amount.clone() {
+            Amount::Native(coin) => AssetInfo::NativeToken { denom: coin.denom },
+            Amount::Cw20(cw20_coin) => AssetInfo::Token {
+                contract_addr: deps.api.addr_validate(cw20_coin.address.as_str())?,
+            },
+        },
+    )?;
 
-    #[test]
-    fn test_split_denom() {
-        let split_denom: Vec<&str> = "orai".splitn(3, '/').collect();
-        assert_eq!(split_denom.len(), 1);
+    // parse denom & compare with user input. Should not use string.includes() because hacker can fake a port that has the same remote denom to return true
+    mappings
+        .into_iter()
+        .find(|pair| -> bool {
+            let (denom, is_native) = parse_voucher_denom(
+                pair.key.as_str(),
+                &IbcEndpoint {
+                    port_id: parse_ibc_wasm_port_id(env.contract.address.clone().into_string()),
+                    channel_id: msg.local_channel_id.clone(), // also verify local channel id
+                },
+            )
+            .unwrap_or(("", true)); // if there's an error, change is_native to true so it automatically returns false
+            if is_native {
+                return false;
+            }
+            if denom.eq(&msg.remote_denom) {
+                return true;
+            }
+            false
+        })
+        .ok_or(ContractError::MappingPairNotFound {})
+}
 
-        let split_denom: Vec<&str> = "a/b/c".splitn(3, '/').collect();
-        assert_eq!(split_denom.len(), 3)
+// offer asset, ask asset and operations of the swap requested before a transfer. None if there is nothing to swap
+fn load_transfer_swap(
+    deps: Deps,
+    config: &Config,
+    msg: &TransferBackMsg,
+    amount: &Amount,
+) -> StdResult<Option<(AssetInfo, AssetInfo, Vec<SwapOperation>)>> {
+    let ask_asset_info = match &msg.swap_to {
+        Some(swap_to) => swap_to.clone(),
+        None => return Ok(None),
+    };
+    let offer_asset_info = denom_to_asset_info(&deps.querier, deps.api, &amount.raw_denom())?;
+    if offer_asset_info.eq(&ask_asset_info) {
+        return Ok(None);
     }
+    let operations = load_swap_route(
+        deps.storage,
+        &offer_asset_info,
+        &ask_asset_info,
+        &config.fee_denom,
+    )?;
+    Ok(Some((offer_asset_info, ask_asset_info, operations)))
+}
 
-    #[test]
-    fn setup_and_query() {
-        let deps = setup(&["channel-3", "channel-7"], &[]);
-
-        let raw_list = query(deps.as_ref(), mock_env(), QueryMsg::ListChannels {}).unwrap();
-        let list_res: ListChannelsResponse = from_binary(&raw_list).unwrap();
-        assert_eq!(2, list_res.channels.len());
-        assert_eq!(mock_channel_info("channel-3"), list_res.channels[0]);
-        assert_eq!(mock_channel_info("channel-7"), list_res.channels[1]);
+/// Reply of the swap of a swap-then-transfer: the swapped tokens are sent to the remote chain, or the sender is refunded if the swap failed
+pub fn handle_swap_transfer_reply(
+    deps: DepsMut,
+    env: Env,
+    nonce: u64,
+    result: SubMsgResult,
+) -> Result<Response, ContractError> {
+    let swap_transfer = SWAP_TRANSFERS.load(deps.storage, nonce)?;
+    SWAP_TRANSFERS.remove(deps.storage, nonce);
+    match result {
+        SubMsgResult::Ok(response) => {
+            let return_amount = parse_swap_return_amount(&response.events).ok_or_else(|| {
+                StdError::generic_err("Cannot find the amount returned by the swap")
+            })?;
+            let res = execute_transfer_back_to_remote_chain(
+                deps,
+                env,
+                swap_transfer.msg,
+                Amount::from_parts(
+                    parse_asset_info_denom(swap_transfer.ask_asset_info),
+                    return_amount,
+                ),
+                swap_transfer.sender,
+            )?;
+            Ok(res.add_attribute("swap_return_amount", return_amount))
+        }
+        SubMsgResult::Err(err) => Ok(Response::new()
+            .add_message(
+                swap_transfer
+                    .offer
+                    .send_amount(swap_transfer.sender.to_string(), None),
+            )
+            .add_attribute("action", "swap_transfer_failure")
+            .add_attribute("error_swap_ops", err)
+            .add_attribute("refund_recipient", swap_transfer.sender)),
+    }
+}
 
-        let raw_channel = query(
-            deps.as_ref(),
-            mock_env(),
-            QueryMsg::Channel {
-                id: "channel-3".to_string(),
-            },
-        )
-        .unwrap();
-        let chan_res: ChannelResponse = from_binary(&raw_channel).unwrap();
-        assert_eq!(chan_res.info, mock_channel_info("channel-3"));
-        assert_eq!(0, chan_res.total_sent.len());
-        assert_eq!(0, chan_res.balances.len());
+pub fn execute_transfer_back_to_remote_chain(
+    deps: DepsMut,
+    env: Env,
+    msg: TransferBackMsg,
+    amount: Amount,
+    sender: Addr,
+) -> Result<Response, ContractError> {
+    if amount.is_empty() {
+        return Err(ContractError::NoFunds {});
+    }
+    let config = CONFIG.load(deps.storage)?;
 
-        let err = query(
+    // the sent tokens are swapped first. The transfer continues in the reply with the swapped tokens
+    if let Some((offer_asset_info, ask_asset_info, operations)) =
+        load_transfer_swap(deps.as_ref(), &config, &msg, &amount)?
+    {
+        let transfer = TransferBackMsg {
+            swap_to: None,
+            min_out: None,
+            ..msg.clone()
+        };
+        // fail before the swap when the swapped tokens cannot be sent to the remote denom
+        find_transfer_back_mapping(
             deps.as_ref(),
-            mock_env(),
-            QueryMsg::Channel {
-                id: "channel-10".to_string(),
+            &env,
+            &transfer,
+            &Amount::from_parts(
+                parse_asset_info_denom(ask_asset_info.clone()),
+                amount.amount(),
+            ),
+        )?;
+        let nonce = next_reply_nonce(deps.storage)?;
+        SWAP_TRANSFERS.save(
+            deps.storage,
+            nonce,
+            &SwapTransfer {
+                sender: sender.clone(),
+                offer: amount.clone(),
+                ask_asset_info: ask_asset_info.clone(),
+                msg: transfer,
             },
-        )
-        .unwrap_err();
-        assert_eq!(err, StdError::not_found("cw_ics20::state::ChannelInfo"));
+        )?;
+        let swap_msg = config.swap_router_contract.execute_operations(
+            offer_asset_info,
+            amount.amount(),
+            operations,
+            msg.min_out,
+            None,
+        )?;
+        return Ok(Response::new()
+            .add_submessage(SubMsg::reply_always(
+                swap_msg,
+                encode_reply_id(SWAP_TRANSFER_ID, nonce),
+            ))
+            .add_attributes(vec![
+                ("action", "swap_then_transfer"),
+                ("sender", sender.as_str()),
+                ("offer_denom", &amount.denom()),
+                ("offer_amount", &amount.amount().to_string()),
+                ("ask_asset_info", &ask_asset_info.to_string()),
+            ]));
     }
 
-    #[test]
-    fn test_query_pair_mapping_by_asset_info() {
-        let mut deps = setup(&["channel-3", "channel-7"], &[]);
-        let asset_info = AssetInfo::Token {
-            contract_addr: Addr::unchecked("cw20:foobar".to_string()),
-        };
-        let mut update = UpdatePairMsg {
-            local_channel_id: "mars-channel".to_string(),
-            denom: "earth".to_string(),
-            local_asset_info: asset_info.clone(),
-            remote_decimals: 18,
-            local_asset_info_decimals: 18,
-        };
+    let mapping = find_transfer_back_mapping(deps.as_ref(), &env, &msg, &amount)?;
 
-        // works with proper funds
-        let mut msg = ExecuteMsg::UpdateMappingPair(update.clone());
+    // ensure the requested channel is registered and still open
+    let channel_info = CHANNEL_INFO
+        .may_load(deps.storage, &msg.local_channel_id)?
+        .ok_or_else(|| ContractError::NoSuchChannel {
+            id: msg.local_channel_id.clone(),
+        })?;
+    if channel_info.closed {
+        return Err(ContractError::ChannelClosed {
+            id: msg.local_channel_id,
+        });
+    }
 
-        let info = mock_info("gov", &coins(1234567, "ucosm"));
-        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+    // if found mapping, then deduct fee based on mapping
+    let ask_asset_info = denom_to_asset_info(&deps.querier, deps.api, &amount.raw_denom())?;
+    let fee_data = process_deduct_fee(
+        deps.storage,
+        &deps.querier,
+        deps.api,
+        sender.as_str(),
+        &msg.remote_address,
+        &msg.remote_denom,
+        amount,
+        &config,
+    )?;
+    // same as the receive, the last known price is only recorded once the fees are resolved
+    record_fee_denom_price(deps.storage, &deps.querier, &config, &ask_asset_info)?;
 
-        // add another pair with the same asset info to filter
-        update.denom = "jupiter".to_string();
-        msg = ExecuteMsg::UpdateMappingPair(update.clone());
-        let info = mock_info("gov", &coins(1234567, "ucosm"));
-        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+    // send response
+    let token_fee_str = fee_data.token_fee.amount().to_string();
+    let relayer_fee_str = fee_data.relayer_fee.amount().to_string();
+    let attributes = vec![
+        ("action", "transfer_back_to_remote_chain"),
+        ("sender", sender.as_str()),
+        ("receiver", &msg.remote_address),
+        ("token_fee", &token_fee_str),
+        ("relayer_fee", &relayer_fee_str),
+    ];
 
-        // add another pair with a different asset info
-        update.denom = "moon".to_string();
-        update.local_asset_info = AssetInfo::NativeToken {
-            denom: "orai".to_string(),
-        };
-        msg = ExecuteMsg::UpdateMappingPair(update.clone());
-        let info = mock_info("gov", &coins(1234567, "ucosm"));
-        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+    // if our fees have drained the initial amount entirely, then we just get all the fees and that's it
+    if fee_data.deducted_amount.is_zero() {
+        accumulate_fee(deps.storage, TOKEN_FEE_ACCUMULATOR, &fee_data.token_fee)?;
+        accumulate_fee(deps.storage, RELAYER_FEE_ACCUMULATOR, &fee_data.relayer_fee)?;
+        return Ok(Response::new().add_attributes(attributes));
+    }
 
-        // query based on asset info
+    let ibc_denom = mapping.key;
 
-        let mappings = query(
-            deps.as_ref(),
-            mock_env(),
-            QueryMsg::PairMappingsFromAssetInfo {
-                asset_info: asset_info,
-            },
-        )
-        .unwrap();
-        let response: Vec<PairQuery> = from_binary(&mappings).unwrap();
-        assert_eq!(response.len(), 2);
+    let timeout = resolve_packet_timeout(
+        deps.storage,
+        &env,
+        &config,
+        &msg.local_channel_id,
+        msg.timeout,
+        msg.packet_timeout.clone(),
+    )?;
+    // need to convert decimal of cw20 to remote decimal before transferring
+    let amount_remote = convert_local_to_remote(
+        fee_data.deducted_amount,
+        mapping.pair_mapping.remote_decimals,
+        mapping.pair_mapping.asset_info_decimals,
+    )?;
 
-        // query native token asset info, should receive moon denom in key
-        let mappings = query(
-            deps.as_ref(),
-            mock_env(),
-            QueryMsg::PairMappingsFromAssetInfo {
-                asset_info: AssetInfo::NativeToken {
-                    denom: "orai".to_string(),
-                },
-            },
-        )
-        .unwrap();
-        let response: Vec<PairQuery> = from_binary(&mappings).unwrap();
-        assert_eq!(response.len(), 1);
-        assert_eq!(response.first().unwrap().key.contains("moon"), true);
+    assert_not_paused(
+        deps.storage,
+        &msg.local_channel_id,
+        &ibc_denom,
+        FlowDirection::Outflow,
+    )?;
+    record_rate_limited_flow(
+        deps.storage,
+        env.block.time,
+        &msg.local_channel_id,
+        &ibc_denom,
+        amount_remote,
+        FlowDirection::Outflow,
+    )?;
 
-        // query asset info that is not in the mapping, should return empty
-        // query native token asset info, should receive moon denom
-        let mappings = query(
-            deps.as_ref(),
-            mock_env(),
-            QueryMsg::PairMappingsFromAssetInfo {
-                asset_info: AssetInfo::NativeToken {
-                    denom: "foobar".to_string(),
-                },
-            },
-        )
-        .unwrap();
-        let response: Vec<PairQuery> = from_binary(&mappings).unwrap();
-        assert_eq!(response.len(), 0);
-    }
+    // now this is processed in ack
+    // // because we are transferring back, we reduce the channel's balance
+    reduce_channel_balance(
+        deps.storage,
+        &msg.local_channel_id,
+        &ibc_denom,
+        amount_remote,
+    )?;
 
-    #[test]
-    fn test_update_cw20_mapping() {
-        let mut deps = setup(&["channel-3", "channel-7"], &[]);
-        let asset_info = AssetInfo::Token {
-            contract_addr: Addr::unchecked("cw20:foobar".to_string()),
-        };
-        let asset_info_second = AssetInfo::Token {
-            contract_addr: Addr::unchecked("cw20:foobar-second".to_string()),
-        };
+    // token fees stay on the contract until they are swept by CollectFees.
+    // The relayer fee is held with the pending transfer for the relayer of the ack
+    accumulate_fee(deps.storage, TOKEN_FEE_ACCUMULATOR, &fee_data.token_fee)?;
 
-        let mut update = UpdatePairMsg {
-            local_channel_id: "mars-channel".to_string(),
-            denom: "earth".to_string(),
-            local_asset_info: asset_info.clone(),
-            remote_decimals: 18,
-            local_asset_info_decimals: 18,
-        };
+    // prepare ibc message
+    let ibc_msg = build_ibc_send_packet(
+        amount_remote,
+        &ibc_denom, // we use ibc denom in form <transfer>/<channel>/<denom> so that when it is sent back to remote chain, it gets parsed correctly and burned
+        sender.as_str(),
+        &msg.remote_address,
+        msg.memo,
+        &msg.local_channel_id,
+        timeout,
+    )?;
 
-        // works with proper funds
-        let mut msg = ExecuteMsg::UpdateMappingPair(update.clone());
+    // snapshot the transfer so that a failed ack or a timeout refunds exactly what was sent
+    let nonce = next_reply_nonce(deps.storage)?;
+    UNSEQUENCED_TRANSFERS.save(
+        deps.storage,
+        nonce,
+        &PendingTransfer {
+            src_channel_id: msg.local_channel_id,
+            local_sender: sender.to_string(),
+            local_amount: Amount::from_parts(fee_data.token_fee.denom(), fee_data.deducted_amount),
+            token_fee: fee_data.token_fee.amount(),
+            relayer_fee: fee_data.relayer_fee.amount(),
+            relayer_fee_on_ack: true,
+            ibc_denom: ibc_denom.clone(),
+            remote_amount: amount_remote,
+            mapping: Some(mapping.pair_mapping),
+            sent_at: Some(env.block.time),
+        },
+    )?;
+    Ok(Response::new()
+        .add_submessage(SubMsg::reply_on_success(
+            ibc_msg,
+            encode_reply_id(SEND_PACKET_ID, nonce),
+        ))
+        .add_attributes(attributes)
+        .add_attributes(vec![
+            ("denom", &ibc_denom),
+            ("amount", &amount_remote.to_string()),
+        ]))
+}
 
-        // unauthorized case
-        let info = mock_info("foobar", &coins(1234567, "ucosm"));
-        let res_err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
-        assert_eq!(res_err, ContractError::Admin(AdminError::NotAdmin {}));
+/// The gov contract can allow new contracts, or increase the gas limit on existing contracts.
+/// It cannot block or reduce the limit to avoid forcible sticking tokens in the channel.
+pub fn execute_allow(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    allow: AllowMsg,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
 
-        let info = mock_info("gov", &coins(1234567, "ucosm"));
-        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+    let contract = deps.api.addr_validate(&allow.contract)?;
+    let set = AllowInfo {
+        gas_limit: allow.gas_limit,
+    };
+    ALLOW_LIST.update(deps.storage, &contract, |old| {
+        if let Some(old) = old {
+            // we must ensure it increases the limit
+            match (old.gas_limit, set.gas_limit) {
+                (None, Some(_)) => return Err(ContractError::CannotLowerGas),
+                (Some(old), Some(new)) if new < old => return Err(ContractError::CannotLowerGas),
+                _ => {}
+            };
+        }
+        Ok(AllowInfo {
+            gas_limit: allow.gas_limit,
+        })
+    })?;
 
-        // query to verify if the mapping has been updated
-        let mappings = query(
-            deps.as_ref(),
-            mock_env(),
-            QueryMsg::PairMappings {
-                start_after: None,
-                limit: None,
-                order: None,
-            },
-        )
-        .unwrap();
-        let response: ListMappingResponse = from_binary(&mappings).unwrap();
-        println!("response: {:?}", response);
-        assert_eq!(
-            response.pairs.first().unwrap().key,
-            format!("{}/mars-channel/earth", CONTRACT_PORT)
-        );
+    let gas = if let Some(gas) = allow.gas_limit {
+        gas.to_string()
+    } else {
+        "None".to_string()
+    };
 
-        // not found case
-        let mappings = query(
-            deps.as_ref(),
-            mock_env(),
-            QueryMsg::PairMappings {
-                start_after: None,
-                limit: None,
-                order: None,
-            },
-        )
-        .unwrap();
-        let response: ListMappingResponse = from_binary(&mappings).unwrap();
-        assert_ne!(response.pairs.first().unwrap().key, "foobar".to_string());
+    let res = Response::new()
+        .add_attribute("action", "allow")
+        .add_attribute("contract", allow.contract)
+        .add_attribute("gas_limit", gas);
+    Ok(res)
+}
 
-        // update existing key case must pass
-        update.local_asset_info = asset_info_second.clone();
-        msg = ExecuteMsg::UpdateMappingPair(update.clone());
+/// The gov contract can allow new contracts, or increase the gas limit on existing contracts.
+/// It cannot block or reduce the limit to avoid forcible sticking tokens in the channel.
+pub fn execute_update_mapping_pair(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    mapping_pair_msg: UpdatePairMsg,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
 
-        let info = mock_info("gov", &coins(1234567, "ucosm"));
-        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    let ibc_denom = get_key_ics20_ibc_denom(
+        &parse_ibc_wasm_port_id(env.contract.address.into_string()),
+        &mapping_pair_msg.local_channel_id,
+        &mapping_pair_msg.denom,
+    );
 
-        // after update, cw20 denom now needs to be updated
-        let mappings = query(
-            deps.as_ref(),
-            mock_env(),
-            QueryMsg::PairMappings {
-                start_after: None,
-                limit: None,
-                order: None,
-            },
-        )
-        .unwrap();
-        let response: ListMappingResponse = from_binary(&mappings).unwrap();
-        println!("response: {:?}", response);
-        assert_eq!(
-            response.pairs.first().unwrap().key,
-            format!("{}/mars-channel/earth", CONTRACT_PORT)
-        );
-        assert_eq!(
-            response.pairs.first().unwrap().pair_mapping.asset_info,
-            asset_info_second
-        )
+    // if pair already exists in list, remove it and create a new one
+    if ics20_denoms().load(deps.storage, &ibc_denom).is_ok() {
+        ics20_denoms().remove(deps.storage, &ibc_denom)?;
     }
 
-    #[test]
-    fn test_delete_cw20_mapping() {
-        let mut deps = setup(&["channel-3", "channel-7"], &[]);
-        let cw20_denom = AssetInfo::Token {
-            contract_addr: Addr::unchecked("cw20:foobar".to_string()),
-        };
-
-        let update = UpdatePairMsg {
-            local_channel_id: "mars-channel".to_string(),
-            denom: "earth".to_string(),
-            local_asset_info: cw20_denom.clone(),
-            remote_decimals: 18,
-            local_asset_info_decimals: 18,
-        };
-
-        // works with proper funds
-        let msg = ExecuteMsg::UpdateMappingPair(update.clone());
-
-        let info = mock_info("gov", &coins(1234567, "ucosm"));
-        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+    ics20_denoms().save(
+        deps.storage,
+        &ibc_denom,
+        &MappingMetadata {
+            asset_info: mapping_pair_msg.local_asset_info.clone(),
+            remote_decimals: mapping_pair_msg.remote_decimals,
+            asset_info_decimals: mapping_pair_msg.local_asset_info_decimals,
+        },
+    )?;
 
-        // query to verify if the mapping has been updated
-        let mappings = query(
-            deps.as_ref(),
-            mock_env(),
-            QueryMsg::PairMappings {
-                start_after: None,
-                limit: None,
-                order: None,
-            },
-        )
-        .unwrap();
-        let response: ListMappingResponse = from_binary(&mappings).unwrap();
-        println!("response: {:?}", response);
-        assert_eq!(
-            response.pairs.first().unwrap().key,
-            format!("{}/mars-channel/earth", CONTRACT_PORT)
+    let res = Response::new()
+        .add_attribute("action", "execute_update_mapping_pair")
+        .add_attribute("denom", mapping_pair_msg.denom)
+        .add_attribute(
+            "new_asset_info",
+            mapping_pair_msg.local_asset_info.to_string(),
         );
+    Ok(res)
+}
 
-        // now try deleting
-        let delete = DeletePairMsg {
-            local_channel_id: "mars-channel".to_string(),
-            denom: "earth".to_string(),
-        };
-
-        let mut msg = ExecuteMsg::DeleteMappingPair(delete.clone());
+pub fn execute_delete_mapping_pair(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    mapping_pair_msg: DeletePairMsg,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
 
-        // unauthorized delete case
-        let info = mock_info("foobar", &coins(1234567, "ucosm"));
-        let delete_err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
-        assert_eq!(delete_err, ContractError::Admin(AdminError::NotAdmin {}));
+    let ibc_denom = get_key_ics20_ibc_denom(
+        &parse_ibc_wasm_port_id(env.contract.address.into_string()),
+        &mapping_pair_msg.local_channel_id,
+        &mapping_pair_msg.denom,
+    );
 
-        let info = mock_info("gov", &coins(1234567, "ucosm"));
+    ics20_denoms().remove(deps.storage, &ibc_denom)?;
 
-        // happy case
-        msg = ExecuteMsg::DeleteMappingPair(delete.clone());
-        execute(deps.as_mut(), mock_env(), info.clone(), msg.clone()).unwrap();
+    let res = Response::new()
+        .add_attribute("action", "execute_delete_mapping_pair")
+        .add_attribute("local_channel_id", mapping_pair_msg.local_channel_id)
+        .add_attribute("original_denom", mapping_pair_msg.denom);
+    Ok(res)
+}
 
-        // after update, the list cw20 mapping should be empty
-        let mappings = query(
-            deps.as_ref(),
-            mock_env(),
-            QueryMsg::PairMappings {
-                start_after: None,
-                limit: None,
-                order: None,
+#[entry_point]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    // we don't need to save anything if migrating from the same version
+    // CONFIG.save(
+    //     deps.storage,
+    //     &Config {
+    //         default_timeout: msg.default_timeout,
+    //         default_gas_limit: msg.default_gas_limit,
+    //         fee_denom: msg.fee_denom,
+    //         swap_router_contract: RouterController(msg.swap_router_contract),
+    //         token_fee_receiver: deps.api.addr_validate(&msg.token_fee_receiver)?,
+    //         relayer_fee_receiver: deps.api.addr_validate(&msg.relayer_fee_receiver)?,
+    //     },
+    // )?;
+    // token fees used to be a plain ratio
+    let legacy_token_fee: Map<&str, Ratio> = Map::new("token_fee");
+    let legacy_fees = legacy_token_fee
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (token_denom, ratio) in legacy_fees {
+        TOKEN_FEE.save(
+            deps.storage,
+            &token_denom,
+            &TokenFeeSchedule {
+                ratio,
+                ..Default::default()
             },
-        )
-        .unwrap();
-        let response: ListMappingResponse = from_binary(&mappings).unwrap();
-        println!("response: {:?}", response);
-        assert_eq!(response.pairs.len(), 0)
+        )?;
+        legacy_token_fee.remove(deps.storage, &token_denom);
     }
+    // relayer fees used to be set per network only, in the fee denom
+    let legacy_relayer_fee: Map<&str, Uint128> = Map::new("relayer_fee");
+    let legacy_fees = legacy_relayer_fee
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (prefix, fee) in legacy_fees {
+        RELAYER_FEE.save(
+            deps.storage,
+            (&prefix, ""),
+            &RelayerFeeSchedule {
+                fee,
+                fee_asset: None,
+            },
+        )?;
+        legacy_relayer_fee.remove(deps.storage, &prefix);
+    }
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    Ok(Response::new())
+}
 
-    // #[test]
-    // fn proper_checks_on_execute_native() {
-    //     let send_channel = "channel-5";
-    //     let mut deps = setup(&[send_channel, "channel-10"], &[]);
-
-    //     let mut transfer = TransferMsg {
-    //         channel: send_channel.to_string(),
-    //         remote_address: "foreign-address".to_string(),
-    //         timeout: None,
-    //         memo: Some("memo".to_string()),
-    //     };
+#[entry_point]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Port {} => to_binary(&query_port(deps)?),
+        QueryMsg::ListChannels {} => to_binary(&query_list(deps)?),
+        QueryMsg::Channel { id } => to_binary(&query_channel(deps, id)?),
+        QueryMsg::ChannelWithKey { channel_id, denom } => {
+            to_binary(&query_channel_with_key(deps, channel_id, denom)?)
+        }
+        QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::Allowed { contract } => to_binary(&query_allowed(deps, contract)?),
+        QueryMsg::ListAllowed {
+            start_after,
+            limit,
+            order,
+        } => to_binary(&list_allowed(deps, start_after, limit, order)?),
+        QueryMsg::PairMappings {
+            start_after,
+            limit,
+            order,
+        } => to_binary(&list_cw20_mapping(deps, start_after, limit, order)?),
+        QueryMsg::PairMapping { key } => to_binary(&get_mapping_from_key(deps, key)?),
+        QueryMsg::PairMappingsFromAssetInfo { asset_info } => {
+            to_binary(&get_mappings_from_asset_info(deps.storage, asset_info)?)
+        }
+        QueryMsg::Admin {} => to_binary(&ADMIN.query_admin(deps)?),
+        QueryMsg::GetTransferTokenFee { remote_token_denom } => {
+            to_binary(&TOKEN_FEE.load(deps.storage, &remote_token_denom)?)
+        }
+        QueryMsg::RecoverableFunds { channel_id } => {
+            to_binary(&query_recoverable_funds(deps, channel_id)?)
+        }
+        QueryMsg::PendingTransfers {
+            channel_id,
+            start_after,
+            limit,
+            order,
+        } => to_binary(&list_pending_transfers(
+            deps,
+            channel_id,
+            start_after,
+            limit,
+            order,
+        )?),
+        QueryMsg::StuckFunds {
+            start_after,
+            limit,
+            order,
+        } => to_binary(&list_stuck_funds(deps, start_after, limit, order)?),
+        QueryMsg::FeeExemptions {
+            start_after,
+            limit,
+            order,
+        } => to_binary(&list_fee_exemptions(deps, start_after, limit, order)?),
+        QueryMsg::FeeHistory {
+            fee,
+            start_after,
+            limit,
+            order,
+        } => to_binary(&list_fee_history(deps, fee, start_after, limit, order)?),
+        QueryMsg::SimulateReceive {
+            dest_channel,
+            packet,
+        } => to_binary(&simulate_receive(deps, env, dest_channel, packet)?),
+        QueryMsg::SimulateTransfer {
+            sender,
+            msg,
+            amount,
+        } => to_binary(&simulate_transfer(deps, env, sender, msg, amount)?),
+        QueryMsg::Price { asset_info } => to_binary(&query_price(deps, asset_info)?),
+        QueryMsg::SwapRoute {
+            offer_asset_info,
+            ask_asset_info,
+        } => to_binary(&query_swap_route(deps, offer_asset_info, ask_asset_info)?),
+        QueryMsg::AccumulatedFees {} => to_binary(&query_accumulated_fees(deps)?),
+        QueryMsg::Relayers {
+            start_after,
+            limit,
+            order,
+        } => to_binary(&list_relayers(deps, start_after, limit, order)?),
+        QueryMsg::RelayerBalances { relayer } => to_binary(&query_relayer_balances(deps, relayer)?),
+        QueryMsg::QueuedChanges {
+            start_after,
+            limit,
+            order,
+        } => to_binary(&list_queued_changes(deps, start_after, limit, order)?),
+        QueryMsg::RateLimit {
+            channel_id,
+            ibc_denom,
+        } => to_binary(&query_rate_limit(deps, env, channel_id, ibc_denom)?),
+        QueryMsg::RateLimits {
+            channel_id,
+            start_after,
+            limit,
+            order,
+        } => to_binary(&list_rate_limits(
+            deps,
+            env,
+            channel_id,
+            start_after,
+            limit,
+            order,
+        )?),
+    }
+}
 
-    //     // works with proper funds
-    //     let msg = ExecuteMsg::Transfer(transfer.clone());
-    //     let info = mock_info("foobar", &coins(1234567, "ucosm"));
-    //     let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-    //     assert_eq!(res.messages[0].gas_limit, None);
-    //     assert_eq!(1, res.messages.len());
-    //     if let CosmosMsg::Ibc(IbcMsg::SendPacket {
-    //         channel_id,
-    //         data,
-    //         timeout,
-    //     }) = &res.messages[0].msg
-    //     {
-    //         let expected_timeout = mock_env().block.time.plus_seconds(DEFAULT_TIMEOUT);
-    //         assert_eq!(timeout, &expected_timeout.into());
-    //         assert_eq!(channel_id.as_str(), send_channel);
-    //         let msg: Ics20Packet = from_binary(data).unwrap();
-    //         assert_eq!(msg.amount, Uint128::new(1234567));
-    //         assert_eq!(msg.denom.as_str(), "ucosm");
-    //         assert_eq!(msg.sender.as_str(), "foobar");
-    //         assert_eq!(msg.receiver.as_str(), "foreign-address");
-    //     } else {
-    //         panic!("Unexpected return message: {:?}", res.messages[0]);
-    //     }
+fn query_port(deps: Deps) -> StdResult<PortResponse> {
+    let query = IbcQuery::PortId {}.into();
+    let PortIdResponse { port_id } = deps.querier.query(&query)?;
+    Ok(PortResponse { port_id })
+}
 
-    //     // reject with no funds
-    //     let msg = ExecuteMsg::Transfer(transfer.clone());
-    //     let info = mock_info("foobar", &[]);
-    //     let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
-    //     assert_eq!(err, ContractError::Payment(PaymentError::NoFunds {}));
+fn query_list(deps: Deps) -> StdResult<ListChannelsResponse> {
+    let channels = CHANNEL_INFO
+        .range_raw(deps.storage, None, None, Order::Ascending)
+        .map(|r| r.map(|(_, v)| v))
+        .collect::<StdResult<_>>()?;
+    Ok(ListChannelsResponse { channels })
+}
 
-    //     // reject with multiple tokens funds
-    //     let msg = ExecuteMsg::Transfer(transfer.clone());
-    //     let info = mock_info("foobar", &[coin(1234567, "ucosm"), coin(54321, "uatom")]);
-    //     let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
-    //     assert_eq!(err, ContractError::Payment(PaymentError::MultipleDenoms {}));
+// make public for ibc tests
+pub fn query_channel(deps: Deps, id: String) -> StdResult<ChannelResponse> {
+    let info = CHANNEL_INFO.load(deps.storage, &id)?;
+    // this returns Vec<(outstanding, total)>
+    let channel_state = CHANNEL_REVERSE_STATE;
+    let state = channel_state
+        .prefix(&id)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|r| {
+            // this denom is
+            r.map(|(denom, v)| {
+                let outstanding = Amount::from_parts(denom.clone(), v.outstanding);
+                let total = Amount::from_parts(denom, v.total_sent);
+                (outstanding, total)
+            })
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+    // we want (Vec<outstanding>, Vec<total>)
+    let (balances, total_sent): (Vec<Amount>, Vec<Amount>) = state.into_iter().unzip();
+    // tokens that originated on this chain and are escrowed for this channel
+    let forward_balances = CHANNEL_FORWARD_STATE
+        .prefix(&id)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|r| r.map(|(denom, v)| Amount::from_parts(denom, v.outstanding)))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(ChannelResponse {
+        info,
+        balances,
+        total_sent,
+        forward_balances,
+        default_timeout: CHANNEL_TIMEOUTS.may_load(deps.storage, &id)?,
+    })
+}
+
+pub fn query_channel_with_key(
+    deps: Deps,
+    channel_id: String,
+    denom: String,
+) -> StdResult<ChannelWithKeyResponse> {
+    let info = CHANNEL_INFO.load(deps.storage, &channel_id)?;
+    // this returns Vec<(outstanding, total)>
+    let (balance, total_sent) = CHANNEL_REVERSE_STATE
+        .load(deps.storage, (&channel_id, &denom))
+        .map(|channel_state| {
+            let outstanding = Amount::from_parts(denom.clone(), channel_state.outstanding);
+            let total = Amount::from_parts(denom, channel_state.total_sent);
+            (outstanding, total)
+        })?;
+
+    Ok(ChannelWithKeyResponse {
+        info,
+        balance,
+        total_sent,
+    })
+}
+
+fn query_recoverable_funds(
+    deps: Deps,
+    channel_id: String,
+) -> StdResult<ListRecoverableFundsResponse> {
+    let funds = CHANNEL_RECOVERABLE_FUNDS
+        .prefix(&channel_id)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(ibc_denom, fund)| RecoverableFundResponse { ibc_denom, fund }))
+        .collect::<StdResult<_>>()?;
+    Ok(ListRecoverableFundsResponse { funds })
+}
+
+fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
+    let cfg = CONFIG.load(deps.storage)?;
+    let admin = ADMIN.get(deps)?.unwrap_or_else(|| Addr::unchecked(""));
+    let res = ConfigResponse {
+        default_timeout: cfg.default_timeout,
+        default_gas_limit: cfg.default_gas_limit,
+        fee_denom: cfg.fee_denom,
+        swap_router_contract: cfg.swap_router_contract.addr(),
+        gov_contract: admin.into(),
+        relayer_fee_receiver: cfg.relayer_fee_receiver,
+        token_fee_receiver: cfg.token_fee_receiver,
+        price_source: cfg.price_source,
+        price_fallback: cfg.price_fallback,
+        unregistered_relayer_policy: cfg.unregistered_relayer_policy,
+        default_slippage_bps: cfg.default_slippage_bps,
+        max_slippage_bps: cfg.max_slippage_bps,
+        min_timeout: cfg.min_timeout,
+        max_timeout: cfg.max_timeout,
+        token_fees: TOKEN_FEE
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|data_result| {
+                data_result.map(|(token_denom, schedule)| TokenFee {
+                    token_denom,
+                    ratio: schedule.ratio,
+                    flat: schedule.flat,
+                    min: schedule.min,
+                    max: schedule.max,
+                })
+            })
+            .collect::<StdResult<Vec<TokenFee>>>()?,
+        relayer_fees: RELAYER_FEE
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|data_result| {
+                data_result.map(|((prefix, remote_denom), schedule)| RelayerFeeResponse {
+                    prefix,
+                    amount: schedule.fee,
+                    remote_denom: Some(remote_denom).filter(|denom| !denom.is_empty()),
+                    fee_asset: schedule.fee_asset,
+                })
+            })
+            .collect::<StdResult<Vec<RelayerFeeResponse>>>()?,
+        guardian: GUARDIAN.may_load(deps.storage)?,
+        pending_admin: PENDING_ADMIN.may_load(deps.storage)?,
+        timelock_delay: TIMELOCK_DELAY.may_load(deps.storage)?.unwrap_or_default(),
+        paused: PAUSED_SCOPES.may_load(deps.storage)?.unwrap_or_default(),
+    };
+    Ok(res)
+}
+
+fn query_allowed(deps: Deps, contract: String) -> StdResult<AllowedResponse> {
+    let addr = deps.api.addr_validate(&contract)?;
+    let info = ALLOW_LIST.may_load(deps.storage, &addr)?;
+    let res = match info {
+        None => AllowedResponse {
+            is_allowed: false,
+            gas_limit: None,
+        },
+        Some(a) => AllowedResponse {
+            is_allowed: true,
+            gas_limit: a.gas_limit,
+        },
+    };
+    Ok(res)
+}
+
+// settings for pagination
+const MAX_LIMIT: u32 = 30;
+const DEFAULT_LIMIT: u32 = 10;
+
+fn list_allowed(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+    order: Option<u8>,
+) -> StdResult<ListAllowedResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let addr = maybe_addr(deps.api, start_after)?;
+    let start = addr.as_ref().map(Bound::exclusive);
+
+    let allow = ALLOW_LIST
+        .range(deps.storage, start, None, map_order(order))
+        .take(limit)
+        .map(|item| {
+            item.map(|(addr, allow)| AllowedInfo {
+                contract: addr.into(),
+                gas_limit: allow.gas_limit,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(ListAllowedResponse { allow })
+}
+
+fn list_cw20_mapping(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+    order: Option<u8>,
+) -> StdResult<ListMappingResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let mut allow_range = ics20_denoms().range(deps.storage, None, None, map_order(order));
+    if let Some(start_after) = start_after {
+        let start = Some(Bound::exclusive::<&str>(&start_after));
+        allow_range = ics20_denoms().range(deps.storage, start, None, map_order(order));
+    }
+    let pairs = allow_range
+        .take(limit)
+        .map(|item| {
+            item.map(|(key, mapping)| PairQuery {
+                key,
+                pair_mapping: mapping,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(ListMappingResponse { pairs })
+}
+
+fn list_pending_transfers(
+    deps: Deps,
+    channel_id: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+    order: Option<u8>,
+) -> StdResult<ListPendingTransfersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let (min, max) = match map_order(order) {
+        Order::Ascending => (start, None),
+        Order::Descending => (None, start),
+    };
+    let transfers = PENDING_TRANSFERS
+        .prefix(&channel_id)
+        .range(deps.storage, min, max, map_order(order))
+        .take(limit)
+        .map(|item| item.map(|(sequence, transfer)| PendingTransferResponse { sequence, transfer }))
+        .collect::<StdResult<_>>()?;
+    Ok(ListPendingTransfersResponse { transfers })
+}
+
+fn list_queued_changes(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+    order: Option<u8>,
+) -> StdResult<ListQueuedChangesResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let (min, max) = match map_order(order) {
+        Order::Ascending => (start, None),
+        Order::Descending => (None, start),
+    };
+    let changes = QUEUED_CHANGES
+        .range(deps.storage, min, max, map_order(order))
+        .take(limit)
+        .map(|item| item.map(|(id, change)| QueuedChangeResponse { id, change }))
+        .collect::<StdResult<_>>()?;
+    Ok(ListQueuedChangesResponse { changes })
+}
+
+fn query_rate_limit(
+    deps: Deps,
+    env: Env,
+    channel_id: String,
+    ibc_denom: String,
+) -> StdResult<RateLimitResponse> {
+    let limit = RATE_LIMITS.load(deps.storage, (&channel_id, &ibc_denom))?;
+    let usage = RATE_LIMIT_USAGE
+        .may_load(deps.storage, (&channel_id, &ibc_denom))?
+        .unwrap_or_default()
+        .at(&limit, env.block.time);
+    Ok(RateLimitResponse {
+        ibc_denom,
+        limit,
+        inflow: usage.inflow(),
+        outflow: usage.outflow(),
+    })
+}
+
+fn list_rate_limits(
+    deps: Deps,
+    env: Env,
+    channel_id: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+    order: Option<u8>,
+) -> StdResult<ListRateLimitsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.as_deref().map(Bound::exclusive);
+    let (min, max) = match map_order(order) {
+        Order::Ascending => (start, None),
+        Order::Descending => (None, start),
+    };
+    let rate_limits = RATE_LIMITS
+        .prefix(&channel_id)
+        .range(deps.storage, min, max, map_order(order))
+        .take(limit)
+        .map(|item| {
+            let (ibc_denom, limit) = item?;
+            let usage = RATE_LIMIT_USAGE
+                .may_load(deps.storage, (&channel_id, &ibc_denom))?
+                .unwrap_or_default()
+                .at(&limit, env.block.time);
+            Ok(RateLimitResponse {
+                ibc_denom,
+                limit,
+                inflow: usage.inflow(),
+                outflow: usage.outflow(),
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(ListRateLimitsResponse { rate_limits })
+}
+
+fn list_stuck_funds(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+    order: Option<u8>,
+) -> StdResult<ListStuckFundsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let (min, max) = match map_order(order) {
+        Order::Ascending => (start, None),
+        Order::Descending => (None, start),
+    };
+    let funds = STUCK_FUNDS
+        .range(deps.storage, min, max, map_order(order))
+        .take(limit)
+        .map(|item| item.map(|(id, fund)| StuckFundResponse { id, fund }))
+        .collect::<StdResult<_>>()?;
+    Ok(ListStuckFundsResponse { funds })
+}
+
+fn list_fee_exemptions(
+    deps: Deps,
+    start_after: Option<FeeExemptionSubject>,
+    limit: Option<u32>,
+    order: Option<u8>,
+) -> StdResult<ListFeeExemptionsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_after = start_after.map(|subject| subject.to_string());
+    let start = start_after.as_deref().map(Bound::exclusive);
+    let (min, max) = match map_order(order) {
+        Order::Ascending => (start, None),
+        Order::Descending => (None, start),
+    };
+    let exemptions = FEE_EXEMPTIONS
+        .range(deps.storage, min, max, map_order(order))
+        .take(limit)
+        .map(|item| item.map(|(_, exemption)| exemption))
+        .collect::<StdResult<_>>()?;
+    Ok(ListFeeExemptionsResponse { exemptions })
+}
+
+fn list_fee_history(
+    deps: Deps,
+    fee: FeeKey,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+    order: Option<u8>,
+) -> StdResult<ListFeeHistoryResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let (min, max) = match map_order(order) {
+        Order::Ascending => (start, None),
+        Order::Descending => (None, start),
+    };
+    let changes = FEE_HISTORY
+        .prefix(&fee.to_string())
+        .range(deps.storage, min, max, map_order(order))
+        .take(limit)
+        .map(|item| item.map(|(_, change)| change))
+        .collect::<StdResult<_>>()?;
+    Ok(ListFeeHistoryResponse { changes })
+}
+
+fn query_price(deps: Deps, asset_info: AssetInfo) -> StdResult<PriceResponse> {
+    let key = asset_info.to_string();
+    Ok(PriceResponse {
+        fixed: FIXED_PRICES.may_load(deps.storage, &key)?,
+        last_known: LAST_KNOWN_PRICES.may_load(deps.storage, &key)?,
+    })
+}
+
+fn query_swap_route(
+    deps: Deps,
+    offer_asset_info: AssetInfo,
+    ask_asset_info: AssetInfo,
+) -> StdResult<SwapRouteResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    Ok(SwapRouteResponse {
+        operations: load_swap_route(
+            deps.storage,
+            &offer_asset_info,
+            &ask_asset_info,
+            &config.fee_denom,
+        )?,
+        registered: SWAP_ROUTES.has(
+            deps.storage,
+            (&offer_asset_info.to_string(), &ask_asset_info.to_string()),
+        ),
+    })
+}
+
+fn simulate_receive(
+    deps: Deps,
+    env: Env,
+    dest_channel: String,
+    packet: Ics20Packet,
+) -> StdResult<SimulateReceiveResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let ibc_denom = get_key_ics20_ibc_denom(
+        &parse_ibc_wasm_port_id(env.contract.address.into_string()),
+        &dest_channel,
+        &packet.denom,
+    );
+    let pair_mapping = ics20_denoms()
+        .load(deps.storage, &ibc_denom)
+        .map_err(|_| StdError::generic_err(ContractError::NotOnMappingList.to_string()))?;
+    let to_send = Amount::from_parts(
+        parse_asset_info_denom(pair_mapping.asset_info.clone()),
+        convert_remote_to_local(
+            packet.amount,
+            pair_mapping.remote_decimals,
+            pair_mapping.asset_info_decimals,
+        )?,
+    );
+    let ReceiveFees {
+        fee_data,
+        destination,
+        destination_asset_info_on_orai,
+        destination_pair_mapping,
+        memo_error,
+    } = compute_receive_fees(
+        deps.storage,
+        deps.api,
+        &deps.querier,
+        &config,
+        &packet,
+        &to_send,
+        &pair_mapping.asset_info,
+    )?;
+
+    // same as the follow up of the receive: a failed swap simulation means the receiver gets the tokens as they are
+    let mut swap_operations = vec![];
+    let mut minimum_receive = fee_data.deducted_amount;
+    if !destination.destination_denom.is_empty() && !fee_data.deducted_amount.is_zero() {
+        let operations = load_swap_route(
+            deps.storage,
+            &pair_mapping.asset_info,
+            &destination_asset_info_on_orai,
+            &config.fee_denom,
+        )?;
+        if !operations.is_empty() {
+            if let Ok(response) = config.swap_router_contract.simulate_swap(
+                &deps.querier,
+                fee_data.deducted_amount,
+                operations.clone(),
+            ) {
+                swap_operations = operations;
+                minimum_receive = apply_slippage(response.amount, &destination, &config);
+            }
+        }
+    }
+    let remote_amount = match &destination_pair_mapping {
+        _ if destination.destination_channel.is_empty() => None,
+        Some((_, mapping)) => Some(convert_local_to_remote(
+            minimum_receive,
+            mapping.remote_decimals,
+            mapping.asset_info_decimals,
+        )?),
+        None => Some(minimum_receive),
+    };
+    Ok(SimulateReceiveResponse {
+        fee_data,
+        pair_mapping: PairQuery {
+            key: ibc_denom,
+            pair_mapping,
+        },
+        swap_operations,
+        minimum_receive,
+        destination_pair_mapping: destination_pair_mapping
+            .map(|(key, pair_mapping)| PairQuery { key, pair_mapping }),
+        remote_amount,
+        memo_error,
+    })
+}
+
+fn simulate_transfer(
+    deps: Deps,
+    env: Env,
+    sender: String,
+    msg: TransferBackMsg,
+    amount: Amount,
+) -> StdResult<SimulateTransferResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    // a swap before the transfer is simulated, the transfer then sends the simulated amount
+    let amount = match load_transfer_swap(deps, &config, &msg, &amount)? {
+        Some((_, ask_asset_info, operations)) => Amount::from_parts(
+            parse_asset_info_denom(ask_asset_info),
+            config
+                .swap_router_contract
+                .simulate_swap(&deps.querier, amount.amount(), operations)?
+                .amount,
+        ),
+        None => amount,
+    };
+    let mapping = find_transfer_back_mapping(deps, &env, &msg, &amount)
+        .map_err(|err| StdError::generic_err(err.to_string()))?;
+    let fee_data = process_deduct_fee(
+        deps.storage,
+        &deps.querier,
+        deps.api,
+        &sender,
+        &msg.remote_address,
+        &msg.remote_denom,
+        amount,
+        &config,
+    )?;
+    let remote_amount = convert_local_to_remote(
+        fee_data.deducted_amount,
+        mapping.pair_mapping.remote_decimals,
+        mapping.pair_mapping.asset_info_decimals,
+    )?;
+    Ok(SimulateTransferResponse {
+        fee_data,
+        pair_mapping: mapping,
+        remote_amount,
+    })
+}
+
+fn query_accumulated_fees(deps: Deps) -> StdResult<AccumulatedFeesResponse> {
+    let accumulated = |fee_accumulator: Map<&str, Uint128>| -> StdResult<Vec<Amount>> {
+        fee_accumulator
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(denom, amount)| Amount::from_parts(denom, amount)))
+            .collect()
+    };
+    Ok(AccumulatedFeesResponse {
+        token_fees: accumulated(TOKEN_FEE_ACCUMULATOR)?,
+        relayer_fees: accumulated(RELAYER_FEE_ACCUMULATOR)?,
+    })
+}
+
+fn list_relayers(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+    order: Option<u8>,
+) -> StdResult<ListRelayersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_after = start_after.map(Addr::unchecked);
+    let start = start_after.as_ref().map(Bound::exclusive);
+    let (min, max) = match map_order(order) {
+        Order::Ascending => (start, None),
+        Order::Descending => (None, start),
+    };
+    let relayers = RELAYERS
+        .range(deps.storage, min, max, map_order(order))
+        .take(limit)
+        .map(|item| {
+            item.map(|(relayer, info)| RelayerResponse {
+                relayer,
+                payout: info.payout,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(ListRelayersResponse { relayers })
+}
+
+fn query_relayer_balances(deps: Deps, relayer: String) -> StdResult<RelayerBalancesResponse> {
+    let balances = RELAYER_BALANCES
+        .prefix(&Addr::unchecked(relayer))
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| Amount::from_parts(denom, amount)))
+        .collect::<StdResult<_>>()?;
+    Ok(RelayerBalancesResponse { balances })
+}
+
+fn get_mapping_from_key(deps: Deps, ibc_denom: String) -> StdResult<PairQuery> {
+    let result = ics20_denoms().load(deps.storage, &ibc_denom)?;
+    Ok(PairQuery {
+        key: ibc_denom,
+        pair_mapping: result,
+    })
+}
+
+fn get_mappings_from_asset_info(
+    storage: &dyn Storage,
+    asset_info: AssetInfo,
+) -> StdResult<Vec<PairQuery>> {
+    let pair_mappings: Vec<(String, MappingMetadata)> = ics20_denoms()
+        .idx
+        .asset_info
+        .prefix(asset_info.to_string())
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+    let pair_queries: Vec<PairQuery> = pair_mappings
+        .into_iter()
+        .map(|pair| PairQuery {
+            key: pair.0,
+            pair_mapping: pair.1,
+        })
+        .collect();
+    Ok(pair_queries)
+}
+
+fn map_order(order: Option<u8>) -> Order {
+    if order.is_none() {
+        return Order::Ascending;
+    }
+    let order_unwrap = order.unwrap();
+    if order_unwrap == 1 {
+        return Order::Ascending;
+    }
+    Order::Descending
+}
+
+#[cfg(test)]
+#[allow(
+    noop_method_call,
+    clippy::bool_assert_comparison,
+    clippy::clone_on_copy,
+    clippy::redundant_field_names,
+    clippy::useless_conversion,
+    clippy::useless_vec
+)]
+mod test {
+    use std::ops::Sub;
+
+    use super::*;
+    use crate::ibc::{
+        build_payout_sub_msg, decode_reply_id, handle_packet_refund, ibc_packet_ack,
+        ibc_packet_receive, ibc_packet_timeout, reply, Ics20Ack, Ics20Packet,
+        FOLLOW_UP_IBC_SEND_FAILURE_ID, NATIVE_RECEIVE_ID, REFUND_FAILURE_ID,
+    };
+    use crate::msg::{FeeData, TransferLeg};
+    use crate::state::{increase_channel_balance, FeeChange, FeeValue, RecoverableFund};
+    use crate::test_helpers::*;
+
+    use cosmwasm_std::testing::{mock_env, mock_info};
+    use cosmwasm_std::{
+        coin, coins, BankMsg, CosmosMsg, Decimal, Event, IbcAcknowledgement, IbcEndpoint, IbcMsg,
+        IbcPacket, IbcPacketAckMsg, IbcPacketReceiveMsg, IbcPacketTimeoutMsg, Reply, StdError,
+        SubMsg, SubMsgResponse, SubMsgResult, Timestamp, Uint128, WasmMsg,
+    };
+    use cw20::Cw20ExecuteMsg;
+    use cw20_ics20_msg::receiver::MemoError;
+    use cw_controllers::AdminError;
+
+    use oraiswap::asset::AssetInfo;
+
+    #[test]
+    fn test_split_denom() {
+        let split_denom: Vec<&str> = "orai".splitn(3, '/').collect();
+        assert_eq!(split_denom.len(), 1);
+
+        let split_denom: Vec<&str> = "a/b/c".splitn(3, '/').collect();
+        assert_eq!(split_denom.len(), 3)
+    }
+
+    #[test]
+    fn setup_and_query() {
+        let deps = setup(&["channel-3", "channel-7"], &[]);
+
+        let raw_list = query(deps.as_ref(), mock_env(), QueryMsg::ListChannels {}).unwrap();
+        let list_res: ListChannelsResponse = from_binary(&raw_list).unwrap();
+        assert_eq!(2, list_res.channels.len());
+        assert_eq!(mock_channel_info("channel-3"), list_res.channels[0]);
+        assert_eq!(mock_channel_info("channel-7"), list_res.channels[1]);
+
+        let raw_channel = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::Channel {
+                id: "channel-3".to_string(),
+            },
+        )
+        .unwrap();
+        let chan_res: ChannelResponse = from_binary(&raw_channel).unwrap();
+        assert_eq!(chan_res.info, mock_channel_info("channel-3"));
+        assert_eq!(0, chan_res.total_sent.len());
+        assert_eq!(0, chan_res.balances.len());
+
+        let err = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::Channel {
+                id: "channel-10".to_string(),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err, StdError::not_found("cw_ics20::state::ChannelInfo"));
+    }
+
+    #[test]
+    fn test_query_pair_mapping_by_asset_info() {
+        let mut deps = setup(&["channel-3", "channel-7"], &[]);
+        let asset_info = AssetInfo::Token {
+            contract_addr: Addr::unchecked("cw20:foobar".to_string()),
+        };
+        let mut update = UpdatePairMsg {
+            local_channel_id: "mars-channel".to_string(),
+            denom: "earth".to_string(),
+            local_asset_info: asset_info.clone(),
+            remote_decimals: 18,
+            local_asset_info_decimals: 18,
+        };
+
+        // works with proper funds
+        let mut msg = ExecuteMsg::UpdateMappingPair(update.clone());
+
+        let info = mock_info("gov", &coins(1234567, "ucosm"));
+        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+
+        // add another pair with the same asset info to filter
+        update.denom = "jupiter".to_string();
+        msg = ExecuteMsg::UpdateMappingPair(update.clone());
+        let info = mock_info("gov", &coins(1234567, "ucosm"));
+        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+
+        // add another pair with a different asset info
+        update.denom = "moon".to_string();
+        update.local_asset_info = AssetInfo::NativeToken {
+            denom: "orai".to_string(),
+        };
+        msg = ExecuteMsg::UpdateMappingPair(update.clone());
+        let info = mock_info("gov", &coins(1234567, "ucosm"));
+        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+
+        // query based on asset info
+
+        let mappings = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::PairMappingsFromAssetInfo {
+                asset_info: asset_info,
+            },
+        )
+        .unwrap();
+        let response: Vec<PairQuery> = from_binary(&mappings).unwrap();
+        assert_eq!(response.len(), 2);
+
+        // query native token asset info, should receive moon denom in key
+        let mappings = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::PairMappingsFromAssetInfo {
+                asset_info: AssetInfo::NativeToken {
+                    denom: "orai".to_string(),
+                },
+            },
+        )
+        .unwrap();
+        let response: Vec<PairQuery> = from_binary(&mappings).unwrap();
+        assert_eq!(response.len(), 1);
+        assert_eq!(response.first().unwrap().key.contains("moon"), true);
+
+        // query asset info that is not in the mapping, should return empty
+        // query native token asset info, should receive moon denom
+        let mappings = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::PairMappingsFromAssetInfo {
+                asset_info: AssetInfo::NativeToken {
+                    denom: "foobar".to_string(),
+                },
+            },
+        )
+        .unwrap();
+        let response: Vec<PairQuery> = from_binary(&mappings).unwrap();
+        assert_eq!(response.len(), 0);
+    }
+
+    #[test]
+    fn test_update_cw20_mapping() {
+        let mut deps = setup(&["channel-3", "channel-7"], &[]);
+        let asset_info = AssetInfo::Token {
+            contract_addr: Addr::unchecked("cw20:foobar".to_string()),
+        };
+        let asset_info_second = AssetInfo::Token {
+            contract_addr: Addr::unchecked("cw20:foobar-second".to_string()),
+        };
+
+        let mut update = UpdatePairMsg {
+            local_channel_id: "mars-channel".to_string(),
+            denom: "earth".to_string(),
+            local_asset_info: asset_info.clone(),
+            remote_decimals: 18,
+            local_asset_info_decimals: 18,
+        };
+
+        // works with proper funds
+        let mut msg = ExecuteMsg::UpdateMappingPair(update.clone());
+
+        // unauthorized case
+        let info = mock_info("foobar", &coins(1234567, "ucosm"));
+        let res_err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert_eq!(res_err, ContractError::Admin(AdminError::NotAdmin {}));
+
+        let info = mock_info("gov", &coins(1234567, "ucosm"));
+        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+
+        // query to verify if the mapping has been updated
+        let mappings = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::PairMappings {
+                start_after: None,
+                limit: None,
+                order: None,
+            },
+        )
+        .unwrap();
+        let response: ListMappingResponse = from_binary(&mappings).unwrap();
+        println!("response: {:?}", response);
+        assert_eq!(
+            response.pairs.first().unwrap().key,
+            format!("{}/mars-channel/earth", CONTRACT_PORT)
+        );
+
+        // not found case
+        let mappings = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::PairMappings {
+                start_after: None,
+                limit: None,
+                order: None,
+            },
+        )
+        .unwrap();
+        let response: ListMappingResponse = from_binary(&mappings).unwrap();
+        assert_ne!(response.pairs.first().unwrap().key, "foobar".to_string());
+
+        // update existing key case must pass
+        update.local_asset_info = asset_info_second.clone();
+        msg = ExecuteMsg::UpdateMappingPair(update.clone());
+
+        let info = mock_info("gov", &coins(1234567, "ucosm"));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // after update, cw20 denom now needs to be updated
+        let mappings = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::PairMappings {
+                start_after: None,
+                limit: None,
+                order: None,
+            },
+        )
+        .unwrap();
+        let response: ListMappingResponse = from_binary(&mappings).unwrap();
+        println!("response: {:?}", response);
+        assert_eq!(
+            response.pairs.first().unwrap().key,
+            format!("{}/mars-channel/earth", CONTRACT_PORT)
+        );
+        assert_eq!(
+            response.pairs.first().unwrap().pair_mapping.asset_info,
+            asset_info_second
+        )
+    }
+
+    #[test]
+    fn test_delete_cw20_mapping() {
+        let mut deps = setup(&["channel-3", "channel-7"], &[]);
+        let cw20_denom = AssetInfo::Token {
+            contract_addr: Addr::unchecked("cw20:foobar".to_string()),
+        };
+
+        let update = UpdatePairMsg {
+            local_channel_id: "mars-channel".to_string(),
+            denom: "earth".to_string(),
+            local_asset_info: cw20_denom.clone(),
+            remote_decimals: 18,
+            local_asset_info_decimals: 18,
+        };
+
+        // works with proper funds
+        let msg = ExecuteMsg::UpdateMappingPair(update.clone());
+
+        let info = mock_info("gov", &coins(1234567, "ucosm"));
+        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+
+        // query to verify if the mapping has been updated
+        let mappings = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::PairMappings {
+                start_after: None,
+                limit: None,
+                order: None,
+            },
+        )
+        .unwrap();
+        let response: ListMappingResponse = from_binary(&mappings).unwrap();
+        println!("response: {:?}", response);
+        assert_eq!(
+            response.pairs.first().unwrap().key,
+            format!("{}/mars-channel/earth", CONTRACT_PORT)
+        );
+
+        // now try deleting
+        let delete = DeletePairMsg {
+            local_channel_id: "mars-channel".to_string(),
+            denom: "earth".to_string(),
+        };
+
+        let mut msg = ExecuteMsg::DeleteMappingPair(delete.clone());
+
+        // unauthorized delete case
+        let info = mock_info("foobar", &coins(1234567, "ucosm"));
+        let delete_err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert_eq!(delete_err, ContractError::Admin(AdminError::NotAdmin {}));
+
+        let info = mock_info("gov", &coins(1234567, "ucosm"));
+
+        // happy case
+        msg = ExecuteMsg::DeleteMappingPair(delete.clone());
+        execute(deps.as_mut(), mock_env(), info.clone(), msg.clone()).unwrap();
+
+        // after update, the list cw20 mapping should be empty
+        let mappings = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::PairMappings {
+                start_after: None,
+                limit: None,
+                order: None,
+            },
+        )
+        .unwrap();
+        let response: ListMappingResponse = from_binary(&mappings).unwrap();
+        println!("response: {:?}", response);
+        assert_eq!(response.pairs.len(), 0)
+    }
+
+    // #[test]
+    // fn proper_checks_on_execute_native() {
+    //     let send_channel = "channel-5";
+    //     let mut deps = setup(&[send_channel, "channel-10"], &[]);
+
+    //     let mut transfer = TransferMsg {
+    //         channel: send_channel.to_string(),
+    //         remote_address: "foreign-address".to_string(),
+    //         timeout: None,
+    //         memo: Some("memo".to_string()),
+    //     };
+
+    //     // works with proper funds
+    //     let msg = ExecuteMsg::Transfer(transfer.clone());
+    //     let info = mock_info("foobar", &coins(1234567, "ucosm"));
+    //     let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    //     assert_eq!(res.messages[0].gas_limit, None);
+    //     assert_eq!(1, res.messages.len());
+    //     if let CosmosMsg::Ibc(IbcMsg::SendPacket {
+    //         channel_id,
+    //         data,
+    //         timeout,
+    //     }) = &res.messages[0].msg
+    //     {
+    //         let expected_timeout = mock_env().block.time.plus_seconds(DEFAULT_TIMEOUT);
+    //         assert_eq!(timeout, &expected_timeout.into());
+    //         assert_eq!(channel_id.as_str(), send_channel);
+    //         let msg: Ics20Packet = from_binary(data).unwrap();
+    //         assert_eq!(msg.amount, Uint128::new(1234567));
+    //         assert_eq!(msg.denom.as_str(), "ucosm");
+    //         assert_eq!(msg.sender.as_str(), "foobar");
+    //         assert_eq!(msg.receiver.as_str(), "foreign-address");
+    //     } else {
+    //         panic!("Unexpected return message: {:?}", res.messages[0]);
+    //     }
+
+    //     // reject with no funds
+    //     let msg = ExecuteMsg::Transfer(transfer.clone());
+    //     let info = mock_info("foobar", &[]);
+    //     let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+    //     assert_eq!(err, ContractError::Payment(PaymentError::NoFunds {}));
+
+    //     // reject with multiple tokens funds
+    //     let msg = ExecuteMsg::Transfer(transfer.clone());
+    //     let info = mock_info("foobar", &[coin(1234567, "ucosm"), coin(54321, "uatom")]);
+    //     let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+    //     assert_eq!(err, ContractError::Payment(PaymentError::MultipleDenoms {}));
+
+    //     // reject with bad channel id
+    //     transfer.channel = "channel-45".to_string();
+    //     let msg = ExecuteMsg::Transfer(transfer);
+    //     let info = mock_info("foobar", &coins(1234567, "ucosm"));
+    //     let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+    //     assert_eq!(
+    //         err,
+    //         ContractError::NoSuchChannel {
+    //             id: "channel-45".to_string()
+    //         }
+    //     );
+    // }
+
+    // #[test]
+    // fn proper_checks_on_execute_cw20() {
+    //     let send_channel = "channel-15";
+    //     let cw20_addr = "my-token";
+    //     let mut deps = setup(&["channel-3", send_channel], &[(cw20_addr, 123456)]);
+
+    //     let transfer = TransferMsg {
+    //         channel: send_channel.to_string(),
+    //         remote_address: "foreign-address".to_string(),
+    //         timeout: Some(7777),
+    //         memo: Some("memo".to_string()),
+    //     };
+    //     let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+    //         sender: "my-account".into(),
+    //         amount: Uint128::new(888777666),
+    //         msg: to_binary(&transfer).unwrap(),
+    //     });
+
+    //     // works with proper funds
+    //     let info = mock_info(cw20_addr, &[]);
+    //     let res = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+    //     assert_eq!(1, res.messages.len());
+    //     assert_eq!(res.messages[0].gas_limit, None);
+    //     if let CosmosMsg::Ibc(IbcMsg::SendPacket {
+    //         channel_id,
+    //         data,
+    //         timeout,
+    //     }) = &res.messages[0].msg
+    //     {
+    //         let expected_timeout = mock_env().block.time.plus_seconds(7777);
+    //         assert_eq!(timeout, &expected_timeout.into());
+    //         assert_eq!(channel_id.as_str(), send_channel);
+    //         let msg: Ics20Packet = from_binary(data).unwrap();
+    //         assert_eq!(msg.amount, Uint128::new(888777666));
+    //         assert_eq!(msg.denom, format!("cw20:{}", cw20_addr));
+    //         assert_eq!(msg.sender.as_str(), "my-account");
+    //         assert_eq!(msg.receiver.as_str(), "foreign-address");
+    //     } else {
+    //         panic!("Unexpected return message: {:?}", res.messages[0]);
+    //     }
+
+    //     // reject with tokens funds
+    //     let info = mock_info("foobar", &coins(1234567, "ucosm"));
+    //     let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+    //     assert_eq!(err, ContractError::Payment(PaymentError::NonPayable {}));
+    // }
+
+    // #[test]
+    // fn execute_cw20_fails_if_not_whitelisted_unless_default_gas_limit() {
+    //     let send_channel = "channel-15";
+    //     let mut deps = setup(&[send_channel], &[]);
+
+    //     let cw20_addr = "my-token";
+    //     let transfer = TransferMsg {
+    //         channel: send_channel.to_string(),
+    //         remote_address: "foreign-address".to_string(),
+    //         timeout: Some(7777),
+    //         memo: Some("memo".to_string()),
+    //     };
+    //     let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+    //         sender: "my-account".into(),
+    //         amount: Uint128::new(888777666),
+    //         msg: to_binary(&transfer).unwrap(),
+    //     });
+
+    //     // rejected as not on allow list
+    //     let info = mock_info(cw20_addr, &[]);
+    //     let err = execute(deps.as_mut(), mock_env(), info.clone(), msg.clone()).unwrap_err();
+    //     assert_eq!(err, ContractError::NotOnAllowList);
+
+    //     // add a default gas limit
+    //     migrate(
+    //         deps.as_mut(),
+    //         mock_env(),
+    //         MigrateMsg {
+    //             default_gas_limit: Some(123456),
+    //             fee_receiver: "receiver".to_string(),
+    //             default_timeout: 100u64,
+    //             fee_denom: "orai".to_string(),
+    //             swap_router_contract: "foobar".to_string(),
+    //         },
+    //     )
+    //     .unwrap();
+
+    //     // try again
+    //     execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    // }
+    // test execute transfer back to native remote chain
+
+    fn mock_receive_packet(
+        remote_channel: &str,
+        local_channel: &str,
+        amount: u128,
+        denom: &str,
+        receiver: &str,
+    ) -> IbcPacket {
+        let data = Ics20Packet {
+            // this is returning a foreign (our) token, thus denom is <port>/<channel>/<denom>
+            denom: denom.to_string(),
+            amount: amount.into(),
+            sender: "remote-sender".to_string(),
+            receiver: receiver.to_string(),
+            memo: Some("memo".to_string()),
+        };
+        IbcPacket::new(
+            to_binary(&data).unwrap(),
+            IbcEndpoint {
+                port_id: REMOTE_PORT.to_string(),
+                channel_id: remote_channel.to_string(),
+            },
+            IbcEndpoint {
+                port_id: CONTRACT_PORT.to_string(),
+                channel_id: local_channel.to_string(),
+            },
+            3,
+            Timestamp::from_seconds(1665321069).into(),
+        )
+    }
+
+    #[test]
+    fn proper_checks_on_execute_native_transfer_back_to_remote() {
+        // arrange
+        let relayer = Addr::unchecked("relayer");
+        let remote_channel = "channel-5";
+        let remote_address = "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0";
+        let custom_addr = "custom-addr";
+        let original_sender = "original_sender";
+        let denom = "uatom0x";
+        let amount = 1234567u128;
+        let token_addr = Addr::unchecked("token-addr".to_string());
+        let asset_info = AssetInfo::Token {
+            contract_addr: token_addr.clone(),
+        };
+        let cw20_raw_denom = token_addr.as_str();
+        let local_channel = "channel-1234";
+        let ibc_denom = get_key_ics20_ibc_denom("wasm.cosmos2contract", local_channel, denom);
+        let ratio = Ratio {
+            nominator: 1,
+            denominator: 10,
+        };
+        let fee_amount =
+            Uint128::from(amount) * Decimal::from_ratio(ratio.nominator, ratio.denominator);
+        let mut deps = setup(&[remote_channel, local_channel], &[]);
+        TOKEN_FEE
+            .save(
+                deps.as_mut().storage,
+                denom,
+                &TokenFeeSchedule {
+                    ratio: ratio.clone(),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        let pair = UpdatePairMsg {
+            local_channel_id: local_channel.to_string(),
+            denom: denom.to_string(),
+            local_asset_info: asset_info.clone(),
+            remote_decimals: 18u8,
+            local_asset_info_decimals: 18u8,
+        };
+
+        let _ = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(pair),
+        )
+        .unwrap();
+
+        // execute
+        let mut transfer = TransferBackMsg {
+            local_channel_id: local_channel.to_string(),
+            remote_address: remote_address.to_string(),
+            remote_denom: denom.to_string(),
+            timeout: Some(DEFAULT_TIMEOUT),
+            memo: None,
+            packet_timeout: None,
+            swap_to: None,
+            min_out: None,
+        };
+
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: original_sender.to_string(),
+            amount: Uint128::from(amount),
+            msg: to_binary(&transfer).unwrap(),
+        });
+
+        // insufficient funds case because we need to receive from remote chain first
+        let info = mock_info(cw20_raw_denom, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info.clone(), msg.clone());
+        assert_eq!(
+            res.unwrap_err(),
+            ContractError::NoSuchChannelState {
+                id: local_channel.to_string(),
+                denom: ibc_denom.clone()
+            }
+        );
+
+        // prepare some mock packets
+        let recv_packet =
+            mock_receive_packet(remote_channel, local_channel, amount, denom, custom_addr);
+
+        // receive some tokens. Assume that the function works perfectly because the test case is elsewhere
+        let ibc_msg = IbcPacketReceiveMsg::new(recv_packet.clone(), relayer);
+        receive_and_reply(&mut deps, mock_env(), ibc_msg);
+
+        // error cases
+        // revert transfer state to correct state
+        transfer.local_channel_id = local_channel.to_string();
+        let receive_msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: original_sender.to_string(),
+            amount: Uint128::from(amount),
+            msg: to_binary(&transfer).unwrap(),
+        });
+
+        // now we execute transfer back to remote chain
+        let res = execute(deps.as_mut(), mock_env(), info.clone(), receive_msg).unwrap();
+
+        assert_eq!(res.messages[0].gas_limit, None);
+        println!("res messages: {:?}", res.messages);
+        assert_eq!(res.messages.len(), 1); // fees are accumulated instead of sent
+        match res.messages[0].msg.clone() {
+            CosmosMsg::Ibc(IbcMsg::SendPacket {
+                channel_id,
+                data,
+                timeout,
+            }) => {
+                let expected_timeout = mock_env().block.time.plus_seconds(DEFAULT_TIMEOUT);
+                assert_eq!(timeout, expected_timeout.into());
+                assert_eq!(channel_id.as_str(), local_channel);
+                let msg: Ics20Packet = from_binary(&data).unwrap();
+                assert_eq!(
+                    msg.amount,
+                    Uint128::new(1234567).sub(Uint128::from(fee_amount))
+                );
+                assert_eq!(
+                    msg.denom.as_str(),
+                    get_key_ics20_ibc_denom(CONTRACT_PORT, local_channel, denom)
+                );
+                assert_eq!(msg.sender.as_str(), original_sender);
+                assert_eq!(msg.receiver.as_str(), remote_address);
+                // assert_eq!(msg.memo, None);
+            }
+            _ => panic!("Unexpected return message: {:?}", res.messages[0]),
+        }
+        // the inbound and the outbound token fees wait on the contract for CollectFees
+        let fees: AccumulatedFeesResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::AccumulatedFees {}).unwrap())
+                .unwrap();
+        assert_eq!(
+            fees.token_fees,
+            vec![Amount::cw20(fee_amount.u128() * 2, token_addr.as_str())]
+        );
+        assert_eq!(fees.relayer_fees, vec![]);
+
+        // check new channel state after reducing balance
+        let chan = query_channel(deps.as_ref(), local_channel.into()).unwrap();
+        assert_eq!(
+            chan.balances,
+            vec![Amount::native(
+                fee_amount.u128(),
+                &get_key_ics20_ibc_denom(CONTRACT_PORT, local_channel, denom)
+            )]
+        );
+        assert_eq!(
+            chan.total_sent,
+            vec![Amount::native(
+                amount,
+                &get_key_ics20_ibc_denom(CONTRACT_PORT, local_channel, denom)
+            )]
+        );
+
+        // mapping pair error with wrong voucher denom
+        let pair = UpdatePairMsg {
+            local_channel_id: "not_registered_channel".to_string(),
+            denom: denom.to_string(),
+            local_asset_info: AssetInfo::Token {
+                contract_addr: Addr::unchecked("random_cw20_denom".to_string()),
+            },
+            remote_decimals: 18u8,
+            local_asset_info_decimals: 18u8,
+        };
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(pair),
+        )
+        .unwrap();
+
+        transfer.local_channel_id = "not_registered_channel".to_string();
+        let invalid_msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: original_sender.to_string(),
+            amount: Uint128::from(amount),
+            msg: to_binary(&transfer).unwrap(),
+        });
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), invalid_msg).unwrap_err();
+        assert_eq!(err, ContractError::MappingPairNotFound {});
+    }
+
+    #[test]
+    fn proper_checks_on_execute_cw20_transfer_back_to_remote() {
+        // arrange
+        let relayer = Addr::unchecked("relayer");
+        let remote_channel = "channel-5";
+        let remote_address = "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0";
+        let custom_addr = "custom-addr";
+        let original_sender = "original_sender";
+        let denom = "uatom0x";
+        let amount = 1234567u128;
+        let asset_info = AssetInfo::NativeToken {
+            denom: denom.clone().into(),
+        };
+        let cw20_raw_denom = original_sender;
+        let local_channel = "channel-1234";
+        let ibc_denom = get_key_ics20_ibc_denom("wasm.cosmos2contract", local_channel, denom);
+        let ratio = Ratio {
+            nominator: 1,
+            denominator: 10,
+        };
+        let fee_amount =
+            Uint128::from(amount) * Decimal::from_ratio(ratio.nominator, ratio.denominator);
+        let mut deps = setup(&[remote_channel, local_channel], &[]);
+        TOKEN_FEE
+            .save(
+                deps.as_mut().storage,
+                denom,
+                &TokenFeeSchedule {
+                    ratio: ratio.clone(),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        let pair = UpdatePairMsg {
+            local_channel_id: local_channel.to_string(),
+            denom: denom.to_string(),
+            local_asset_info: asset_info.clone(),
+            remote_decimals: 18u8,
+            local_asset_info_decimals: 18u8,
+        };
+
+        let _ = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(pair),
+        )
+        .unwrap();
+
+        // execute
+        let mut transfer = TransferBackMsg {
+            local_channel_id: local_channel.to_string(),
+            remote_address: remote_address.to_string(),
+            remote_denom: denom.to_string(),
+            timeout: Some(DEFAULT_TIMEOUT),
+            memo: None,
+            packet_timeout: None,
+            swap_to: None,
+            min_out: None,
+        };
+
+        let msg = ExecuteMsg::TransferToRemote(transfer.clone());
+
+        // insufficient funds case because we need to receive from remote chain first
+        let info = mock_info(cw20_raw_denom, &[coin(amount, denom)]);
+        let res = execute(deps.as_mut(), mock_env(), info.clone(), msg.clone());
+        assert_eq!(
+            res.unwrap_err(),
+            ContractError::NoSuchChannelState {
+                id: local_channel.to_string(),
+                denom: ibc_denom.clone()
+            }
+        );
+
+        // prepare some mock packets
+        let recv_packet =
+            mock_receive_packet(remote_channel, local_channel, amount, denom, custom_addr);
+
+        // receive some tokens. Assume that the function works perfectly because the test case is elsewhere
+        let ibc_msg = IbcPacketReceiveMsg::new(recv_packet.clone(), relayer);
+        receive_and_reply(&mut deps, mock_env(), ibc_msg);
+
+        // error cases
+        // revert transfer state to correct state
+        transfer.local_channel_id = local_channel.to_string();
+        let msg: ExecuteMsg = ExecuteMsg::TransferToRemote(transfer.clone());
+
+        // now we execute transfer back to remote chain
+        let res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+        assert_eq!(res.messages[0].gas_limit, None);
+        println!("res messages: {:?}", res.messages);
+        assert_eq!(res.messages.len(), 1); // fees are accumulated instead of sent
+        match res.messages[0].msg.clone() {
+            CosmosMsg::Ibc(IbcMsg::SendPacket {
+                channel_id,
+                data,
+                timeout,
+            }) => {
+                let expected_timeout = mock_env().block.time.plus_seconds(DEFAULT_TIMEOUT);
+                assert_eq!(timeout, expected_timeout.into());
+                assert_eq!(channel_id.as_str(), local_channel);
+                let msg: Ics20Packet = from_binary(&data).unwrap();
+                assert_eq!(
+                    msg.amount,
+                    Uint128::new(1234567).sub(Uint128::from(fee_amount))
+                );
+                assert_eq!(
+                    msg.denom.as_str(),
+                    get_key_ics20_ibc_denom(CONTRACT_PORT, local_channel, denom)
+                );
+                assert_eq!(msg.sender.as_str(), original_sender);
+                assert_eq!(msg.receiver.as_str(), remote_address);
+                // assert_eq!(msg.memo, None);
+            }
+            _ => panic!("Unexpected return message: {:?}", res.messages[0]),
+        }
+        let fees: AccumulatedFeesResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::AccumulatedFees {}).unwrap())
+                .unwrap();
+        assert_eq!(
+            fees.token_fees,
+            vec![Amount::native(fee_amount.u128() * 2, denom)]
+        );
+
+        // check new channel state after reducing balance
+        let chan = query_channel(deps.as_ref(), local_channel.into()).unwrap();
+        assert_eq!(
+            chan.balances,
+            vec![Amount::native(
+                fee_amount.u128(),
+                &get_key_ics20_ibc_denom(CONTRACT_PORT, local_channel, denom)
+            )]
+        );
+        assert_eq!(
+            chan.total_sent,
+            vec![Amount::native(
+                amount,
+                &get_key_ics20_ibc_denom(CONTRACT_PORT, local_channel, denom)
+            )]
+        );
+
+        // mapping pair error with wrong voucher denom
+        let pair = UpdatePairMsg {
+            local_channel_id: "not_registered_channel".to_string(),
+            denom: denom.to_string(),
+            local_asset_info: AssetInfo::Token {
+                contract_addr: Addr::unchecked("random_cw20_denom".to_string()),
+            },
+            remote_decimals: 18u8,
+            local_asset_info_decimals: 18u8,
+        };
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(pair),
+        )
+        .unwrap();
+
+        transfer.local_channel_id = "not_registered_channel".to_string();
+        let invalid_msg = ExecuteMsg::TransferToRemote(transfer);
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), invalid_msg).unwrap_err();
+        assert_eq!(err, ContractError::MappingPairNotFound {});
+    }
+
+    #[test]
+    fn test_update_config() {
+        // arrange
+        let mut deps = setup(&[], &[]);
+        let new_config = ExecuteMsg::UpdateConfig {
+            admin: Some("helloworld".to_string()),
+            default_timeout: Some(1),
+            default_gas_limit: None,
+            fee_denom: Some("hehe".to_string()),
+            swap_router_contract: Some("new_router".to_string()),
+            token_fee: Some(vec![
+                TokenFee {
+                    token_denom: "orai".to_string(),
+                    ratio: Ratio {
+                        nominator: 1,
+                        denominator: 10,
+                    },
+                    flat: Some(Uint128::from(10u128)),
+                    min: Some(Uint128::from(20u128)),
+                    max: Some(Uint128::from(1000u128)),
+                },
+                TokenFee {
+                    token_denom: "atom".to_string(),
+                    ratio: Ratio {
+                        nominator: 1,
+                        denominator: 5,
+                    },
+                    flat: None,
+                    min: None,
+                    max: None,
+                },
+            ]),
+            relayer_fee: Some(vec![RelayerFee {
+                prefix: "foo".to_string(),
+                fee: Uint128::from(1000000u64),
+                remote_denom: None,
+                fee_asset: None,
+            }]),
+            fee_receiver: Some("token_fee_receiver".to_string()),
+            relayer_fee_receiver: Some("relayer_fee_receiver".to_string()),
+        };
+        // unauthorized case
+        let unauthorized_info = mock_info(&String::from("somebody"), &[]);
+        let is_err = execute(
+            deps.as_mut(),
+            mock_env(),
+            unauthorized_info,
+            new_config.clone(),
+        )
+        .is_err();
+        assert_eq!(is_err, true);
+        // valid case
+        let info = mock_info(&String::from("gov"), &[]);
+        execute(deps.as_mut(), mock_env(), info, new_config).unwrap();
+        let config: ConfigResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap()).unwrap();
+        assert_eq!(config.default_gas_limit, None);
+        assert_eq!(config.default_timeout, 1);
+        assert_eq!(config.fee_denom, "hehe".to_string());
+        assert_eq!(config.swap_router_contract, "new_router".to_string());
+        assert_eq!(
+            config.relayer_fee_receiver,
+            Addr::unchecked("relayer_fee_receiver")
+        );
+        assert_eq!(
+            config.token_fee_receiver,
+            Addr::unchecked("token_fee_receiver")
+        );
+        assert_eq!(config.token_fees.len(), 2usize);
+        assert_eq!(config.token_fees[0].ratio.denominator, 5);
+        assert_eq!(config.token_fees[0].token_denom, "atom".to_string());
+        assert_eq!(config.token_fees[1].ratio.denominator, 10);
+        assert_eq!(config.token_fees[1].token_denom, "orai".to_string());
+        assert_eq!(config.token_fees[1].max, Some(Uint128::from(1000u128)));
+        let schedule: TokenFeeSchedule = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::GetTransferTokenFee {
+                    remote_token_denom: "orai".to_string(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(schedule.flat, Some(Uint128::from(10u128)));
+        assert_eq!(schedule.min, Some(Uint128::from(20u128)));
+        assert_eq!(config.relayer_fees.len(), 1);
+        assert_eq!(config.relayer_fees[0].prefix, "foo".to_string());
+        assert_eq!(config.relayer_fees[0].amount, Uint128::from(1000000u64));
+        // the new admin still has to accept
+        assert_eq!(config.gov_contract, "gov".to_string());
+        assert_eq!(config.pending_admin, Some(Addr::unchecked("helloworld")));
+    }
+
+    #[test]
+    fn test_asset_info() {
+        let asset_info = AssetInfo::NativeToken {
+            denom: "orai".to_string(),
+        };
+        assert_eq!(asset_info.to_string(), "orai".to_string());
+        let asset_info = AssetInfo::Token {
+            contract_addr: Addr::unchecked("oraiaxbc".to_string()),
+        };
+        assert_eq!(asset_info.to_string(), "oraiaxbc".to_string())
+    }
+
+    #[test]
+    fn test_handle_packet_refund() {
+        let local_channel_id = "channel-0";
+        let mut deps = setup(&[local_channel_id], &[]);
+        let native_denom = "cosmos";
+        let amount = Uint128::from(100u128);
+        let sender = "sender";
+        let local_asset_info = AssetInfo::NativeToken {
+            denom: "orai".to_string(),
+        };
+        let mapping_denom = format!("wasm.cosmos2contract/{}/{}", local_channel_id, native_denom);
+
+        let result =
+            handle_packet_refund(deps.as_mut().storage, sender, native_denom, amount).unwrap_err();
+        assert_eq!(
+            result.to_string(),
+            "cw_ics20::state::MappingMetadata not found"
+        );
+
+        // update mapping pair so that we can get refunded
+        // cosmos based case with mapping found. Should be successful & cosmos msg is ibc send packet
+        // add a pair mapping so we can test the happy case evm based happy case
+        let update = UpdatePairMsg {
+            local_channel_id: local_channel_id.to_string(),
+            denom: native_denom.to_string(),
+            local_asset_info: local_asset_info.clone(),
+            remote_decimals: 6,
+            local_asset_info_decimals: 6,
+        };
+
+        let msg = ExecuteMsg::UpdateMappingPair(update.clone());
+
+        let info = mock_info("gov", &coins(1234567, "ucosm"));
+        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+
+        // now we handle packet failure. should get sub msg
+        let result =
+            handle_packet_refund(deps.as_mut().storage, sender, &mapping_denom, amount).unwrap();
+        assert_eq!(
+            result,
+            SubMsg::reply_always(
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: sender.to_string(),
+                    amount: coins(amount.u128(), "orai")
+                }),
+                encode_reply_id(REFUND_FAILURE_ID, 1)
+            )
+        );
+    }
+
+    #[test]
+    fn test_query_channel_balance_with_key() {
+        // fixture
+        let channel = "foo-channel";
+        let ibc_denom = "port/channel/denom";
+        let amount = Uint128::from(10u128);
+        let reduce_amount = Uint128::from(1u128);
+        let mut deps = setup(&[channel], &[]);
+        increase_channel_balance(deps.as_mut().storage, channel, ibc_denom, amount).unwrap();
+        reduce_channel_balance(
+            deps.as_mut().storage,
+            channel,
+            ibc_denom,
+            Uint128::from(1u128),
+        )
+        .unwrap();
+
+        let result =
+            query_channel_with_key(deps.as_ref(), channel.to_string(), ibc_denom.to_string())
+                .unwrap();
+        assert_eq!(
+            result.balance,
+            Amount::from_parts(
+                ibc_denom.to_string(),
+                amount.checked_sub(reduce_amount).unwrap()
+            )
+        );
+        assert_eq!(
+            result.total_sent,
+            Amount::from_parts(ibc_denom.to_string(), amount)
+        );
+    }
+
+    #[test]
+    fn test_handle_override_channel_balance() {
+        // fixture
+        let channel = "foo-channel";
+        let ibc_denom = "port/channel/denom";
+        let amount = Uint128::from(10u128);
+        let override_amount = Uint128::from(100u128);
+        let total_sent_override = Uint128::from(1000u128);
+        let mut deps = setup(&[channel], &[]);
+        increase_channel_balance(deps.as_mut().storage, channel, ibc_denom, amount).unwrap();
+
+        // unauthorized case
+        let unauthorized = handle_override_channel_balance(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("attacker", &vec![]),
+            channel.to_string(),
+            ibc_denom.to_string(),
+            amount,
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(unauthorized, ContractError::Admin(AdminError::NotAdmin {}));
+
+        // execution, valid case
+        handle_override_channel_balance(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &vec![]),
+            channel.to_string(),
+            ibc_denom.to_string(),
+            override_amount,
+            Some(total_sent_override),
+        )
+        .unwrap();
+
+        // we query to validate the result after overriding
+
+        let result =
+            query_channel_with_key(deps.as_ref(), channel.to_string(), ibc_denom.to_string())
+                .unwrap();
+        assert_eq!(
+            result.balance,
+            Amount::from_parts(ibc_denom.to_string(), override_amount)
+        );
+        assert_eq!(
+            result.total_sent,
+            Amount::from_parts(ibc_denom.to_string(), total_sent_override)
+        );
+    }
+
+    #[test]
+    fn test_recover_channel_funds() {
+        let channel = "channel-0";
+        let denom = "uatom";
+        let ibc_denom = get_key_ics20_ibc_denom(CONTRACT_PORT, channel, denom);
+        let mut deps = setup(&[channel], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                local_channel_id: channel.to_string(),
+                denom: denom.to_string(),
+                local_asset_info: AssetInfo::NativeToken {
+                    denom: "ibc/atom".to_string(),
+                },
+                remote_decimals: 18,
+                local_asset_info_decimals: 6,
+            }),
+        )
+        .unwrap();
+        increase_channel_balance(
+            deps.as_mut().storage,
+            channel,
+            &ibc_denom,
+            Uint128::from(10000000000000u128),
+        )
+        .unwrap();
+        let recover_msg = ExecuteMsg::RecoverChannelFunds {
+            channel_id: channel.to_string(),
+            ibc_denom: ibc_denom.clone(),
+            amount: Uint128::from(6000000000000u128),
+            recipient: "recipient".to_string(),
+        };
+
+        // channel is still open
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            recover_msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::ChannelNotClosed {
+                id: channel.to_string()
+            }
+        );
+
+        crate::ibc::ibc_channel_close(
+            deps.as_mut(),
+            mock_env(),
+            cosmwasm_std::IbcChannelCloseMsg::new_init(mock_channel(channel)),
+        )
+        .unwrap();
+
+        // transfers on a closed channel are rejected
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("sender", &coins(100, "ibc/atom")),
+            ExecuteMsg::TransferToRemote(TransferBackMsg {
+                local_channel_id: channel.to_string(),
+                remote_address: "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0".to_string(),
+                remote_denom: denom.to_string(),
+                timeout: None,
+                memo: None,
+                packet_timeout: None,
+                swap_to: None,
+                min_out: None,
+            }),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::ChannelClosed {
+                id: channel.to_string()
+            }
+        );
+
+        // unauthorized case
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("attacker", &[]),
+            recover_msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Admin(AdminError::NotAdmin {}));
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            recover_msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "recipient".to_string(),
+                amount: coins(6, "ibc/atom")
+            }))]
+        );
+
+        let funds: ListRecoverableFundsResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::RecoverableFunds {
+                    channel_id: channel.to_string(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(funds.funds.len(), 1);
+        assert_eq!(
+            funds.funds[0].fund.recovered,
+            Uint128::from(6000000000000u128)
+        );
+        let channel_state = CHANNEL_REVERSE_STATE
+            .load(deps.as_ref().storage, (channel, &ibc_denom))
+            .unwrap();
+        assert_eq!(channel_state.outstanding, Uint128::from(4000000000000u128));
+
+        // cannot recover more than what was snapshotted
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            recover_msg,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InsufficientRecoverableFunds {
+                id: channel.to_string(),
+                denom: ibc_denom
+            }
+        );
+    }
+
+    #[test]
+    fn test_recover_escrowed_funds() {
+        let channel = "channel-0";
+        let mut deps = setup(&[channel], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("sender", &coins(100, "ucosm")),
+            ExecuteMsg::Transfer(TransferMsg {
+                channel: channel.to_string(),
+                remote_address: "foreign-address".to_string(),
+                timeout: None,
+                memo: None,
+                packet_timeout: None,
+            }),
+        )
+        .unwrap();
+
+        let res = crate::ibc::ibc_channel_close(
+            deps.as_mut(),
+            mock_env(),
+            cosmwasm_std::IbcChannelCloseMsg::new_init(mock_channel(channel)),
+        )
+        .unwrap();
+        assert!(res
+            .attributes
+            .contains(&attr("recoverable_escrow", "ucosm:100")));
+        let funds: ListRecoverableFundsResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::RecoverableFunds {
+                    channel_id: channel.to_string(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            funds.funds,
+            vec![RecoverableFundResponse {
+                ibc_denom: "ucosm".to_string(),
+                fund: RecoverableFund {
+                    outstanding: Uint128::from(100u128),
+                    recovered: Uint128::zero(),
+                    closed_height: mock_env().block.height,
+                    escrowed: true,
+                },
+            }]
+        );
+
+        // the escrow is paid out in the local denom, without a pair mapping
+        let recover_msg = |amount: u128| ExecuteMsg::RecoverChannelFunds {
+            channel_id: channel.to_string(),
+            ibc_denom: "ucosm".to_string(),
+            amount: Uint128::from(amount),
+            recipient: "recipient".to_string(),
+        };
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            recover_msg(60),
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "recipient".to_string(),
+                amount: coins(60, "ucosm")
+            }))]
+        );
+        let escrow = CHANNEL_FORWARD_STATE
+            .load(deps.as_ref().storage, (channel, "ucosm"))
+            .unwrap();
+        assert_eq!(escrow.outstanding, Uint128::from(40u128));
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            recover_msg(41),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InsufficientRecoverableFunds {
+                id: channel.to_string(),
+                denom: "ucosm".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_pending_transfer_refund_uses_snapshot() {
+        let channel = "channel-0";
+        let denom = "uatom";
+        let ibc_denom = get_key_ics20_ibc_denom(CONTRACT_PORT, channel, denom);
+        let token_addr = "token-addr";
+        let sender = "original_sender";
+        let amount = Uint128::from(1000000u128);
+        let remote_amount = Uint128::from(1000000000000000000u128);
+        let mut deps = setup(&[channel], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                local_channel_id: channel.to_string(),
+                denom: denom.to_string(),
+                local_asset_info: AssetInfo::Token {
+                    contract_addr: Addr::unchecked(token_addr),
+                },
+                remote_decimals: 18,
+                local_asset_info_decimals: 6,
+            }),
+        )
+        .unwrap();
+        increase_channel_balance(deps.as_mut().storage, channel, &ibc_denom, remote_amount)
+            .unwrap();
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(token_addr, &[]),
+            ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: sender.to_string(),
+                amount,
+                msg: to_binary(&TransferBackMsg {
+                    local_channel_id: channel.to_string(),
+                    remote_address: "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0".to_string(),
+                    remote_denom: denom.to_string(),
+                    timeout: Some(DEFAULT_TIMEOUT),
+                    memo: None,
+                    packet_timeout: None,
+                    swap_to: None,
+                    min_out: None,
+                })
+                .unwrap(),
+            }),
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(res.messages[0].id, encode_reply_id(SEND_PACKET_ID, 1));
+        let packet_data = match res.messages[0].msg.clone() {
+            CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => data,
+            msg => panic!("Unexpected return message: {:?}", msg),
+        };
+
+        // the ibc module assigns sequence 7 to the packet
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: res.messages[0].id,
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![Event::new("send_packet").add_attribute("packet_sequence", "7")],
+                    data: None,
+                }),
+            },
+        )
+        .unwrap();
+        let pending: ListPendingTransfersResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::PendingTransfers {
+                    channel_id: channel.to_string(),
+                    start_after: None,
+                    limit: None,
+                    order: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(pending.transfers.len(), 1);
+        assert_eq!(pending.transfers[0].sequence, 7);
+        assert_eq!(pending.transfers[0].transfer.local_sender, sender);
+        assert_eq!(pending.transfers[0].transfer.remote_amount, remote_amount);
+
+        // the mapping is removed while the packet is in flight
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::DeleteMappingPair(DeletePairMsg {
+                local_channel_id: channel.to_string(),
+                denom: denom.to_string(),
+            }),
+        )
+        .unwrap();
+
+        let packet = IbcPacket::new(
+            packet_data,
+            IbcEndpoint {
+                port_id: CONTRACT_PORT.to_string(),
+                channel_id: channel.to_string(),
+            },
+            IbcEndpoint {
+                port_id: REMOTE_PORT.to_string(),
+                channel_id: "channel-1234".to_string(),
+            },
+            7,
+            Timestamp::from_seconds(1665321069).into(),
+        );
+        let res = ibc_packet_timeout(
+            deps.as_mut(),
+            mock_env(),
+            IbcPacketTimeoutMsg::new(packet, Addr::unchecked("relayer")),
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::reply_always(
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: token_addr.to_string(),
+                    msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                        recipient: sender.to_string(),
+                        amount,
+                    })
+                    .unwrap(),
+                    funds: vec![],
+                }),
+                encode_reply_id(REFUND_FAILURE_ID, 2)
+            )]
+        );
+        assert_eq!(
+            CHANNEL_REVERSE_STATE
+                .load(deps.as_ref().storage, (channel, &ibc_denom))
+                .unwrap()
+                .outstanding,
+            remote_amount
+        );
+        assert!(PENDING_TRANSFERS
+            .may_load(deps.as_ref().storage, (channel, 7))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_packet_sent_without_sequence() {
+        let channel = "channel-0";
+        let mut deps = setup(&[channel], &[]);
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("sender", &coins(100, "ucosm")),
+            ExecuteMsg::Transfer(TransferMsg {
+                channel: channel.to_string(),
+                remote_address: "foreign-address".to_string(),
+                timeout: None,
+                memo: None,
+                packet_timeout: None,
+            }),
+        )
+        .unwrap();
+        let err = reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: res.messages[0].id,
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![],
+                    data: None,
+                }),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::PacketSequenceNotFound);
+
+        // a forward of a receive is not reverted, it stays unsequenced for the admin to recover
+        let forward = PendingTransfer {
+            src_channel_id: channel.to_string(),
+            local_sender: "receiver".to_string(),
+            local_amount: Amount::from_parts("ucosm".to_string(), Uint128::from(100u128)),
+            token_fee: Uint128::zero(),
+            relayer_fee: Uint128::zero(),
+            relayer_fee_on_ack: false,
+            ibc_denom: "ucosm".to_string(),
+            remote_amount: Uint128::from(100u128),
+            mapping: None,
+            sent_at: None,
+        };
+        UNSEQUENCED_TRANSFERS
+            .save(deps.as_mut().storage, 7, &forward)
+            .unwrap();
+        let res = reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: encode_reply_id(FOLLOW_UP_IBC_SEND_FAILURE_ID, 7),
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![],
+                    data: None,
+                }),
+            },
+        )
+        .unwrap();
+        assert!(res
+            .attributes
+            .contains(&attr("action", "follow_up_without_sequence")));
+        assert_eq!(
+            UNSEQUENCED_TRANSFERS
+                .load(deps.as_ref().storage, 7)
+                .unwrap(),
+            forward
+        );
+    }
+
+    #[test]
+    fn test_local_origin_refund_without_snapshot() {
+        let channel = "channel-0";
+        let mut deps = setup(&[channel], &[]);
+        // escrowed before the transfers were snapshotted
+        increase_channel_forward_balance(
+            deps.as_mut().storage,
+            channel,
+            "ucosm",
+            Uint128::from(100u128),
+        )
+        .unwrap();
+        let packet = IbcPacket::new(
+            to_binary(&Ics20Packet::new(
+                Uint128::from(100u128),
+                "ucosm",
+                "sender",
+                "foreign-address",
+                None,
+            ))
+            .unwrap(),
+            IbcEndpoint {
+                port_id: CONTRACT_PORT.to_string(),
+                channel_id: channel.to_string(),
+            },
+            IbcEndpoint {
+                port_id: REMOTE_PORT.to_string(),
+                channel_id: "channel-1234".to_string(),
+            },
+            3,
+            Timestamp::from_seconds(1665321069).into(),
+        );
+        let res = ibc_packet_timeout(
+            deps.as_mut(),
+            mock_env(),
+            IbcPacketTimeoutMsg::new(packet, Addr::unchecked("relayer")),
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::reply_always(
+                BankMsg::Send {
+                    to_address: "sender".to_string(),
+                    amount: coins(100, "ucosm"),
+                },
+                encode_reply_id(REFUND_FAILURE_ID, 1)
+            )]
+        );
+        assert_eq!(
+            CHANNEL_FORWARD_STATE
+                .load(deps.as_ref().storage, (channel, "ucosm"))
+                .unwrap()
+                .outstanding,
+            Uint128::zero()
+        );
+    }
+
+    #[test]
+    fn test_stuck_funds() {
+        let mut deps = setup(&["channel-0"], &[]);
+        let receiver = "receiver";
+        let amount = Amount::native(100, "orai");
+        // two payouts fail in their replies
+        let mut stuck_ids = vec![];
+        for reply_kind in [NATIVE_RECEIVE_ID, REFUND_FAILURE_ID] {
+            let sub_msg = build_payout_sub_msg(
+                deps.as_mut().storage,
+                amount.send_amount(receiver.to_string(), None),
+                reply_kind,
+                receiver,
+                amount.clone(),
+            )
+            .unwrap();
+            reply(
+                deps.as_mut(),
+                mock_env(),
+                Reply {
+                    id: sub_msg.id,
+                    result: SubMsgResult::Err("insufficient funds".to_string()),
+                },
+            )
+            .unwrap();
+            stuck_ids.push(decode_reply_id(sub_msg.id).1);
+        }
+
+        let funds: ListStuckFundsResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::StuckFunds {
+                    start_after: None,
+                    limit: None,
+                    order: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            funds.funds[0],
+            StuckFundResponse {
+                id: stuck_ids[0],
+                fund: StuckFund {
+                    receiver: receiver.to_string(),
+                    asset: "orai".to_string(),
+                    amount: Uint128::from(100u128),
+                    reason: "insufficient funds".to_string(),
+                    height: mock_env().block.height,
+                    paid_to: None,
+                    paid_height: None,
+                }
+            }
+        );
+        assert_eq!(funds.funds.len(), 2);
+
+        // only the receiver can claim
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("attacker", &[]),
+            ExecuteMsg::ClaimStuckFund { id: stuck_ids[0] },
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::NotStuckFundReceiver { id: stuck_ids[0] }
+        );
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(receiver, &[]),
+            ExecuteMsg::ClaimStuckFund { id: stuck_ids[0] },
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: receiver.to_string(),
+                amount: coins(100, "orai")
+            })]
+        );
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(receiver, &[]),
+            ExecuteMsg::ClaimStuckFund { id: stuck_ids[0] },
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::StuckFundAlreadyPaid { id: stuck_ids[0] }
+        );
+
+        // only the admin can pay out to another address
+        let resolve_msg = ExecuteMsg::ResolveStuckFund {
+            id: stuck_ids[1],
+            recipient: "new_receiver".to_string(),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(receiver, &[]),
+            resolve_msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Admin(AdminError::NotAdmin {}));
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            resolve_msg,
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: "new_receiver".to_string(),
+                amount: coins(100, "orai")
+            })]
+        );
+        let fund = STUCK_FUNDS
+            .load(deps.as_ref().storage, stuck_ids[1])
+            .unwrap();
+        assert_eq!(fund.paid_to, Some("new_receiver".to_string()));
+        assert_eq!(fund.paid_height, Some(mock_env().block.height));
+    }
+
+    #[test]
+    fn test_local_origin_tokens_round_trip() {
+        let channel = "channel-0";
+        let remote_channel = "channel-05";
+        let token_addr = "token-addr";
+        let sender = "local_sender";
+        let remote_address = "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0";
+        let mut deps = setup(&[channel], &[(token_addr, 123456)]);
+        let query_forward_balances = |deps: Deps| {
+            let res: ChannelResponse = from_binary(
+                &query(
+                    deps,
+                    mock_env(),
+                    QueryMsg::Channel {
+                        id: channel.to_string(),
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap();
+            res.forward_balances
+        };
+
+        // escrow cw20 tokens
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(token_addr, &[]),
+            ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: sender.to_string(),
+                amount: Uint128::from(1000u128),
+                msg: to_binary(&TransferMsg {
+                    channel: channel.to_string(),
+                    remote_address: remote_address.to_string(),
+                    timeout: None,
+                    memo: None,
+                    packet_timeout: None,
+                })
+                .unwrap(),
+            }),
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+        let packet_data = match res.messages[0].msg.clone() {
+            CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => data,
+            msg => panic!("Unexpected return message: {:?}", msg),
+        };
+        let packet: Ics20Packet = from_binary(&packet_data).unwrap();
+        assert_eq!(packet.denom, format!("cw20:{}", token_addr));
+        assert_eq!(packet.amount, Uint128::from(1000u128));
+        assert_eq!(
+            query_forward_balances(deps.as_ref()),
+            vec![Amount::cw20(1000, token_addr)]
+        );
+
+        // half of it comes back as a voucher of the remote chain
+        let voucher = Ics20Packet::new(
+            Uint128::from(400u128),
+            format!("{}/{}/cw20:{}", REMOTE_PORT, remote_channel, token_addr),
+            remote_address,
+            "receiver",
+            None,
+        );
+        let receive_packet = IbcPacket::new(
+            to_binary(&voucher).unwrap(),
+            IbcEndpoint {
+                port_id: REMOTE_PORT.to_string(),
+                channel_id: remote_channel.to_string(),
+            },
+            IbcEndpoint {
+                port_id: CONTRACT_PORT.to_string(),
+                channel_id: channel.to_string(),
+            },
+            3,
+            Timestamp::from_seconds(1665321069).into(),
+        );
+        let res = receive_and_reply(
+            &mut deps,
+            mock_env(),
+            IbcPacketReceiveMsg::new(receive_packet.clone(), Addr::unchecked("relayer")),
+        );
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: token_addr.to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "receiver".to_string(),
+                    amount: Uint128::from(400u128),
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
+        assert_eq!(
+            query_forward_balances(deps.as_ref()),
+            vec![Amount::cw20(600, token_addr)]
+        );
+
+        // cannot unescrow more than what is left on the channel
+        let mut too_much = voucher;
+        too_much.amount = Uint128::from(601u128);
+        let mut receive_packet = receive_packet;
+        receive_packet.data = to_binary(&too_much).unwrap();
+        let res = receive_and_reply(
+            &mut deps,
+            mock_env(),
+            IbcPacketReceiveMsg::new(receive_packet, Addr::unchecked("relayer")),
+        );
+        assert!(res.messages.is_empty());
+        assert_eq!(
+            query_forward_balances(deps.as_ref()),
+            vec![Amount::cw20(600, token_addr)]
+        );
+
+        // escrow native tokens, the packet times out and the escrow is refunded
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(sender, &coins(500, "orai")),
+            ExecuteMsg::Transfer(TransferMsg {
+                channel: channel.to_string(),
+                remote_address: remote_address.to_string(),
+                timeout: None,
+                memo: None,
+                packet_timeout: None,
+            }),
+        )
+        .unwrap();
+        let packet_data = match res.messages[0].msg.clone() {
+            CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => data,
+            msg => panic!("Unexpected return message: {:?}", msg),
+        };
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: res.messages[0].id,
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![Event::new("send_packet").add_attribute("packet_sequence", "2")],
+                    data: None,
+                }),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            query_forward_balances(deps.as_ref()),
+            vec![Amount::cw20(600, token_addr), Amount::native(500, "orai")]
+        );
+
+        let sent_packet = IbcPacket::new(
+            packet_data,
+            IbcEndpoint {
+                port_id: CONTRACT_PORT.to_string(),
+                channel_id: channel.to_string(),
+            },
+            IbcEndpoint {
+                port_id: REMOTE_PORT.to_string(),
+                channel_id: remote_channel.to_string(),
+            },
+            2,
+            Timestamp::from_seconds(1665321069).into(),
+        );
+        let res = ibc_packet_timeout(
+            deps.as_mut(),
+            mock_env(),
+            IbcPacketTimeoutMsg::new(sent_packet, Addr::unchecked("relayer")),
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: sender.to_string(),
+                amount: coins(500, "orai"),
+            })
+        );
+        assert_eq!(
+            query_forward_balances(deps.as_ref()),
+            vec![Amount::cw20(600, token_addr), Amount::native(0, "orai")]
+        );
+    }
+
+    #[test]
+    fn test_transfer_multiple_native_coins_to_remote() {
+        let channel = "channel-0";
+        let remote_address = "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0";
+        let mut deps = setup(&[channel], &[]);
+        for (remote_denom, local_denom) in [("uatom", "ibc/atom"), ("uosmo", "ibc/osmo")] {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("gov", &[]),
+                ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                    local_channel_id: channel.to_string(),
+                    denom: remote_denom.to_string(),
+                    local_asset_info: AssetInfo::NativeToken {
+                        denom: local_denom.to_string(),
+                    },
+                    remote_decimals: 6,
+                    local_asset_info_decimals: 6,
+                }),
+            )
+            .unwrap();
+            increase_channel_balance(
+                deps.as_mut().storage,
+                channel,
+                &get_key_ics20_ibc_denom(CONTRACT_PORT, channel, remote_denom),
+                Uint128::from(1000u128),
+            )
+            .unwrap();
+        }
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("sender", &[coin(100, "ibc/atom"), coin(200, "ibc/osmo")]),
+            ExecuteMsg::TransferToRemote(TransferBackMsg {
+                local_channel_id: channel.to_string(),
+                remote_address: remote_address.to_string(),
+                remote_denom: "uatom".to_string(),
+                timeout: None,
+                memo: None,
+                packet_timeout: None,
+                swap_to: None,
+                min_out: None,
+            }),
+        )
+        .unwrap();
+
+        // one packet per coin, each with its own reply nonce
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(res.messages[0].id, encode_reply_id(SEND_PACKET_ID, 1));
+        assert_eq!(res.messages[1].id, encode_reply_id(SEND_PACKET_ID, 2));
+        let packets: Vec<Ics20Packet> = res
+            .messages
+            .iter()
+            .map(|sub_msg| match sub_msg.msg.clone() {
+                CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => from_binary(&data).unwrap(),
+                msg => panic!("Unexpected return message: {:?}", msg),
+            })
+            .collect();
+        assert_eq!(
+            packets[0].denom,
+            get_key_ics20_ibc_denom(CONTRACT_PORT, channel, "uatom")
+        );
+        assert_eq!(packets[0].amount, Uint128::from(100u128));
+        assert_eq!(
+            packets[1].denom,
+            get_key_ics20_ibc_denom(CONTRACT_PORT, channel, "uosmo")
+        );
+        assert_eq!(packets[1].amount, Uint128::from(200u128));
+
+        let chan = query_channel(deps.as_ref(), channel.to_string()).unwrap();
+        assert_eq!(
+            chan.balances,
+            vec![
+                Amount::native(
+                    900,
+                    &get_key_ics20_ibc_denom(CONTRACT_PORT, channel, "uatom")
+                ),
+                Amount::native(
+                    800,
+                    &get_key_ics20_ibc_denom(CONTRACT_PORT, channel, "uosmo")
+                )
+            ]
+        );
+
+        // a coin that is not mapped on the channel fails the whole call
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("sender", &[coin(100, "ibc/atom"), coin(200, "orai")]),
+            ExecuteMsg::TransferToRemote(TransferBackMsg {
+                local_channel_id: channel.to_string(),
+                remote_address: remote_address.to_string(),
+                remote_denom: "uatom".to_string(),
+                timeout: None,
+                memo: None,
+                packet_timeout: None,
+                swap_to: None,
+                min_out: None,
+            }),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::MappingPairNotFound {});
+    }
+
+    #[test]
+    fn test_token_fee_schedule_on_receive() {
+        let channel = "channel-0";
+        let remote_channel = "channel-1234";
+        let mut deps = setup(&[channel], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                local_channel_id: channel.to_string(),
+                denom: "uatom".to_string(),
+                local_asset_info: AssetInfo::NativeToken {
+                    denom: "ibc/atom".to_string(),
+                },
+                remote_decimals: 6,
+                local_asset_info_decimals: 6,
+            }),
+        )
+        .unwrap();
+        let set_fee = |denominator: u64, min: u128, max: u128| ExecuteMsg::SetFees {
+            token_fees: vec![TokenFee {
+                token_denom: "uatom".to_string(),
+                ratio: Ratio {
+                    nominator: 1,
+                    denominator,
+                },
+                flat: Some(Uint128::from(5u128)),
+                min: Some(Uint128::from(min)),
+                max: Some(Uint128::from(max)),
+            }],
+            relayer_fees: vec![],
+        };
+
+        // schedules that cannot be applied are rejected when they are set
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            set_fee(0, 20, 50),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidTokenFee {
+                token_denom: "uatom".to_string(),
+                reason: "the ratio denominator is zero".to_string()
+            }
+        );
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            set_fee(100, 50, 20),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidTokenFee {
+                token_denom: "uatom".to_string(),
+                reason: "the minimum is above the maximum".to_string()
+            }
+        );
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            set_fee(100, 20, 50),
+        )
+        .unwrap();
+
+        // 1% plus 5, at least 20 and at most 50
+        for (amount, fee) in [(1000u128, 20u128), (3000, 35), (10000, 50)] {
+            let packet = mock_receive_packet(remote_channel, channel, amount, "uatom", "receiver");
+            let res = receive_and_reply(
+                &mut deps,
+                mock_env(),
+                IbcPacketReceiveMsg::new(packet, Addr::unchecked("relayer")),
+            );
+            assert_eq!(
+                res.messages[0].msg,
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: "receiver".to_string(),
+                    amount: coins(amount - fee, "ibc/atom"),
+                })
+            );
+            assert!(res
+                .attributes
+                .iter()
+                .any(|attr| attr.key == "token_fee" && attr.value == fee.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_rate_limits() {
+        let channel = "channel-0";
+        let remote_channel = "channel-1234";
+        let ibc_denom = get_key_ics20_ibc_denom(CONTRACT_PORT, channel, "uatom");
+        let remote_address = "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0";
+        let mut deps = setup(&[channel], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                local_channel_id: channel.to_string(),
+                denom: "uatom".to_string(),
+                local_asset_info: AssetInfo::NativeToken {
+                    denom: "ibc/atom".to_string(),
+                },
+                remote_decimals: 6,
+                local_asset_info_decimals: 6,
+            }),
+        )
+        .unwrap();
+        increase_channel_balance(
+            deps.as_mut().storage,
+            channel,
+            &ibc_denom,
+            Uint128::from(10000u128),
+        )
+        .unwrap();
+
+        let set_rate_limit = ExecuteMsg::SetRateLimit {
+            channel_id: channel.to_string(),
+            ibc_denom: ibc_denom.clone(),
+            window_seconds: 0,
+            max_inflow: Some(Uint128::from(1000u128)),
+            max_outflow: Some(Uint128::from(500u128)),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("attacker", &[]),
+            set_rate_limit.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Admin(AdminError::NotAdmin {}));
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            set_rate_limit,
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidRateLimitWindow);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::SetRateLimit {
+                channel_id: channel.to_string(),
+                ibc_denom: ibc_denom.clone(),
+                window_seconds: 3600,
+                max_inflow: Some(Uint128::from(1000u128)),
+                max_outflow: Some(Uint128::from(500u128)),
+            },
+        )
+        .unwrap();
+
+        // outflow
+        let transfer = |amount: u128| {
+            (
+                mock_info("sender", &coins(amount, "ibc/atom")),
+                ExecuteMsg::TransferToRemote(TransferBackMsg {
+                    local_channel_id: channel.to_string(),
+                    remote_address: remote_address.to_string(),
+                    remote_denom: "uatom".to_string(),
+                    timeout: None,
+                    memo: None,
+                    packet_timeout: None,
+                    swap_to: None,
+                    min_out: None,
+                }),
+            )
+        };
+        let (info, msg) = transfer(300);
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let (info, msg) = transfer(300);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::RateLimitExceeded {
+                id: channel.to_string(),
+                denom: ibc_denom.clone()
+            }
+        );
+
+        // a refunded packet no longer counts towards the cap
+        let packet_data = match res.messages[0].msg.clone() {
+            CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => data,
+            msg => panic!("Unexpected return message: {:?}", msg),
+        };
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: res.messages[0].id,
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![Event::new("send_packet").add_attribute("packet_sequence", "1")],
+                    data: None,
+                }),
+            },
+        )
+        .unwrap();
+        ibc_packet_timeout(
+            deps.as_mut(),
+            mock_env(),
+            IbcPacketTimeoutMsg::new(
+                IbcPacket::new(
+                    packet_data,
+                    IbcEndpoint {
+                        port_id: CONTRACT_PORT.to_string(),
+                        channel_id: channel.to_string(),
+                    },
+                    IbcEndpoint {
+                        port_id: REMOTE_PORT.to_string(),
+                        channel_id: remote_channel.to_string(),
+                    },
+                    1,
+                    Timestamp::from_seconds(1665321069).into(),
+                ),
+                Addr::unchecked("relayer"),
+            ),
+        )
+        .unwrap();
+        let (info, msg) = transfer(300);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // inflow over the cap gets a failed ack
+        let packet = mock_receive_packet(remote_channel, channel, 600, "uatom", "receiver");
+        let res = receive_and_reply(
+            &mut deps,
+            mock_env(),
+            IbcPacketReceiveMsg::new(packet.clone(), Addr::unchecked("relayer")),
+        );
+        assert!(!res.messages.is_empty());
+        let res = receive_and_reply(
+            &mut deps,
+            mock_env(),
+            IbcPacketReceiveMsg::new(packet.clone(), Addr::unchecked("relayer")),
+        );
+        assert!(res.messages.is_empty());
+        assert!(res.attributes.iter().any(|attr| attr.key == "error"
+            && attr.value
+                == ContractError::RateLimitExceeded {
+                    id: channel.to_string(),
+                    denom: ibc_denom.clone()
+                }
+                .to_string()));
+
+        let rate_limits: ListRateLimitsResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::RateLimits {
+                    channel_id: channel.to_string(),
+                    start_after: None,
+                    limit: None,
+                    order: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(rate_limits.rate_limits.len(), 1);
+        assert_eq!(rate_limits.rate_limits[0].ibc_denom, ibc_denom);
+        assert_eq!(rate_limits.rate_limits[0].inflow, Uint128::from(600u128));
+        assert_eq!(rate_limits.rate_limits[0].outflow, Uint128::from(300u128));
+
+        // once the rolling window, plus the sub-window it started in, has passed the usage is back to zero
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(3600 + 360);
+        let rate_limit: RateLimitResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::RateLimit {
+                    channel_id: channel.to_string(),
+                    ibc_denom: ibc_denom.clone(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(rate_limit.inflow, Uint128::zero());
+        assert_eq!(rate_limit.outflow, Uint128::zero());
+        let (info, msg) = transfer(300);
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        let res = receive_and_reply(
+            &mut deps,
+            env,
+            IbcPacketReceiveMsg::new(packet, Addr::unchecked("relayer")),
+        );
+        assert!(!res.messages.is_empty());
+
+        // without a rate limit nothing is capped
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::RemoveRateLimit {
+                channel_id: channel.to_string(),
+                ibc_denom: ibc_denom.clone(),
+            },
+        )
+        .unwrap();
+        let (info, msg) = transfer(3000);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    }
+
+    #[test]
+    fn test_rate_limit_rolling_window() {
+        let channel = "channel-0";
+        let denom = "ucosm";
+        let mut deps = setup(&[channel], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::SetRateLimit {
+                channel_id: channel.to_string(),
+                ibc_denom: denom.to_string(),
+                window_seconds: 100,
+                max_inflow: None,
+                max_outflow: Some(Uint128::from(1000u128)),
+            },
+        )
+        .unwrap();
+        let start = mock_env().block.time;
+        let send = |storage: &mut dyn Storage, seconds: u64, amount: u128| {
+            record_rate_limited_flow(
+                storage,
+                start.plus_seconds(seconds),
+                channel,
+                denom,
+                Uint128::from(amount),
+                FlowDirection::Outflow,
+            )
+        };
+
+        send(deps.as_mut().storage, 0, 100).unwrap();
+        send(deps.as_mut().storage, 90, 800).unwrap();
+        // a window restarting at 100 seconds would let 1800 through within 11 seconds
+        let err = send(deps.as_mut().storage, 101, 500).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::RateLimitExceeded {
+                id: channel.to_string(),
+                denom: denom.to_string()
+            }
+        );
+        // the 800 still counts until its sub-window has fully left the window
+        send(deps.as_mut().storage, 190, 300).unwrap_err();
+        send(deps.as_mut().storage, 191, 1000).unwrap();
+
+        // a new window length restarts the usage
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::SetRateLimit {
+                channel_id: channel.to_string(),
+                ibc_denom: denom.to_string(),
+                window_seconds: 200,
+                max_inflow: None,
+                max_outflow: Some(Uint128::from(1000u128)),
+            },
+        )
+        .unwrap();
+        send(deps.as_mut().storage, 192, 1000).unwrap();
+    }
+
+    #[test]
+    fn test_rate_limits_local_origin() {
+        let channel = "channel-0";
+        let remote_channel = "channel-1234";
+        let denom = "ucosm";
+        let mut deps = setup(&[channel], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::SetRateLimit {
+                channel_id: channel.to_string(),
+                ibc_denom: denom.to_string(),
+                window_seconds: 3600,
+                max_inflow: Some(Uint128::from(500u128)),
+                max_outflow: Some(Uint128::from(1000u128)),
+            },
+        )
+        .unwrap();
+
+        // the escrow is capped under the local denom
+        let transfer = ExecuteMsg::Transfer(TransferMsg {
+            channel: channel.to_string(),
+            remote_address: "foreign-address".to_string(),
+            timeout: None,
+            memo: None,
+            packet_timeout: None,
+        });
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("sender", &coins(800, denom)),
+            transfer.clone(),
+        )
+        .unwrap();
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("sender", &coins(800, denom)),
+            transfer,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::RateLimitExceeded {
+                id: channel.to_string(),
+                denom: denom.to_string()
+            }
+        );
+
+        // so is the unescrow, which leaves the escrow untouched when it gets a failed ack
+        let voucher = format!("{}/{}/{}", REMOTE_PORT, remote_channel, denom);
+        let packet = mock_receive_packet(remote_channel, channel, 600, &voucher, "receiver");
+        let res = receive_and_reply(
+            &mut deps,
+            mock_env(),
+            IbcPacketReceiveMsg::new(packet, Addr::unchecked("relayer")),
+        );
+        assert!(res.messages.is_empty());
+        assert_eq!(
+            CHANNEL_FORWARD_STATE
+                .load(deps.as_ref().storage, (channel, denom))
+                .unwrap()
+                .outstanding,
+            Uint128::from(800u128)
+        );
+        let packet = mock_receive_packet(remote_channel, channel, 400, &voucher, "receiver");
+        let res = receive_and_reply(
+            &mut deps,
+            mock_env(),
+            IbcPacketReceiveMsg::new(packet, Addr::unchecked("relayer")),
+        );
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(
+            CHANNEL_FORWARD_STATE
+                .load(deps.as_ref().storage, (channel, denom))
+                .unwrap()
+                .outstanding,
+            Uint128::from(400u128)
+        );
+    }
+
+    #[test]
+    fn test_pause_scopes() {
+        let channel = "channel-0";
+        let remote_channel = "channel-1234";
+        let ibc_denom = get_key_ics20_ibc_denom(CONTRACT_PORT, channel, "uatom");
+        let mut deps = setup(&[channel], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                local_channel_id: channel.to_string(),
+                denom: "uatom".to_string(),
+                local_asset_info: AssetInfo::NativeToken {
+                    denom: "ibc/atom".to_string(),
+                },
+                remote_decimals: 6,
+                local_asset_info_decimals: 6,
+            }),
+        )
+        .unwrap();
+        increase_channel_balance(
+            deps.as_mut().storage,
+            channel,
+            &ibc_denom,
+            Uint128::from(10000u128),
+        )
+        .unwrap();
+        let transfer_back = ExecuteMsg::TransferToRemote(TransferBackMsg {
+            local_channel_id: channel.to_string(),
+            remote_address: "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0".to_string(),
+            remote_denom: "uatom".to_string(),
+            timeout: None,
+            memo: None,
+            packet_timeout: None,
+            swap_to: None,
+            min_out: None,
+        });
+        let sender = mock_info("sender", &coins(100, "ibc/atom"));
+        let packet = mock_receive_packet(remote_channel, channel, 100, "uatom", "receiver");
+        let pause = |scope: PauseScope| ExecuteMsg::Pause { scope };
+        let unpause = |scope: PauseScope| ExecuteMsg::Unpause { scope };
+
+        // only the admin sets the guardian, which can pause but not unpause
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("guardian", &[]),
+            pause(PauseScope::All {}),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Admin(AdminError::NotAdmin {}));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::SetGuardian {
+                guardian: Some("guardian".to_string()),
+            },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("guardian", &[]),
+            pause(PauseScope::Outbound {}),
+        )
+        .unwrap();
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("guardian", &[]),
+            unpause(PauseScope::Outbound {}),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Admin(AdminError::NotAdmin {}));
+
+        let config: ConfigResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap()).unwrap();
+        assert_eq!(config.guardian, Some(Addr::unchecked("guardian")));
+        assert_eq!(config.paused, vec![PauseScope::Outbound {}]);
+
+        // outbound is paused, inbound still goes through
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            sender.clone(),
+            transfer_back.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::Paused {
+                scope: "outbound".to_string()
+            }
+        );
+        let res = receive_and_reply(
+            &mut deps,
+            mock_env(),
+            IbcPacketReceiveMsg::new(packet.clone(), Addr::unchecked("relayer")),
+        );
+        assert!(!res.messages.is_empty());
+
+        // pausing the mapping halts both directions
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            unpause(PauseScope::Outbound {}),
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            pause(PauseScope::IbcDenom {
+                ibc_denom: ibc_denom.clone(),
+            }),
+        )
+        .unwrap();
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            sender.clone(),
+            transfer_back.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::Paused {
+                scope: format!("ibc_denom:{}", ibc_denom)
+            }
+        );
+        let res = receive_and_reply(
+            &mut deps,
+            mock_env(),
+            IbcPacketReceiveMsg::new(packet.clone(), Addr::unchecked("relayer")),
+        );
+        assert!(res.messages.is_empty());
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert_eq!(
+            ack,
+            Ics20Ack::Error(
+                ContractError::Paused {
+                    scope: format!("ibc_denom:{}", ibc_denom)
+                }
+                .to_string()
+            )
+        );
+
+        // another channel is not affected by a channel pause
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            unpause(PauseScope::IbcDenom {
+                ibc_denom: ibc_denom.clone(),
+            }),
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("guardian", &[]),
+            pause(PauseScope::Channel {
+                channel_id: "channel-1".to_string(),
+            }),
+        )
+        .unwrap();
+        execute(deps.as_mut(), mock_env(), sender, transfer_back).unwrap();
+    }
 
-    //     // reject with bad channel id
-    //     transfer.channel = "channel-45".to_string();
-    //     let msg = ExecuteMsg::Transfer(transfer);
-    //     let info = mock_info("foobar", &coins(1234567, "ucosm"));
-    //     let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
-    //     assert_eq!(
-    //         err,
-    //         ContractError::NoSuchChannel {
-    //             id: "channel-45".to_string()
-    //         }
-    //     );
-    // }
+    #[test]
+    fn test_receive_locks_execute_until_last_reply() {
+        let channel = "channel-0";
+        let ibc_denom = get_key_ics20_ibc_denom(CONTRACT_PORT, channel, "uatom");
+        let mut deps = setup(&[channel], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                local_channel_id: channel.to_string(),
+                denom: "uatom".to_string(),
+                local_asset_info: AssetInfo::NativeToken {
+                    denom: "ibc/atom".to_string(),
+                },
+                remote_decimals: 6,
+                local_asset_info_decimals: 6,
+            }),
+        )
+        .unwrap();
 
-    // #[test]
-    // fn proper_checks_on_execute_cw20() {
-    //     let send_channel = "channel-15";
-    //     let cw20_addr = "my-token";
-    //     let mut deps = setup(&["channel-3", send_channel], &[(cw20_addr, 123456)]);
+        let mut packet = mock_receive_packet("channel-1234", channel, 100, "uatom", "receiver");
+        let data: Ics20Packet = from_binary(&packet.data).unwrap();
+        packet.data = to_binary(&Ics20Packet {
+            memo: Some(
+                r#"{"v1":{"receiver":"receiver","hook":{"contract":"hook-contract","msg":"e30="}}}"#
+                    .to_string(),
+            ),
+            ..data
+        })
+        .unwrap();
+        let res = ibc_packet_receive(
+            deps.as_mut(),
+            mock_env(),
+            IbcPacketReceiveMsg::new(packet, Addr::unchecked("relayer")),
+        )
+        .unwrap();
 
-    //     let transfer = TransferMsg {
-    //         channel: send_channel.to_string(),
-    //         remote_address: "foreign-address".to_string(),
-    //         timeout: Some(7777),
-    //         memo: Some("memo".to_string()),
-    //     };
-    //     let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
-    //         sender: "my-account".into(),
-    //         amount: Uint128::new(888777666),
-    //         msg: to_binary(&transfer).unwrap(),
-    //     });
+        // the balance is accounted within the receive, without a call back into the contract
+        let channel_state = CHANNEL_REVERSE_STATE
+            .load(deps.as_ref().storage, (channel, &ibc_denom))
+            .unwrap();
+        assert_eq!(channel_state.outstanding, Uint128::from(100u128));
+        let last = res.messages.last().unwrap();
+        assert!(
+            matches!(&last.msg, CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) if contract_addr == "hook-contract")
+        );
 
-    //     // works with proper funds
-    //     let info = mock_info(cw20_addr, &[]);
-    //     let res = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
-    //     assert_eq!(1, res.messages.len());
-    //     assert_eq!(res.messages[0].gas_limit, None);
-    //     if let CosmosMsg::Ibc(IbcMsg::SendPacket {
-    //         channel_id,
-    //         data,
-    //         timeout,
-    //     }) = &res.messages[0].msg
-    //     {
-    //         let expected_timeout = mock_env().block.time.plus_seconds(7777);
-    //         assert_eq!(timeout, &expected_timeout.into());
-    //         assert_eq!(channel_id.as_str(), send_channel);
-    //         let msg: Ics20Packet = from_binary(data).unwrap();
-    //         assert_eq!(msg.amount, Uint128::new(888777666));
-    //         assert_eq!(msg.denom, format!("cw20:{}", cw20_addr));
-    //         assert_eq!(msg.sender.as_str(), "my-account");
-    //         assert_eq!(msg.receiver.as_str(), "foreign-address");
-    //     } else {
-    //         panic!("Unexpected return message: {:?}", res.messages[0]);
-    //     }
+        // while the hook runs, it cannot transfer the received tokens on
+        let transfer_to_remote = ExecuteMsg::TransferToRemote(TransferBackMsg {
+            local_channel_id: channel.to_string(),
+            remote_address: "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0".to_string(),
+            remote_denom: "uatom".to_string(),
+            timeout: None,
+            memo: None,
+            packet_timeout: None,
+            swap_to: None,
+            min_out: None,
+        });
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("hook-contract", &coins(100, "ibc/atom")),
+            transfer_to_remote.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Reentrancy);
+        // nor can the admin rewrite the balances, only pausing stays open
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::OverrideChannelBalance {
+                channel_id: channel.to_string(),
+                ibc_denom: ibc_denom.clone(),
+                outstanding: Uint128::from(10u128),
+                total_sent: None,
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Reentrancy);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::Pause {
+                scope: PauseScope::Channel {
+                    channel_id: "channel-9".to_string(),
+                },
+            },
+        )
+        .unwrap();
 
-    //     // reject with tokens funds
-    //     let info = mock_info("foobar", &coins(1234567, "ucosm"));
-    //     let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
-    //     assert_eq!(err, ContractError::Payment(PaymentError::NonPayable {}));
-    // }
+        // the hook replies, execute is allowed again
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: last.id,
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![],
+                    data: None,
+                }),
+            },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("hook-contract", &coins(100, "ibc/atom")),
+            transfer_to_remote,
+        )
+        .unwrap();
+    }
 
-    // #[test]
-    // fn execute_cw20_fails_if_not_whitelisted_unless_default_gas_limit() {
-    //     let send_channel = "channel-15";
-    //     let mut deps = setup(&[send_channel], &[]);
+    #[test]
+    fn test_forward_back_on_the_receiving_channel() {
+        let channel = "channel-0";
+        let ibc_denom = get_key_ics20_ibc_denom(CONTRACT_PORT, channel, "uatom");
+        let mut deps = setup(&[channel], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                local_channel_id: channel.to_string(),
+                denom: "uatom".to_string(),
+                local_asset_info: AssetInfo::NativeToken {
+                    denom: "ibc/atom".to_string(),
+                },
+                remote_decimals: 6,
+                local_asset_info_decimals: 6,
+            }),
+        )
+        .unwrap();
 
-    //     let cw20_addr = "my-token";
-    //     let transfer = TransferMsg {
-    //         channel: send_channel.to_string(),
-    //         remote_address: "foreign-address".to_string(),
-    //         timeout: Some(7777),
-    //         memo: Some("memo".to_string()),
-    //     };
-    //     let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
-    //         sender: "my-account".into(),
-    //         amount: Uint128::new(888777666),
-    //         msg: to_binary(&transfer).unwrap(),
-    //     });
+        // nothing is outstanding before the packet, the forward spends what the packet itself brings in
+        let mut packet = mock_receive_packet("channel-1234", channel, 100, "uatom", "receiver");
+        let data: Ics20Packet = from_binary(&packet.data).unwrap();
+        packet.data = to_binary(&Ics20Packet {
+            memo: Some(
+                r#"{"v1":{"receiver":"cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0","destination_channel":"channel-0","destination_denom":"ibc/atom"}}"#
+                    .to_string(),
+            ),
+            ..data
+        })
+        .unwrap();
+        let res = ibc_packet_receive(
+            deps.as_mut(),
+            mock_env(),
+            IbcPacketReceiveMsg::new(packet, Addr::unchecked("relayer")),
+        )
+        .unwrap();
+        assert!(!res
+            .attributes
+            .iter()
+            .any(|attribute| attribute.key == "ibc_error_msg"));
+        assert_eq!(res.messages.len(), 1);
+        match &res.messages[0].msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket {
+                channel_id, data, ..
+            }) => {
+                assert_eq!(channel_id, channel);
+                let packet: Ics20Packet = from_binary(data).unwrap();
+                assert_eq!(packet.amount, Uint128::from(100u128));
+                assert_eq!(packet.denom, ibc_denom);
+            }
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+        let channel_state = CHANNEL_REVERSE_STATE
+            .load(deps.as_ref().storage, (channel, &ibc_denom))
+            .unwrap();
+        assert_eq!(channel_state.outstanding, Uint128::zero());
+        assert_eq!(channel_state.total_sent, Uint128::from(100u128));
+    }
 
-    //     // rejected as not on allow list
-    //     let info = mock_info(cw20_addr, &[]);
-    //     let err = execute(deps.as_mut(), mock_env(), info.clone(), msg.clone()).unwrap_err();
-    //     assert_eq!(err, ContractError::NotOnAllowList);
+    #[test]
+    fn test_admin_handover_and_timelock() {
+        let mut deps = setup(&["channel-0"], &[]);
+        let config = |deps: Deps| -> ConfigResponse {
+            from_binary(&query(deps, mock_env(), QueryMsg::Config {}).unwrap()).unwrap()
+        };
 
-    //     // add a default gas limit
-    //     migrate(
-    //         deps.as_mut(),
-    //         mock_env(),
-    //         MigrateMsg {
-    //             default_gas_limit: Some(123456),
-    //             fee_receiver: "receiver".to_string(),
-    //             default_timeout: 100u64,
-    //             fee_denom: "orai".to_string(),
-    //             swap_router_contract: "foobar".to_string(),
-    //         },
-    //     )
-    //     .unwrap();
+        // admin handover needs the proposed admin to accept
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::AcceptAdmin {},
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NoPendingAdmin);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::ProposeAdmin {
+                admin: "new_gov".to_string(),
+            },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::CancelAdminProposal {},
+        )
+        .unwrap();
+        assert_eq!(config(deps.as_ref()).pending_admin, None);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::ProposeAdmin {
+                admin: "new_gov".to_string(),
+            },
+        )
+        .unwrap();
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("somebody", &[]),
+            ExecuteMsg::AcceptAdmin {},
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NotPendingAdmin);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("new_gov", &[]),
+            ExecuteMsg::AcceptAdmin {},
+        )
+        .unwrap();
+        let res = config(deps.as_ref());
+        assert_eq!(res.gov_contract, "new_gov".to_string());
+        assert_eq!(res.pending_admin, None);
 
-    //     // try again
-    //     execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-    // }
-    // test execute transfer back to native remote chain
+        // with a timelock, sensitive changes are queued until the delay has passed
+        let admin = mock_info("new_gov", &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            admin.clone(),
+            ExecuteMsg::SetTimelockDelay { delay: 3600 },
+        )
+        .unwrap();
+        assert_eq!(config(deps.as_ref()).timelock_delay, 3600);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            admin.clone(),
+            ExecuteMsg::UpdateConfig {
+                admin: None,
+                default_timeout: Some(1),
+                default_gas_limit: None,
+                fee_denom: None,
+                swap_router_contract: Some("new_router".to_string()),
+                token_fee: None,
+                relayer_fee: None,
+                fee_receiver: None,
+                relayer_fee_receiver: None,
+            },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            admin.clone(),
+            ExecuteMsg::OverrideChannelBalance {
+                channel_id: "channel-0".to_string(),
+                ibc_denom: "foobar".to_string(),
+                outstanding: Uint128::from(100u128),
+                total_sent: None,
+            },
+        )
+        .unwrap();
+        let res = config(deps.as_ref());
+        assert_eq!(res.default_timeout, 1);
+        assert_eq!(res.swap_router_contract, "router".to_string());
 
-    fn mock_receive_packet(
-        remote_channel: &str,
-        local_channel: &str,
-        amount: u128,
-        denom: &str,
-        receiver: &str,
-    ) -> IbcPacket {
-        let data = Ics20Packet {
-            // this is returning a foreign (our) token, thus denom is <port>/<channel>/<denom>
-            denom: denom.to_string(),
-            amount: amount.into(),
-            sender: "remote-sender".to_string(),
-            receiver: receiver.to_string(),
-            memo: Some("memo".to_string()),
+        let queued: ListQueuedChangesResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::QueuedChanges {
+                    start_after: None,
+                    limit: None,
+                    order: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(queued.changes.len(), 2);
+        assert_eq!(queued.changes[0].id, 1);
+        assert_eq!(
+            queued.changes[0].change.change,
+            SensitiveChange::SwapRouterContract {
+                swap_router_contract: "new_router".to_string()
+            }
+        );
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            admin.clone(),
+            ExecuteMsg::ExecuteQueuedChange { id: 1 },
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::TimelockNotExpired {
+                id: 1,
+                eta: mock_env().block.time.plus_seconds(3600).seconds()
+            }
+        );
+        let mut later = mock_env();
+        later.block.time = later.block.time.plus_seconds(3600);
+        execute(
+            deps.as_mut(),
+            later.clone(),
+            mock_info("somebody", &[]),
+            ExecuteMsg::ExecuteQueuedChange { id: 1 },
+        )
+        .unwrap_err();
+        execute(
+            deps.as_mut(),
+            later.clone(),
+            admin.clone(),
+            ExecuteMsg::ExecuteQueuedChange { id: 1 },
+        )
+        .unwrap();
+        assert_eq!(
+            config(deps.as_ref()).swap_router_contract,
+            "new_router".to_string()
+        );
+
+        // cancelled changes can no longer be executed
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            admin.clone(),
+            ExecuteMsg::CancelQueuedChange { id: 2 },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            later,
+            admin,
+            ExecuteMsg::ExecuteQueuedChange { id: 2 },
+        )
+        .unwrap_err();
+        let res = query_channel(deps.as_ref(), "channel-0".to_string()).unwrap();
+        assert!(res.balances.is_empty());
+    }
+    #[test]
+    fn test_timelocked_fee_and_price_changes() {
+        let mut deps = setup(&["channel-0"], &[]);
+        let admin = mock_info("gov", &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            admin.clone(),
+            ExecuteMsg::SetTimelockDelay { delay: 3600 },
+        )
+        .unwrap();
+        let token_fee = |min: u128, max: u128| TokenFee {
+            token_denom: "uatom".to_string(),
+            ratio: Ratio {
+                nominator: 1,
+                denominator: 100,
+            },
+            flat: None,
+            min: Some(Uint128::from(min)),
+            max: Some(Uint128::from(max)),
         };
-        IbcPacket::new(
-            to_binary(&data).unwrap(),
-            IbcEndpoint {
-                port_id: REMOTE_PORT.to_string(),
-                channel_id: remote_channel.to_string(),
+        let usdt = AssetInfo::NativeToken {
+            denom: "usdt".to_string(),
+        };
+        let orai = AssetInfo::NativeToken {
+            denom: "orai".to_string(),
+        };
+
+        // an invalid fee is rejected right away instead of being queued
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            admin.clone(),
+            ExecuteMsg::SetFees {
+                token_fees: vec![token_fee(50, 20)],
+                relayer_fees: vec![],
             },
-            IbcEndpoint {
-                port_id: CONTRACT_PORT.to_string(),
-                channel_id: local_channel.to_string(),
+        )
+        .unwrap_err();
+        for msg in [
+            ExecuteMsg::SetFees {
+                token_fees: vec![token_fee(20, 50)],
+                relayer_fees: vec![],
+            },
+            ExecuteMsg::SetPriceSource {
+                price_source: PriceSource::Fixed {},
+                price_fallback: PriceFallback::Reject {},
+            },
+            ExecuteMsg::SetFixedPrice {
+                asset_info: usdt.clone(),
+                price: Some(Decimal::percent(200)),
+            },
+            ExecuteMsg::SetSwapRoute {
+                offer_asset_info: orai.clone(),
+                ask_asset_info: usdt.clone(),
+                operations: Some(vec![SwapOperation::OraiSwap {
+                    offer_asset_info: orai.clone(),
+                    ask_asset_info: usdt.clone(),
+                }]),
+            },
+        ] {
+            let res = execute(deps.as_mut(), mock_env(), admin.clone(), msg).unwrap();
+            assert!(res
+                .attributes
+                .iter()
+                .any(|attr| attr.key == "queued_change_id"));
+        }
+        // rate limits and fee exemptions apply right away
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            admin.clone(),
+            ExecuteMsg::SetRateLimit {
+                channel_id: "channel-0".to_string(),
+                ibc_denom: "ucosm".to_string(),
+                window_seconds: 3600,
+                max_inflow: None,
+                max_outflow: Some(Uint128::from(1u128)),
+            },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            admin.clone(),
+            ExecuteMsg::SetFeeExemption {
+                subject: FeeExemptionSubject::RemotePrefix {
+                    prefix: "cosmos".to_string(),
+                },
+                discount: None,
             },
-            3,
-            Timestamp::from_seconds(1665321069).into(),
         )
+        .unwrap();
+        assert!(RATE_LIMITS.has(deps.as_ref().storage, ("channel-0", "ucosm")));
+        assert!(!FEE_EXEMPTIONS.is_empty(deps.as_ref().storage));
+
+        assert!(TOKEN_FEE.is_empty(deps.as_ref().storage));
+        assert!(FIXED_PRICES.is_empty(deps.as_ref().storage));
+        assert!(SWAP_ROUTES.is_empty(deps.as_ref().storage));
+        let config = CONFIG.load(deps.as_ref().storage).unwrap();
+        assert_eq!(config.price_source, PriceSource::Router {});
+
+        let mut later = mock_env();
+        later.block.time = later.block.time.plus_seconds(3600);
+        for id in 1..=4 {
+            execute(
+                deps.as_mut(),
+                later.clone(),
+                admin.clone(),
+                ExecuteMsg::ExecuteQueuedChange { id },
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            TOKEN_FEE.load(deps.as_ref().storage, "uatom").unwrap(),
+            token_fee(20, 50).schedule()
+        );
+        assert_eq!(
+            FIXED_PRICES
+                .load(deps.as_ref().storage, &usdt.to_string())
+                .unwrap(),
+            Decimal::percent(200)
+        );
+        assert!(SWAP_ROUTES.has(
+            deps.as_ref().storage,
+            (&orai.to_string(), &usdt.to_string())
+        ));
+        let config = CONFIG.load(deps.as_ref().storage).unwrap();
+        assert_eq!(config.price_source, PriceSource::Fixed {});
+        assert_eq!(config.price_fallback, PriceFallback::Reject {});
     }
 
     #[test]
-    fn proper_checks_on_execute_native_transfer_back_to_remote() {
-        // arrange
-        let relayer = Addr::unchecked("relayer");
-        let remote_channel = "channel-5";
-        let remote_address = "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0";
-        let custom_addr = "custom-addr";
-        let original_sender = "original_sender";
-        let denom = "uatom0x";
-        let amount = 1234567u128;
-        let token_addr = Addr::unchecked("token-addr".to_string());
-        let asset_info = AssetInfo::Token {
-            contract_addr: token_addr.clone(),
+    fn test_collect_fees() {
+        let mut deps = setup(&[], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateConfig {
+                admin: None,
+                default_timeout: None,
+                default_gas_limit: None,
+                fee_denom: None,
+                swap_router_contract: None,
+                token_fee: None,
+                relayer_fee: None,
+                fee_receiver: Some("token_fee_receiver".to_string()),
+                relayer_fee_receiver: Some("relayer_fee_receiver".to_string()),
+            },
+        )
+        .unwrap();
+        let storage = deps.as_mut().storage;
+        accumulate_fee(storage, TOKEN_FEE_ACCUMULATOR, &Amount::native(100, "orai")).unwrap();
+        accumulate_fee(storage, TOKEN_FEE_ACCUMULATOR, &Amount::native(50, "orai")).unwrap();
+        accumulate_fee(storage, TOKEN_FEE_ACCUMULATOR, &Amount::cw20(10, "usdt")).unwrap();
+        accumulate_fee(storage, RELAYER_FEE_ACCUMULATOR, &Amount::native(7, "orai")).unwrap();
+        accumulate_fee(storage, RELAYER_FEE_ACCUMULATOR, &Amount::native(0, "atom")).unwrap();
+
+        let query_fees = |deps: Deps| -> AccumulatedFeesResponse {
+            from_binary(&query(deps, mock_env(), QueryMsg::AccumulatedFees {}).unwrap()).unwrap()
         };
-        let cw20_raw_denom = token_addr.as_str();
-        let local_channel = "channel-1234";
-        let ibc_denom = get_key_ics20_ibc_denom("wasm.cosmos2contract", local_channel, denom);
-        let ratio = Ratio {
+        let fees = query_fees(deps.as_ref());
+        assert_eq!(
+            fees.token_fees,
+            vec![Amount::cw20(10, "usdt"), Amount::native(150, "orai")]
+        );
+        assert_eq!(fees.relayer_fees, vec![Amount::native(7, "orai")]);
+
+        // only the admin and the receivers can sweep, and the fees always go to the configured receivers
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("somebody", &[]),
+            ExecuteMsg::CollectFees {},
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NotFeeCollector);
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("relayer_fee_receiver", &[]),
+            ExecuteMsg::CollectFees {},
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(
+                    Amount::cw20(10, "usdt").send_amount("token_fee_receiver".to_string(), None)
+                ),
+                SubMsg::new(BankMsg::Send {
+                    to_address: "token_fee_receiver".to_string(),
+                    amount: coins(150, "orai"),
+                }),
+                SubMsg::new(BankMsg::Send {
+                    to_address: "relayer_fee_receiver".to_string(),
+                    amount: coins(7, "orai"),
+                }),
+            ]
+        );
+        let fees = query_fees(deps.as_ref());
+        assert!(fees.token_fees.is_empty());
+        assert!(fees.relayer_fees.is_empty());
+    }
+    #[test]
+    fn test_fee_exemptions() {
+        let channel = "channel-0";
+        let ibc_denom = get_key_ics20_ibc_denom(CONTRACT_PORT, channel, "uatom");
+        let mut deps = setup(&[channel], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                local_channel_id: channel.to_string(),
+                denom: "uatom".to_string(),
+                local_asset_info: AssetInfo::NativeToken {
+                    denom: "ibc/atom".to_string(),
+                },
+                remote_decimals: 6,
+                local_asset_info_decimals: 6,
+            }),
+        )
+        .unwrap();
+        TOKEN_FEE
+            .save(
+                deps.as_mut().storage,
+                "uatom",
+                &TokenFeeSchedule {
+                    ratio: Ratio {
+                        nominator: 1,
+                        denominator: 10,
+                    },
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        increase_channel_balance(
+            deps.as_mut().storage,
+            channel,
+            &ibc_denom,
+            Uint128::from(10000u128),
+        )
+        .unwrap();
+        let transfer_back = ExecuteMsg::TransferToRemote(TransferBackMsg {
+            local_channel_id: channel.to_string(),
+            remote_address: "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0".to_string(),
+            remote_denom: "uatom".to_string(),
+            timeout: None,
+            memo: None,
+            packet_timeout: None,
+            swap_to: None,
+            min_out: None,
+        });
+        let sent_amount = |res: &Response| -> Uint128 {
+            match &res.messages[0].msg {
+                CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => {
+                    from_binary::<Ics20Packet>(data).unwrap().amount
+                }
+                msg => panic!("Unexpected message: {:?}", msg),
+            }
+        };
+
+        // only the admin manages exemptions, with discounts between zero and one
+        let set_exemption = |subject: FeeExemptionSubject, discount: Option<Ratio>| {
+            ExecuteMsg::SetFeeExemption { subject, discount }
+        };
+        let treasury = FeeExemptionSubject::LocalAddress {
+            address: "treasury".to_string(),
+        };
+        let half = Ratio {
             nominator: 1,
-            denominator: 10,
+            denominator: 2,
         };
-        let fee_amount =
-            Uint128::from(amount) * Decimal::from_ratio(ratio.nominator, ratio.denominator);
-        let mut deps = setup(&[remote_channel, local_channel], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("treasury", &[]),
+            set_exemption(treasury.clone(), None),
+        )
+        .unwrap_err();
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            set_exemption(
+                treasury.clone(),
+                Some(Ratio {
+                    nominator: 3,
+                    denominator: 2,
+                }),
+            ),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidFeeDiscount);
+
+        // without an exemption, 10% is charged
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("treasury", &coins(100, "ibc/atom")),
+            transfer_back.clone(),
+        )
+        .unwrap();
+        assert_eq!(sent_amount(&res), Uint128::from(90u128));
+
+        // half of the fee is waived for the treasury
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            set_exemption(treasury.clone(), Some(half.clone())),
+        )
+        .unwrap();
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("treasury", &coins(100, "ibc/atom")),
+            transfer_back.clone(),
+        )
+        .unwrap();
+        assert_eq!(sent_amount(&res), Uint128::from(95u128));
+
+        // the largest discount applies, here the whole remote prefix is exempted
+        let cosmos = FeeExemptionSubject::RemotePrefix {
+            prefix: "cosmos".to_string(),
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            set_exemption(cosmos.clone(), None),
+        )
+        .unwrap();
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("treasury", &coins(100, "ibc/atom")),
+            transfer_back.clone(),
+        )
+        .unwrap();
+        assert_eq!(sent_amount(&res), Uint128::from(100u128));
+
+        let exemptions: ListFeeExemptionsResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::FeeExemptions {
+                    start_after: None,
+                    limit: None,
+                    order: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            exemptions.exemptions,
+            vec![
+                FeeExemption {
+                    subject: treasury.clone(),
+                    discount: Some(half),
+                },
+                FeeExemption {
+                    subject: cosmos.clone(),
+                    discount: None,
+                },
+            ]
+        );
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::RemoveFeeExemption { subject: cosmos },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::RemoveFeeExemption { subject: treasury },
+        )
+        .unwrap();
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("treasury", &coins(100, "ibc/atom")),
+            transfer_back,
+        )
+        .unwrap();
+        assert_eq!(sent_amount(&res), Uint128::from(90u128));
+    }
+    #[test]
+    fn test_simulate_transfer_and_receive() {
+        let channel = "channel-0";
+        let ibc_denom = get_key_ics20_ibc_denom(CONTRACT_PORT, channel, "uatom");
+        let mut deps = setup(&[channel], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                local_channel_id: channel.to_string(),
+                denom: "uatom".to_string(),
+                local_asset_info: AssetInfo::NativeToken {
+                    denom: "ibc/atom".to_string(),
+                },
+                remote_decimals: 18,
+                local_asset_info_decimals: 6,
+            }),
+        )
+        .unwrap();
         TOKEN_FEE
-            .save(deps.as_mut().storage, denom, &ratio)
+            .save(
+                deps.as_mut().storage,
+                "uatom",
+                &TokenFeeSchedule {
+                    ratio: Ratio {
+                        nominator: 1,
+                        denominator: 10,
+                    },
+                    ..Default::default()
+                },
+            )
             .unwrap();
+        let expected_mapping = PairQuery {
+            key: ibc_denom.clone(),
+            pair_mapping: MappingMetadata {
+                asset_info: AssetInfo::NativeToken {
+                    denom: "ibc/atom".to_string(),
+                },
+                remote_decimals: 18,
+                asset_info_decimals: 6,
+            },
+        };
 
-        let pair = UpdatePairMsg {
-            local_channel_id: local_channel.to_string(),
-            denom: denom.to_string(),
-            local_asset_info: asset_info.clone(),
-            remote_decimals: 18u8,
-            local_asset_info_decimals: 18u8,
+        let res: SimulateTransferResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::SimulateTransfer {
+                    sender: "sender".to_string(),
+                    msg: TransferBackMsg {
+                        local_channel_id: channel.to_string(),
+                        remote_address: "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0".to_string(),
+                        remote_denom: "uatom".to_string(),
+                        timeout: None,
+                        memo: None,
+                        packet_timeout: None,
+                        swap_to: None,
+                        min_out: None,
+                    },
+                    amount: Amount::native(100, "ibc/atom"),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            res,
+            SimulateTransferResponse {
+                fee_data: FeeData {
+                    deducted_amount: Uint128::from(90u128),
+                    token_fee: Amount::native(10, "ibc/atom"),
+                    relayer_fee: Amount::native(0, "ibc/atom"),
+                },
+                pair_mapping: expected_mapping.clone(),
+                remote_amount: Uint128::from(90_000_000_000_000u128),
+            }
+        );
+
+        let packet = Ics20Packet::new(
+            Uint128::from(100_000_000_000_000u128),
+            "uatom",
+            "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0",
+            "receiver",
+            None,
+        );
+        let simulate_receive = |deps: Deps, packet: Ics20Packet| {
+            query(
+                deps,
+                mock_env(),
+                QueryMsg::SimulateReceive {
+                    dest_channel: channel.to_string(),
+                    packet,
+                },
+            )
+        };
+        let res: SimulateReceiveResponse =
+            from_binary(&simulate_receive(deps.as_ref(), packet.clone()).unwrap()).unwrap();
+        assert_eq!(
+            res,
+            SimulateReceiveResponse {
+                fee_data: FeeData {
+                    deducted_amount: Uint128::from(90u128),
+                    token_fee: Amount::native(10, "ibc/atom"),
+                    relayer_fee: Amount::native(0, "ibc/atom"),
+                },
+                pair_mapping: expected_mapping,
+                swap_operations: vec![],
+                minimum_receive: Uint128::from(90u128),
+                destination_pair_mapping: None,
+                remote_amount: None,
+                memo_error: None,
+            }
+        );
+        // simulating leaves the channel untouched
+        assert!(query_channel(deps.as_ref(), channel.to_string())
+            .unwrap()
+            .balances
+            .is_empty());
+
+        // a memo that cannot be parsed is reported and the tokens stay with the receiver
+        let bad_memo = Ics20Packet {
+            memo: Some(r#"{"v1":{"receiver":""}}"#.to_string()),
+            ..packet.clone()
+        };
+        let res: SimulateReceiveResponse =
+            from_binary(&simulate_receive(deps.as_ref(), bad_memo).unwrap()).unwrap();
+        assert_eq!(res.memo_error, Some(MemoError::MissingReceiver.to_string()));
+        assert_eq!(res.minimum_receive, Uint128::from(90u128));
+
+        // so is a slippage above the max of the config, which is zero by default
+        let loose_slippage = Ics20Packet {
+            memo: Some(
+                r#"{"v1":{"receiver":"receiver","destination_denom":"orai","slippage_bps":100}}"#
+                    .to_string(),
+            ),
+            ..packet.clone()
+        };
+        let res: SimulateReceiveResponse =
+            from_binary(&simulate_receive(deps.as_ref(), loose_slippage).unwrap()).unwrap();
+        assert_eq!(
+            res.memo_error,
+            Some(MemoError::InvalidSlippage { max: 0 }.to_string())
+        );
+        assert!(res.swap_operations.is_empty());
+        assert_eq!(res.minimum_receive, Uint128::from(90u128));
+
+        let unmapped = Ics20Packet {
+            denom: "uosmo".to_string(),
+            ..packet
         };
+        simulate_receive(deps.as_ref(), unmapped).unwrap_err();
+    }
 
-        let _ = execute(
+    #[test]
+    fn test_price_sources() {
+        let channel = "channel-0";
+        let ibc_denom = get_key_ics20_ibc_denom(CONTRACT_PORT, channel, "uatom");
+        let atom = AssetInfo::NativeToken {
+            denom: "ibc/atom".to_string(),
+        };
+        let mut deps = setup(&[channel], &[]);
+        execute(
             deps.as_mut(),
             mock_env(),
             mock_info("gov", &[]),
-            ExecuteMsg::UpdateMappingPair(pair),
+            ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                local_channel_id: channel.to_string(),
+                denom: "uatom".to_string(),
+                local_asset_info: atom.clone(),
+                remote_decimals: 6,
+                local_asset_info_decimals: 6,
+            }),
         )
         .unwrap();
-
-        // execute
-        let mut transfer = TransferBackMsg {
-            local_channel_id: local_channel.to_string(),
-            remote_address: remote_address.to_string(),
-            remote_denom: denom.to_string(),
-            timeout: Some(DEFAULT_TIMEOUT),
+        RELAYER_FEE
+            .save(
+                deps.as_mut().storage,
+                ("cosmos", ""),
+                &RelayerFeeSchedule {
+                    fee: Uint128::from(10u128),
+                    fee_asset: None,
+                },
+            )
+            .unwrap();
+        increase_channel_balance(
+            deps.as_mut().storage,
+            channel,
+            &ibc_denom,
+            Uint128::from(10000u128),
+        )
+        .unwrap();
+        let transfer_back = ExecuteMsg::TransferToRemote(TransferBackMsg {
+            local_channel_id: channel.to_string(),
+            remote_address: "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0".to_string(),
+            remote_denom: "uatom".to_string(),
+            timeout: None,
             memo: None,
+            packet_timeout: None,
+            swap_to: None,
+            min_out: None,
+        });
+        let transfer = |deps: DepsMut| {
+            execute(
+                deps,
+                mock_env(),
+                mock_info("sender", &coins(100, "ibc/atom")),
+                transfer_back.clone(),
+            )
+        };
+        let sent_amount = |res: &Response| -> Uint128 {
+            match &res.messages[0].msg {
+                CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => {
+                    from_binary::<Ics20Packet>(data).unwrap().amount
+                }
+                msg => panic!("Unexpected message: {:?}", msg),
+            }
         };
+        let set_price_source =
+            |price_source: PriceSource, price_fallback: PriceFallback| ExecuteMsg::SetPriceSource {
+                price_source,
+                price_fallback,
+            };
 
-        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
-            sender: original_sender.to_string(),
-            amount: Uint128::from(amount),
-            msg: to_binary(&transfer).unwrap(),
-        });
+        // the router has no pool in the mock querier, so by default no relayer fee is charged
+        let res = transfer(deps.as_mut()).unwrap();
+        assert_eq!(sent_amount(&res), Uint128::from(100u128));
 
-        // insufficient funds case because we need to receive from remote chain first
-        let info = mock_info(cw20_raw_denom, &[]);
-        let res = execute(deps.as_mut(), mock_env(), info.clone(), msg.clone());
+        // only the admin picks the price source
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("sender", &[]),
+            set_price_source(PriceSource::Fixed {}, PriceFallback::Reject {}),
+        )
+        .unwrap_err();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            set_price_source(PriceSource::Fixed {}, PriceFallback::Reject {}),
+        )
+        .unwrap();
+        let config = query_config(deps.as_ref()).unwrap();
+        assert_eq!(config.price_source, PriceSource::Fixed {});
+        assert_eq!(config.price_fallback, PriceFallback::Reject {});
+
+        // rejected without a price
+        let err = transfer(deps.as_mut()).unwrap_err();
         assert_eq!(
-            res.unwrap_err(),
-            ContractError::NoSuchChannelState {
-                id: local_channel.to_string(),
-                denom: get_key_ics20_ibc_denom("wasm.cosmos2contract", local_channel, denom)
-            }
+            err,
+            ContractError::Std(StdError::generic_err(
+                ContractError::PriceUnavailable {
+                    asset: atom.to_string()
+                }
+                .to_string()
+            ))
         );
 
-        // prepare some mock packets
-        let recv_packet =
-            mock_receive_packet(remote_channel, local_channel, amount, denom, custom_addr);
-
-        // receive some tokens. Assume that the function works perfectly because the test case is elsewhere
-        let ibc_msg = IbcPacketReceiveMsg::new(recv_packet.clone(), relayer);
-        ibc_packet_receive(deps.as_mut(), mock_env(), ibc_msg).unwrap();
-        // need to trigger increase channel balance because it is executed through submsg
+        // 2 atom per orai
         execute(
             deps.as_mut(),
             mock_env(),
-            mock_info(mock_env().contract.address.as_str(), &[]),
-            ExecuteMsg::IncreaseChannelBalanceIbcReceive {
-                dest_channel_id: local_channel.to_string(),
-                ibc_denom: ibc_denom.clone(),
-                amount: Uint128::from(amount),
-                local_receiver: custom_addr.to_string(),
+            mock_info("gov", &[]),
+            ExecuteMsg::SetFixedPrice {
+                asset_info: atom.clone(),
+                price: Some(Decimal::from_ratio(2u128, 1u128)),
             },
         )
         .unwrap();
+        let res = transfer(deps.as_mut()).unwrap();
+        assert_eq!(sent_amount(&res), Uint128::from(80u128));
 
-        // error cases
-        // revert transfer state to correct state
-        transfer.local_channel_id = local_channel.to_string();
-        let receive_msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
-            sender: original_sender.to_string(),
-            amount: Uint128::from(amount),
-            msg: to_binary(&transfer).unwrap(),
-        });
-
-        // now we execute transfer back to remote chain
-        let res = execute(deps.as_mut(), mock_env(), info.clone(), receive_msg).unwrap();
-
-        assert_eq!(res.messages[0].gas_limit, None);
-        println!("res messages: {:?}", res.messages);
-        assert_eq!(res.messages.len(), 2); // 2 because it also has deduct fee msg
-        match res.messages[1].msg.clone() {
-            CosmosMsg::Ibc(IbcMsg::SendPacket {
-                channel_id,
-                data,
-                timeout,
-            }) => {
-                let expected_timeout = mock_env().block.time.plus_seconds(DEFAULT_TIMEOUT);
-                assert_eq!(timeout, expected_timeout.into());
-                assert_eq!(channel_id.as_str(), local_channel);
-                let msg: Ics20Packet = from_binary(&data).unwrap();
-                assert_eq!(
-                    msg.amount,
-                    Uint128::new(1234567).sub(Uint128::from(fee_amount))
-                );
-                assert_eq!(
-                    msg.denom.as_str(),
-                    get_key_ics20_ibc_denom(CONTRACT_PORT, local_channel, denom)
-                );
-                assert_eq!(msg.sender.as_str(), original_sender);
-                assert_eq!(msg.receiver.as_str(), remote_address);
-                // assert_eq!(msg.memo, None);
-            }
-            _ => panic!("Unexpected return message: {:?}", res.messages[0]),
-        }
-        match res.messages[0].msg.clone() {
-            CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr,
-                msg,
-                funds: _,
-            }) => {
-                assert_eq!(contract_addr, token_addr.to_string());
-                assert_eq!(
-                    msg,
-                    to_binary(&Cw20ExecuteMsg::Transfer {
-                        recipient: "gov".to_string(),
-                        amount: fee_amount.clone()
-                    })
-                    .unwrap()
-                );
+        // the last known price is remembered and used once the fixed price is removed
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            set_price_source(PriceSource::Fixed {}, PriceFallback::LastKnown {}),
+        )
+        .unwrap();
+        transfer(deps.as_mut()).unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::SetFixedPrice {
+                asset_info: atom.clone(),
+                price: None,
+            },
+        )
+        .unwrap();
+        let price: PriceResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Price {
+                    asset_info: atom.clone(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            price,
+            PriceResponse {
+                fixed: None,
+                last_known: Some(Decimal::from_ratio(2u128, 1u128)),
             }
-            _ => panic!("Unexpected return message: {:?}", res.messages[0]),
-        }
+        );
+        let res = transfer(deps.as_mut()).unwrap();
+        assert_eq!(sent_amount(&res), Uint128::from(80u128));
 
-        // check new channel state after reducing balance
-        let chan = query_channel(deps.as_ref(), local_channel.into()).unwrap();
+        // a packet whose relayer fee cannot be priced gets a failed ack, without remembering the new price of atom
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::SetFixedPrice {
+                asset_info: atom.clone(),
+                price: Some(Decimal::from_ratio(3u128, 1u128)),
+            },
+        )
+        .unwrap();
+        RELAYER_FEE
+            .save(
+                deps.as_mut().storage,
+                ("cosmos", "uatom"),
+                &RelayerFeeSchedule {
+                    fee: Uint128::from(10u128),
+                    fee_asset: Some(AssetInfo::NativeToken {
+                        denom: "unpriced".to_string(),
+                    }),
+                },
+            )
+            .unwrap();
+        let mut packet = mock_receive_packet("channel-5", channel, 100, "uatom", "receiver");
+        let mut data: Ics20Packet = from_binary(&packet.data).unwrap();
+        data.sender = "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0".to_string();
+        packet.data = to_binary(&data).unwrap();
+        let res = ibc_packet_receive(
+            deps.as_mut(),
+            mock_env(),
+            IbcPacketReceiveMsg::new(packet, Addr::unchecked("relayer")),
+        )
+        .unwrap();
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert!(matches!(ack, Ics20Ack::Error(_)));
         assert_eq!(
-            chan.balances,
-            vec![Amount::native(
-                fee_amount.u128(),
-                &get_key_ics20_ibc_denom(CONTRACT_PORT, local_channel, denom)
-            )]
+            LAST_KNOWN_PRICES
+                .load(deps.as_ref().storage, &atom.to_string())
+                .unwrap(),
+            Decimal::from_ratio(2u128, 1u128)
         );
+        // and so does a transfer
+        transfer(deps.as_mut()).unwrap_err();
         assert_eq!(
-            chan.total_sent,
-            vec![Amount::native(
-                amount,
-                &get_key_ics20_ibc_denom(CONTRACT_PORT, local_channel, denom)
-            )]
+            LAST_KNOWN_PRICES
+                .load(deps.as_ref().storage, &atom.to_string())
+                .unwrap(),
+            Decimal::from_ratio(2u128, 1u128)
         );
+    }
 
-        // mapping pair error with wrong voucher denom
-        let pair = UpdatePairMsg {
-            local_channel_id: "not_registered_channel".to_string(),
-            denom: denom.to_string(),
-            local_asset_info: AssetInfo::Token {
-                contract_addr: Addr::unchecked("random_cw20_denom".to_string()),
+    #[test]
+    fn test_fee_management_and_history() {
+        let mut deps = setup(&["channel-0"], &[]);
+        let mut env = mock_env();
+        let token_fee = |token_denom: &str, nominator: u64| TokenFee {
+            token_denom: token_denom.to_string(),
+            ratio: Ratio {
+                nominator,
+                denominator: 100,
             },
-            remote_decimals: 18u8,
-            local_asset_info_decimals: 18u8,
+            flat: None,
+            min: None,
+            max: None,
+        };
+        let relayer_fee = |prefix: &str, fee: u128| RelayerFee {
+            prefix: prefix.to_string(),
+            fee: Uint128::from(fee),
+            remote_denom: None,
+            fee_asset: None,
+        };
+        let history = |deps: Deps, fee: FeeKey| -> Vec<FeeChange> {
+            from_binary::<ListFeeHistoryResponse>(
+                &query(
+                    deps,
+                    mock_env(),
+                    QueryMsg::FeeHistory {
+                        fee,
+                        start_after: None,
+                        limit: None,
+                        order: None,
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap()
+            .changes
+        };
+        let uatom = FeeKey::TokenFee {
+            token_denom: "uatom".to_string(),
+        };
+        let cosmos = FeeKey::RelayerFee {
+            prefix: "cosmos".to_string(),
+            remote_denom: None,
         };
 
+        // only the admin manages fees
+        let set_fees = ExecuteMsg::SetFees {
+            token_fees: vec![token_fee("uatom", 1), token_fee("uosmo", 2)],
+            relayer_fees: vec![relayer_fee("cosmos", 10)],
+        };
         execute(
             deps.as_mut(),
-            mock_env(),
+            env.clone(),
+            mock_info("sender", &[]),
+            set_fees.clone(),
+        )
+        .unwrap_err();
+        execute(deps.as_mut(), env.clone(), mock_info("gov", &[]), set_fees).unwrap();
+        assert_eq!(
+            TOKEN_FEE.load(deps.as_ref().storage, "uatom").unwrap(),
+            token_fee("uatom", 1).schedule()
+        );
+        assert_eq!(
+            RELAYER_FEE
+                .load(deps.as_ref().storage, ("cosmos", ""))
+                .unwrap()
+                .fee,
+            Uint128::from(10u128)
+        );
+
+        // saving the same fee again is not recorded
+        env.block.height += 1;
+        execute(
+            deps.as_mut(),
+            env.clone(),
             mock_info("gov", &[]),
-            ExecuteMsg::UpdateMappingPair(pair),
+            ExecuteMsg::SetFees {
+                token_fees: vec![token_fee("uatom", 1)],
+                relayer_fees: vec![relayer_fee("cosmos", 20)],
+            },
         )
         .unwrap();
+        assert_eq!(history(deps.as_ref(), uatom.clone()).len(), 1);
+        let changes = history(deps.as_ref(), cosmos.clone());
+        assert_eq!(
+            changes[1],
+            FeeChange {
+                id: changes[1].id,
+                height: env.block.height,
+                key: cosmos.clone(),
+                previous: Some(FeeValue::RelayerFee {
+                    schedule: relayer_fee("cosmos", 10).schedule()
+                }),
+                current: Some(FeeValue::RelayerFee {
+                    schedule: relayer_fee("cosmos", 20).schedule()
+                }),
+            }
+        );
 
-        transfer.local_channel_id = "not_registered_channel".to_string();
-        let invalid_msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
-            sender: original_sender.to_string(),
-            amount: Uint128::from(amount),
-            msg: to_binary(&transfer).unwrap(),
-        });
-        let err = execute(deps.as_mut(), mock_env(), info.clone(), invalid_msg).unwrap_err();
-        assert_eq!(err, ContractError::MappingPairNotFound {});
-    }
+        // removal deletes the entry
+        env.block.height += 1;
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("gov", &[]),
+            ExecuteMsg::RemoveFees {
+                fees: vec![uatom.clone()],
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            TOKEN_FEE.may_load(deps.as_ref().storage, "uatom").unwrap(),
+            None
+        );
+        let changes = history(deps.as_ref(), uatom.clone());
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[1].height, env.block.height);
+        assert_eq!(
+            changes[1].previous,
+            Some(FeeValue::TokenFee {
+                schedule: token_fee("uatom", 1).schedule()
+            })
+        );
+        assert_eq!(changes[1].current, None);
 
-    #[test]
-    fn proper_checks_on_execute_cw20_transfer_back_to_remote() {
-        // arrange
-        let relayer = Addr::unchecked("relayer");
-        let remote_channel = "channel-5";
-        let remote_address = "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0";
-        let custom_addr = "custom-addr";
-        let original_sender = "original_sender";
-        let denom = "uatom0x";
-        let amount = 1234567u128;
-        let asset_info = AssetInfo::NativeToken {
-            denom: denom.clone().into(),
-        };
-        let cw20_raw_denom = original_sender;
-        let local_channel = "channel-1234";
-        let ibc_denom = get_key_ics20_ibc_denom("wasm.cosmos2contract", local_channel, denom);
-        let ratio = Ratio {
-            nominator: 1,
-            denominator: 10,
-        };
-        let fee_amount =
-            Uint128::from(amount) * Decimal::from_ratio(ratio.nominator, ratio.denominator);
-        let mut deps = setup(&[remote_channel, local_channel], &[]);
-        TOKEN_FEE
-            .save(deps.as_mut().storage, denom, &ratio)
+        // replacing the token fees drops the ones not listed and keeps relayer fees
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("gov", &[]),
+            ExecuteMsg::ReplaceFees {
+                token_fees: Some(vec![token_fee("orai", 3)]),
+                relayer_fees: None,
+            },
+        )
+        .unwrap();
+        let token_denoms: Vec<String> = TOKEN_FEE
+            .keys(deps.as_ref().storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()
             .unwrap();
+        assert_eq!(token_denoms, vec!["orai".to_string()]);
+        assert_eq!(
+            history(
+                deps.as_ref(),
+                FeeKey::TokenFee {
+                    token_denom: "uosmo".to_string()
+                }
+            )[1]
+            .current,
+            None
+        );
+        assert_eq!(
+            RELAYER_FEE
+                .load(deps.as_ref().storage, ("cosmos", ""))
+                .unwrap()
+                .fee,
+            Uint128::from(20u128)
+        );
 
-        let pair = UpdatePairMsg {
-            local_channel_id: local_channel.to_string(),
-            denom: denom.to_string(),
-            local_asset_info: asset_info.clone(),
-            remote_decimals: 18u8,
-            local_asset_info_decimals: 18u8,
+        // a relayer fee for one token of the network is kept apart from the network default
+        let cosmos_atom = FeeKey::RelayerFee {
+            prefix: "cosmos".to_string(),
+            remote_denom: Some("uatom".to_string()),
         };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("gov", &[]),
+            ExecuteMsg::SetFees {
+                token_fees: vec![],
+                relayer_fees: vec![RelayerFee {
+                    remote_denom: Some("uatom".to_string()),
+                    ..relayer_fee("cosmos", 5)
+                }],
+            },
+        )
+        .unwrap();
+        let config = query_config(deps.as_ref()).unwrap();
+        assert_eq!(config.relayer_fees.len(), 2);
+        assert_eq!(
+            config.relayer_fees[1].remote_denom,
+            Some("uatom".to_string())
+        );
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("gov", &[]),
+            ExecuteMsg::RemoveFees {
+                fees: vec![cosmos_atom.clone()],
+            },
+        )
+        .unwrap();
+        assert_eq!(history(deps.as_ref(), cosmos_atom).len(), 2);
+        assert_eq!(history(deps.as_ref(), cosmos).len(), 2);
+    }
 
-        let _ = execute(
+    #[test]
+    fn test_relayer_registry_and_claims() {
+        let channel = "channel-0";
+        let ibc_denom = get_key_ics20_ibc_denom(CONTRACT_PORT, channel, "uatom");
+        let atom = AssetInfo::NativeToken {
+            denom: "ibc/atom".to_string(),
+        };
+        let mut deps = setup(&[channel], &[]);
+        execute(
             deps.as_mut(),
             mock_env(),
             mock_info("gov", &[]),
-            ExecuteMsg::UpdateMappingPair(pair),
+            ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                local_channel_id: channel.to_string(),
+                denom: "uatom".to_string(),
+                local_asset_info: atom.clone(),
+                remote_decimals: 6,
+                local_asset_info_decimals: 6,
+            }),
         )
         .unwrap();
-
-        // execute
-        let mut transfer = TransferBackMsg {
-            local_channel_id: local_channel.to_string(),
-            remote_address: remote_address.to_string(),
-            remote_denom: denom.to_string(),
-            timeout: Some(DEFAULT_TIMEOUT),
-            memo: None,
+        RELAYER_FEE
+            .save(
+                deps.as_mut().storage,
+                ("cosmos", "uatom"),
+                &RelayerFeeSchedule {
+                    fee: Uint128::from(10u128),
+                    fee_asset: Some(atom),
+                },
+            )
+            .unwrap();
+        increase_channel_balance(
+            deps.as_mut().storage,
+            channel,
+            &ibc_denom,
+            Uint128::from(10000u128),
+        )
+        .unwrap();
+        // sends a packet and lets the ibc module give it the sequence
+        let send_packet = |deps: DepsMut, sequence: u64| -> IbcPacket {
+            let mut deps = deps;
+            let res = execute(
+                deps.branch(),
+                mock_env(),
+                mock_info("sender", &coins(100, "ibc/atom")),
+                ExecuteMsg::TransferToRemote(TransferBackMsg {
+                    local_channel_id: channel.to_string(),
+                    remote_address: "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0".to_string(),
+                    remote_denom: "uatom".to_string(),
+                    timeout: None,
+                    memo: None,
+                    packet_timeout: None,
+                    swap_to: None,
+                    min_out: None,
+                }),
+            )
+            .unwrap();
+            reply(
+                deps,
+                mock_env(),
+                Reply {
+                    id: res.messages[0].id,
+                    result: SubMsgResult::Ok(SubMsgResponse {
+                        events: vec![Event::new("send_packet")
+                            .add_attribute("packet_sequence", sequence.to_string())],
+                        data: None,
+                    }),
+                },
+            )
+            .unwrap();
+            let data = match &res.messages[0].msg {
+                CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => data.clone(),
+                msg => panic!("Unexpected message: {:?}", msg),
+            };
+            IbcPacket::new(
+                data,
+                IbcEndpoint {
+                    port_id: CONTRACT_PORT.to_string(),
+                    channel_id: channel.to_string(),
+                },
+                IbcEndpoint {
+                    port_id: REMOTE_PORT.to_string(),
+                    channel_id: "channel-1234".to_string(),
+                },
+                sequence,
+                Timestamp::from_seconds(1665321069).into(),
+            )
+        };
+        let ack = |deps: DepsMut, packet: IbcPacket, relayer: &str| {
+            ibc_packet_ack(
+                deps,
+                mock_env(),
+                IbcPacketAckMsg::new(
+                    IbcAcknowledgement::new(to_binary(&Ics20Ack::Result(b"1".into())).unwrap()),
+                    packet,
+                    Addr::unchecked(relayer),
+                ),
+            )
+            .unwrap();
+        };
+        let balances = |deps: Deps, relayer: &str| -> Vec<Amount> {
+            query_relayer_balances(deps, relayer.to_string())
+                .unwrap()
+                .balances
         };
 
-        let msg = ExecuteMsg::TransferToRemote(transfer.clone());
+        // only the admin registers relayers
+        let register = ExecuteMsg::RegisterRelayer {
+            relayer: "relayer".to_string(),
+            payout: Some("payout".to_string()),
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("relayer", &[]),
+            register.clone(),
+        )
+        .unwrap_err();
+        execute(deps.as_mut(), mock_env(), mock_info("gov", &[]), register).unwrap();
+        assert_eq!(
+            list_relayers(deps.as_ref(), None, None, None)
+                .unwrap()
+                .relayers,
+            vec![RelayerResponse {
+                relayer: Addr::unchecked("relayer"),
+                payout: Addr::unchecked("payout"),
+            }]
+        );
 
-        // insufficient funds case because we need to receive from remote chain first
-        let info = mock_info(cw20_raw_denom, &[coin(amount, denom)]);
-        let res = execute(deps.as_mut(), mock_env(), info.clone(), msg.clone());
+        // the relayer fee is held until the ack, then credited to the relayer that delivered it
+        let packet = send_packet(deps.as_mut(), 1);
+        assert_eq!(balances(deps.as_ref(), "relayer"), vec![]);
+        ack(deps.as_mut(), packet, "relayer");
         assert_eq!(
-            res.unwrap_err(),
-            ContractError::NoSuchChannelState {
-                id: local_channel.to_string(),
-                denom: ibc_denom.clone()
-            }
+            balances(deps.as_ref(), "relayer"),
+            vec![Amount::native(10, "ibc/atom")]
         );
 
-        // prepare some mock packets
-        let recv_packet =
-            mock_receive_packet(remote_channel, local_channel, amount, denom, custom_addr);
+        // a timed out packet gives its relayer fee to the relayer fee receiver
+        let packet = send_packet(deps.as_mut(), 2);
+        ibc_packet_timeout(
+            deps.as_mut(),
+            mock_env(),
+            IbcPacketTimeoutMsg::new(packet, Addr::unchecked("relayer")),
+        )
+        .unwrap();
+        assert_eq!(
+            query_accumulated_fees(deps.as_ref()).unwrap().relayer_fees,
+            vec![Amount::native(10, "ibc/atom")]
+        );
 
-        // receive some tokens. Assume that the function works perfectly because the test case is elsewhere
-        let ibc_msg = IbcPacketReceiveMsg::new(recv_packet.clone(), relayer);
-        ibc_packet_receive(deps.as_mut(), mock_env(), ibc_msg).unwrap();
-        // need to trigger increase channel balance because it is executed through submsg
+        // unregistered relayers earn their fees too, unless the policy sends them to the treasury
+        let packet = send_packet(deps.as_mut(), 3);
+        ack(deps.as_mut(), packet, "other_relayer");
+        assert_eq!(
+            balances(deps.as_ref(), "other_relayer"),
+            vec![Amount::native(10, "ibc/atom")]
+        );
         execute(
             deps.as_mut(),
             mock_env(),
-            mock_info(mock_env().contract.address.as_str(), &[]),
-            ExecuteMsg::IncreaseChannelBalanceIbcReceive {
-                dest_channel_id: local_channel.to_string(),
-                ibc_denom: ibc_denom.clone(),
-                amount: Uint128::from(amount),
-                local_receiver: custom_addr.to_string(),
+            mock_info("gov", &[]),
+            ExecuteMsg::SetUnregisteredRelayerPolicy {
+                policy: UnregisteredRelayerPolicy::Treasury {},
             },
         )
         .unwrap();
-
-        // error cases
-        // revert transfer state to correct state
-        transfer.local_channel_id = local_channel.to_string();
-        let msg: ExecuteMsg = ExecuteMsg::TransferToRemote(transfer.clone());
-
-        // now we execute transfer back to remote chain
-        let res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
-
-        assert_eq!(res.messages[0].gas_limit, None);
-        println!("res messages: {:?}", res.messages);
-        assert_eq!(2, res.messages.len()); // 2 because it also has deduct fee msg
-        match res.messages[1].msg.clone() {
-            CosmosMsg::Ibc(IbcMsg::SendPacket {
-                channel_id,
-                data,
-                timeout,
-            }) => {
-                let expected_timeout = mock_env().block.time.plus_seconds(DEFAULT_TIMEOUT);
-                assert_eq!(timeout, expected_timeout.into());
-                assert_eq!(channel_id.as_str(), local_channel);
-                let msg: Ics20Packet = from_binary(&data).unwrap();
-                assert_eq!(
-                    msg.amount,
-                    Uint128::new(1234567).sub(Uint128::from(fee_amount))
-                );
-                assert_eq!(
-                    msg.denom.as_str(),
-                    get_key_ics20_ibc_denom(CONTRACT_PORT, local_channel, denom)
-                );
-                assert_eq!(msg.sender.as_str(), original_sender);
-                assert_eq!(msg.receiver.as_str(), remote_address);
-                // assert_eq!(msg.memo, None);
-            }
-            _ => panic!("Unexpected return message: {:?}", res.messages[0]),
-        }
-        match res.messages[0].msg.clone() {
-            CosmosMsg::Bank(BankMsg::Send {
-                to_address,
-                amount: message_amount,
-            }) => {
-                assert_eq!(to_address, "gov".to_string());
-                assert_eq!(message_amount, coins(fee_amount.u128(), denom));
-            }
-            _ => panic!("Unexpected return message: {:?}", res.messages[0]),
-        }
-
-        // check new channel state after reducing balance
-        let chan = query_channel(deps.as_ref(), local_channel.into()).unwrap();
+        let packet = send_packet(deps.as_mut(), 4);
+        ack(deps.as_mut(), packet, "other_relayer");
         assert_eq!(
-            chan.balances,
-            vec![Amount::native(
-                fee_amount.u128(),
-                &get_key_ics20_ibc_denom(CONTRACT_PORT, local_channel, denom)
-            )]
+            balances(deps.as_ref(), "other_relayer"),
+            vec![Amount::native(10, "ibc/atom")]
         );
         assert_eq!(
-            chan.total_sent,
-            vec![Amount::native(
-                amount,
-                &get_key_ics20_ibc_denom(CONTRACT_PORT, local_channel, denom)
-            )]
+            query_accumulated_fees(deps.as_ref()).unwrap().relayer_fees,
+            vec![Amount::native(20, "ibc/atom")]
         );
 
-        // mapping pair error with wrong voucher denom
-        let pair = UpdatePairMsg {
-            local_channel_id: "not_registered_channel".to_string(),
-            denom: denom.to_string(),
-            local_asset_info: AssetInfo::Token {
-                contract_addr: Addr::unchecked("random_cw20_denom".to_string()),
+        // incoming packets credit the relayer that delivered them
+        let packet = IbcPacket::new(
+            to_binary(&Ics20Packet {
+                denom: "uatom".to_string(),
+                amount: Uint128::from(100u128),
+                sender: "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0".to_string(),
+                receiver: "receiver".to_string(),
+                memo: None,
+            })
+            .unwrap(),
+            IbcEndpoint {
+                port_id: REMOTE_PORT.to_string(),
+                channel_id: "channel-1234".to_string(),
             },
-            remote_decimals: 18u8,
-            local_asset_info_decimals: 18u8,
-        };
+            IbcEndpoint {
+                port_id: CONTRACT_PORT.to_string(),
+                channel_id: channel.to_string(),
+            },
+            5,
+            Timestamp::from_seconds(1665321069).into(),
+        );
+        receive_and_reply(
+            &mut deps,
+            mock_env(),
+            IbcPacketReceiveMsg::new(packet, Addr::unchecked("relayer")),
+        );
+        assert_eq!(
+            balances(deps.as_ref(), "relayer"),
+            vec![Amount::native(20, "ibc/atom")]
+        );
+
+        // claims are paid out to the payout address
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("relayer", &[]),
+            ExecuteMsg::ClaimRelayerFees {},
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: "payout".to_string(),
+                amount: coins(20, "ibc/atom"),
+            })]
+        );
+        assert_eq!(balances(deps.as_ref(), "relayer"), vec![]);
 
+        // a removed relayer keeps what it already earned
         execute(
             deps.as_mut(),
             mock_env(),
             mock_info("gov", &[]),
-            ExecuteMsg::UpdateMappingPair(pair),
+            ExecuteMsg::RemoveRelayer {
+                relayer: "relayer".to_string(),
+            },
         )
         .unwrap();
-
-        transfer.local_channel_id = "not_registered_channel".to_string();
-        let invalid_msg = ExecuteMsg::TransferToRemote(transfer);
-        let err = execute(deps.as_mut(), mock_env(), info.clone(), invalid_msg).unwrap_err();
-        assert_eq!(err, ContractError::MappingPairNotFound {});
-    }
-
-    #[test]
-    fn test_update_config() {
-        // arrange
-        let mut deps = setup(&[], &[]);
-        let new_config = ExecuteMsg::UpdateConfig {
-            admin: Some("helloworld".to_string()),
-            default_timeout: Some(1),
-            default_gas_limit: None,
-            fee_denom: Some("hehe".to_string()),
-            swap_router_contract: Some("new_router".to_string()),
-            token_fee: Some(vec![
-                TokenFee {
-                    token_denom: "orai".to_string(),
-                    ratio: Ratio {
-                        nominator: 1,
-                        denominator: 10,
-                    },
-                },
-                TokenFee {
-                    token_denom: "atom".to_string(),
-                    ratio: Ratio {
-                        nominator: 1,
-                        denominator: 5,
-                    },
-                },
-            ]),
-            relayer_fee: Some(vec![RelayerFee {
-                prefix: "foo".to_string(),
-                fee: Uint128::from(1000000u64),
-            }]),
-            fee_receiver: Some("token_fee_receiver".to_string()),
-            relayer_fee_receiver: Some("relayer_fee_receiver".to_string()),
-        };
-        // unauthorized case
-        let unauthorized_info = mock_info(&String::from("somebody"), &[]);
-        let is_err = execute(
+        let res = execute(
             deps.as_mut(),
             mock_env(),
-            unauthorized_info,
-            new_config.clone(),
+            mock_info("other_relayer", &[]),
+            ExecuteMsg::ClaimRelayerFees {},
         )
-        .is_err();
-        assert_eq!(is_err, true);
-        // valid case
-        let info = mock_info(&String::from("gov"), &[]);
-        execute(deps.as_mut(), mock_env(), info, new_config).unwrap();
-        let config: ConfigResponse =
-            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap()).unwrap();
-        assert_eq!(config.default_gas_limit, None);
-        assert_eq!(config.default_timeout, 1);
-        assert_eq!(config.fee_denom, "hehe".to_string());
-        assert_eq!(config.swap_router_contract, "new_router".to_string());
-        assert_eq!(
-            config.relayer_fee_receiver,
-            Addr::unchecked("relayer_fee_receiver")
-        );
+        .unwrap();
         assert_eq!(
-            config.token_fee_receiver,
-            Addr::unchecked("token_fee_receiver")
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: "other_relayer".to_string(),
+                amount: coins(10, "ibc/atom"),
+            })]
         );
-        assert_eq!(config.token_fees.len(), 2usize);
-        assert_eq!(config.token_fees[0].ratio.denominator, 5);
-        assert_eq!(config.token_fees[0].token_denom, "atom".to_string());
-        assert_eq!(config.token_fees[1].ratio.denominator, 10);
-        assert_eq!(config.token_fees[1].token_denom, "orai".to_string());
-        assert_eq!(config.relayer_fees.len(), 1);
-        assert_eq!(config.relayer_fees[0].prefix, "foo".to_string());
-        assert_eq!(config.relayer_fees[0].amount, Uint128::from(1000000u64));
+        assert!(list_relayers(deps.as_ref(), None, None, None)
+            .unwrap()
+            .relayers
+            .is_empty());
     }
 
     #[test]
-    fn test_asset_info() {
-        let asset_info = AssetInfo::NativeToken {
-            denom: "orai".to_string(),
+    fn test_swap_routes() {
+        let mut deps = setup(&["channel-0"], &[]);
+        let native = |denom: &str| AssetInfo::NativeToken {
+            denom: denom.to_string(),
         };
-        assert_eq!(asset_info.to_string(), "orai".to_string());
-        let asset_info = AssetInfo::Token {
-            contract_addr: Addr::unchecked("oraiaxbc".to_string()),
+        let swap = |offer: &str, ask: &str| SwapOperation::OraiSwap {
+            offer_asset_info: native(offer),
+            ask_asset_info: native(ask),
         };
-        assert_eq!(asset_info.to_string(), "oraiaxbc".to_string())
-    }
-
-    #[test]
-    fn test_handle_packet_refund() {
-        let local_channel_id = "channel-0";
-        let mut deps = setup(&[local_channel_id], &[]);
-        let native_denom = "cosmos";
-        let amount = Uint128::from(100u128);
-        let sender = "sender";
-        let local_asset_info = AssetInfo::NativeToken {
-            denom: "orai".to_string(),
+        let route = |deps: Deps, offer: &str, ask: &str| -> SwapRouteResponse {
+            from_binary(
+                &query(
+                    deps,
+                    mock_env(),
+                    QueryMsg::SwapRoute {
+                        offer_asset_info: native(offer),
+                        ask_asset_info: native(ask),
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
+        let set_route = |deps: DepsMut, sender: &str, operations: Option<Vec<SwapOperation>>| {
+            execute(
+                deps,
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::SetSwapRoute {
+                    offer_asset_info: native("atom"),
+                    ask_asset_info: native("usdt"),
+                    operations,
+                },
+            )
         };
-        let mapping_denom = format!("wasm.cosmos2contract/{}/{}", local_channel_id, native_denom);
 
-        let result =
-            handle_packet_refund(deps.as_mut().storage, sender, native_denom, amount).unwrap_err();
+        // without a registered route the swap goes through the fee denom
         assert_eq!(
-            result.to_string(),
-            "cw_ics20::state::MappingMetadata not found"
+            route(deps.as_ref(), "atom", "usdt"),
+            SwapRouteResponse {
+                operations: vec![swap("atom", "orai"), swap("orai", "usdt")],
+                registered: false,
+            }
         );
 
-        // update mapping pair so that we can get refunded
-        // cosmos based case with mapping found. Should be successful & cosmos msg is ibc send packet
-        // add a pair mapping so we can test the happy case evm based happy case
-        let update = UpdatePairMsg {
-            local_channel_id: local_channel_id.to_string(),
-            denom: native_denom.to_string(),
-            local_asset_info: local_asset_info.clone(),
-            remote_decimals: 6,
-            local_asset_info_decimals: 6,
-        };
-
-        let msg = ExecuteMsg::UpdateMappingPair(update.clone());
-
-        let info = mock_info("gov", &coins(1234567, "ucosm"));
-        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+        assert_eq!(
+            set_route(deps.as_mut(), "foo", Some(vec![swap("atom", "usdt")])).unwrap_err(),
+            ContractError::Admin(AdminError::NotAdmin {})
+        );
+        // routes that do not chain from the offer to the ask asset are rejected
+        for operations in [
+            vec![],
+            vec![swap("atom", "usdc")],
+            vec![swap("orai", "usdt")],
+            vec![swap("atom", "usdc"), swap("orai", "usdt")],
+            vec![swap("atom", "atom"), swap("atom", "usdt")],
+        ] {
+            assert_eq!(
+                set_route(deps.as_mut(), "gov", Some(operations)).unwrap_err(),
+                ContractError::InvalidSwapRoute {
+                    offer: "atom".to_string(),
+                    ask: "usdt".to_string(),
+                }
+            );
+        }
 
-        // now we handle packet failure. should get sub msg
-        let result =
-            handle_packet_refund(deps.as_mut().storage, sender, &mapping_denom, amount).unwrap();
+        // a direct pool skips the fee denom, a stable pool hop is allowed too
+        set_route(deps.as_mut(), "gov", Some(vec![swap("atom", "usdt")])).unwrap();
         assert_eq!(
-            result,
-            SubMsg::reply_on_error(
-                CosmosMsg::Bank(BankMsg::Send {
-                    to_address: sender.to_string(),
-                    amount: coins(amount.u128(), "orai")
-                }),
-                REFUND_FAILURE_ID
-            )
+            route(deps.as_ref(), "atom", "usdt"),
+            SwapRouteResponse {
+                operations: vec![swap("atom", "usdt")],
+                registered: true,
+            }
+        );
+        set_route(
+            deps.as_mut(),
+            "gov",
+            Some(vec![swap("atom", "usdc"), swap("usdc", "usdt")]),
+        )
+        .unwrap();
+        assert_eq!(
+            route(deps.as_ref(), "atom", "usdt").operations,
+            vec![swap("atom", "usdc"), swap("usdc", "usdt")]
         );
+        // routes are directed
+        assert!(!route(deps.as_ref(), "usdt", "atom").registered);
+
+        set_route(deps.as_mut(), "gov", None).unwrap();
+        assert!(!route(deps.as_ref(), "atom", "usdt").registered);
     }
 
     #[test]
-    fn test_increase_channel_balance_ibc_receive() {
-        let local_channel_id = "channel-0";
-        let amount = Uint128::from(10u128);
-        let ibc_denom = "foobar";
-        let local_receiver = "receiver";
-        let mut deps = setup(&[local_channel_id], &[]);
+    fn test_swap_then_transfer() {
+        let channel = "channel-0";
+        let ibc_denom = get_key_ics20_ibc_denom(CONTRACT_PORT, channel, "uatom");
+        let atom = AssetInfo::NativeToken {
+            denom: "ibc/atom".to_string(),
+        };
+        let orai = AssetInfo::NativeToken {
+            denom: "orai".to_string(),
+        };
+        let mut deps = setup(&[channel], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                local_channel_id: channel.to_string(),
+                denom: "uatom".to_string(),
+                local_asset_info: atom.clone(),
+                remote_decimals: 6,
+                local_asset_info_decimals: 6,
+            }),
+        )
+        .unwrap();
+        increase_channel_balance(
+            deps.as_mut().storage,
+            channel,
+            &ibc_denom,
+            Uint128::from(1000u128),
+        )
+        .unwrap();
+        let transfer = |swap_to: AssetInfo, remote_denom: &str| {
+            ExecuteMsg::TransferToRemote(TransferBackMsg {
+                local_channel_id: channel.to_string(),
+                remote_address: "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0".to_string(),
+                remote_denom: remote_denom.to_string(),
+                timeout: None,
+                memo: None,
+                packet_timeout: None,
+                swap_to: Some(swap_to),
+                min_out: Some(Uint128::from(90u128)),
+            })
+        };
+
+        // the swapped asset must be mapped to the remote denom before anything is swapped
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("sender", &coins(100, "orai")),
+            transfer(atom.clone(), "uosmo"),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::MappingPairNotFound {});
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("sender", &[coin(100, "orai"), coin(100, "uusdt")]),
+            transfer(atom.clone(), "uatom"),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::SwapWithSeveralCoins);
 
+        // the sent tokens are swapped into the asset of the mapping first
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("sender", &coins(100, "orai")),
+            transfer(atom.clone(), "uatom"),
+        )
+        .unwrap();
         assert_eq!(
-            execute(
-                deps.as_mut(),
-                mock_env(),
-                mock_info("attacker", &vec![]),
-                ExecuteMsg::IncreaseChannelBalanceIbcReceive {
-                    dest_channel_id: local_channel_id.to_string(),
-                    ibc_denom: ibc_denom.to_string(),
-                    amount: amount.clone(),
-                    local_receiver: local_receiver.to_string(),
-                },
-            )
-            .unwrap_err(),
-            ContractError::Std(StdError::generic_err("Caller is not the contract itself!"))
+            res.messages,
+            vec![SubMsg::reply_always(
+                RouterController("router".to_string())
+                    .execute_operations(
+                        orai.clone(),
+                        Uint128::from(100u128),
+                        vec![SwapOperation::OraiSwap {
+                            offer_asset_info: orai,
+                            ask_asset_info: atom.clone(),
+                        }],
+                        Some(Uint128::from(90u128)),
+                        None,
+                    )
+                    .unwrap(),
+                encode_reply_id(SWAP_TRANSFER_ID, 1)
+            )]
         );
 
-        execute(
+        // once swapped, the packet carries the swapped amount
+        let res = reply(
             deps.as_mut(),
             mock_env(),
-            mock_info(mock_env().contract.address.as_str(), &vec![]),
-            ExecuteMsg::IncreaseChannelBalanceIbcReceive {
-                dest_channel_id: local_channel_id.to_string(),
-                ibc_denom: ibc_denom.to_string(),
-                amount: amount.clone(),
-                local_receiver: local_receiver.to_string(),
+            Reply {
+                id: encode_reply_id(SWAP_TRANSFER_ID, 1),
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![Event::new("wasm").add_attribute("return_amount", "95")],
+                    data: None,
+                }),
             },
         )
         .unwrap();
-        let channel_state = CHANNEL_REVERSE_STATE
-            .load(deps.as_ref().storage, (local_channel_id, ibc_denom))
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(decode_reply_id(res.messages[0].id).0, SEND_PACKET_ID);
+        assert!(res.attributes.contains(&attr("swap_return_amount", "95")));
+        let balance = CHANNEL_REVERSE_STATE
+            .load(deps.as_ref().storage, (channel, &ibc_denom))
             .unwrap();
-        assert_eq!(channel_state.outstanding, amount.clone());
-        assert_eq!(channel_state.total_sent, amount.clone());
-        let reply_args = REPLY_ARGS.load(deps.as_ref().storage).unwrap();
-        assert_eq!(reply_args.amount, amount.clone());
-        assert_eq!(reply_args.channel, local_channel_id.clone());
-        assert_eq!(reply_args.denom, ibc_denom.to_string());
-        assert_eq!(reply_args.local_receiver, local_receiver.to_string());
-    }
+        assert_eq!(balance.outstanding, Uint128::from(905u128));
 
-    #[test]
-    fn test_reduce_channel_balance_ibc_receive() {
-        let local_channel_id = "channel-0";
-        let amount = Uint128::from(10u128);
-        let ibc_denom = "foobar";
-        let local_receiver = "receiver";
-        let mut deps = setup(&[local_channel_id], &[]);
+        // a failed swap refunds the sent tokens
         execute(
             deps.as_mut(),
             mock_env(),
-            mock_info(mock_env().contract.address.as_str(), &vec![]),
-            ExecuteMsg::IncreaseChannelBalanceIbcReceive {
-                dest_channel_id: local_channel_id.to_string(),
-                ibc_denom: ibc_denom.to_string(),
-                amount: amount.clone(),
-                local_receiver: local_receiver.to_string(),
+            mock_info("sender", &coins(100, "orai")),
+            transfer(atom, "uatom"),
+        )
+        .unwrap();
+        let res = reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: encode_reply_id(SWAP_TRANSFER_ID, 3),
+                result: SubMsgResult::Err("assertion failed; minimum receive".to_string()),
             },
         )
         .unwrap();
-
         assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: "sender".to_string(),
+                amount: coins(100, "orai"),
+            })]
+        );
+        assert!(SWAP_TRANSFERS.is_empty(deps.as_ref().storage));
+    }
+
+    #[test]
+    fn test_batch_transfer_to_remote() {
+        let atom = AssetInfo::NativeToken {
+            denom: "ibc/atom".to_string(),
+        };
+        let mut deps = setup(&["channel-0", "channel-1"], &[]);
+        for channel in ["channel-0", "channel-1"] {
             execute(
                 deps.as_mut(),
                 mock_env(),
-                mock_info("attacker", &vec![]),
-                ExecuteMsg::ReduceChannelBalanceIbcReceive {
-                    src_channel_id: local_channel_id.to_string(),
-                    ibc_denom: ibc_denom.to_string(),
-                    amount: amount.clone(),
-                    local_receiver: local_receiver.to_string(),
-                },
+                mock_info("gov", &[]),
+                ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                    local_channel_id: channel.to_string(),
+                    denom: "uatom".to_string(),
+                    local_asset_info: atom.clone(),
+                    remote_decimals: 6,
+                    local_asset_info_decimals: 6,
+                }),
+            )
+            .unwrap();
+            increase_channel_balance(
+                deps.as_mut().storage,
+                channel,
+                &get_key_ics20_ibc_denom(CONTRACT_PORT, channel, "uatom"),
+                Uint128::from(1000u128),
             )
-            .unwrap_err(),
-            ContractError::Std(StdError::generic_err("Caller is not the contract itself!"))
+            .unwrap();
+        }
+        let leg = |channel: &str, amount: u128| TransferLeg {
+            amount: Uint128::from(amount),
+            msg: TransferBackMsg {
+                local_channel_id: channel.to_string(),
+                remote_address: "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0".to_string(),
+                remote_denom: "uatom".to_string(),
+                timeout: None,
+                memo: None,
+                packet_timeout: None,
+                swap_to: None,
+                min_out: None,
+            },
+        };
+        let batch = |legs: Vec<TransferLeg>| {
+            ExecuteMsg::BatchTransferToRemote(BatchTransferBackMsg { legs })
+        };
+
+        // the legs must add up to the sent coin
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("sender", &coins(100, "ibc/atom")),
+            batch(vec![leg("channel-0", 60), leg("channel-1", 30)]),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::BatchAmountMismatch {
+                sent: Uint128::from(100u128),
+                legs: Uint128::from(90u128)
+            }
         );
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("sender", &coins(100, "ibc/atom")),
+            batch(vec![leg("channel-0", 100), leg("channel-1", 0)]),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NoFunds {});
+        // one leg that cannot be sent fails the whole batch
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("sender", &coins(100, "ibc/atom")),
+            batch(vec![leg("channel-0", 60), leg("channel-9", 40)]),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::MappingPairNotFound {});
 
-        execute(
+        // each leg is its own packet
+        let res = execute(
             deps.as_mut(),
             mock_env(),
-            mock_info(mock_env().contract.address.as_str(), &vec![]),
-            ExecuteMsg::ReduceChannelBalanceIbcReceive {
-                src_channel_id: local_channel_id.to_string(),
-                ibc_denom: ibc_denom.to_string(),
-                amount: amount.clone(),
-                local_receiver: local_receiver.to_string(),
-            },
+            mock_info("sender", &coins(100, "ibc/atom")),
+            batch(vec![leg("channel-0", 60), leg("channel-1", 40)]),
         )
         .unwrap();
-        let channel_state = CHANNEL_REVERSE_STATE
-            .load(deps.as_ref().storage, (local_channel_id, ibc_denom))
-            .unwrap();
-        assert_eq!(channel_state.outstanding, Uint128::zero());
-        assert_eq!(channel_state.total_sent, Uint128::from(10u128));
-        let reply_args = REPLY_ARGS.load(deps.as_ref().storage).unwrap();
-        assert_eq!(reply_args.amount, amount.clone());
-        assert_eq!(reply_args.channel, local_channel_id.clone());
-        assert_eq!(reply_args.denom, ibc_denom.to_string());
-        assert_eq!(reply_args.local_receiver, local_receiver.to_string());
+        assert_eq!(res.messages.len(), 2);
+        for (message, (channel, amount)) in res
+            .messages
+            .iter()
+            .zip([("channel-0", 60u128), ("channel-1", 40u128)])
+        {
+            assert_eq!(decode_reply_id(message.id).0, SEND_PACKET_ID);
+            if let CosmosMsg::Ibc(IbcMsg::SendPacket {
+                channel_id, data, ..
+            }) = &message.msg
+            {
+                assert_eq!(channel_id, channel);
+                let packet: Ics20Packet = from_binary(data).unwrap();
+                assert_eq!(packet.amount, Uint128::from(amount));
+            } else {
+                panic!("Unexpected return message: {:?}", message.msg);
+            }
+        }
+
+        // cw20 tokens are batched with a Receive
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("token", &[]),
+            ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: "sender".to_string(),
+                amount: Uint128::from(100u128),
+                msg: to_binary(&BatchTransferBackMsg {
+                    legs: vec![leg("channel-0", 50)],
+                })
+                .unwrap(),
+            }),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::BatchAmountMismatch {
+                sent: Uint128::from(100u128),
+                legs: Uint128::from(50u128)
+            }
+        );
     }
 
     #[test]
-    fn test_query_channel_balance_with_key() {
-        // fixture
-        let channel = "foo-channel";
-        let ibc_denom = "port/channel/denom";
-        let amount = Uint128::from(10u128);
-        let reduce_amount = Uint128::from(1u128);
+    fn test_packet_timeouts() {
+        let channel = "channel-0";
         let mut deps = setup(&[channel], &[]);
-        increase_channel_balance(deps.as_mut().storage, channel, ibc_denom, amount).unwrap();
-        reduce_channel_balance(
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                local_channel_id: channel.to_string(),
+                denom: "uatom".to_string(),
+                local_asset_info: AssetInfo::NativeToken {
+                    denom: "ibc/atom".to_string(),
+                },
+                remote_decimals: 6,
+                local_asset_info_decimals: 6,
+            }),
+        )
+        .unwrap();
+        increase_channel_balance(
             deps.as_mut().storage,
             channel,
-            ibc_denom,
-            Uint128::from(1u128),
+            &get_key_ics20_ibc_denom(CONTRACT_PORT, channel, "uatom"),
+            Uint128::from(1000u128),
         )
         .unwrap();
+        let now = mock_env().block.time;
+        let block = IbcTimeoutBlock {
+            revision: 1,
+            height: 12345,
+        };
+        let transfer =
+            |deps: DepsMut, timeout: Option<u64>, packet_timeout: Option<PacketTimeout>| {
+                execute(
+                    deps,
+                    mock_env(),
+                    mock_info("sender", &coins(10, "ibc/atom")),
+                    ExecuteMsg::TransferToRemote(TransferBackMsg {
+                        local_channel_id: channel.to_string(),
+                        remote_address: "cosmos1603j3e4juddh7cuhfquxspl0p0nsun046us7n0".to_string(),
+                        remote_denom: "uatom".to_string(),
+                        timeout,
+                        memo: None,
+                        packet_timeout,
+                        swap_to: None,
+                        min_out: None,
+                    }),
+                )
+                .map(|res| match &res.messages[0].msg {
+                    CosmosMsg::Ibc(IbcMsg::SendPacket { timeout, .. }) => timeout.clone(),
+                    msg => panic!("Unexpected return message: {:?}", msg),
+                })
+            };
 
-        let result =
-            query_channel_with_key(deps.as_ref(), channel.to_string(), ibc_denom.to_string())
-                .unwrap();
+        // the sender picks the block time, or the block height along with it
         assert_eq!(
-            result.balance,
-            Amount::from_parts(
-                ibc_denom.to_string(),
-                amount.checked_sub(reduce_amount).unwrap()
+            transfer(deps.as_mut(), Some(100), None).unwrap(),
+            IbcTimeout::with_timestamp(now.plus_seconds(100))
+        );
+        assert_eq!(
+            transfer(
+                deps.as_mut(),
+                None,
+                Some(PacketTimeout::Absolute {
+                    timestamp: now.plus_seconds(500)
+                })
             )
+            .unwrap(),
+            IbcTimeout::with_timestamp(now.plus_seconds(500))
         );
         assert_eq!(
-            result.total_sent,
-            Amount::from_parts(ibc_denom.to_string(), amount)
+            transfer(deps.as_mut(), None, Some(PacketTimeout::Height { block })).unwrap_err(),
+            ContractError::InvalidTimeout {
+                reason: "a block height needs a timestamp, use height or timestamp".to_string()
+            }
         );
-    }
-
-    #[test]
-    fn test_handle_override_channel_balance() {
-        // fixture
-        let channel = "foo-channel";
-        let ibc_denom = "port/channel/denom";
-        let amount = Uint128::from(10u128);
-        let override_amount = Uint128::from(100u128);
-        let total_sent_override = Uint128::from(1000u128);
-        let mut deps = setup(&[channel], &[]);
-        increase_channel_balance(deps.as_mut().storage, channel, ibc_denom, amount).unwrap();
+        assert_eq!(
+            transfer(
+                deps.as_mut(),
+                None,
+                Some(PacketTimeout::HeightOrTimestamp {
+                    block,
+                    timestamp: now.plus_seconds(500)
+                })
+            )
+            .unwrap(),
+            IbcTimeout::with_both(block, now.plus_seconds(500))
+        );
+        assert!(matches!(
+            transfer(
+                deps.as_mut(),
+                Some(100),
+                Some(PacketTimeout::Relative { seconds: 100 })
+            ),
+            Err(ContractError::InvalidTimeout { .. })
+        ));
+        assert!(matches!(
+            transfer(
+                deps.as_mut(),
+                None,
+                Some(PacketTimeout::Absolute {
+                    timestamp: now.minus_seconds(1)
+                })
+            ),
+            Err(ContractError::InvalidTimeout { .. })
+        ));
+        assert!(matches!(
+            transfer(
+                deps.as_mut(),
+                None,
+                Some(PacketTimeout::HeightOrTimestamp {
+                    block: IbcTimeoutBlock {
+                        revision: 1,
+                        height: 0
+                    },
+                    timestamp: now.plus_seconds(500)
+                })
+            ),
+            Err(ContractError::InvalidTimeout { .. })
+        ));
 
-        // unauthorized case
-        let unauthorized = handle_override_channel_balance(
+        // the admin bounds the seconds a packet lives
+        execute(
             deps.as_mut(),
-            mock_info("attacker", &vec![]),
-            channel.to_string(),
-            ibc_denom.to_string(),
-            amount,
-            None,
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::SetTimeoutBounds {
+                min_timeout: 60,
+                max_timeout: 3600,
+            },
         )
-        .unwrap_err();
-        assert_eq!(unauthorized, ContractError::Admin(AdminError::NotAdmin {}));
+        .unwrap();
+        assert!(matches!(
+            transfer(deps.as_mut(), Some(30), None),
+            Err(ContractError::InvalidTimeout { .. })
+        ));
+        assert!(matches!(
+            transfer(
+                deps.as_mut(),
+                None,
+                Some(PacketTimeout::Absolute {
+                    timestamp: now.plus_seconds(7200)
+                })
+            ),
+            Err(ContractError::InvalidTimeout { .. })
+        ));
+        // with a block height too, the timestamp keeps the packet within the bounds
+        assert!(matches!(
+            transfer(
+                deps.as_mut(),
+                None,
+                Some(PacketTimeout::HeightOrTimestamp {
+                    block,
+                    timestamp: now.plus_seconds(7200)
+                })
+            ),
+            Err(ContractError::InvalidTimeout { .. })
+        ));
+        transfer(deps.as_mut(), Some(3600), None).unwrap();
 
-        // execution, valid case
-        handle_override_channel_balance(
+        // a channel default overrides the global default
+        assert_eq!(
+            transfer(deps.as_mut(), None, None).unwrap(),
+            IbcTimeout::with_timestamp(now.plus_seconds(DEFAULT_TIMEOUT))
+        );
+        execute(
             deps.as_mut(),
-            mock_info("gov", &vec![]),
-            channel.to_string(),
-            ibc_denom.to_string(),
-            override_amount,
-            Some(total_sent_override),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::SetChannelTimeout {
+                channel_id: channel.to_string(),
+                timeout: Some(600),
+            },
         )
         .unwrap();
-
-        // we query to validate the result after overriding
-
-        let result =
-            query_channel_with_key(deps.as_ref(), channel.to_string(), ibc_denom.to_string())
-                .unwrap();
         assert_eq!(
-            result.balance,
-            Amount::from_parts(ibc_denom.to_string(), override_amount)
+            transfer(deps.as_mut(), None, None).unwrap(),
+            IbcTimeout::with_timestamp(now.plus_seconds(600))
         );
+        let res: ChannelResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Channel {
+                    id: channel.to_string(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res.default_timeout, Some(600));
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::SetChannelTimeout {
+                channel_id: "channel-9".to_string(),
+                timeout: Some(600),
+            },
+        )
+        .unwrap_err();
         assert_eq!(
-            result.total_sent,
-            Amount::from_parts(ibc_denom.to_string(), total_sent_override)
+            err,
+            ContractError::NoSuchChannel {
+                id: "channel-9".to_string()
+            }
         );
     }
 }
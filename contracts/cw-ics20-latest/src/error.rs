@@ -2,7 +2,7 @@ use std::num::TryFromIntError;
 use std::string::FromUtf8Error;
 use thiserror::Error;
 
-use cosmwasm_std::{OverflowError, StdError};
+use cosmwasm_std::{OverflowError, StdError, Uint128};
 use cw_controllers::AdminError;
 use cw_utils::PaymentError;
 
@@ -27,6 +27,54 @@ pub enum ContractError {
     #[error("Channel doesn't exist: {id}")]
     NoSuchChannel { id: String },
 
+    #[error("Channel is closed: {id}")]
+    ChannelClosed { id: String },
+
+    #[error("Channel is still open: {id}")]
+    ChannelNotClosed { id: String },
+
+    #[error("Insufficient recoverable funds on closed channel {id}, {denom}")]
+    InsufficientRecoverableFunds { id: String, denom: String },
+
+    #[error("There is no admin proposal to accept")]
+    NoPendingAdmin,
+
+    #[error("Only the proposed admin can accept the proposal")]
+    NotPendingAdmin,
+
+    #[error("Queued change {id} cannot be executed before {eta}")]
+    TimelockNotExpired { id: u64, eta: u64 },
+
+    #[error("Cannot execute while a packet is being received")]
+    Reentrancy,
+
+    #[error("Transfers are paused on scope {scope}")]
+    Paused { scope: String },
+
+    #[error("Rate limit exceeded on channel {id} for denom {denom}")]
+    RateLimitExceeded { id: String, denom: String },
+
+    #[error("Rate limit window must be longer than zero seconds")]
+    InvalidRateLimitWindow,
+
+    #[error("No price for {asset} to convert the relayer fee")]
+    PriceUnavailable { asset: String },
+
+    #[error("Invalid token fee for {token_denom}: {reason}")]
+    InvalidTokenFee { token_denom: String, reason: String },
+
+    #[error("Fee discount must be a ratio between zero and one")]
+    InvalidFeeDiscount,
+
+    #[error("Only the admin and the fee receivers can collect the fees")]
+    NotFeeCollector,
+
+    #[error("Stuck fund {id} has already been paid out")]
+    StuckFundAlreadyPaid { id: u64 },
+
+    #[error("Only the receiver of stuck fund {id} can claim it")]
+    NotStuckFundReceiver { id: u64 },
+
     #[error("Channel state doesn't exist: {id}, {denom}")]
     NoSuchChannelState { id: String, denom: String },
 
@@ -60,6 +108,9 @@ pub enum ContractError {
     #[error("Cannot migrate from unsupported version: {previous_version}")]
     CannotMigrateVersion { previous_version: String },
 
+    #[error("The ibc module did not return the sequence of the sent packet")]
+    PacketSequenceNotFound,
+
     #[error("Got a submessage reply with unknown id: {id}")]
     UnknownReplyId { id: u64 },
 
@@ -77,6 +128,21 @@ pub enum ContractError {
 
     #[error("Could not find the mapping pair")]
     MappingPairNotFound,
+
+    #[error("The swap route must be a chain of operations from {offer} to {ask}")]
+    InvalidSwapRoute { offer: String, ask: String },
+
+    #[error("The legs of the batch add up to {legs} instead of the sent {sent}")]
+    BatchAmountMismatch { sent: Uint128, legs: Uint128 },
+
+    #[error("Invalid packet timeout: {reason}")]
+    InvalidTimeout { reason: String },
+
+    #[error("Only one coin can be swapped before a transfer")]
+    SwapWithSeveralCoins,
+
+    #[error("Invalid slippage: default {default_bps} and max {max_bps} basis points, max must be at least default and at most 10000")]
+    InvalidSlippage { default_bps: u16, max_bps: u16 },
 }
 
 impl From<FromUtf8Error> for ContractError {
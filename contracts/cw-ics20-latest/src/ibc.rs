@@ -2,27 +2,37 @@ use std::ops::Mul;
 
 use cosmwasm_schema::cw_serde;
 use cosmwasm_std::{
-    attr, coin, entry_point, from_binary, to_binary, Addr, Api, Binary, CosmosMsg, Decimal, Deps,
-    DepsMut, Env, Ibc3ChannelOpenResponse, IbcBasicResponse, IbcChannel, IbcChannelCloseMsg,
-    IbcChannelConnectMsg, IbcChannelOpenMsg, IbcEndpoint, IbcMsg, IbcOrder, IbcPacket,
-    IbcPacketAckMsg, IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse, IbcTimeout,
-    Order, QuerierWrapper, Reply, Response, StdError, StdResult, Storage, SubMsg, SubMsgResult,
-    Timestamp, Uint128,
+    attr, coin, entry_point, from_binary, to_binary, Addr, Api, Attribute, Binary, CosmosMsg,
+    Decimal, Deps, DepsMut, Env, Event, Ibc3ChannelOpenResponse, IbcBasicResponse, IbcChannel,
+    IbcChannelCloseMsg, IbcChannelConnectMsg, IbcChannelOpenMsg, IbcEndpoint, IbcMsg, IbcOrder,
+    IbcPacket, IbcPacketAckMsg, IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse,
+    IbcTimeout, Order, QuerierWrapper, Reply, ReplyOn, Response, StdError, StdResult, Storage,
+    SubMsg, SubMsgResult, Timestamp, Uint128, WasmMsg,
 };
 use cw20_ics20_msg::helper::{
     denom_to_asset_info, get_prefix_decode_bech32, parse_asset_info_denom,
 };
-use cw20_ics20_msg::receiver::DestinationInfo;
+use cw20_ics20_msg::receiver::{ContractHook, DestinationInfo, MemoError, MAX_SLIPPAGE_BPS};
 use cw_storage_plus::Map;
 use oraiswap::asset::AssetInfo;
 use oraiswap::router::{RouterController, SwapOperation};
 
+use crate::contract::handle_swap_transfer_reply;
 use crate::error::{ContractError, Never};
-use crate::msg::{ExecuteMsg, FeeData, FollowUpMsgsData};
+use crate::msg::{FeeData, FollowUpMsgsData, OraclePriceResponse, OracleQueryMsg};
 use crate::state::{
-    get_key_ics20_ibc_denom, ics20_denoms, undo_reduce_channel_balance, ChannelInfo,
-    MappingMetadata, Ratio, ALLOW_LIST, CHANNEL_INFO, CONFIG, RELAYER_FEE, REPLY_ARGS,
-    SINGLE_STEP_REPLY_ARGS, TOKEN_FEE,
+    accumulate_fee, assert_not_paused, channel_default_timeout, check_rate_limited_flow,
+    credit_relayer_fee, get_key_ics20_ibc_denom, ics20_denoms, increase_channel_balance,
+    next_reply_nonce, record_rate_limited_flow, reduce_channel_balance,
+    reduce_channel_forward_balance, release_rate_limited_outflow, undo_increase_channel_balance,
+    undo_increase_channel_forward_balance, undo_reduce_channel_balance, ChannelInfo, Config,
+    FeeExemptionSubject, FlowDirection, MappingMetadata, PayoutReplyArgs, PendingTransfer,
+    PriceFallback, PriceSource, Ratio, RecoverableFund, RelayerFeeSchedule, ReplyArgs, StuckFund,
+    SwapFollowUp, TokenFeeSchedule, ALLOW_LIST, CHANNEL_FORWARD_STATE, CHANNEL_INFO,
+    CHANNEL_RECOVERABLE_FUNDS, CHANNEL_REVERSE_STATE, CONFIG, FEE_EXEMPTIONS, FIXED_PRICES,
+    LAST_KNOWN_PRICES, PAYOUT_REPLY_ARGS, PENDING_TRANSFERS, RECEIVE_LOCK, RELAYER_FEE,
+    RELAYER_FEE_ACCUMULATOR, REPLY_ARGS, STUCK_FUNDS, SWAP_ROUTES, TOKEN_FEE,
+    TOKEN_FEE_ACCUMULATOR, UNSEQUENCED_TRANSFERS,
 };
 use cw20_ics20_msg::amount::{convert_local_to_remote, convert_remote_to_local, Amount};
 
@@ -65,11 +75,8 @@ impl Ics20Packet {
     }
 
     pub fn validate(&self) -> Result<(), ContractError> {
-        if self.amount.u128() > (u128::MAX as u128) {
-            Err(ContractError::AmountOverflow {})
-        } else {
-            Ok(())
-        }
+        // any Uint128 fits the amount of an ics20 packet, there is nothing to reject yet
+        Ok(())
     }
 }
 
@@ -100,29 +107,117 @@ pub const FOLLOW_UP_IBC_SEND_FAILURE_ID: u64 = 1339;
 pub const REFUND_FAILURE_ID: u64 = 1340;
 pub const IBC_TRANSFER_NATIVE_ERROR_ID: u64 = 1341;
 pub const SWAP_OPS_FAILURE_ID: u64 = 1342;
+pub const SEND_PACKET_ID: u64 = 1343;
+pub const HOOK_FAILURE_ID: u64 = 1344;
+pub const SWAP_TRANSFER_ID: u64 = 1345;
 pub const ACK_FAILURE_ID: u64 = 64023;
 
+// the lower bits of a reply id select the handler, the upper bits carry the nonce of the submessage's own reply state
+const REPLY_ID_KIND_BITS: u32 = 16;
+
+pub fn encode_reply_id(kind: u64, nonce: u64) -> u64 {
+    (nonce << REPLY_ID_KIND_BITS) | kind
+}
+
+pub fn decode_reply_id(id: u64) -> (u64, u64) {
+    (
+        id & ((1 << REPLY_ID_KIND_BITS) - 1),
+        id >> REPLY_ID_KIND_BITS,
+    )
+}
+
 #[entry_point]
-pub fn reply(deps: DepsMut, _env: Env, reply: Reply) -> Result<Response, ContractError> {
-    match reply.id {
-        // happens only when send cw20 amount to recipient failed. Wont refund because this case is unlikely to happen
+pub fn reply(deps: DepsMut, env: Env, reply: Reply) -> Result<Response, ContractError> {
+    let (reply_kind, nonce) = decode_reply_id(reply.id);
+    // the last submessage of a received packet has replied, execute is allowed again
+    if RECEIVE_LOCK.may_load(deps.storage)? == Some(reply.id) {
+        RECEIVE_LOCK.remove(deps.storage);
+    }
+    match reply_kind {
+        // happens only when send cw20 amount to recipient failed. Wont refund because this case is unlikely to happen, the amount is recorded as a stuck fund instead
         NATIVE_RECEIVE_ID => match reply.result {
-            SubMsgResult::Ok(_) => Ok(Response::new()),
+            SubMsgResult::Ok(_) => {
+                PAYOUT_REPLY_ARGS.remove(deps.storage, nonce);
+                Ok(Response::new())
+            }
             // we all set ack success so that the token is stuck on Oraichain, not on OraiBridge because if ack fail => token refunded on OraiBridge yet still refund on Oraichain
             // so no undo increase
             SubMsgResult::Err(err) => Ok(Response::new()
                 .set_data(ack_success())
+                .add_attributes(save_stuck_fund(deps.storage, &env, nonce, &err)?)
                 .add_attribute("action", "native_receive_id")
                 .add_attribute("error_transferring_ibc_tokens_to_cw20", err)),
         },
         // happens when swap failed. Will refund by sending to the initial receiver of the packet receive, amount is local on Oraichain & send through cw20
+        // on success, the hook or the forward of the swapped tokens is sent from here
         SWAP_OPS_FAILURE_ID => match reply.result {
-            SubMsgResult::Ok(_) => Ok(Response::new()),
+            SubMsgResult::Ok(response) => {
+                // swaps sent before the reply args kept the minimum receive have nothing to report
+                let reply_args = match REPLY_ARGS.may_load(deps.storage, nonce)? {
+                    Some(reply_args) => reply_args,
+                    None => return Ok(Response::new()),
+                };
+                REPLY_ARGS.remove(deps.storage, nonce);
+                let mut res = Response::new()
+                    .add_attribute("action", "swap_ops_success")
+                    .add_attribute("swap_minimum_receive", reply_args.minimum_receive);
+                // a router that does not report its output still gave at least the minimum receive
+                let return_amount = parse_swap_return_amount(&response.events);
+                if let Some(return_amount) = return_amount {
+                    res = res.add_attribute("swap_return_amount", return_amount);
+                }
+                let follow_up = match reply_args.follow_up {
+                    Some(follow_up) => follow_up,
+                    None => return Ok(res),
+                };
+                let swapped = Amount::from_parts(
+                    follow_up.ask_denom,
+                    return_amount.unwrap_or(reply_args.minimum_receive),
+                );
+                let default_timeout = CONFIG.load(deps.storage)?.default_timeout;
+                let sub_msgs = match &follow_up.destination.hook {
+                    Some(hook) => build_hook_sub_msg(
+                        deps.storage,
+                        hook,
+                        swapped.clone(),
+                        &reply_args.local_receiver,
+                    )
+                    .map(|sub_msg| vec![sub_msg]),
+                    None => build_ibc_msg(
+                        deps.storage,
+                        env,
+                        &reply_args.local_receiver,
+                        &follow_up.local_channel_id,
+                        swapped.amount(),
+                        &follow_up.remote_sender,
+                        &follow_up.destination,
+                        default_timeout,
+                        follow_up.pair_mapping,
+                    ),
+                };
+                res = match sub_msgs {
+                    Ok(sub_msgs) => res.add_submessages(sub_msgs),
+                    // the swapped tokens cannot be forwarded, the local receiver gets them instead
+                    Err(err) => res
+                        .add_submessage(build_payout_sub_msg(
+                            deps.storage,
+                            swapped.send_amount(reply_args.local_receiver.clone(), None),
+                            NATIVE_RECEIVE_ID,
+                            &reply_args.local_receiver,
+                            swapped.clone(),
+                        )?)
+                        .add_attribute("follow_up_error", err.to_string())
+                        .add_attribute("refund_recipient", reply_args.local_receiver),
+                };
+                // the packet is still being received until the hook or the forward replies
+                hold_receive_lock(deps.storage, &res.messages)?;
+                Ok(res)
+            }
             // we all set ack success so that the token is stuck on Oraichain, not on OraiBridge because if ack fail => token refunded on OraiBridge yet still refund on Oraichain
             // so no undo increase
             SubMsgResult::Err(err) => {
-                let reply_args = REPLY_ARGS.load(deps.storage)?;
-                REPLY_ARGS.remove(deps.storage);
+                let reply_args = REPLY_ARGS.load(deps.storage, nonce)?;
+                REPLY_ARGS.remove(deps.storage, nonce);
                 let sub_msg = handle_packet_refund(
                     deps.storage,
                     &reply_args.local_receiver,
@@ -134,30 +229,32 @@ pub fn reply(deps: DepsMut, _env: Env, reply: Reply) -> Result<Response, Contrac
                     .set_data(ack_success())
                     .add_submessage(sub_msg)
                     .add_attribute("action", "swap_ops_failure_id")
-                    .add_attribute("error_swap_ops", err))
+                    .add_attribute("error_swap_ops", err)
+                    .add_attribute("refund_recipient", reply_args.local_receiver))
             }
         },
         // happens when failed to ibc send the packet to another chain after receiving the packet from the first remote chain.
         // also when swap is successful. Will refund similarly to swap ops
         FOLLOW_UP_IBC_SEND_FAILURE_ID => match reply.result {
-            SubMsgResult::Ok(_) => Ok(Response::new()),
+            // failing here would revert the whole receive. Without a sequence the forward stays unsequenced for the admin to recover
+            SubMsgResult::Ok(response) => match parse_packet_sequence(&response.events) {
+                Some(sequence) => save_pending_transfer(deps.storage, nonce, sequence),
+                None => {
+                    let transfer = UNSEQUENCED_TRANSFERS.load(deps.storage, nonce)?;
+                    Ok(Response::new().add_attributes(vec![
+                        attr("action", "follow_up_without_sequence"),
+                        attr("unsequenced_transfer", nonce.to_string()),
+                        attr("channel_id", transfer.src_channel_id),
+                        attr("ibc_denom", transfer.ibc_denom),
+                        attr("remote_amount", transfer.remote_amount),
+                    ]))
+                }
+            },
             SubMsgResult::Err(err) => {
-                let reply_args = SINGLE_STEP_REPLY_ARGS.load(deps.storage)?;
-                SINGLE_STEP_REPLY_ARGS.remove(deps.storage);
+                let transfer = UNSEQUENCED_TRANSFERS.load(deps.storage, nonce)?;
+                UNSEQUENCED_TRANSFERS.remove(deps.storage, nonce);
                 // only time where we undo reduce chann balance because this message is sent and reduced optimistically on Oraichain. If fail then we undo and then refund
-                undo_reduce_channel_balance(
-                    deps.storage,
-                    &reply_args.channel,
-                    &reply_args.denom,
-                    reply_args.amount,
-                )?;
-
-                let sub_msg = handle_packet_refund(
-                    deps.storage,
-                    &reply_args.local_receiver,
-                    &reply_args.denom,
-                    reply_args.amount,
-                )?;
+                let sub_msg = refund_pending_transfer(deps.storage, &transfer)?;
                 Ok(Response::new()
                     // we all set ack success so that this token is stuck on Oraichain, not on OraiBridge because if ack fail => token refunded on OraiBridge yet still refund on Oraichain
                     .set_data(ack_success())
@@ -165,36 +262,158 @@ pub fn reply(deps: DepsMut, _env: Env, reply: Reply) -> Result<Response, Contrac
                     .add_attributes(vec![
                         attr("action", "follow_up_failure_id"),
                         attr("error_ibc_send_failure", err),
-                        attr("undo_reduce_channel", reply_args.channel),
-                        attr("undo_reduce_channel_ibc_denom", reply_args.denom),
-                        attr("undo_reduce_channel_balance", reply_args.amount),
-                        attr("refund_recipient", reply_args.local_receiver),
+                        attr("undo_reduce_channel", transfer.src_channel_id),
+                        attr("undo_reduce_channel_ibc_denom", transfer.ibc_denom),
+                        attr("undo_reduce_channel_balance", transfer.remote_amount),
+                        attr("refund_recipient", transfer.local_sender),
                     ]))
             }
         },
-        // fallback case when refund fails. Wont retry => will refund manually
+        // happens when a packet built by TransferToRemote has been sent. Only replies on success because a failed send reverts the whole transfer
+        SEND_PACKET_ID => match reply.result {
+            // without a sequence the ack could not be matched to the snapshot, so the send is reverted instead
+            SubMsgResult::Ok(response) => {
+                let sequence = parse_packet_sequence(&response.events)
+                    .ok_or(ContractError::PacketSequenceNotFound)?;
+                save_pending_transfer(deps.storage, nonce, sequence)
+            }
+            SubMsgResult::Err(err) => Err(ContractError::Std(StdError::generic_err(err))),
+        },
+        // fallback case when refund fails. Wont retry => the amount is recorded as a stuck fund and refunded manually
         REFUND_FAILURE_ID => match reply.result {
-            SubMsgResult::Ok(_) => Ok(Response::new()),
+            SubMsgResult::Ok(_) => {
+                PAYOUT_REPLY_ARGS.remove(deps.storage, nonce);
+                Ok(Response::new())
+            }
             SubMsgResult::Err(err) => Ok(Response::new()
                 // we all set ack success so that this token is stuck on Oraichain, not on OraiBridge because if ack fail => token refunded on OraiBridge yet still refund on Oraichain
                 .set_data(ack_success())
+                .add_attributes(save_stuck_fund(deps.storage, &env, nonce, &err)?)
                 .add_attribute("action", "refund_failure_id")
                 .add_attribute("error_trying_to_refund_single_step", err)),
         },
         // fallback case when we dont have a mapping and have to do IBC transfer and it also failed. Wont refund because it is a rare case as we dont use IBC transfer as much
         // this means that we are sending to a normal ibc transfer channel, not ibc wasm.
         IBC_TRANSFER_NATIVE_ERROR_ID => match reply.result {
-            SubMsgResult::Ok(_) => Ok(Response::new()),
+            SubMsgResult::Ok(_) => {
+                PAYOUT_REPLY_ARGS.remove(deps.storage, nonce);
+                Ok(Response::new())
+            }
             SubMsgResult::Err(err) => Ok(Response::new()
                 // we all set ack success so that this token is stuck on Oraichain, not on OraiBridge because if ack fail => token refunded on OraiBridge yet still refund on Oraichain
                 .set_data(ack_success())
+                .add_attributes(save_stuck_fund(deps.storage, &env, nonce, &err)?)
                 .add_attribute("action", "ibc_transfer_native_error_id")
                 .add_attribute("error_trying_to_transfer_ibc_native_with_error", err)),
         },
+        // happens when the hook contract of the memo rejected the tokens. They are still in this contract, so they are refunded to the receiver
+        HOOK_FAILURE_ID => match reply.result {
+            SubMsgResult::Ok(_) => {
+                PAYOUT_REPLY_ARGS.remove(deps.storage, nonce);
+                Ok(Response::new())
+            }
+            SubMsgResult::Err(err) => {
+                let payout = PAYOUT_REPLY_ARGS.load(deps.storage, nonce)?;
+                PAYOUT_REPLY_ARGS.remove(deps.storage, nonce);
+                let sub_msg = build_payout_sub_msg(
+                    deps.storage,
+                    payout.amount.send_amount(payout.receiver.clone(), None),
+                    NATIVE_RECEIVE_ID,
+                    &payout.receiver,
+                    payout.amount.clone(),
+                )?;
+                Ok(Response::new()
+                    // we all set ack success so that the token is refunded on Oraichain, not on the remote chain
+                    .set_data(ack_success())
+                    .add_submessage(sub_msg)
+                    .add_attribute("action", "hook_failure_id")
+                    .add_attribute("error_hook", err)
+                    .add_attribute("refund_recipient", payout.receiver))
+            }
+        },
+        // happens when the swap of a swap-then-transfer is done. The transfer continues with the swapped tokens, or the sender is refunded
+        SWAP_TRANSFER_ID => handle_swap_transfer_reply(deps, env, nonce, reply.result),
         _ => Err(ContractError::UnknownReplyId { id: reply.id }),
     }
 }
 
+// wraps a message paying `amount` out to `receiver`. If it fails, the reply records the amount as a stuck fund
+pub fn build_payout_sub_msg(
+    storage: &mut dyn Storage,
+    msg: CosmosMsg,
+    reply_kind: u64,
+    receiver: &str,
+    amount: Amount,
+) -> StdResult<SubMsg> {
+    let nonce = next_reply_nonce(storage)?;
+    PAYOUT_REPLY_ARGS.save(
+        storage,
+        nonce,
+        &PayoutReplyArgs {
+            receiver: receiver.to_string(),
+            amount,
+        },
+    )?;
+    Ok(SubMsg::reply_always(
+        msg,
+        encode_reply_id(reply_kind, nonce),
+    ))
+}
+
+// the funds of a failed payout stay in this contract. Keep a record so that they can be claimed later
+fn save_stuck_fund(
+    storage: &mut dyn Storage,
+    env: &Env,
+    nonce: u64,
+    reason: &str,
+) -> StdResult<Vec<Attribute>> {
+    // payouts sent before stuck funds were recorded have no reply args
+    let payout = match PAYOUT_REPLY_ARGS.may_load(storage, nonce)? {
+        Some(payout) => payout,
+        None => return Ok(vec![]),
+    };
+    PAYOUT_REPLY_ARGS.remove(storage, nonce);
+    STUCK_FUNDS.save(
+        storage,
+        nonce,
+        &StuckFund {
+            receiver: payout.receiver,
+            asset: payout.amount.denom(),
+            amount: payout.amount.amount(),
+            reason: reason.to_string(),
+            height: env.block.height,
+            paid_to: None,
+            paid_height: None,
+        },
+    )?;
+    Ok(vec![attr("stuck_fund_id", nonce.to_string())])
+}
+
+// moves an outgoing transfer to the pending ledger once the ibc module has assigned its sequence
+fn save_pending_transfer(
+    storage: &mut dyn Storage,
+    nonce: u64,
+    sequence: u64,
+) -> Result<Response, ContractError> {
+    let transfer = UNSEQUENCED_TRANSFERS.load(storage, nonce)?;
+    UNSEQUENCED_TRANSFERS.remove(storage, nonce);
+    PENDING_TRANSFERS.save(storage, (&transfer.src_channel_id, sequence), &transfer)?;
+    Ok(Response::new().add_attributes(vec![
+        attr("action", "save_pending_transfer"),
+        attr("channel_id", transfer.src_channel_id),
+        attr("sequence", sequence.to_string()),
+    ]))
+}
+
+pub fn parse_packet_sequence(events: &[Event]) -> Option<u64> {
+    events
+        .iter()
+        .filter(|event| event.ty == "send_packet")
+        .flat_map(|event| event.attributes.iter())
+        .find(|attribute| attribute.key == "packet_sequence")
+        .and_then(|attribute| attribute.value.parse::<u64>().ok())
+}
+
 #[entry_point]
 /// enforces ordering and versioning constraints
 pub fn ibc_channel_open(
@@ -221,6 +440,7 @@ pub fn ibc_channel_connect(
         id: channel.endpoint.channel_id,
         counterparty_endpoint: channel.counterparty_endpoint,
         connection_id: channel.connection_id,
+        closed: false,
     };
     CHANNEL_INFO.save(deps.storage, &info.id, &info)?;
 
@@ -250,14 +470,63 @@ fn enforce_order_and_version(
 }
 
 #[entry_point]
+/// mark the channel as closed and snapshot its outstanding balances so that the admin can recover them later
 pub fn ibc_channel_close(
-    _deps: DepsMut,
-    _env: Env,
-    _channel: IbcChannelCloseMsg,
+    deps: DepsMut,
+    env: Env,
+    msg: IbcChannelCloseMsg,
 ) -> Result<IbcBasicResponse, ContractError> {
-    // TODO: what to do here?
-    // we will have locked funds that need to be returned somehow
-    unimplemented!();
+    let channel_id = msg.channel().endpoint.channel_id.as_str();
+    let mut info = CHANNEL_INFO
+        .may_load(deps.storage, channel_id)?
+        .ok_or_else(|| ContractError::NoSuchChannel {
+            id: channel_id.to_string(),
+        })?;
+    info.closed = true;
+    CHANNEL_INFO.save(deps.storage, channel_id, &info)?;
+
+    // the remote side can no longer send these tokens back, so we freeze them in a recoverable record.
+    // So are the tokens of this chain escrowed on the channel, since their vouchers cannot come back to unescrow them
+    let reverse_states = CHANNEL_REVERSE_STATE
+        .prefix(channel_id)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, state)| (denom, state, false)));
+    let forward_states = CHANNEL_FORWARD_STATE
+        .prefix(channel_id)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, state)| (denom, state, true)));
+    let states = reverse_states
+        .chain(forward_states)
+        .collect::<StdResult<Vec<_>>>()?;
+    let mut attributes = vec![
+        attr("action", "ibc_channel_close"),
+        attr("channel_id", channel_id),
+    ];
+    for (denom, state, escrowed) in states {
+        if state.outstanding.is_zero() {
+            continue;
+        }
+        CHANNEL_RECOVERABLE_FUNDS.save(
+            deps.storage,
+            (channel_id, &denom),
+            &RecoverableFund {
+                outstanding: state.outstanding,
+                recovered: Uint128::zero(),
+                closed_height: env.block.height,
+                escrowed,
+            },
+        )?;
+        attributes.push(attr(
+            if escrowed {
+                "recoverable_escrow"
+            } else {
+                "recoverable_fund"
+            },
+            format!("{}:{}", denom, state.outstanding),
+        ));
+    }
+
+    Ok(IbcBasicResponse::new().add_attributes(attributes))
 }
 
 #[entry_point]
@@ -278,6 +547,10 @@ pub fn ibc_packet_receive(
         &packet,
         &msg.relayer.into_string(),
     )
+    .and_then(|res| {
+        hold_receive_lock(deps.storage, &res.messages)?;
+        Ok(res)
+    })
     .or_else(|err| {
         Ok(IbcReceiveResponse::new()
             // trade-off between reentrancy & refunding. If error, then it should be a serious error => refund to oraibridge
@@ -294,6 +567,17 @@ pub fn ibc_packet_receive(
     })
 }
 
+// blocks execute until the last submessage of the received packet replies, so the payouts and hooks cannot call back into this contract.
+// Submessages that reply always are the last ones of a receive. Without one, nothing runs that could call back
+fn hold_receive_lock(storage: &mut dyn Storage, messages: &[SubMsg]) -> StdResult<()> {
+    if let Some(last) = messages.last() {
+        if last.reply_on == ReplyOn::Always {
+            RECEIVE_LOCK.save(storage, &last.id)?;
+        }
+    }
+    Ok(())
+}
+
 // Returns local denom if the denom is an encoded voucher from the expected endpoint
 // Otherwise, error
 pub fn parse_voucher_denom<'a>(
@@ -326,7 +610,7 @@ pub fn parse_voucher_denom<'a>(
 
 // Returns local denom if the denom is an encoded voucher from the expected endpoint
 // Otherwise, error
-pub fn parse_ibc_denom_without_sanity_checks<'a>(ibc_denom: &'a str) -> StdResult<&'a str> {
+pub fn parse_ibc_denom_without_sanity_checks(ibc_denom: &str) -> StdResult<&str> {
     let split_denom: Vec<&str> = ibc_denom.splitn(3, '/').collect();
 
     if split_denom.len() != 3 {
@@ -339,7 +623,7 @@ pub fn parse_ibc_denom_without_sanity_checks<'a>(ibc_denom: &'a str) -> StdResul
 
 // Returns
 // Otherwise, error
-pub fn parse_ibc_channel_without_sanity_checks<'a>(ibc_denom: &'a str) -> StdResult<&'a str> {
+pub fn parse_ibc_channel_without_sanity_checks(ibc_denom: &str) -> StdResult<&str> {
     let split_denom: Vec<&str> = ibc_denom.splitn(3, '/').collect();
 
     if split_denom.len() != 3 {
@@ -362,20 +646,96 @@ fn do_ibc_packet_receive(
     let msg: Ics20Packet = from_binary(&packet.data)?;
     // let channel = packet.dest.channel_id.clone();
 
+    // a closed channel is frozen, funds on it can only be recovered by the admin
+    if let Some(channel_info) = CHANNEL_INFO.may_load(storage, &packet.dest.channel_id)? {
+        if channel_info.closed {
+            return Err(ContractError::ChannelClosed {
+                id: packet.dest.channel_id.clone(),
+            });
+        }
+    }
+
     // If the token originated on the remote chain, it looks like "ucosm".
     // If it originated on our chain, it looks like "port/channel/ucosm".
     let denom = parse_voucher_denom(&msg.denom, &packet.src)?;
 
+    // the mapping key of a remote token, or the local denom of a token that originated on this chain
+    let paused_denom = if denom.1 {
+        get_key_ics20_ibc_denom(&packet.dest.port_id, &packet.dest.channel_id, denom.0)
+    } else {
+        denom.0.to_string()
+    };
+    assert_not_paused(
+        storage,
+        &packet.dest.channel_id,
+        &paused_denom,
+        FlowDirection::Inflow,
+    )?;
+
     // if denom is native, we handle it the native way
     if denom.1 {
         return handle_ibc_packet_receive_native_remote_chain(
-            storage, api, &querier, env, &denom.0, &packet, &msg, relayer,
+            storage, api, querier, env, denom.0, packet, &msg, relayer,
         );
     }
 
-    Err(ContractError::Std(StdError::generic_err("Not suppported")))
+    handle_ibc_packet_receive_local_origin(storage, env, denom.0, packet, &msg, relayer)
+}
+
+// a voucher of a token that originated on this chain is coming back, so we unescrow it
+fn handle_ibc_packet_receive_local_origin(
+    storage: &mut dyn Storage,
+    env: Env,
+    denom: &str,
+    packet: &IbcPacket,
+    msg: &Ics20Packet,
+    relayer: &str,
+) -> Result<IbcReceiveResponse, ContractError> {
+    // a packet over the inflow cap gets a failed ack, so the remote chain refunds its sender
+    check_rate_limited_flow(
+        storage,
+        env.block.time,
+        &packet.dest.channel_id,
+        denom,
+        msg.amount,
+        FlowDirection::Inflow,
+    )?;
+    // we can only unescrow what has been sent over this channel
+    reduce_channel_forward_balance(storage, &packet.dest.channel_id, denom, msg.amount)?;
+    record_rate_limited_flow(
+        storage,
+        env.block.time,
+        &packet.dest.channel_id,
+        denom,
+        msg.amount,
+        FlowDirection::Inflow,
+    )?;
+
+    let to_send = Amount::from_parts(denom.to_string(), msg.amount);
+    let cosmos_msg = to_send.send_amount(msg.receiver.clone(), None);
+    let sub_msg = build_payout_sub_msg(
+        storage,
+        cosmos_msg,
+        NATIVE_RECEIVE_ID,
+        &msg.receiver,
+        to_send,
+    )?;
+
+    Ok(IbcReceiveResponse::new()
+        .set_ack(ack_success())
+        .add_submessage(sub_msg)
+        .add_attributes(vec![
+            ("action", "receive"),
+            ("sender", &msg.sender),
+            ("receiver", &msg.receiver),
+            ("denom", denom),
+            ("amount", &msg.amount.to_string()),
+            ("success", "true"),
+            ("relayer", relayer),
+        ]))
 }
 
+#[allow(clippy::too_many_arguments)]
 fn handle_ibc_packet_receive_native_remote_chain(
     storage: &mut dyn Storage,
     api: &dyn Api,
@@ -387,7 +747,6 @@ fn handle_ibc_packet_receive_native_remote_chain(
     relayer: &str,
 ) -> Result<IbcReceiveResponse, ContractError> {
     let config = CONFIG.load(storage)?;
-    let mut cosmos_msgs: Vec<CosmosMsg> = vec![];
     let ibc_packet_amount = msg.amount.to_string();
     let attributes = vec![
         ("action", "receive_native"),
@@ -404,6 +763,18 @@ fn handle_ibc_packet_receive_native_remote_chain(
     let pair_mapping = ics20_denoms()
         .load(storage, &ibc_denom)
         .map_err(|_| ContractError::NotOnMappingList {})?;
+    // the payouts and swaps go to the receiver, so an invalid one fails the packet before anything is written
+    api.addr_validate(&msg.receiver)?;
+
+    // a packet over the inflow cap gets a failed ack, so the remote chain refunds its sender
+    check_rate_limited_flow(
+        storage,
+        env.block.time,
+        &packet.dest.channel_id,
+        &ibc_denom,
+        msg.amount,
+        FlowDirection::Inflow,
+    )?;
 
     let to_send = Amount::from_parts(
         parse_asset_info_denom(pair_mapping.asset_info.clone()),
@@ -416,17 +787,175 @@ fn handle_ibc_packet_receive_native_remote_chain(
 
     let initial_receive_asset_info = pair_mapping.asset_info.clone();
 
+    let ReceiveFees {
+        fee_data,
+        destination,
+        destination_asset_info_on_orai,
+        destination_pair_mapping,
+        memo_error,
+    } = compute_receive_fees(
+        storage,
+        api,
+        querier,
+        &config,
+        msg,
+        &to_send,
+        &initial_receive_asset_info,
+    )?;
+
+    // the tokens are minted on this side of the channel before the follow-ups are built, so a forward back out on the same channel can spend them
+    increase_channel_balance(storage, &packet.dest.channel_id, &ibc_denom, msg.amount)?;
+
+    // if the fees have consumed all user funds, there is nothing to follow up with
+    let follow_up_msg_data = if fee_data.deducted_amount.is_zero() {
+        None
+    } else {
+        let new_deducted_to_send = Amount::from_parts(to_send.denom(), fee_data.deducted_amount);
+        let follow_up_msg_data = get_follow_up_msgs(
+            storage,
+            api,
+            querier,
+            env.clone(),
+            new_deducted_to_send,
+            pair_mapping.asset_info,
+            destination_asset_info_on_orai,
+            &msg.sender,
+            &msg.receiver,
+            &destination,
+            packet.dest.channel_id.as_str(),
+            destination_pair_mapping,
+            ReplyArgs {
+                channel: packet.dest.channel_id.clone(),
+                local_receiver: destination
+                    .refund_address
+                    .clone()
+                    .unwrap_or_else(|| msg.receiver.clone()),
+                denom: ibc_denom.clone(),
+                amount: msg.amount,
+                minimum_receive: Uint128::zero(),
+                follow_up: None,
+            },
+        );
+        match follow_up_msg_data {
+            Ok(follow_up_msg_data) => Some(follow_up_msg_data),
+            // a packet that gets a failed ack must leave the balance untouched
+            Err(err) => {
+                undo_increase_channel_balance(
+                    storage,
+                    &packet.dest.channel_id,
+                    &ibc_denom,
+                    msg.amount,
+                )?;
+                return Err(err);
+            }
+        }
+    };
+
+    // the rate limit usage, the fees and the last known price are only written once the follow-up msgs are built,
+    // so a packet that gets a failed ack leaves them untouched
+    record_rate_limited_flow(
+        storage,
+        env.block.time,
+        &packet.dest.channel_id,
+        &ibc_denom,
+        msg.amount,
+        FlowDirection::Inflow,
+    )?;
+    record_fee_denom_price(storage, querier, &config, &initial_receive_asset_info)?;
+    let follow_up_msg_data = match follow_up_msg_data {
+        Some(follow_up_msg_data) => follow_up_msg_data,
+        // we keep all of the funds as token fee
+        None => {
+            accumulate_fee(storage, TOKEN_FEE_ACCUMULATOR, &to_send)?;
+            return Ok(IbcReceiveResponse::new()
+                .set_ack(ack_success())
+                .add_attributes(attributes)
+                .add_attributes(vec![
+                    ("token_fee", &fee_data.token_fee.amount().to_string()),
+                    ("relayer_fee", &fee_data.relayer_fee.amount().to_string()),
+                ]));
+        }
+    };
+    // token fees stay on the contract until they are swept by CollectFees, relayer fees until the relayer claims them
+    accumulate_fee(storage, TOKEN_FEE_ACCUMULATOR, &fee_data.token_fee)?;
+    credit_relayer_fee(storage, &Addr::unchecked(relayer), &fee_data.relayer_fee)?;
+
+    let mut res = IbcReceiveResponse::new()
+        .set_ack(ack_success())
+        .add_submessages(follow_up_msg_data.sub_msgs)
+        .add_attributes(attributes)
+        .add_attributes(vec![
+            ("token_fee", &fee_data.token_fee.amount().to_string()),
+            ("relayer_fee", &fee_data.relayer_fee.amount().to_string()),
+        ]);
+    if let Some(memo_error) = memo_error {
+        res = res.add_attribute("ibc_error_msg", memo_error);
+    } else if !follow_up_msg_data.follow_up_msg.is_empty() {
+        res = res.add_attribute("ibc_error_msg", follow_up_msg_data.follow_up_msg);
+    }
+
+    Ok(res)
+}
+
+pub struct ReceiveFees {
+    pub fee_data: FeeData,
+    pub destination: DestinationInfo,
+    pub destination_asset_info_on_orai: AssetInfo,
+    pub destination_pair_mapping: Option<(String, MappingMetadata)>,
+    /// why the memo could not be parsed, if it could not
+    pub memo_error: Option<String>,
+}
+
+/// Fees of an incoming packet of a remote native token, including the extra fees of a round trip requested in the memo.
+/// It only reads storage so that the simulation queries can share it
+#[allow(clippy::too_many_arguments)]
+pub fn compute_receive_fees(
+    storage: &dyn Storage,
+    api: &dyn Api,
+    querier: &QuerierWrapper,
+    config: &Config,
+    msg: &Ics20Packet,
+    to_send: &Amount,
+    initial_receive_asset_info: &AssetInfo,
+) -> StdResult<ReceiveFees> {
     let mut fee_data = process_deduct_fee(
         storage,
         querier,
         api,
+        &msg.receiver,
         &msg.sender,
         &msg.denom,
         to_send.clone(),
-        &config.swap_router_contract,
+        config,
     )?;
 
-    let destination = DestinationInfo::from_str(&msg.memo.clone().unwrap_or_default());
+    // a memo that cannot be parsed leaves the tokens with the receiver, and the error is reported.
+    // So does a slippage the config does not allow, rather than swapping with a tighter one than asked
+    let max_slippage_bps = config.max_slippage_bps.min(MAX_SLIPPAGE_BPS);
+    let (destination, memo_error) =
+        match DestinationInfo::parse(&msg.memo.clone().unwrap_or_default()) {
+            Ok(destination) if destination.slippage_bps > Some(max_slippage_bps) => (
+                DestinationInfo::default(),
+                Some(
+                    MemoError::InvalidSlippage {
+                        max: max_slippage_bps,
+                    }
+                    .to_string(),
+                ),
+            ),
+            Ok(destination) => match (&destination.refund_address, &destination.hook) {
+                (Some(refund_address), _) if api.addr_validate(refund_address).is_err() => (
+                    DestinationInfo::default(),
+                    Some(format!("Invalid memo refund address: {}", refund_address)),
+                ),
+                (_, Some(hook)) if api.addr_validate(&hook.contract).is_err() => (
+                    DestinationInfo::default(),
+                    Some(format!("Invalid memo hook contract: {}", hook.contract)),
+                ),
+                _ => (destination, None),
+            },
+            Err(err) => (DestinationInfo::default(), Some(err.to_string())),
+        };
     let destination_asset_info_on_orai =
         denom_to_asset_info(querier, api, &destination.destination_denom)?;
     let mut remote_destination_denom: String = "".to_string();
@@ -443,7 +972,7 @@ fn handle_ibc_packet_receive_native_remote_chain(
         let (is_evm_based, evm_prefix) = destination.is_receiver_evm_based();
         if is_evm_based {
             destination_pair_mapping = pair_mappings.clone().into_iter().find(|(key, _)| {
-                find_evm_pair_mapping(&key, &evm_prefix, &destination.destination_channel)
+                find_evm_pair_mapping(key, &evm_prefix, &destination.destination_channel)
             });
         }
         let is_cosmos_based = destination.is_receiver_cosmos_based();
@@ -460,8 +989,10 @@ fn handle_ibc_packet_receive_native_remote_chain(
         }
         // if there's a round trip to a different network, we deduct the token fee based on the remote destination denom
         // for relayer fee, we need to deduct using the destination network
+        let discount = get_fee_discount(storage, &msg.receiver, &msg.sender)?;
         let (_, additional_token_fee) =
             deduct_token_fee(storage, &remote_destination_denom, to_send.amount())?;
+        let additional_token_fee = apply_fee_discount(additional_token_fee, discount)?;
         fee_data.token_fee = Amount::from_parts(
             fee_data.token_fee.denom(),
             fee_data
@@ -469,36 +1000,17 @@ fn handle_ibc_packet_receive_native_remote_chain(
                 .amount()
                 .checked_add(additional_token_fee)?,
         );
-        let mut additional_relayer_fee = deduct_relayer_fee(
+        // the relayer fee of the destination network is priced directly in the received token
+        let additional_relayer_fee = deduct_relayer_fee(
             storage,
             api,
             querier,
             &destination.receiver,
             &remote_destination_denom,
-            destination_asset_info_on_orai.clone(),
-            &config.swap_router_contract,
+            initial_receive_asset_info.clone(),
+            config,
         )?;
-
-        // if initial asset info is different with destination asset info,
-        // we need convert relayer fee from destination_asset_info_on_orai to initial token receive
-
-        let swap_operations = build_swap_operations(
-            initial_receive_asset_info,
-            destination_asset_info_on_orai.clone(),
-            config.fee_denom.as_str(),
-        );
-
-        if !swap_operations.is_empty() {
-            // if simulate swap fails, set fee to zero
-            additional_relayer_fee = match config.swap_router_contract.simulate_swap(
-                querier,
-                additional_relayer_fee,
-                swap_operations,
-            ) {
-                Ok(res) => res.amount,
-                Err(_) => Uint128::default(),
-            };
-        }
+        let additional_relayer_fee = apply_fee_discount(additional_relayer_fee, discount)?;
 
         fee_data.relayer_fee = Amount::from_parts(
             fee_data.relayer_fee.denom(),
@@ -514,71 +1026,16 @@ fn handle_ibc_packet_receive_native_remote_chain(
             .unwrap_or_default();
     }
 
-    // if the fees have consumed all user funds, we send all the fees to our token fee receiver
-    if fee_data.deducted_amount.is_zero() {
-        return Ok(IbcReceiveResponse::new()
-            .set_ack(ack_success())
-            .add_message(to_send.send_amount(config.token_fee_receiver.into_string(), None))
-            .add_attributes(attributes)
-            .add_attributes(vec![
-                ("token_fee", &fee_data.token_fee.amount().to_string()),
-                ("relayer_fee", &fee_data.relayer_fee.amount().to_string()),
-            ]));
-    }
-    if !fee_data.token_fee.is_empty() {
-        cosmos_msgs.push(
-            fee_data
-                .token_fee
-                .send_amount(config.token_fee_receiver.into_string(), None),
-        )
-    }
-    if !fee_data.relayer_fee.is_empty() {
-        cosmos_msgs.push(fee_data.relayer_fee.send_amount(relayer.to_string(), None))
-    }
-
-    let new_deducted_to_send = Amount::from_parts(to_send.denom(), fee_data.deducted_amount);
-    let follow_up_msg_data = get_follow_up_msgs(
-        storage,
-        api,
-        querier,
-        env.clone(),
-        new_deducted_to_send,
-        pair_mapping.asset_info,
+    Ok(ReceiveFees {
+        fee_data,
+        destination,
         destination_asset_info_on_orai,
-        &msg.sender,
-        &msg.receiver,
-        &destination,
-        packet.dest.channel_id.as_str(),
         destination_pair_mapping,
-    )?;
-
-    // increase channel balance submsg. We increase it first before doing other tasks
-    cosmos_msgs.push(CosmosMsg::Wasm(cosmwasm_std::WasmMsg::Execute {
-        contract_addr: env.contract.address.to_string(),
-        msg: to_binary(&ExecuteMsg::IncreaseChannelBalanceIbcReceive {
-            dest_channel_id: packet.dest.channel_id.clone(),
-            ibc_denom,
-            amount: msg.amount,
-            local_receiver: msg.receiver.clone(),
-        })?,
-        funds: vec![],
-    }));
-    let mut res = IbcReceiveResponse::new()
-        .set_ack(ack_success())
-        .add_messages(cosmos_msgs)
-        .add_submessages(follow_up_msg_data.sub_msgs)
-        .add_attributes(attributes)
-        .add_attributes(vec![
-            ("token_fee", &fee_data.token_fee.amount().to_string()),
-            ("relayer_fee", &fee_data.relayer_fee.amount().to_string()),
-        ]);
-    if !follow_up_msg_data.follow_up_msg.is_empty() {
-        res = res.add_attribute("ibc_error_msg", follow_up_msg_data.follow_up_msg);
-    }
-
-    Ok(res)
+        memo_error,
+    })
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn get_follow_up_msgs(
     storage: &mut dyn Storage,
     api: &dyn Api,
@@ -592,58 +1049,103 @@ pub fn get_follow_up_msgs(
     destination: &DestinationInfo,
     initial_dest_channel_id: &str, // channel id on Oraichain receiving the token from other chain,
     destination_pair_mapping: Option<(String, MappingMetadata)>,
+    reply_args: ReplyArgs,
 ) -> Result<FollowUpMsgsData, ContractError> {
     let config = CONFIG.load(storage)?;
     let mut sub_msgs: Vec<SubMsg> = vec![];
-    let send_only_sub_msg = SubMsg::reply_on_error(
-        to_send.send_amount(receiver.to_string(), None),
-        NATIVE_RECEIVE_ID,
-    );
-    let mut follow_up_msgs_data = FollowUpMsgsData {
-        sub_msgs: vec![send_only_sub_msg],
-        follow_up_msg: "".to_string(),
-    };
+    // a failed hook or forward refunds the refund address of the memo, if any
+    let refund_receiver = destination.refund_address.as_deref().unwrap_or(receiver);
     if destination.destination_denom.is_empty() {
-        return Ok(follow_up_msgs_data);
+        if let Some(hook) = &destination.hook {
+            return Ok(FollowUpMsgsData {
+                sub_msgs: vec![build_hook_sub_msg(storage, hook, to_send, refund_receiver)?],
+                follow_up_msg: "".to_string(),
+            });
+        }
+        return build_send_only_follow_up(storage, to_send, receiver, "".to_string());
     }
     // successful case. We dont care if this msg is going to be successful or not because it does not affect our ibc receive flow (just submsgs)
-    let swap_operations = build_swap_operations(
-        destination_asset_info_on_orai.clone(),
-        initial_receive_asset_info.clone(),
-        config.fee_denom.as_str(),
-    );
+    let swap_operations = load_swap_route(
+        storage,
+        &initial_receive_asset_info,
+        &destination_asset_info_on_orai,
+        &config.fee_denom,
+    )?;
     let mut minimum_receive = to_send.amount();
-    if swap_operations.len() > 0 {
+    if !swap_operations.is_empty() {
         let response = config.swap_router_contract.simulate_swap(
             querier,
-            to_send.amount().clone(),
+            to_send.amount(),
             swap_operations.clone(),
         );
-        if response.is_err() {
-            follow_up_msgs_data.follow_up_msg = format!(
-                "Cannot simulate swap with ops: {:?} with error: {:?}",
-                swap_operations,
-                response.unwrap_err().to_string()
-            );
-            return Ok(follow_up_msgs_data);
+        match response {
+            Ok(response) => minimum_receive = apply_slippage(response.amount, destination, &config),
+            Err(err) => {
+                let follow_up_msg = format!(
+                    "Cannot simulate swap with ops: {:?} with error: {:?}",
+                    swap_operations,
+                    err.to_string()
+                );
+                return build_send_only_follow_up(storage, to_send, receiver, follow_up_msg);
+            }
         }
-        minimum_receive = response.unwrap().amount;
     }
 
-    let mut build_ibc_msg_result = build_ibc_msg(
-        env,
-        receiver,
-        initial_dest_channel_id,
-        minimum_receive.clone(),
-        &sender,
-        &destination,
-        config.default_timeout,
-        destination_pair_mapping,
-    );
+    // the hook or the forward of a swap waits for its reply, a failed swap leaves no tokens to send
+    if !swap_operations.is_empty()
+        && (destination.hook.is_some() || !destination.destination_channel.is_empty())
+    {
+        let swap_reply_nonce = next_reply_nonce(storage)?;
+        REPLY_ARGS.save(
+            storage,
+            swap_reply_nonce,
+            &ReplyArgs {
+                minimum_receive,
+                follow_up: Some(SwapFollowUp {
+                    ask_denom: parse_asset_info_denom(destination_asset_info_on_orai),
+                    remote_sender: sender.to_string(),
+                    destination: destination.clone(),
+                    local_channel_id: initial_dest_channel_id.to_string(),
+                    pair_mapping: destination_pair_mapping,
+                }),
+                ..reply_args
+            },
+        )?;
+        build_swap_msgs(
+            minimum_receive,
+            &config.swap_router_contract,
+            to_send.amount(),
+            initial_receive_asset_info,
+            None,
+            &mut sub_msgs,
+            swap_operations,
+            swap_reply_nonce,
+        )?;
+        return Ok(FollowUpMsgsData {
+            sub_msgs,
+            follow_up_msg: "".to_string(),
+        });
+    }
+
+    let mut build_ibc_msg_result = match &destination.hook {
+        Some(hook) => build_hook_sub_msg(storage, hook, to_send.clone(), refund_receiver)
+            .map(|sub_msg| vec![sub_msg]),
+        None => build_ibc_msg(
+            storage,
+            env,
+            refund_receiver,
+            initial_dest_channel_id,
+            minimum_receive,
+            sender,
+            destination,
+            config.default_timeout,
+            destination_pair_mapping,
+        ),
+    };
 
     // by default, the receiver is the original address sent in ics20packet
     let mut to = Some(api.addr_validate(receiver)?);
-    follow_up_msgs_data.follow_up_msg = if let Some(ibc_msg) = build_ibc_msg_result.as_mut().ok() {
+    let follow_up_msg = if let Ok(ibc_msg) = build_ibc_msg_result.as_mut() {
         sub_msgs.append(ibc_msg);
         // if there's an ibc msg => swap receiver is None so the receiver is this ibc wasm address
         to = None;
@@ -651,6 +1153,7 @@ pub fn get_follow_up_msgs(
     } else {
         build_ibc_msg_result.unwrap_err().to_string()
     };
+    let swap_reply_nonce = next_reply_nonce(storage)?;
     build_swap_msgs(
         minimum_receive,
         &config.swap_router_contract,
@@ -659,13 +1162,120 @@ pub fn get_follow_up_msgs(
         to.clone(),
         &mut sub_msgs,
         swap_operations,
+        swap_reply_nonce,
     )?;
+    // each swap keeps its own refund data so that packets received in the same tx cannot override each other's
+    let swap_reply_id = encode_reply_id(SWAP_OPS_FAILURE_ID, swap_reply_nonce);
+    if sub_msgs.iter().any(|sub_msg| sub_msg.id == swap_reply_id) {
+        REPLY_ARGS.save(
+            storage,
+            swap_reply_nonce,
+            &ReplyArgs {
+                minimum_receive,
+                ..reply_args
+            },
+        )?;
+    }
     // fallback case. If there's no cosmos message then we return send amount
     if sub_msgs.is_empty() {
-        return Ok(follow_up_msgs_data);
+        return build_send_only_follow_up(storage, to_send, receiver, follow_up_msg);
+    };
+    Ok(FollowUpMsgsData {
+        sub_msgs,
+        follow_up_msg,
+    })
+}
+
+// sends the amount to the hook contract along with its msg. If the hook fails, the reply refunds the amount to the refund receiver
+fn build_hook_sub_msg(
+    storage: &mut dyn Storage,
+    hook: &ContractHook,
+    amount: Amount,
+    refund_receiver: &str,
+) -> StdResult<SubMsg> {
+    let msg: CosmosMsg = match &amount {
+        Amount::Native(coin) => WasmMsg::Execute {
+            contract_addr: hook.contract.clone(),
+            msg: hook.msg.clone(),
+            funds: vec![coin.clone()],
+        }
+        .into(),
+        Amount::Cw20(_) => amount.send_amount(hook.contract.clone(), Some(hook.msg.clone())),
     };
-    follow_up_msgs_data.sub_msgs = sub_msgs;
-    return Ok(follow_up_msgs_data);
+    build_payout_sub_msg(storage, msg, HOOK_FAILURE_ID, refund_receiver, amount)
+}
+
+// the receiver simply gets the received amount when there is nothing to follow up with
+fn build_send_only_follow_up(
+    storage: &mut dyn Storage,
+    to_send: Amount,
+    receiver: &str,
+    follow_up_msg: String,
+) -> Result<FollowUpMsgsData, ContractError> {
+    let send_only_sub_msg = build_payout_sub_msg(
+        storage,
+        to_send.send_amount(receiver.to_string(), None),
+        NATIVE_RECEIVE_ID,
+        receiver,
+        to_send,
+    )?;
+    Ok(FollowUpMsgsData {
+        sub_msgs: vec![send_only_sub_msg],
+        follow_up_msg,
+    })
+}
+
+/// Least amount a universal swap may give. A memo minimum out wins over a slippage,
+/// but neither can go further below the simulated amount than the max slippage of the config.
+/// A minimum out above the simulated amount is lowered to it, the swap would only fail otherwise.
+/// A memo slippage above the max is rejected before, by compute_receive_fees
+pub fn apply_slippage(
+    simulated_amount: Uint128,
+    destination: &DestinationInfo,
+    config: &Config,
+) -> Uint128 {
+    let max_bps = config.max_slippage_bps.min(MAX_SLIPPAGE_BPS);
+    let below_simulated =
+        |bps: u16| simulated_amount.multiply_ratio(MAX_SLIPPAGE_BPS - bps, MAX_SLIPPAGE_BPS);
+    match destination.min_out {
+        Some(min_out) => min_out.min(simulated_amount).max(below_simulated(max_bps)),
+        None => below_simulated(
+            destination
+                .slippage_bps
+                .unwrap_or(config.default_slippage_bps)
+                .min(max_bps),
+        ),
+    }
+}
+
+// the output of a swap is the return amount of its last pool
+pub fn parse_swap_return_amount(events: &[Event]) -> Option<Uint128> {
+    events
+        .iter()
+        .filter(|event| event.ty == "wasm")
+        .flat_map(|event| event.attributes.iter())
+        .rev()
+        .find(|attribute| attribute.key == "return_amount")
+        .and_then(|attribute| attribute.value.parse::<Uint128>().ok())
+}
+
+/// Operations swapping the offer into the ask asset: the route registered by the admin, or through the fee denom otherwise
+pub fn load_swap_route(
+    storage: &dyn Storage,
+    offer_asset_info: &AssetInfo,
+    ask_asset_info: &AssetInfo,
+    fee_denom: &str,
+) -> StdResult<Vec<SwapOperation>> {
+    if offer_asset_info.eq(ask_asset_info) {
+        return Ok(vec![]);
+    }
+    let registered = SWAP_ROUTES.may_load(
+        storage,
+        (&offer_asset_info.to_string(), &ask_asset_info.to_string()),
+    )?;
+    Ok(registered.unwrap_or_else(|| {
+        build_swap_operations(ask_asset_info.clone(), offer_asset_info.clone(), fee_denom)
+    }))
 }
 
 pub fn build_swap_operations(
@@ -696,6 +1306,7 @@ pub fn build_swap_operations(
     swap_operations
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn build_swap_msgs(
     minimum_receive: Uint128,
     swap_router_contract: &RouterController,
@@ -704,13 +1315,14 @@ pub fn build_swap_msgs(
     to: Option<Addr>,
     sub_msgs: &mut Vec<SubMsg>,
     operations: Vec<SwapOperation>,
+    reply_nonce: u64,
 ) -> StdResult<()> {
     // the swap msg must be executed before other msgs because we need the ask token amount to create ibc msg => insert in first index
-    if operations.len() == 0 {
+    if operations.is_empty() {
         return Ok(());
     }
     // double check. We cannot let swap ops with Some(to) aka swap to someone else, not this contract and then transfer ibc => would be double spending
-    if to.is_some() && sub_msgs.len() > 0 {
+    if to.is_some() && !sub_msgs.is_empty() {
         // forbidden case. Pop all sub messages and return empty
         while sub_msgs.pop().is_some() {
             sub_msgs.pop();
@@ -719,7 +1331,7 @@ pub fn build_swap_msgs(
     }
     sub_msgs.insert(
         0,
-        SubMsg::reply_on_error(
+        SubMsg::reply_always(
             swap_router_contract.execute_operations(
                 initial_receive_asset_info,
                 amount,
@@ -727,14 +1339,16 @@ pub fn build_swap_msgs(
                 Some(minimum_receive),
                 to,
             )?,
-            SWAP_OPS_FAILURE_ID,
+            encode_reply_id(SWAP_OPS_FAILURE_ID, reply_nonce),
         ),
     );
 
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn build_ibc_msg(
+    storage: &mut dyn Storage,
     env: Env,
     local_receiver: &str,
     local_channel_id: &str,
@@ -750,7 +1364,16 @@ pub fn build_ibc_msg(
             "Destination channel empty in build ibc msg",
         ));
     }
-    let timeout = env.block.time.plus_seconds(default_timeout);
+    // the channel the packet leaves on may have its own default timeout
+    let timeout_channel = match destination.is_receiver_evm_based() {
+        (true, _) => local_channel_id,
+        _ => destination.destination_channel.as_str(),
+    };
+    let timeout = env.block.time.plus_seconds(channel_default_timeout(
+        storage,
+        timeout_channel,
+        default_timeout,
+    )?);
     // let pair_mappings: Vec<(String, MappingMetadata)> = ics20_denoms()
     //     .idx
     //     .asset_info
@@ -761,17 +1384,18 @@ pub fn build_ibc_msg(
     let (is_evm_based, _) = destination.is_receiver_evm_based();
     if is_evm_based {
         if let Some(mapping) = pair_mapping {
-            return Ok(process_ibc_msg(
+            return process_ibc_msg(
+                storage,
                 mapping,
-                env.contract.address.to_string(),
                 local_receiver,
                 local_channel_id,
                 env.contract.address.as_str(),
                 remote_address, // use sender from ICS20Packet as receiver when transferring back because we have the actual receiver in memo for evm cases
                 Some(destination.receiver.clone()),
                 amount,
+                env.block.time,
                 timeout,
-            )?);
+            );
         }
         return Err(StdError::generic_err("cannot find pair mappings"));
     }
@@ -779,17 +1403,18 @@ pub fn build_ibc_msg(
     let is_cosmos_based = destination.is_receiver_cosmos_based();
     if is_cosmos_based {
         if let Some(mapping) = pair_mapping {
-            return Ok(process_ibc_msg(
+            return process_ibc_msg(
+                storage,
                 mapping,
-                env.contract.address.to_string(),
                 local_receiver,
                 &destination.destination_channel,
                 env.contract.address.as_str(),
                 &destination.receiver, // now we use dest receiver since cosmos based universal swap wont be sent to oraibridge, so the receiver is the correct receive addr
                 None, // no need memo because it is not used in the remote cosmos based chain
                 amount,
+                env.block.time,
                 timeout,
-            )?);
+            );
         }
 
         // final case, where the destination token is from a remote chain that we dont have a pair mapping with.
@@ -803,10 +1428,14 @@ pub fn build_ibc_msg(
             timeout: IbcTimeout::with_timestamp(timeout),
         }
         .into();
-        return Ok(vec![SubMsg::reply_on_error(
+        // if the transfer fails, the swapped tokens stay in this contract and belong to the local receiver
+        return Ok(vec![build_payout_sub_msg(
+            storage,
             ibc_msg,
             IBC_TRANSFER_NATIVE_ERROR_ID,
-        )]);
+            local_receiver,
+            Amount::native(amount.u128(), &destination.destination_denom),
+        )?]);
     }
     Err(StdError::generic_err(
         "The destination info is neither evm or cosmos based",
@@ -814,15 +1443,17 @@ pub fn build_ibc_msg(
 }
 
 // TODO: Write unit tests for relayer fee & cosmos based universal swap in simulate js
+#[allow(clippy::too_many_arguments)]
 pub fn process_ibc_msg(
+    storage: &mut dyn Storage,
     pair_mapping: (String, MappingMetadata),
-    contract_addr: String,
     local_receiver: &str,
     src_channel: &str,
     ibc_msg_sender: &str,
     ibc_msg_receiver: &str,
     memo: Option<String>,
     amount: Uint128,
+    now: Timestamp,
     timeout: Timestamp,
 ) -> StdResult<Vec<SubMsg>> {
     let remote_amount = convert_local_to_remote(
@@ -843,21 +1474,62 @@ pub fn process_ibc_msg(
     )?
     .into();
 
-    let reduce_balance_msg = SubMsg::new(CosmosMsg::Wasm(cosmwasm_std::WasmMsg::Execute {
-        contract_addr,
-        msg: to_binary(&ExecuteMsg::ReduceChannelBalanceIbcReceive {
+    // because we are transferring back, we reduce the channel's balance. If it is too low, the packet is over the
+    // outflow cap or its channel or mapping is paused, the caller falls back to paying the receiver locally
+    assert_not_paused(
+        storage,
+        src_channel,
+        &pair_mapping.0,
+        FlowDirection::Outflow,
+    )
+    .map_err(|err| StdError::generic_err(err.to_string()))?;
+    check_rate_limited_flow(
+        storage,
+        now,
+        src_channel,
+        &pair_mapping.0,
+        remote_amount,
+        FlowDirection::Outflow,
+    )
+    .map_err(|err| StdError::generic_err(err.to_string()))?;
+    reduce_channel_balance(storage, src_channel, &pair_mapping.0, remote_amount)
+        .map_err(|err| StdError::generic_err(err.to_string()))?;
+    record_rate_limited_flow(
+        storage,
+        now,
+        src_channel,
+        &pair_mapping.0,
+        remote_amount,
+        FlowDirection::Outflow,
+    )
+    .map_err(|err| StdError::generic_err(err.to_string()))?;
+
+    // the local receiver of the inbound packet gets the refund if this follow-up packet fails. Fees were charged on the inbound leg
+    let nonce = next_reply_nonce(storage)?;
+    UNSEQUENCED_TRANSFERS.save(
+        storage,
+        nonce,
+        &PendingTransfer {
             src_channel_id: src_channel.to_string(),
-            ibc_denom: pair_mapping.0.clone(),
-            amount: remote_amount.clone(),
-            local_receiver: local_receiver.to_string(),
-        })?,
-        funds: vec![],
-    }));
-
-    Ok(vec![
-        reduce_balance_msg,
-        SubMsg::reply_on_error(msg, FOLLOW_UP_IBC_SEND_FAILURE_ID),
-    ])
+            local_sender: local_receiver.to_string(),
+            local_amount: Amount::from_parts(
+                parse_asset_info_denom(pair_mapping.1.asset_info.clone()),
+                amount,
+            ),
+            token_fee: Uint128::zero(),
+            relayer_fee: Uint128::zero(),
+            relayer_fee_on_ack: false,
+            ibc_denom: pair_mapping.0,
+            remote_amount,
+            mapping: Some(pair_mapping.1),
+            sent_at: Some(now),
+        },
+    )?;
+
+    Ok(vec![SubMsg::reply_always(
+        msg,
+        encode_reply_id(FOLLOW_UP_IBC_SEND_FAILURE_ID, nonce),
+    )])
 }
 
 pub fn check_gas_limit(deps: Deps, amount: &Amount) -> Result<Option<u64>, ContractError> {
@@ -878,18 +1550,22 @@ pub fn check_gas_limit(deps: Deps, amount: &Amount) -> Result<Option<u64>, Contr
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn process_deduct_fee(
-    storage: &mut dyn Storage,
+    storage: &dyn Storage,
     querier: &QuerierWrapper,
     api: &dyn Api,
+    local_address: &str,
     remote_sender: &str,
     remote_token_denom: &str,
     local_amount: Amount, // local amount
-    swap_router_contract: &RouterController,
+    config: &Config,
 ) -> StdResult<FeeData> {
     let local_denom = local_amount.denom();
-    let (deducted_amount, token_fee) =
-        deduct_token_fee(storage, remote_token_denom, local_amount.amount())?;
+    let discount = get_fee_discount(storage, local_address, remote_sender)?;
+    let (_, token_fee) = deduct_token_fee(storage, remote_token_denom, local_amount.amount())?;
+    let token_fee = apply_fee_discount(token_fee, discount)?;
+    let deducted_amount = local_amount.amount().checked_sub(token_fee)?;
     // simulate for relayer fee
     let ask_asset_info = denom_to_asset_info(querier, api, &local_amount.raw_denom())?;
 
@@ -900,8 +1576,9 @@ pub fn process_deduct_fee(
         remote_sender,
         remote_token_denom,
         ask_asset_info,
-        swap_router_contract,
+        config,
     )?;
+    let relayer_fee = apply_fee_discount(relayer_fee, discount)?;
 
     let mut fee_data = FeeData {
         deducted_amount: deducted_amount.checked_sub(relayer_fee).unwrap_or_default(),
@@ -926,14 +1603,44 @@ pub fn process_deduct_fee(
     Ok(fee_data)
 }
 
+/// the largest discount among the exemptions of the local address, the remote address and its bech32 prefix
+pub fn get_fee_discount(
+    storage: &dyn Storage,
+    local_address: &str,
+    remote_address: &str,
+) -> StdResult<Decimal> {
+    let mut subjects = vec![
+        FeeExemptionSubject::LocalAddress {
+            address: local_address.to_string(),
+        },
+        FeeExemptionSubject::RemoteAddress {
+            address: remote_address.to_string(),
+        },
+    ];
+    if let Ok(prefix) = get_prefix_decode_bech32(remote_address) {
+        subjects.push(FeeExemptionSubject::RemotePrefix { prefix });
+    }
+    let mut discount = Decimal::zero();
+    for subject in subjects {
+        if let Some(exemption) = FEE_EXEMPTIONS.may_load(storage, &subject.to_string())? {
+            discount = discount.max(exemption.discount());
+        }
+    }
+    Ok(discount)
+}
+
+pub fn apply_fee_discount(fee: Uint128, discount: Decimal) -> StdResult<Uint128> {
+    Ok(fee.checked_sub(fee.mul(discount.min(Decimal::one())))?)
+}
+
 pub fn deduct_token_fee(
-    storage: &mut dyn Storage,
+    storage: &dyn Storage,
     remote_token_denom: &str,
     amount: Uint128,
 ) -> StdResult<(Uint128, Uint128)> {
-    let token_fee = TOKEN_FEE.may_load(storage, &remote_token_denom)?;
+    let token_fee = TOKEN_FEE.may_load(storage, remote_token_denom)?;
     if let Some(token_fee) = token_fee {
-        let fee = deduct_fee(token_fee, amount);
+        let fee = compute_token_fee(&token_fee, amount)?;
         let new_deducted_amount = amount.checked_sub(fee)?;
         return Ok((new_deducted_amount, fee));
     }
@@ -941,44 +1648,79 @@ pub fn deduct_token_fee(
 }
 
 pub fn deduct_relayer_fee(
-    storage: &mut dyn Storage,
+    storage: &dyn Storage,
     _api: &dyn Api,
     querier: &QuerierWrapper,
     remote_address: &str,
     remote_token_denom: &str,
     ask_asset_info: AssetInfo,
-    swap_router_contract: &RouterController,
+    config: &Config,
 ) -> StdResult<Uint128> {
     // this is bech32 prefix of sender from other chains. Should not error because we are in the cosmos ecosystem. Every address should have prefix
     // evm case, need to filter remote token denom since prefix is always oraib
     let prefix_result = get_prefix_decode_bech32(remote_address);
     // api.debug(format!("prefix: {}", prefix).as_str());
-    let prefix: String = if prefix_result.is_err() {
-        convert_remote_denom_to_evm_prefix(remote_token_denom)
-    } else {
-        let prefix = prefix_result.unwrap();
-        if prefix.eq(ORAIBRIDGE_PREFIX) {
-            convert_remote_denom_to_evm_prefix(remote_token_denom)
-        } else {
-            prefix
+    let prefix: String = match prefix_result {
+        Err(_) => convert_remote_denom_to_evm_prefix(remote_token_denom),
+        Ok(prefix) => {
+            if prefix.eq(ORAIBRIDGE_PREFIX) {
+                convert_remote_denom_to_evm_prefix(remote_token_denom)
+            } else {
+                prefix
+            }
         }
     };
     // api.debug(format!("prefix after evm prefix: {}", prefix).as_str());
-    let relayer_fee = RELAYER_FEE.may_load(storage, &prefix)?;
-    // api.debug(format!("relayer fee: {}", relayer_fee.unwrap_or_default()).as_str());
+    // the fee of the token on that network wins over the default of the network
+    let relayer_fee = match RELAYER_FEE.may_load(storage, (&prefix, remote_token_denom))? {
+        Some(relayer_fee) => Some(relayer_fee),
+        None => RELAYER_FEE.may_load(storage, (&prefix, ""))?,
+    };
     // no need to deduct fee if no fee is found in the mapping
     if relayer_fee.is_none() {
         return Ok(Uint128::from(0u64));
     }
 
-    let relayer_fee = get_swap_token_amount_out_from_orai(
+    convert_relayer_fee(
+        storage,
         querier,
-        relayer_fee.unwrap(),
-        swap_router_contract,
-        ask_asset_info,
-    );
+        config,
+        &relayer_fee.unwrap(),
+        &ask_asset_info,
+    )
+}
 
-    Ok(relayer_fee)
+/// Converts a relayer fee from its fee asset into the asset, through the price of both in the fee denom
+pub fn convert_relayer_fee(
+    storage: &dyn Storage,
+    querier: &QuerierWrapper,
+    config: &Config,
+    relayer_fee: &RelayerFeeSchedule,
+    ask_asset_info: &AssetInfo,
+) -> StdResult<Uint128> {
+    let fee_asset = match &relayer_fee.fee_asset {
+        None => {
+            return convert_fee_denom_amount(
+                storage,
+                querier,
+                config,
+                relayer_fee.fee,
+                ask_asset_info,
+            )
+        }
+        Some(fee_asset) if fee_asset.eq(ask_asset_info) => return Ok(relayer_fee.fee),
+        Some(fee_asset) => fee_asset,
+    };
+    let unit = Uint128::from(FEE_DENOM_UNIT);
+    let fee_asset_per_unit = convert_fee_denom_amount(storage, querier, config, unit, fee_asset)?;
+    // the zero fallback charges nothing when the fee asset has no price
+    if fee_asset_per_unit.is_zero() {
+        return Ok(Uint128::zero());
+    }
+    let ask_per_unit = convert_fee_denom_amount(storage, querier, config, unit, ask_asset_info)?;
+    Ok(relayer_fee
+        .fee
+        .multiply_ratio(ask_per_unit, fee_asset_per_unit))
 }
 
 pub fn deduct_fee(token_fee: Ratio, amount: Uint128) -> Uint128 {
@@ -992,36 +1734,161 @@ pub fn deduct_fee(token_fee: Ratio, amount: Uint128) -> Uint128 {
     ))
 }
 
-pub fn get_swap_token_amount_out_from_orai(
+/// ratio of the amount plus the flat fee, clamped between min and max. The fee never exceeds the amount
+pub fn compute_token_fee(schedule: &TokenFeeSchedule, amount: Uint128) -> StdResult<Uint128> {
+    let mut fee = deduct_fee(schedule.ratio.clone(), amount)
+        .checked_add(schedule.flat.unwrap_or_default())?;
+    if let Some(min) = schedule.min {
+        fee = fee.max(min);
+    }
+    if let Some(max) = schedule.max {
+        fee = fee.min(max);
+    }
+    Ok(fee.min(amount))
+}
+
+pub fn get_swap_token_amount_out_from_fee_denom(
     querier: &QuerierWrapper,
     offer_amount: Uint128,
     swap_router_contract: &RouterController,
+    fee_denom: &str,
     ask_asset_info: AssetInfo,
-) -> Uint128 {
-    let orai_asset_info = AssetInfo::NativeToken {
-        denom: "orai".to_string(),
+) -> Option<Uint128> {
+    let fee_denom_asset_info = AssetInfo::NativeToken {
+        denom: fee_denom.to_string(),
     };
-    if ask_asset_info.eq(&orai_asset_info) {
-        return offer_amount;
+    if ask_asset_info.eq(&fee_denom_asset_info) {
+        return Some(offer_amount);
     }
-    let token_price = swap_router_contract
+
+    swap_router_contract
         .simulate_swap(
             querier,
             offer_amount,
             vec![SwapOperation::OraiSwap {
-                offer_asset_info: orai_asset_info,
-                // always swap with orai. If it does not share a pool with ORAI => ignore, no fee
+                offer_asset_info: fee_denom_asset_info,
+                // always swap with the fee denom. If it does not share a pool with it => no price
                 ask_asset_info,
             }],
         )
         .map(|data| data.amount)
-        .unwrap_or_default();
-    token_price
+        .ok()
+}
+
+// amount of the asset the price source gives for the offer amount of fee denom. None if it has no price
+fn quote_fee_denom_amount(
+    storage: &dyn Storage,
+    querier: &QuerierWrapper,
+    config: &Config,
+    offer_amount: Uint128,
+    ask_asset_info: &AssetInfo,
+) -> StdResult<Option<Uint128>> {
+    let fee_denom_asset_info = AssetInfo::NativeToken {
+        denom: config.fee_denom.clone(),
+    };
+    let quote = match &config.price_source {
+        // a registered route from the fee denom is priced along its operations
+        PriceSource::Router {} => match SWAP_ROUTES.may_load(
+            storage,
+            (
+                &fee_denom_asset_info.to_string(),
+                &ask_asset_info.to_string(),
+            ),
+        )? {
+            Some(operations) => config
+                .swap_router_contract
+                .simulate_swap(querier, offer_amount, operations)
+                .map(|data| data.amount)
+                .ok(),
+            None => get_swap_token_amount_out_from_fee_denom(
+                querier,
+                offer_amount,
+                &config.swap_router_contract,
+                &config.fee_denom,
+                ask_asset_info.clone(),
+            ),
+        },
+        PriceSource::Fixed {} => FIXED_PRICES
+            .may_load(storage, &ask_asset_info.to_string())?
+            .map(|price| offer_amount.mul(price)),
+        PriceSource::Oracle { contract } => querier
+            .query_wasm_smart::<OraclePriceResponse>(
+                contract,
+                &OracleQueryMsg::Price {
+                    base: config.fee_denom.clone(),
+                    quote: ask_asset_info.clone(),
+                },
+            )
+            .map(|res| offer_amount.mul(res.price))
+            .ok(),
+    };
+    Ok(quote)
+}
+
+/// Converts an amount of the fee denom into the asset with the configured price source.
+/// Without a price, the fallback policy decides
+pub fn convert_fee_denom_amount(
+    storage: &dyn Storage,
+    querier: &QuerierWrapper,
+    config: &Config,
+    offer_amount: Uint128,
+    ask_asset_info: &AssetInfo,
+) -> StdResult<Uint128> {
+    let is_fee_denom =
+        matches!(ask_asset_info, AssetInfo::NativeToken { denom } if denom == &config.fee_denom);
+    if offer_amount.is_zero() || is_fee_denom {
+        return Ok(offer_amount);
+    }
+    if let Some(amount) =
+        quote_fee_denom_amount(storage, querier, config, offer_amount, ask_asset_info)?
+    {
+        return Ok(amount);
+    }
+    let unavailable = || {
+        StdError::generic_err(
+            ContractError::PriceUnavailable {
+                asset: ask_asset_info.to_string(),
+            }
+            .to_string(),
+        )
+    };
+    match config.price_fallback {
+        PriceFallback::Zero {} => Ok(Uint128::zero()),
+        PriceFallback::Reject {} => Err(unavailable()),
+        PriceFallback::LastKnown {} => LAST_KNOWN_PRICES
+            .may_load(storage, &ask_asset_info.to_string())?
+            .map(|price| offer_amount.mul(price))
+            .ok_or_else(unavailable),
+    }
+}
+
+// relayer fees in the fee denom are set with 6 decimals, so this is one unit of the fee denom
+const FEE_DENOM_UNIT: u128 = 1_000_000;
+
+/// Remembers the current price of the asset for the last known price fallback. Only queried when that fallback is set
+pub fn record_fee_denom_price(
+    storage: &mut dyn Storage,
+    querier: &QuerierWrapper,
+    config: &Config,
+    ask_asset_info: &AssetInfo,
+) -> StdResult<()> {
+    if !matches!(config.price_fallback, PriceFallback::LastKnown {}) {
+        return Ok(());
+    }
+    let unit = Uint128::from(FEE_DENOM_UNIT);
+    if let Some(amount) = quote_fee_denom_amount(storage, querier, config, unit, ask_asset_info)? {
+        LAST_KNOWN_PRICES.save(
+            storage,
+            &ask_asset_info.to_string(),
+            &Decimal::from_ratio(amount, unit),
+        )?;
+    }
+    Ok(())
 }
 
 pub fn convert_remote_denom_to_evm_prefix(remote_denom: &str) -> String {
     match remote_denom.split_once("0x") {
-        Some((evm_prefix, _)) => return evm_prefix.to_string(),
+        Some((evm_prefix, _)) => evm_prefix.to_string(),
         None => "".to_string(),
     }
 }
@@ -1079,7 +1946,7 @@ pub fn ibc_packet_ack(
     // retried again and again. is that good?
     let ics20msg: Ics20Ack = from_binary(&msg.acknowledgement.data)?;
     match ics20msg {
-        Ics20Ack::Result(_) => on_packet_success(deps, msg.original_packet),
+        Ics20Ack::Result(_) => on_packet_success(deps, msg.original_packet, &msg.relayer),
         Ics20Ack::Error(err) => on_packet_failure(deps, msg.original_packet, err),
     }
 }
@@ -1096,8 +1963,23 @@ pub fn ibc_packet_timeout(
 }
 
 // update the balance stored on this (channel, denom) index
-fn on_packet_success(_deps: DepsMut, packet: IbcPacket) -> Result<IbcBasicResponse, ContractError> {
+fn on_packet_success(
+    deps: DepsMut,
+    packet: IbcPacket,
+    relayer: &Addr,
+) -> Result<IbcBasicResponse, ContractError> {
     let msg: Ics20Packet = from_binary(&packet.data)?;
+    if let Some(transfer) =
+        PENDING_TRANSFERS.may_load(deps.storage, (&packet.src.channel_id, packet.sequence))?
+    {
+        PENDING_TRANSFERS.remove(deps.storage, (&packet.src.channel_id, packet.sequence));
+        // the relayer of the ack is the one that got the packet delivered
+        if transfer.relayer_fee_on_ack {
+            let relayer_fee =
+                Amount::from_parts(transfer.local_amount.denom(), transfer.relayer_fee);
+            credit_relayer_fee(deps.storage, relayer, &relayer_fee)?;
+        }
+    }
 
     // similar event messages like ibctransfer module
     let attributes = vec![
@@ -1107,6 +1989,7 @@ fn on_packet_success(_deps: DepsMut, packet: IbcPacket) -> Result<IbcBasicRespon
         attr("denom", &msg.denom),
         attr("amount", msg.amount),
         attr("success", "true"),
+        attr("relayer", relayer),
     ];
 
     // if let Some(memo) = msg.memo {
@@ -1128,14 +2011,51 @@ fn on_packet_failure(
 ) -> Result<IbcBasicResponse, ContractError> {
     let msg: Ics20Packet = from_binary(&packet.data)?;
 
-    // in case that the denom is not in the mapping list, meaning that it is not transferred back, but transfer originally from this local chain
-    if ics20_denoms().may_load(deps.storage, &msg.denom)?.is_none() {
-        return Ok(IbcBasicResponse::new());
+    // refund from the snapshot taken when the packet was sent, so later mapping changes cannot affect it
+    if let Some(transfer) =
+        PENDING_TRANSFERS.may_load(deps.storage, (&packet.src.channel_id, packet.sequence))?
+    {
+        PENDING_TRANSFERS.remove(deps.storage, (&packet.src.channel_id, packet.sequence));
+        let sub_msg = refund_pending_transfer(deps.storage, &transfer)?;
+        return Ok(IbcBasicResponse::new()
+            .add_submessage(sub_msg)
+            .add_attribute("action", "acknowledge")
+            .add_attribute("sender", msg.sender)
+            .add_attribute("receiver", msg.receiver)
+            .add_attribute("denom", msg.denom)
+            .add_attribute("amount", msg.amount.to_string())
+            .add_attribute("refund_recipient", transfer.local_sender)
+            .add_attribute("refund_amount", transfer.local_amount.amount().to_string())
+            .add_attribute("success", "false")
+            .add_attribute("error", err));
     }
 
-    let sub_msg = handle_packet_refund(deps.storage, &msg.sender, &msg.denom, msg.amount)?;
-    // since we reduce the channel's balance optimistically when transferring back, we undo reduce it again when receiving failed ack
-    undo_reduce_channel_balance(deps.storage, &packet.src.channel_id, &msg.denom, msg.amount)?;
+    // packets sent before the transfers were snapshotted are refunded from the packet itself
+    let sub_msg = if ics20_denoms().may_load(deps.storage, &msg.denom)?.is_some() {
+        let sub_msg = handle_packet_refund(deps.storage, &msg.sender, &msg.denom, msg.amount)?;
+        // since we reduce the channel's balance optimistically when transferring back, we undo reduce it again when receiving failed ack
+        undo_reduce_channel_balance(deps.storage, &packet.src.channel_id, &msg.denom, msg.amount)?;
+        sub_msg
+    } else if CHANNEL_FORWARD_STATE.has(deps.storage, (&packet.src.channel_id, &msg.denom)) {
+        // the denom of a token that originated on this chain is its local denom, and it was escrowed when sent
+        undo_increase_channel_forward_balance(
+            deps.storage,
+            &packet.src.channel_id,
+            &msg.denom,
+            msg.amount,
+        )?;
+        let to_send = Amount::from_parts(msg.denom.clone(), msg.amount);
+        let cosmos_msg = to_send.send_amount(msg.sender.clone(), None);
+        build_payout_sub_msg(
+            deps.storage,
+            cosmos_msg,
+            REFUND_FAILURE_ID,
+            &msg.sender,
+            to_send,
+        )?
+    } else {
+        return Ok(IbcBasicResponse::new());
+    };
 
     let res = IbcBasicResponse::new()
         .add_submessage(sub_msg)
@@ -1152,6 +2072,57 @@ fn on_packet_failure(
     // send ack fail to custom contract for refund
 }
 
+// undo the optimistic channel balance reduction of a failed outgoing packet and refund its local sender
+pub fn refund_pending_transfer(
+    storage: &mut dyn Storage,
+    transfer: &PendingTransfer,
+) -> Result<SubMsg, ContractError> {
+    // nobody delivered the packet, so the held relayer fee goes to the relayer fee receiver
+    if transfer.relayer_fee_on_ack {
+        accumulate_fee(
+            storage,
+            RELAYER_FEE_ACCUMULATOR,
+            &Amount::from_parts(transfer.local_amount.denom(), transfer.relayer_fee),
+        )?;
+    }
+    match transfer.mapping {
+        Some(_) => undo_reduce_channel_balance(
+            storage,
+            &transfer.src_channel_id,
+            &transfer.ibc_denom,
+            transfer.remote_amount,
+        )?,
+        // local-origin tokens were escrowed, so the escrow is released instead
+        None => undo_increase_channel_forward_balance(
+            storage,
+            &transfer.src_channel_id,
+            &transfer.ibc_denom,
+            transfer.remote_amount,
+        )?,
+    }
+    // the tokens never left, so they no longer count towards the outflow cap
+    if let Some(sent_at) = transfer.sent_at {
+        release_rate_limited_outflow(
+            storage,
+            sent_at,
+            &transfer.src_channel_id,
+            &transfer.ibc_denom,
+            transfer.remote_amount,
+        )?;
+    }
+    let cosmos_msg = transfer
+        .local_amount
+        .send_amount(transfer.local_sender.clone(), None);
+    // same as handle_packet_refund, a failed refund is left for manual handling
+    Ok(build_payout_sub_msg(
+        storage,
+        cosmos_msg,
+        REFUND_FAILURE_ID,
+        &transfer.local_sender,
+        transfer.local_amount.clone(),
+    )?)
+}
+
 pub fn handle_packet_refund(
     storage: &mut dyn Storage,
     packet_sender: &str,
@@ -1159,7 +2130,7 @@ pub fn handle_packet_refund(
     packet_amount: Uint128,
 ) -> Result<SubMsg, ContractError> {
     // get ibc denom mapping to get cw20 denom & from decimals in case of packet failure, we can refund the corresponding user & amount
-    let pair_mapping = ics20_denoms().load(storage, &packet_denom)?;
+    let pair_mapping = ics20_denoms().load(storage, packet_denom)?;
     let to_send = Amount::from_parts(
         parse_asset_info_denom(pair_mapping.asset_info),
         convert_remote_to_local(
@@ -1170,9 +2141,15 @@ pub fn handle_packet_refund(
     );
     let cosmos_msg = to_send.send_amount(packet_sender.to_string(), None);
 
-    // used submsg here & reply on error. This means that if the refund process fails => tokens will be locked in this IBC Wasm contract. We will manually handle that case. No retry
+    // used submsg here. This means that if the refund process fails => tokens will be locked in this IBC Wasm contract and recorded as a stuck fund. We will manually handle that case. No retry
     // similar event messages like ibctransfer module
-    Ok(SubMsg::reply_on_error(cosmos_msg, REFUND_FAILURE_ID))
+    Ok(build_payout_sub_msg(
+        storage,
+        cosmos_msg,
+        REFUND_FAILURE_ID,
+        packet_sender,
+        to_send,
+    )?)
 }
 
 pub fn build_ibc_send_packet(
@@ -1186,11 +2163,9 @@ pub fn build_ibc_send_packet(
 ) -> StdResult<IbcMsg> {
     // build ics20 packet
     let packet = Ics20Packet::new(
-        amount.clone(),
+        amount,
         denom, // we use ibc denom in form <transfer>/<channel>/<denom> so that when it is sent back to remote chain, it gets parsed correctly and burned
-        sender,
-        receiver,
-        memo,
+        sender, receiver, memo,
     );
     packet
         .validate()
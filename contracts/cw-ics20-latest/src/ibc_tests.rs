@@ -1,35 +1,60 @@
 #[cfg(test)]
+#[allow(
+    noop_method_call,
+    clippy::bool_assert_comparison,
+    clippy::clone_on_copy,
+    clippy::needless_borrow,
+    clippy::redundant_field_names,
+    clippy::unnecessary_unwrap,
+    clippy::useless_conversion
+)]
 mod test {
-    use cosmwasm_std::{coin, Addr, CosmosMsg, IbcTimeout, StdError};
-    use cw20_ics20_msg::receiver::DestinationInfo;
+    use cosmwasm_std::{
+        coin, Addr, BankMsg, Binary, CosmosMsg, Decimal, DepsMut, Event, IbcChannelCloseMsg,
+        IbcTimeout, Order, StdError, StdResult, Storage, WasmQuery,
+    };
+    use cw20_ics20_msg::receiver::{ContractHook, DestinationInfo};
     use oraiswap::asset::AssetInfo;
-    use oraiswap::router::{RouterController, SwapOperation};
+    use oraiswap::router::{
+        QueryMsg as RouterQueryMsg, RouterController, SimulateSwapOperationsResponse, SwapOperation,
+    };
 
+    use crate::ibc::{
+        apply_slippage, build_swap_operations, decode_reply_id, get_follow_up_msgs, reply,
+    };
     use crate::ibc::{
         build_ibc_msg, build_swap_msgs, convert_remote_denom_to_evm_prefix, deduct_fee,
-        deduct_relayer_fee, deduct_token_fee, get_swap_token_amount_out_from_orai,
-        ibc_packet_receive, parse_ibc_channel_without_sanity_checks,
-        parse_ibc_denom_without_sanity_checks, parse_voucher_denom, process_ibc_msg, Ics20Ack,
-        Ics20Packet, FOLLOW_UP_IBC_SEND_FAILURE_ID, IBC_TRANSFER_NATIVE_ERROR_ID,
-        NATIVE_RECEIVE_ID, SWAP_OPS_FAILURE_ID,
+        deduct_relayer_fee, deduct_token_fee, encode_reply_id,
+        get_swap_token_amount_out_from_fee_denom, ibc_channel_close, ibc_packet_receive,
+        parse_ibc_channel_without_sanity_checks, parse_ibc_denom_without_sanity_checks,
+        parse_voucher_denom, process_ibc_msg, Ics20Ack, Ics20Packet, FOLLOW_UP_IBC_SEND_FAILURE_ID,
+        HOOK_FAILURE_ID, IBC_TRANSFER_NATIVE_ERROR_ID, NATIVE_RECEIVE_ID, REFUND_FAILURE_ID,
+        SWAP_OPS_FAILURE_ID,
     };
-    use crate::ibc::{build_swap_operations, get_follow_up_msgs};
     use crate::test_helpers::*;
     use cosmwasm_std::{
-        from_binary, to_binary, IbcEndpoint, IbcMsg, IbcPacket, IbcPacketReceiveMsg, SubMsg,
-        Timestamp, Uint128, WasmMsg,
+        from_binary, to_binary, ContractResult, IbcEndpoint, IbcMsg, IbcPacket,
+        IbcPacketReceiveMsg, Reply, SubMsg, SubMsgResponse, SubMsgResult, SystemResult, Timestamp,
+        Uint128, WasmMsg,
     };
 
     use crate::error::ContractError;
     use crate::state::{
-        get_key_ics20_ibc_denom, increase_channel_balance, ChannelState, MappingMetadata, Ratio,
-        CHANNEL_REVERSE_STATE, RELAYER_FEE, TOKEN_FEE,
+        get_key_ics20_ibc_denom, increase_channel_balance, ChannelState, Config, MappingMetadata,
+        PauseScope, PriceFallback, PriceSource, RateLimit, Ratio, RecoverableFund,
+        RelayerFeeSchedule, ReplyArgs, SwapFollowUp, TokenFeeSchedule, CHANNEL_INFO,
+        CHANNEL_RECOVERABLE_FUNDS, CHANNEL_REVERSE_STATE, CONFIG, FIXED_PRICES, LAST_KNOWN_PRICES,
+        RATE_LIMITS, RECEIVE_LOCK, RELAYER_BALANCES, RELAYER_FEE, REPLY_ARGS, TOKEN_FEE,
+        TOKEN_FEE_ACCUMULATOR,
     };
     use cw20::{Cw20Coin, Cw20ExecuteMsg};
     use cw20_ics20_msg::amount::{convert_local_to_remote, Amount};
 
-    use crate::contract::execute;
-    use crate::msg::{ExecuteMsg, UpdatePairMsg};
+    use crate::contract::{execute, query};
+    use crate::msg::{
+        AccumulatedFeesResponse, ExecuteMsg, OraclePriceResponse, OracleQueryMsg, QueryMsg,
+        UpdatePairMsg,
+    };
     use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
     use cosmwasm_std::{coins, to_vec};
 
@@ -263,9 +288,12 @@ mod test {
             .save(
                 deps.as_mut().storage,
                 denom,
-                &Ratio {
-                    nominator: 1,
-                    denominator: 10,
+                &TokenFeeSchedule {
+                    ratio: Ratio {
+                        nominator: 1,
+                        denominator: 10,
+                    },
+                    ..Default::default()
                 },
             )
             .unwrap();
@@ -299,50 +327,100 @@ mod test {
         let msg = IbcPacketReceiveMsg::new(recv_packet.clone(), relayer);
         let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
         println!("res: {:?}", res);
-        // TODO: fix test cases. Possibly because we are adding two add_submessages?
-        assert_eq!(res.messages.len(), 3); // 3 messages because we also have deduct fee msg and increase channel balance msg
-        match res.messages[0].msg.clone() {
-            CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr,
-                msg,
-                funds: _,
-            }) => {
-                assert_eq!(contract_addr, cw20_addr);
-                assert_eq!(
-                    msg,
-                    to_binary(&Cw20ExecuteMsg::Transfer {
-                        recipient: "gov".to_string(),
-                        amount: Uint128::from(87654321u64) // send amount / token fee
-                    })
-                    .unwrap()
-                );
-            }
-            _ => panic!("Unexpected return message: {:?}", res.messages[0]),
-        }
+        // the token fee stays on the contract until CollectFees
+        assert_eq!(res.messages.len(), 1);
+        let fees: AccumulatedFeesResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::AccumulatedFees {}).unwrap())
+                .unwrap();
+        assert_eq!(fees.token_fees, vec![Amount::cw20(87654321u128, cw20_addr)]);
         let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
         assert!(matches!(ack, Ics20Ack::Result(_)));
 
-        // query channel state|_|
-        match res.messages[1].msg.clone() {
-            CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr,
-                msg,
-                funds: _,
-            }) => {
-                assert_eq!(contract_addr, "cosmos2contract".to_string()); // self-call msg
-                assert_eq!(
-                    msg,
-                    to_binary(&ExecuteMsg::IncreaseChannelBalanceIbcReceive {
-                        dest_channel_id: send_channel.to_string(),
-                        ibc_denom: get_key_ics20_ibc_denom(CONTRACT_PORT, send_channel, denom),
-                        amount: send_amount,
-                        local_receiver: custom_addr.to_string(),
-                    })
-                    .unwrap()
-                );
-            }
-            _ => panic!("Unexpected return message: {:?}", res.messages[0]),
-        }
+        // query channel state, the balance is increased within the receive
+        let channel_state = CHANNEL_REVERSE_STATE
+            .load(
+                deps.as_ref().storage,
+                (
+                    send_channel,
+                    &get_key_ics20_ibc_denom(CONTRACT_PORT, send_channel, denom),
+                ),
+            )
+            .unwrap();
+        assert_eq!(channel_state.outstanding, send_amount);
+        assert_eq!(channel_state.total_sent, send_amount);
+    }
+
+    #[test]
+    fn send_from_remote_to_invalid_receiver_leaves_no_accounting() {
+        let relayer = Addr::unchecked("relayer");
+        let send_channel = "channel-9";
+        let cw20_addr = "token-addr";
+        let denom = "uatom0x";
+        let asset_info = AssetInfo::Token {
+            contract_addr: Addr::unchecked(cw20_addr),
+        };
+        let mut deps = setup(&[send_channel], &[(cw20_addr, 1234567)]);
+        TOKEN_FEE
+            .save(
+                deps.as_mut().storage,
+                denom,
+                &TokenFeeSchedule {
+                    ratio: Ratio {
+                        nominator: 1,
+                        denominator: 10,
+                    },
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        RELAYER_FEE
+            .save(
+                deps.as_mut().storage,
+                ("orai", ""),
+                &RelayerFeeSchedule {
+                    fee: Uint128::from(1000u64),
+                    fee_asset: Some(asset_info.clone()),
+                },
+            )
+            .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                local_channel_id: send_channel.to_string(),
+                denom: denom.to_string(),
+                local_asset_info: asset_info,
+                remote_decimals: 18u8,
+                local_asset_info_decimals: 18u8,
+            }),
+        )
+        .unwrap();
+
+        // the receiver is not a valid address on this chain
+        let recv_packet = mock_receive_packet_remote_to_local(
+            send_channel,
+            876543210,
+            denom,
+            "Invalid Receiver",
+            Some("orai1cdhkt9ps47hwn9sqren70uw9cyrfka9fpauuks"),
+        );
+        let msg = IbcPacketReceiveMsg::new(recv_packet, relayer.clone());
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert!(matches!(ack, Ics20Ack::Error(_)));
+        assert!(res.messages.is_empty());
+
+        // the remote chain refunds the sender, so neither the balance nor the fees may count the packet
+        let ibc_denom = get_key_ics20_ibc_denom(CONTRACT_PORT, send_channel, denom);
+        assert!(CHANNEL_REVERSE_STATE
+            .may_load(deps.as_ref().storage, (send_channel, &ibc_denom))
+            .unwrap()
+            .is_none());
+        assert!(TOKEN_FEE_ACCUMULATOR.is_empty(deps.as_ref().storage));
+        assert!(RELAYER_BALANCES
+            .prefix(&relayer)
+            .is_empty(deps.as_ref().storage));
     }
 
     #[test]
@@ -442,6 +520,7 @@ mod test {
             to.clone(),
             &mut cosmos_msgs,
             operations.clone(),
+            1,
         )
         .unwrap();
         assert_eq!(cosmos_msgs.len(), 0);
@@ -457,6 +536,7 @@ mod test {
             to.clone(),
             &mut cosmos_msgs,
             operations.clone(),
+            1,
         )
         .unwrap();
         // send in Cw20 send
@@ -475,6 +555,7 @@ mod test {
             to.clone(),
             &mut cosmos_msgs,
             operations.clone(),
+            1,
         )
         .unwrap();
         assert_eq!(
@@ -482,7 +563,7 @@ mod test {
             format!("{:?}", cosmos_msgs[0]).contains("execute_swap_operations")
         );
         assert_eq!(
-            SubMsg::reply_on_error(
+            SubMsg::reply_always(
                 CosmosMsg::Wasm(WasmMsg::Execute {
                     contract_addr: swap_router_contract.to_string(),
                     msg: to_binary(&oraiswap::router::ExecuteMsg::ExecuteSwapOperations {
@@ -493,7 +574,7 @@ mod test {
                     .unwrap(),
                     funds: coins(amount.u128(), native_denom)
                 }),
-                SWAP_OPS_FAILURE_ID
+                encode_reply_id(SWAP_OPS_FAILURE_ID, 1)
             ),
             cosmos_msgs[0]
         );
@@ -512,15 +593,15 @@ mod test {
             offer_asset_info: initial_receive_asset_info.clone(),
             ask_asset_info: initial_receive_asset_info.clone(),
         }];
-        cosmos_msgs.push(SubMsg::new(CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+        cosmos_msgs.push(SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
             to_address: "foobar".to_string(),
             amount: coins(1u128, "orai"),
         })));
-        cosmos_msgs.push(SubMsg::new(CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+        cosmos_msgs.push(SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
             to_address: "foobar".to_string(),
             amount: coins(1u128, "orai"),
         })));
-        cosmos_msgs.push(SubMsg::new(CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+        cosmos_msgs.push(SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
             to_address: "foobar".to_string(),
             amount: coins(1u128, "orai"),
         })));
@@ -532,6 +613,7 @@ mod test {
             Some(Addr::unchecked("attacker")),
             &mut cosmos_msgs,
             operations.clone(),
+            1,
         )
         .unwrap();
         // should pop everything since 'to' is not None, and ops have items in it
@@ -562,6 +644,7 @@ mod test {
             receiver: "0x1234".to_string(),
             destination_channel: "channel-10".to_string(),
             destination_denom: "atom".to_string(),
+            ..Default::default()
         };
         let timeout = 1000u64;
         let local_receiver = "local_receiver";
@@ -570,6 +653,7 @@ mod test {
         destination.destination_channel = "".to_string();
 
         let err = build_ibc_msg(
+            deps.as_mut().storage,
             env.clone(),
             local_receiver,
             receive_channel,
@@ -589,6 +673,7 @@ mod test {
         destination.receiver = "trx-mainnet0x73Ddc880916021EFC4754Cb42B53db6EAB1f9D64".to_string();
         destination.destination_channel = send_channel.to_string();
         let err = build_ibc_msg(
+            deps.as_mut().storage,
             env.clone(),
             local_receiver,
             receive_channel,
@@ -629,6 +714,7 @@ mod test {
         destination.receiver = "trx-mainnet0x73Ddc880916021EFC4754Cb42B53db6EAB1f9D64".to_string();
         destination.destination_channel = update.local_channel_id;
         let result = build_ibc_msg(
+            deps.as_mut().storage,
             env.clone(),
             local_receiver,
             receive_channel,
@@ -648,8 +734,8 @@ mod test {
         .unwrap();
 
         assert_eq!(
-            result[1],
-            SubMsg::reply_on_error(
+            result[0],
+            SubMsg::reply_always(
                 CosmosMsg::Ibc(IbcMsg::SendPacket {
                     channel_id: receive_channel.to_string(),
                     data: to_binary(&Ics20Packet::new(
@@ -662,22 +748,16 @@ mod test {
                     .unwrap(),
                     timeout: env.block.time.plus_seconds(timeout).into()
                 }),
-                FOLLOW_UP_IBC_SEND_FAILURE_ID
+                encode_reply_id(FOLLOW_UP_IBC_SEND_FAILURE_ID, 1)
             )
         );
+        // the channel balance is reduced within the receive
         assert_eq!(
-            result[0],
-            SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: env.contract.address.into_string(),
-                msg: to_binary(&ExecuteMsg::ReduceChannelBalanceIbcReceive {
-                    src_channel_id: receive_channel.to_string(),
-                    ibc_denom: pair_mapping_key,
-                    amount: remote_amount,
-                    local_receiver: local_receiver.to_string()
-                })
-                .unwrap(),
-                funds: vec![]
-            }))
+            CHANNEL_REVERSE_STATE
+                .load(deps.as_ref().storage, (receive_channel, &pair_mapping_key))
+                .unwrap()
+                .outstanding,
+            Uint128::zero()
         );
     }
 
@@ -701,6 +781,7 @@ mod test {
             receiver: "cosmos1zedxv25ah8fksmg2lzrndrpkvsjqgk4zt5ff7n".to_string(),
             destination_channel: send_channel.to_string(),
             destination_denom: "atom".to_string(),
+            ..Default::default()
         };
         let env = mock_env();
         let remote_address = "foobar";
@@ -736,6 +817,7 @@ mod test {
 
         // cosmos based case but no mapping found. should be successful & cosmos msg is ibc transfer
         let result = build_ibc_msg(
+            deps.as_mut().storage,
             env.clone(),
             local_receiver,
             local_channel_id,
@@ -748,14 +830,14 @@ mod test {
         .unwrap();
         assert_eq!(
             result[0],
-            SubMsg::reply_on_error(
+            SubMsg::reply_always(
                 CosmosMsg::Ibc(IbcMsg::Transfer {
                     channel_id: send_channel.to_string(),
                     to_address: destination.receiver.clone(),
                     amount: coin(1000u128, "atom"),
                     timeout: mock_env().block.time.plus_seconds(timeout).into()
                 }),
-                IBC_TRANSFER_NATIVE_ERROR_ID
+                encode_reply_id(IBC_TRANSFER_NATIVE_ERROR_ID, 1)
             )
         );
 
@@ -785,8 +867,65 @@ mod test {
             )
             .unwrap();
 
+        // a paused outgoing channel stops the forward before the balance is reduced
+        let scope = PauseScope::Channel {
+            channel_id: send_channel.to_string(),
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::Pause {
+                scope: scope.clone(),
+            },
+        )
+        .unwrap();
+        let err = build_ibc_msg(
+            deps.as_mut().storage,
+            env.clone(),
+            local_receiver,
+            local_channel_id,
+            amount,
+            remote_address,
+            &destination,
+            timeout,
+            Some((
+                pair_mapping_key.clone(),
+                MappingMetadata {
+                    asset_info: receiver_asset_info.clone(),
+                    remote_decimals,
+                    asset_info_decimals,
+                },
+            )),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            StdError::generic_err(
+                ContractError::Paused {
+                    scope: format!("channel:{}", send_channel)
+                }
+                .to_string()
+            )
+        );
+        assert_eq!(
+            CHANNEL_REVERSE_STATE
+                .load(deps.as_ref().storage, (send_channel, &pair_mapping_key))
+                .unwrap()
+                .outstanding,
+            remote_amount
+        );
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::Unpause { scope },
+        )
+        .unwrap();
+
         // now we get ibc msg
         let result = build_ibc_msg(
+            deps.as_mut().storage,
             env.clone(),
             local_receiver,
             local_channel_id,
@@ -806,8 +945,8 @@ mod test {
         .unwrap();
 
         assert_eq!(
-            result[1],
-            SubMsg::reply_on_error(
+            result[0],
+            SubMsg::reply_always(
                 CosmosMsg::Ibc(IbcMsg::SendPacket {
                     channel_id: send_channel.to_string(),
                     data: to_binary(&Ics20Packet::new(
@@ -820,28 +959,23 @@ mod test {
                     .unwrap(),
                     timeout: env.block.time.plus_seconds(timeout).into()
                 }),
-                FOLLOW_UP_IBC_SEND_FAILURE_ID
+                encode_reply_id(FOLLOW_UP_IBC_SEND_FAILURE_ID, 2)
             )
         );
+        // the channel balance is reduced within the receive
         assert_eq!(
-            result[0],
-            SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: env.contract.address.into_string(),
-                msg: to_binary(&ExecuteMsg::ReduceChannelBalanceIbcReceive {
-                    src_channel_id: send_channel.to_string(),
-                    ibc_denom: pair_mapping_key,
-                    amount: remote_amount,
-                    local_receiver: local_receiver.to_string()
-                })
-                .unwrap(),
-                funds: vec![]
-            }))
+            CHANNEL_REVERSE_STATE
+                .load(deps.as_ref().storage, (send_channel, &pair_mapping_key))
+                .unwrap()
+                .outstanding,
+            Uint128::zero()
         );
     }
 
     #[test]
     fn test_get_ibc_msg_neither_cosmos_or_evm_based_case() {
         // setup
+        let mut deps = mock_dependencies();
         let amount = Uint128::from(1000u64);
         let local_channel_id = "channel";
         let local_receiver = "receiver";
@@ -850,11 +984,13 @@ mod test {
             receiver: "foo".to_string(),
             destination_channel: "channel-10".to_string(),
             destination_denom: "atom".to_string(),
+            ..Default::default()
         };
         let env = mock_env();
         let remote_address = "foobar";
         // cosmos based case but no mapping found. should be successful & cosmos msg is ibc transfer
         let result = build_ibc_msg(
+            deps.as_mut().storage,
             env.clone(),
             local_receiver,
             local_channel_id,
@@ -886,6 +1022,14 @@ mod test {
         let initial_asset_info = AssetInfo::Token {
             contract_addr: Addr::unchecked("addr"),
         };
+        let reply_args = ReplyArgs {
+            channel: local_channel.to_string(),
+            local_receiver: receiver.to_string(),
+            denom: "foo/bar/atom".to_string(),
+            amount,
+            minimum_receive: Uint128::zero(),
+            follow_up: None,
+        };
 
         // first case, memo empty => return send amount with receiver input
         let result = get_follow_up_msgs(
@@ -906,12 +1050,13 @@ mod test {
             &DestinationInfo::from_str(""),
             local_channel,
             None,
+            reply_args.clone(),
         )
         .unwrap();
 
         assert_eq!(
             result.sub_msgs,
-            vec![SubMsg::reply_on_error(
+            vec![SubMsg::reply_always(
                 CosmosMsg::Wasm(WasmMsg::Execute {
                     contract_addr: env.contract.address.to_string(),
                     msg: to_binary(&Cw20ExecuteMsg::Transfer {
@@ -921,7 +1066,7 @@ mod test {
                     .unwrap(),
                     funds: vec![]
                 }),
-                NATIVE_RECEIVE_ID
+                encode_reply_id(NATIVE_RECEIVE_ID, 1)
             )]
         );
 
@@ -945,12 +1090,13 @@ mod test {
             &DestinationInfo::from_str(memo),
             local_channel,
             None,
+            reply_args.clone(),
         )
         .unwrap();
 
         assert_eq!(
             result.sub_msgs,
-            vec![SubMsg::reply_on_error(
+            vec![SubMsg::reply_always(
                 CosmosMsg::Wasm(WasmMsg::Execute {
                     contract_addr: env.contract.address.to_string(),
                     msg: to_binary(&Cw20ExecuteMsg::Transfer {
@@ -960,7 +1106,7 @@ mod test {
                     .unwrap(),
                     funds: vec![]
                 }),
-                NATIVE_RECEIVE_ID
+                encode_reply_id(NATIVE_RECEIVE_ID, 2)
             )]
         );
 
@@ -986,12 +1132,13 @@ mod test {
             &DestinationInfo::from_str(memo),
             local_channel,
             None,
+            reply_args.clone(),
         )
         .unwrap();
 
         assert_eq!(
             result.sub_msgs,
-            vec![SubMsg::reply_on_error(
+            vec![SubMsg::reply_always(
                 CosmosMsg::Wasm(WasmMsg::Execute {
                     contract_addr: env.contract.address.to_string(),
                     msg: to_binary(&Cw20ExecuteMsg::Transfer {
@@ -1001,7 +1148,7 @@ mod test {
                     .unwrap(),
                     funds: vec![]
                 }),
-                NATIVE_RECEIVE_ID
+                encode_reply_id(NATIVE_RECEIVE_ID, 4)
             )]
         );
     }
@@ -1090,9 +1237,12 @@ mod test {
             .save(
                 storage,
                 token_fee_denom,
-                &Ratio {
-                    nominator: 1,
-                    denominator: 100,
+                &TokenFeeSchedule {
+                    ratio: Ratio {
+                        nominator: 1,
+                        denominator: 100,
+                    },
+                    ..Default::default()
                 },
             )
             .unwrap();
@@ -1102,6 +1252,37 @@ mod test {
                 .0,
             Uint128::from(990u64)
         );
+
+        // 1% + 5 flat, bounded by min and max
+        let schedule = TokenFeeSchedule {
+            ratio: Ratio {
+                nominator: 1,
+                denominator: 100,
+            },
+            flat: Some(Uint128::from(5u64)),
+            min: Some(Uint128::from(8u64)),
+            max: Some(Uint128::from(50u64)),
+        };
+        TOKEN_FEE.save(storage, token_fee_denom, &schedule).unwrap();
+        assert_eq!(
+            deduct_token_fee(storage, token_fee_denom, amount).unwrap(),
+            (Uint128::from(985u64), Uint128::from(15u64))
+        );
+        // below the min
+        assert_eq!(
+            deduct_token_fee(storage, token_fee_denom, Uint128::from(100u64)).unwrap(),
+            (Uint128::from(92u64), Uint128::from(8u64))
+        );
+        // above the max
+        assert_eq!(
+            deduct_token_fee(storage, token_fee_denom, Uint128::from(100000u64)).unwrap(),
+            (Uint128::from(99950u64), Uint128::from(50u64))
+        );
+        // the fee never exceeds the amount
+        assert_eq!(
+            deduct_token_fee(storage, token_fee_denom, Uint128::from(3u64)).unwrap(),
+            (Uint128::zero(), Uint128::from(3u64))
+        );
     }
 
     #[test]
@@ -1113,7 +1294,21 @@ mod test {
         let destination_asset_on_orai = AssetInfo::NativeToken {
             denom: "orai".to_string(),
         };
-        let swap_router_contract = RouterController("foo".to_string());
+        let config = Config {
+            default_timeout: 3600,
+            default_gas_limit: None,
+            fee_denom: "orai".to_string(),
+            swap_router_contract: RouterController("foo".to_string()),
+            token_fee_receiver: Addr::unchecked("token_fee_receiver"),
+            relayer_fee_receiver: Addr::unchecked("relayer_fee_receiver"),
+            price_source: PriceSource::default(),
+            price_fallback: PriceFallback::default(),
+            unregistered_relayer_policy: Default::default(),
+            default_slippage_bps: 0,
+            max_slippage_bps: 0,
+            min_timeout: 0,
+            max_timeout: 0,
+        };
 
         // token price empty case. Should return zero fee
         let result = deduct_relayer_fee(
@@ -1123,7 +1318,7 @@ mod test {
             remote_address,
             token_fee_denom,
             destination_asset_on_orai.clone(),
-            &swap_router_contract,
+            &config,
         )
         .unwrap();
         assert_eq!(result, Uint128::from(0u64));
@@ -1137,7 +1332,7 @@ mod test {
                 "foobar",
                 token_fee_denom,
                 destination_asset_on_orai.clone(),
-                &swap_router_contract,
+                &config,
             )
             .unwrap(),
             Uint128::from(0u128)
@@ -1152,19 +1347,27 @@ mod test {
                 remote_address,
                 token_fee_denom,
                 destination_asset_on_orai.clone(),
-                &swap_router_contract,
+                &config,
             )
             .unwrap(),
             Uint128::from(0u64)
         );
 
         // oraib prefix case.
+        let relayer_fee = |fee: u64, fee_asset: Option<AssetInfo>| RelayerFeeSchedule {
+            fee: Uint128::from(fee),
+            fee_asset,
+        };
         RELAYER_FEE
-            .save(deps_mut.storage, token_fee_denom, &Uint128::from(100u64))
+            .save(
+                deps_mut.storage,
+                (token_fee_denom, ""),
+                &relayer_fee(100, None),
+            )
             .unwrap();
 
         RELAYER_FEE
-            .save(deps_mut.storage, "foo", &Uint128::from(1000u64))
+            .save(deps_mut.storage, ("foo", ""), &relayer_fee(1000, None))
             .unwrap();
 
         assert_eq!(
@@ -1175,7 +1378,7 @@ mod test {
                 "oraib1603j3e4juddh7cuhfquxspl0p0nsun047wz3rl",
                 "foo0x",
                 destination_asset_on_orai.clone(),
-                &swap_router_contract,
+                &config,
             )
             .unwrap(),
             Uint128::from(1000u64)
@@ -1189,12 +1392,203 @@ mod test {
                 &deps_mut.querier,
                 remote_address,
                 token_fee_denom,
-                destination_asset_on_orai,
-                &swap_router_contract,
+                destination_asset_on_orai.clone(),
+                &config,
             )
             .unwrap(),
             Uint128::from(100u64)
         );
+
+        // a fee set for the token on that network wins over the network default
+        let atom = AssetInfo::NativeToken {
+            denom: "ibc/atom".to_string(),
+        };
+        RELAYER_FEE
+            .save(
+                deps_mut.storage,
+                (token_fee_denom, "uatom"),
+                &relayer_fee(7, Some(atom.clone())),
+            )
+            .unwrap();
+        // no price needed when the fee is set in the transferred asset
+        assert_eq!(
+            deduct_relayer_fee(
+                deps_mut.storage,
+                deps_mut.api,
+                &deps_mut.querier,
+                remote_address,
+                "uatom",
+                atom.clone(),
+                &config,
+            )
+            .unwrap(),
+            Uint128::from(7u64)
+        );
+        // the fee asset has no price, so with the zero fallback nothing is charged
+        assert_eq!(
+            deduct_relayer_fee(
+                deps_mut.storage,
+                deps_mut.api,
+                &deps_mut.querier,
+                remote_address,
+                "uatom",
+                destination_asset_on_orai,
+                &config,
+            )
+            .unwrap(),
+            Uint128::zero()
+        );
+    }
+
+    #[test]
+    fn test_relayer_fee_resolution_and_conversion() {
+        let mut deps = mock_dependencies();
+        let remote_address = "cosmos1zedxv25ah8fksmg2lzrndrpkvsjqgk4zt5ff7n";
+        let usdt = AssetInfo::NativeToken {
+            denom: "usdt".to_string(),
+        };
+        let atom = AssetInfo::NativeToken {
+            denom: "ibc/atom".to_string(),
+        };
+        // one orai is worth 1 usdt and 1 atom on the router, 2 usdt and 1 atom on the oracle
+        deps.querier.update_wasm(|query| {
+            let (contract_addr, msg) = match query {
+                WasmQuery::Smart { contract_addr, msg } => (contract_addr, msg),
+                _ => panic!("Unexpected query: {:?}", query),
+            };
+            let res = if contract_addr == "oracle" {
+                let OracleQueryMsg::Price { quote, .. } = from_binary(msg).unwrap();
+                let price = match quote.to_string().as_str() {
+                    "usdt" => Decimal::from_ratio(2u128, 1u128),
+                    _ => Decimal::one(),
+                };
+                to_binary(&OraclePriceResponse { price })
+            } else {
+                let offer_amount = match from_binary(msg).unwrap() {
+                    RouterQueryMsg::SimulateSwapOperations { offer_amount, .. } => offer_amount,
+                    msg => panic!("Unexpected router query: {:?}", msg),
+                };
+                to_binary(&SimulateSwapOperationsResponse {
+                    amount: offer_amount,
+                })
+            };
+            SystemResult::Ok(ContractResult::Ok(res.unwrap()))
+        });
+        let deps_mut = deps.as_mut();
+        let mut config = Config {
+            default_timeout: 3600,
+            default_gas_limit: None,
+            fee_denom: "orai".to_string(),
+            swap_router_contract: RouterController("router".to_string()),
+            token_fee_receiver: Addr::unchecked("token_fee_receiver"),
+            relayer_fee_receiver: Addr::unchecked("relayer_fee_receiver"),
+            price_source: PriceSource::Router {},
+            price_fallback: PriceFallback::Zero {},
+            unregistered_relayer_policy: Default::default(),
+            default_slippage_bps: 0,
+            max_slippage_bps: 0,
+            min_timeout: 0,
+            max_timeout: 0,
+        };
+        // the network default is in the fee denom, the fee of uatom in usdt
+        RELAYER_FEE
+            .save(
+                deps_mut.storage,
+                ("cosmos", ""),
+                &RelayerFeeSchedule {
+                    fee: Uint128::from(100u128),
+                    fee_asset: None,
+                },
+            )
+            .unwrap();
+        RELAYER_FEE
+            .save(
+                deps_mut.storage,
+                ("cosmos", "uatom"),
+                &RelayerFeeSchedule {
+                    fee: Uint128::from(8u128),
+                    fee_asset: Some(usdt.clone()),
+                },
+            )
+            .unwrap();
+        let relayer_fee = |storage: &dyn Storage, config: &Config, remote_denom: &str| {
+            deduct_relayer_fee(
+                storage,
+                deps_mut.api,
+                &deps_mut.querier,
+                remote_address,
+                remote_denom,
+                atom.clone(),
+                config,
+            )
+        };
+
+        // the exact (prefix, remote denom) key wins, other denoms get the network default
+        assert_eq!(
+            relayer_fee(deps_mut.storage, &config, "uatom").unwrap(),
+            Uint128::from(8u128)
+        );
+        assert_eq!(
+            relayer_fee(deps_mut.storage, &config, "uosmo").unwrap(),
+            Uint128::from(100u128)
+        );
+
+        // the usdt fee is converted into atom through the price of both in orai
+        config.price_source = PriceSource::Oracle {
+            contract: Addr::unchecked("oracle"),
+        };
+        assert_eq!(
+            relayer_fee(deps_mut.storage, &config, "uatom").unwrap(),
+            Uint128::from(4u128)
+        );
+        config.price_source = PriceSource::Fixed {};
+        FIXED_PRICES
+            .save(
+                deps_mut.storage,
+                &usdt.to_string(),
+                &Decimal::from_ratio(2u128, 1u128),
+            )
+            .unwrap();
+        FIXED_PRICES
+            .save(
+                deps_mut.storage,
+                &atom.to_string(),
+                &Decimal::from_ratio(1u128, 2u128),
+            )
+            .unwrap();
+        assert_eq!(
+            relayer_fee(deps_mut.storage, &config, "uatom").unwrap(),
+            Uint128::from(2u128)
+        );
+
+        // without a price for usdt, the fallback decides
+        FIXED_PRICES.remove(deps_mut.storage, &usdt.to_string());
+        assert_eq!(
+            relayer_fee(deps_mut.storage, &config, "uatom").unwrap(),
+            Uint128::zero()
+        );
+        config.price_fallback = PriceFallback::Reject {};
+        assert_eq!(
+            relayer_fee(deps_mut.storage, &config, "uatom").unwrap_err(),
+            StdError::generic_err(
+                ContractError::PriceUnavailable {
+                    asset: usdt.to_string()
+                }
+                .to_string()
+            )
+        );
+        config.price_fallback = PriceFallback::LastKnown {};
+        LAST_KNOWN_PRICES
+            .save(
+                deps_mut.storage,
+                &usdt.to_string(),
+                &Decimal::from_ratio(4u128, 1u128),
+            )
+            .unwrap();
+        assert_eq!(
+            relayer_fee(deps_mut.storage, &config, "uatom").unwrap(),
+            Uint128::from(1u128)
+        );
     }
 
     #[test]
@@ -1232,39 +1626,45 @@ mod test {
                 },
             )
             .unwrap();
+        // the forward takes the whole outflow cap
+        RATE_LIMITS
+            .save(
+                storage,
+                (local_channel_id, ibc_denom),
+                &RateLimit {
+                    window_seconds: 3600,
+                    max_inflow: None,
+                    max_outflow: Some(remote_amount),
+                },
+            )
+            .unwrap();
 
         // action
         let result = process_ibc_msg(
-            pair_mapping,
-            mock_env().contract.address.into_string(),
+            storage,
+            pair_mapping.clone(),
             local_receiver,
             local_channel_id,
             ibc_msg_sender,
             ibc_msg_receiver,
-            memo,
+            memo.clone(),
             amount,
+            Timestamp::from_seconds(1u64),
             timeout,
         )
         .unwrap();
 
         assert_eq!(
-            result[0],
-            SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: mock_env().contract.address.into_string(),
-                msg: to_binary(&ExecuteMsg::ReduceChannelBalanceIbcReceive {
-                    src_channel_id: local_channel_id.to_string(),
-                    ibc_denom: ibc_denom.to_string(),
-                    amount: remote_amount,
-                    local_receiver: local_receiver.to_string()
-                })
-                .unwrap(),
-                funds: vec![]
-            }))
+            CHANNEL_REVERSE_STATE
+                .load(storage, (local_channel_id, ibc_denom))
+                .unwrap()
+                .outstanding,
+            Uint128::zero()
         );
 
         assert_eq!(
-            result[1],
-            SubMsg::reply_on_error(
+            result[0],
+            SubMsg::reply_always(
                 IbcMsg::SendPacket {
                     channel_id: local_channel_id.to_string(),
                     data: to_binary(&Ics20Packet {
@@ -1277,23 +1677,819 @@ mod test {
                     .unwrap(),
                     timeout: IbcTimeout::with_timestamp(timeout)
                 },
-                FOLLOW_UP_IBC_SEND_FAILURE_ID
+                encode_reply_id(FOLLOW_UP_IBC_SEND_FAILURE_ID, 1)
             )
+        );
+
+        // a second forward is over the outflow cap and leaves the balance alone
+        increase_channel_balance(storage, local_channel_id, ibc_denom, remote_amount).unwrap();
+        process_ibc_msg(
+            storage,
+            pair_mapping,
+            local_receiver,
+            local_channel_id,
+            ibc_msg_sender,
+            ibc_msg_receiver,
+            memo,
+            amount,
+            Timestamp::from_seconds(2u64),
+            timeout,
         )
+        .unwrap_err();
+        assert_eq!(
+            CHANNEL_REVERSE_STATE
+                .load(storage, (local_channel_id, ibc_denom))
+                .unwrap()
+                .outstanding,
+            remote_amount
+        );
     }
 
     #[test]
-    fn test_get_swap_token_amount_out_from_orai() {
-        let deps = mock_dependencies();
-        let simulate_amount = Uint128::from(10u128);
-        let result = get_swap_token_amount_out_from_orai(
-            &deps.as_ref().querier,
-            simulate_amount,
-            &RouterController("foo".to_string()),
-            AssetInfo::NativeToken {
+    fn test_increase_channel_balance_ibc_receive() {
+        let channel = "channel-0";
+        let ibc_denom = get_key_ics20_ibc_denom(CONTRACT_PORT, channel, "uatom");
+        let relayer = Addr::unchecked("relayer");
+        let mut deps = setup(&[channel], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                local_channel_id: channel.to_string(),
+                denom: "uatom".to_string(),
+                local_asset_info: AssetInfo::NativeToken {
+                    denom: "ibc/atom".to_string(),
+                },
+                remote_decimals: 6,
+                local_asset_info_decimals: 6,
+            }),
+        )
+        .unwrap();
+
+        // the receive mints the vouchers on this side, both outstanding and total sent grow
+        let recv_packet =
+            mock_receive_packet_remote_to_local(channel, 100, "uatom", "receiver", None);
+        receive_and_reply(
+            &mut deps,
+            mock_env(),
+            IbcPacketReceiveMsg::new(recv_packet, relayer.clone()),
+        );
+        assert_eq!(
+            CHANNEL_REVERSE_STATE
+                .load(deps.as_ref().storage, (channel, &ibc_denom))
+                .unwrap(),
+            ChannelState {
+                outstanding: Uint128::from(100u128),
+                total_sent: Uint128::from(100u128),
+            }
+        );
+
+        // a balance that would overflow fails the packet and is left as it was
+        let full = ChannelState {
+            outstanding: Uint128::MAX - Uint128::from(10u128),
+            total_sent: Uint128::from(100u128),
+        };
+        CHANNEL_REVERSE_STATE
+            .save(deps.as_mut().storage, (channel, &ibc_denom), &full)
+            .unwrap();
+        let recv_packet =
+            mock_receive_packet_remote_to_local(channel, 100, "uatom", "receiver", None);
+        let res = ibc_packet_receive(
+            deps.as_mut(),
+            mock_env(),
+            IbcPacketReceiveMsg::new(recv_packet, relayer),
+        )
+        .unwrap();
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert!(matches!(ack, Ics20Ack::Error(err) if err.contains("Overflow")));
+        assert!(res.messages.is_empty());
+        assert_eq!(
+            CHANNEL_REVERSE_STATE
+                .load(deps.as_ref().storage, (channel, &ibc_denom))
+                .unwrap(),
+            full
+        );
+    }
+
+    #[test]
+    fn test_reduce_channel_balance_ibc_receive() {
+        let mut deps = mock_dependencies();
+        let storage = deps.as_mut().storage;
+        let channel = "channel";
+        let ibc_denom = "foo/bar/cosmos";
+        let pair_mapping = (
+            ibc_denom.to_string(),
+            MappingMetadata {
+                asset_info: AssetInfo::NativeToken {
+                    denom: "orai".to_string(),
+                },
+                remote_decimals: 6,
+                asset_info_decimals: 6,
+            },
+        );
+        let forward = |storage: &mut dyn Storage, channel: &str, amount: u128| {
+            process_ibc_msg(
+                storage,
+                pair_mapping.clone(),
+                "local_receiver",
+                channel,
+                "sender",
+                "receiver",
+                None,
+                Uint128::from(amount),
+                Timestamp::from_seconds(1),
+                Timestamp::from_seconds(10),
+            )
+        };
+        CHANNEL_REVERSE_STATE
+            .save(
+                storage,
+                (channel, ibc_denom),
+                &ChannelState {
+                    outstanding: Uint128::from(100u128),
+                    total_sent: Uint128::from(500u128),
+                },
+            )
+            .unwrap();
+
+        // the forward takes its amount out of the outstanding balance only
+        forward(storage, channel, 60).unwrap();
+        let reduced = ChannelState {
+            outstanding: Uint128::from(40u128),
+            total_sent: Uint128::from(500u128),
+        };
+        assert_eq!(
+            CHANNEL_REVERSE_STATE
+                .load(storage, (channel, ibc_denom))
+                .unwrap(),
+            reduced
+        );
+
+        // more than the outstanding balance is rejected, the balance stays as it was
+        let err = forward(storage, channel, 60).unwrap_err();
+        assert_eq!(
+            err,
+            StdError::generic_err(
+                ContractError::InsufficientFunds {
+                    id: channel.to_string(),
+                    denom: ibc_denom.to_string(),
+                }
+                .to_string()
+            )
+        );
+        assert_eq!(
+            CHANNEL_REVERSE_STATE
+                .load(storage, (channel, ibc_denom))
+                .unwrap(),
+            reduced
+        );
+
+        // so is a channel that never received the denom
+        let err = forward(storage, "channel-9", 1).unwrap_err();
+        assert_eq!(
+            err,
+            StdError::generic_err(
+                ContractError::NoSuchChannelState {
+                    id: "channel-9".to_string(),
+                    denom: ibc_denom.to_string(),
+                }
+                .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_get_swap_token_amount_out_from_fee_denom() {
+        let deps = mock_dependencies();
+        let simulate_amount = Uint128::from(10u128);
+        let result = get_swap_token_amount_out_from_fee_denom(
+            &deps.as_ref().querier,
+            simulate_amount,
+            &RouterController("foo".to_string()),
+            "orai",
+            AssetInfo::NativeToken {
                 denom: "orai".to_string(),
             },
         );
-        assert_eq!(result, simulate_amount)
+        assert_eq!(result, Some(simulate_amount))
+    }
+
+    #[test]
+    fn test_ibc_channel_close() {
+        let relayer = Addr::unchecked("relayer");
+        let local_channel = "channel-9";
+        let denom = "uatom";
+        let ibc_denom = get_key_ics20_ibc_denom(CONTRACT_PORT, local_channel, denom);
+        let amount = Uint128::from(100u128);
+        let mut deps = setup(&[local_channel, "channel-1"], &[]);
+        increase_channel_balance(deps.as_mut().storage, local_channel, &ibc_denom, amount).unwrap();
+        // zero balances are not worth recovering
+        increase_channel_balance(
+            deps.as_mut().storage,
+            local_channel,
+            "foo/bar/empty",
+            amount,
+        )
+        .unwrap();
+        CHANNEL_REVERSE_STATE
+            .save(
+                deps.as_mut().storage,
+                (local_channel, "foo/bar/empty"),
+                &ChannelState {
+                    outstanding: Uint128::zero(),
+                    total_sent: amount,
+                },
+            )
+            .unwrap();
+
+        // unknown channel
+        let err = ibc_channel_close(
+            deps.as_mut(),
+            mock_env(),
+            IbcChannelCloseMsg::new_confirm(mock_channel("channel-unknown")),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::NoSuchChannel {
+                id: "channel-unknown".to_string()
+            }
+        );
+
+        let res = ibc_channel_close(
+            deps.as_mut(),
+            mock_env(),
+            IbcChannelCloseMsg::new_confirm(mock_channel(local_channel)),
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 0);
+
+        let channel_info = CHANNEL_INFO
+            .load(deps.as_ref().storage, local_channel)
+            .unwrap();
+        assert!(channel_info.closed);
+        // other channels are untouched
+        assert!(
+            !CHANNEL_INFO
+                .load(deps.as_ref().storage, "channel-1")
+                .unwrap()
+                .closed
+        );
+
+        let funds = CHANNEL_RECOVERABLE_FUNDS
+            .prefix(local_channel)
+            .range(deps.as_ref().storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            funds,
+            vec![(
+                ibc_denom,
+                RecoverableFund {
+                    outstanding: amount,
+                    recovered: Uint128::zero(),
+                    closed_height: mock_env().block.height,
+                    escrowed: false,
+                }
+            )]
+        );
+
+        // receiving on a closed channel returns a failed ack
+        let recv_packet =
+            mock_receive_packet_remote_to_local(local_channel, 10, denom, "custom-addr", None);
+        let res = ibc_packet_receive(
+            deps.as_mut(),
+            mock_env(),
+            IbcPacketReceiveMsg::new(recv_packet, relayer),
+        )
+        .unwrap();
+        let ack: Ics20Ack = from_binary(&res.acknowledgement).unwrap();
+        assert_eq!(
+            ack,
+            Ics20Ack::Error(
+                ContractError::ChannelClosed {
+                    id: local_channel.to_string()
+                }
+                .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_swap_failure_refunds_each_packet_in_the_same_tx() {
+        let relayer = Addr::unchecked("relayer");
+        let local_channel = "channel-9";
+        let denom = "uatom";
+        let cw20_addr = "token-addr";
+        let mut deps = setup(&[local_channel], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                local_channel_id: local_channel.to_string(),
+                denom: denom.to_string(),
+                local_asset_info: AssetInfo::Token {
+                    contract_addr: Addr::unchecked(cw20_addr),
+                },
+                remote_decimals: 18,
+                local_asset_info_decimals: 6,
+            }),
+        )
+        .unwrap();
+        deps.querier.update_wasm(|_| {
+            SystemResult::Ok(ContractResult::Ok(
+                to_binary(&SimulateSwapOperationsResponse {
+                    amount: Uint128::from(1u128),
+                })
+                .unwrap(),
+            ))
+        });
+
+        // a relayer delivers two packets that both swap to orai in the same tx
+        let mut swap_reply_ids = vec![];
+        for (receiver, amount) in [
+            ("receiver-a", 1000000000000000000u128),
+            ("receiver-b", 2000000000000000000u128),
+        ] {
+            let mut packet =
+                mock_receive_packet_remote_to_local(local_channel, amount, denom, receiver, None);
+            let mut data: Ics20Packet = from_binary(&packet.data).unwrap();
+            data.memo = Some(format!("{}:orai", receiver));
+            packet.data = to_binary(&data).unwrap();
+            let res = ibc_packet_receive(
+                deps.as_mut(),
+                mock_env(),
+                IbcPacketReceiveMsg::new(packet, relayer.clone()),
+            )
+            .unwrap();
+            let swap_msg = res
+                .messages
+                .iter()
+                .find(|sub_msg| decode_reply_id(sub_msg.id).0 == SWAP_OPS_FAILURE_ID)
+                .unwrap();
+            swap_reply_ids.push(swap_msg.id);
+        }
+        assert_ne!(swap_reply_ids[0], swap_reply_ids[1]);
+
+        // both swaps fail, the replies arrive in the reverse order
+        let expected_refunds = [
+            ("receiver-b", 2000000u128, 3),
+            ("receiver-a", 1000000u128, 4),
+        ];
+        for (reply_id, (receiver, amount, refund_nonce)) in
+            swap_reply_ids.iter().rev().zip(expected_refunds)
+        {
+            let res = reply(
+                deps.as_mut(),
+                mock_env(),
+                Reply {
+                    id: *reply_id,
+                    result: SubMsgResult::Err("swap failed".to_string()),
+                },
+            )
+            .unwrap();
+            assert_eq!(
+                res.messages,
+                vec![SubMsg::reply_always(
+                    CosmosMsg::Wasm(WasmMsg::Execute {
+                        contract_addr: cw20_addr.to_string(),
+                        msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                            recipient: receiver.to_string(),
+                            amount: Uint128::from(amount),
+                        })
+                        .unwrap(),
+                        funds: vec![],
+                    }),
+                    encode_reply_id(REFUND_FAILURE_ID, refund_nonce)
+                )]
+            );
+        }
+        assert!(REPLY_ARGS.is_empty(deps.as_ref().storage));
+    }
+
+    #[test]
+    fn test_successful_swap_clears_reply_args() {
+        let mut deps = mock_dependencies();
+        let reply_args = ReplyArgs {
+            channel: "channel-9".to_string(),
+            local_receiver: "receiver".to_string(),
+            denom: "foo/bar/atom".to_string(),
+            amount: Uint128::from(10u128),
+            minimum_receive: Uint128::zero(),
+            follow_up: None,
+        };
+        REPLY_ARGS
+            .save(deps.as_mut().storage, 1, &reply_args)
+            .unwrap();
+        REPLY_ARGS
+            .save(deps.as_mut().storage, 2, &reply_args)
+            .unwrap();
+        let res = reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: encode_reply_id(SWAP_OPS_FAILURE_ID, 2),
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![],
+                    data: None,
+                }),
+            },
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 0);
+        // only the args of the replied swap are removed
+        assert_eq!(
+            REPLY_ARGS.load(deps.as_ref().storage, 1).unwrap(),
+            reply_args
+        );
+        assert!(!REPLY_ARGS.has(deps.as_ref().storage, 2));
+    }
+
+    #[test]
+    fn test_swap_slippage_and_follow_up() {
+        let mut deps = setup(&["channel-1"], &[]);
+        let simulated = Uint128::from(10000u128);
+        let destination = |min_out: Option<u128>, slippage_bps: Option<u16>| DestinationInfo {
+            receiver: "receiver".to_string(),
+            destination_denom: "atom".to_string(),
+            min_out: min_out.map(Uint128::from),
+            slippage_bps,
+            ..Default::default()
+        };
+
+        // without a slippage config the swap must give the simulated amount, as before
+        let config = CONFIG.load(deps.as_ref().storage).unwrap();
+        assert_eq!(
+            apply_slippage(simulated, &destination(None, Some(100)), &config),
+            simulated
+        );
+        assert_eq!(
+            apply_slippage(simulated, &destination(Some(9000), None), &config),
+            simulated
+        );
+        // a minimum out far above the simulation cannot force the swap to fail
+        assert_eq!(
+            apply_slippage(simulated, &destination(Some(1_000_000), None), &config),
+            simulated
+        );
+
+        // default must not exceed max
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::SetSlippage {
+                default_bps: 200,
+                max_bps: 100,
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidSlippage {
+                default_bps: 200,
+                max_bps: 100
+            }
+        );
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::SetSlippage {
+                default_bps: 50,
+                max_bps: 300,
+            },
+        )
+        .unwrap();
+        let config = CONFIG.load(deps.as_ref().storage).unwrap();
+        // default, memo slippage, memo slippage capped by the max
+        assert_eq!(
+            apply_slippage(simulated, &destination(None, None), &config),
+            Uint128::from(9950u128)
+        );
+        assert_eq!(
+            apply_slippage(simulated, &destination(None, Some(100)), &config),
+            Uint128::from(9900u128)
+        );
+        assert_eq!(
+            apply_slippage(simulated, &destination(None, Some(5000)), &config),
+            Uint128::from(9700u128)
+        );
+        // a minimum out is kept unless it is below the max slippage or above the simulated amount
+        assert_eq!(
+            apply_slippage(simulated, &destination(Some(9800), None), &config),
+            Uint128::from(9800u128)
+        );
+        assert_eq!(
+            apply_slippage(simulated, &destination(Some(10100), None), &config),
+            simulated
+        );
+        assert_eq!(
+            apply_slippage(simulated, &destination(Some(1), None), &config),
+            Uint128::from(9700u128)
+        );
+
+        // the swap gave more than its minimum, the hook gets all of it
+        REPLY_ARGS
+            .save(
+                deps.as_mut().storage,
+                1,
+                &ReplyArgs {
+                    channel: "channel-1".to_string(),
+                    local_receiver: "receiver".to_string(),
+                    denom: "foo/bar/atom".to_string(),
+                    amount: Uint128::from(100u128),
+                    minimum_receive: Uint128::from(9900u128),
+                    follow_up: Some(SwapFollowUp {
+                        ask_denom: "atom".to_string(),
+                        remote_sender: "sender".to_string(),
+                        destination: DestinationInfo {
+                            hook: Some(ContractHook {
+                                contract: "vault".to_string(),
+                                msg: Binary::from(br#"{"deposit":{}}"#),
+                            }),
+                            ..destination(None, Some(100))
+                        },
+                        local_channel_id: "channel-1".to_string(),
+                        pair_mapping: None,
+                    }),
+                },
+            )
+            .unwrap();
+        let swap_events = vec![
+            Event::new("wasm").add_attribute("return_amount", "500"),
+            Event::new("wasm").add_attribute("return_amount", "9990"),
+        ];
+        let res = reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: encode_reply_id(SWAP_OPS_FAILURE_ID, 1),
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: swap_events,
+                    data: None,
+                }),
+            },
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "vault".to_string(),
+                msg: Binary::from(br#"{"deposit":{}}"#),
+                funds: coins(9990, "atom"),
+            })
+        );
+        assert_eq!(decode_reply_id(res.messages[0].id).0, HOOK_FAILURE_ID);
+        let attribute = |key: &str| {
+            res.attributes
+                .iter()
+                .find(|attribute| attribute.key == key)
+                .map(|attribute| attribute.value.clone())
+        };
+        assert_eq!(attribute("swap_return_amount"), Some("9990".to_string()));
+        assert_eq!(attribute("swap_minimum_receive"), Some("9900".to_string()));
+        assert!(REPLY_ARGS.is_empty(deps.as_ref().storage));
+    }
+
+    #[test]
+    fn test_swapped_hook_waits_for_the_swap() {
+        let relayer = Addr::unchecked("relayer");
+        let local_channel = "channel-9";
+        let mut deps = setup(&[local_channel], &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::UpdateMappingPair(UpdatePairMsg {
+                local_channel_id: local_channel.to_string(),
+                denom: "uatom".to_string(),
+                local_asset_info: AssetInfo::NativeToken {
+                    denom: "ibc/atom".to_string(),
+                },
+                remote_decimals: 6,
+                local_asset_info_decimals: 6,
+            }),
+        )
+        .unwrap();
+        deps.querier.update_wasm(|_| {
+            SystemResult::Ok(ContractResult::Ok(
+                to_binary(&SimulateSwapOperationsResponse {
+                    amount: Uint128::from(90u128),
+                })
+                .unwrap(),
+            ))
+        });
+        let receive_swap = |deps: DepsMut| {
+            let mut packet =
+                mock_receive_packet_remote_to_local(local_channel, 100, "uatom", "receiver", None);
+            let mut data: Ics20Packet = from_binary(&packet.data).unwrap();
+            data.memo = Some(
+                r#"{"v1":{"receiver":"receiver","destination_denom":"orai","hook":{"contract":"vault","msg":"e30="}}}"#
+                    .to_string(),
+            );
+            packet.data = to_binary(&data).unwrap();
+            let res = ibc_packet_receive(
+                deps,
+                mock_env(),
+                IbcPacketReceiveMsg::new(packet, relayer.clone()),
+            )
+            .unwrap();
+            // only the swap goes out with the packet, the hook waits for its reply
+            assert_eq!(res.messages.len(), 1);
+            assert_eq!(decode_reply_id(res.messages[0].id).0, SWAP_OPS_FAILURE_ID);
+            res.messages[0].id
+        };
+        let calls_vault = |sub_msg: &SubMsg| matches!(&sub_msg.msg, CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) if contract_addr == "vault");
+
+        // the swap fails: the received tokens are refunded and the hook is never called
+        let swap_reply_id = receive_swap(deps.as_mut());
+        let res = reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: swap_reply_id,
+                result: SubMsgResult::Err("max spread assertion".to_string()),
+            },
+        )
+        .unwrap();
+        assert!(!res.messages.iter().any(calls_vault));
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "receiver".to_string(),
+                amount: coins(100, "ibc/atom"),
+            })
+        );
+        assert!(REPLY_ARGS.is_empty(deps.as_ref().storage));
+        assert!(!RECEIVE_LOCK.exists(deps.as_ref().storage));
+
+        // the swap succeeds: the hook gets the swapped tokens and holds the lock until it replies
+        let swap_reply_id = receive_swap(deps.as_mut());
+        let res = reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: swap_reply_id,
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![Event::new("wasm").add_attribute("return_amount", "95")],
+                    data: None,
+                }),
+            },
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert!(calls_vault(&res.messages[0]));
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "vault".to_string(),
+                msg: Binary::from(b"{}"),
+                funds: coins(95, "orai"),
+            })
+        );
+        assert_eq!(
+            RECEIVE_LOCK.load(deps.as_ref().storage).unwrap(),
+            res.messages[0].id
+        );
+    }
+
+    #[test]
+    fn test_hook_follow_up() {
+        let mut deps = setup(&["channel-1"], &[]);
+        let receiver = "receiver";
+        let hook_msg = Binary::from(br#"{"deposit":{}}"#);
+        let destination = DestinationInfo {
+            receiver: receiver.to_string(),
+            refund_address: Some("refund".to_string()),
+            hook: Some(ContractHook {
+                contract: "vault".to_string(),
+                msg: hook_msg.clone(),
+            }),
+            ..Default::default()
+        };
+        let reply_args = ReplyArgs {
+            channel: "channel-1".to_string(),
+            local_receiver: "refund".to_string(),
+            denom: "foo/bar/atom".to_string(),
+            amount: Uint128::from(100u128),
+            minimum_receive: Uint128::zero(),
+            follow_up: None,
+        };
+        let follow_up = |deps: DepsMut, to_send: Amount, asset_info: AssetInfo| {
+            get_follow_up_msgs(
+                deps.storage,
+                deps.api,
+                &deps.querier,
+                mock_env(),
+                to_send,
+                asset_info,
+                AssetInfo::NativeToken {
+                    denom: "".to_string(),
+                },
+                "sender",
+                receiver,
+                &destination,
+                "channel-1",
+                None,
+                reply_args.clone(),
+            )
+            .unwrap()
+        };
+
+        // cw20 tokens are delivered with a cw20 send
+        let result = follow_up(
+            deps.as_mut(),
+            Amount::Cw20(Cw20Coin {
+                address: "token".to_string(),
+                amount: Uint128::from(100u128),
+            }),
+            AssetInfo::Token {
+                contract_addr: Addr::unchecked("token"),
+            },
+        );
+        assert_eq!(
+            result.sub_msgs,
+            vec![SubMsg::reply_always(
+                WasmMsg::Execute {
+                    contract_addr: "token".to_string(),
+                    msg: to_binary(&Cw20ExecuteMsg::Send {
+                        contract: "vault".to_string(),
+                        amount: Uint128::from(100u128),
+                        msg: hook_msg.clone(),
+                    })
+                    .unwrap(),
+                    funds: vec![],
+                },
+                encode_reply_id(HOOK_FAILURE_ID, 1)
+            )]
+        );
+
+        // native tokens are attached as funds of the hook msg
+        let result = follow_up(
+            deps.as_mut(),
+            Amount::native(100, "ibc/atom"),
+            AssetInfo::NativeToken {
+                denom: "ibc/atom".to_string(),
+            },
+        );
+        assert_eq!(
+            result.sub_msgs,
+            vec![SubMsg::reply_always(
+                WasmMsg::Execute {
+                    contract_addr: "vault".to_string(),
+                    msg: hook_msg,
+                    funds: coins(100, "ibc/atom"),
+                },
+                encode_reply_id(HOOK_FAILURE_ID, 2)
+            )]
+        );
+
+        // a failed hook refunds the refund address
+        let res = reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: encode_reply_id(HOOK_FAILURE_ID, 2),
+                result: SubMsgResult::Err("vault is closed".to_string()),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::reply_always(
+                BankMsg::Send {
+                    to_address: "refund".to_string(),
+                    amount: coins(100, "ibc/atom"),
+                },
+                encode_reply_id(NATIVE_RECEIVE_ID, 3)
+            )]
+        );
+
+        // a successful hook has nothing left to refund
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: encode_reply_id(HOOK_FAILURE_ID, 1),
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![],
+                    data: None,
+                }),
+            },
+        )
+        .unwrap();
+        assert!(reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: encode_reply_id(HOOK_FAILURE_ID, 1),
+                result: SubMsgResult::Err("replayed".to_string()),
+            },
+        )
+        .is_err());
     }
 }
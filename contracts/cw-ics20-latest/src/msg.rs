@@ -1,9 +1,18 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{Addr, Binary, IbcEndpoint, SubMsg, Uint128};
+use cosmwasm_std::{
+    Addr, Binary, Decimal, IbcEndpoint, IbcTimeoutBlock, SubMsg, Timestamp, Uint128,
+};
 use cw20::Cw20ReceiveMsg;
 use oraiswap::asset::AssetInfo;
+use oraiswap::router::SwapOperation;
 
-use crate::state::{ChannelInfo, MappingMetadata, Ratio, RelayerFee, TokenFee};
+use crate::ibc::Ics20Packet;
+
+use crate::state::{
+    ChannelInfo, FeeChange, FeeExemption, FeeExemptionSubject, FeeKey, MappingMetadata, PauseScope,
+    PendingTransfer, PriceFallback, PriceSource, QueuedChange, RateLimit, Ratio, RecoverableFund,
+    RelayerFee, StuckFund, TokenFee, TokenFeeSchedule, UnregisteredRelayerPolicy,
+};
 use cw20_ics20_msg::amount::Amount;
 
 #[cw_serde]
@@ -41,14 +50,18 @@ pub struct MigrateMsg {
 pub enum ExecuteMsg {
     /// This accepts a properly-encoded ReceiveMsg from a cw20 contract
     Receive(Cw20ReceiveMsg),
-    /// This allows us to transfer *exactly one* native token
-    // Transfer(TransferMsg),
+    /// This allows us to transfer *exactly one* native token that originated on this chain
+    Transfer(TransferMsg),
+    /// Sends the attached native coins back to the remote chain, one packet per coin
     TransferToRemote(TransferBackMsg),
+    /// Splits the attached native coin into several transfers back to remote chains, one packet per leg
+    BatchTransferToRemote(BatchTransferBackMsg),
     UpdateMappingPair(UpdatePairMsg),
     DeleteMappingPair(DeletePairMsg),
     /// This must be called by gov_contract, will allow a new cw20 token to be sent
     Allow(AllowMsg),
-    /// Change the admin (must be called by current admin)
+    /// Update the config (must be called by current admin).
+    /// admin only proposes the new admin, and the swap router, the fees and the fee receivers go through the timelock if one is set
     UpdateConfig {
         admin: Option<String>,
         default_timeout: Option<u64>,
@@ -60,25 +73,158 @@ pub enum ExecuteMsg {
         fee_receiver: Option<String>,
         relayer_fee_receiver: Option<String>,
     },
-    // self-call msgs to deal with on_ibc_receive reentrancy error
-    IncreaseChannelBalanceIbcReceive {
-        dest_channel_id: String,
+    OverrideChannelBalance {
+        channel_id: String,
         ibc_denom: String,
-        amount: Uint128,
-        local_receiver: String,
+        outstanding: Uint128,
+        total_sent: Option<Uint128>,
     },
-    ReduceChannelBalanceIbcReceive {
-        src_channel_id: String,
+    /// Pay out funds snapshotted when a channel was closed (must be called by the admin)
+    RecoverChannelFunds {
+        channel_id: String,
+        /// the local denom for tokens of this chain escrowed on the channel
         ibc_denom: String,
+        /// amount in remote decimals, converted to the local asset using the pair mapping.
+        /// Escrowed tokens are paid out as they are
         amount: Uint128,
-        local_receiver: String,
+        recipient: String,
     },
-    OverrideChannelBalance {
+    /// Waive the token and relayer fees of an address or a prefix, fully or partially (must be called by the admin).
+    /// Applies right away, since it only lowers a fee below the timelocked schedule
+    SetFeeExemption {
+        subject: FeeExemptionSubject,
+        /// share of the fees that is waived. None waives them entirely
+        discount: Option<Ratio>,
+    },
+    /// Make an exempted address or prefix pay the full fees again (must be called by the admin).
+    /// Applies right away, the fees it restores are the timelocked ones
+    RemoveFeeExemption {
+        subject: FeeExemptionSubject,
+    },
+    /// Set or overwrite token fees by remote denom and relayer fees by prefix and remote denom (must be called by the admin).
+    /// Goes through the timelock if one is set, like the other fee and price changes below
+    SetFees {
+        token_fees: Vec<TokenFee>,
+        relayer_fees: Vec<RelayerFee>,
+    },
+    /// Remove token and relayer fees (must be called by the admin)
+    RemoveFees {
+        fees: Vec<FeeKey>,
+    },
+    /// Replace every token fee or every relayer fee with the given list. None leaves that kind untouched (must be called by the admin)
+    ReplaceFees {
+        token_fees: Option<Vec<TokenFee>>,
+        relayer_fees: Option<Vec<RelayerFee>>,
+    },
+    /// Choose where relayer fees get their price and what happens without one (must be called by the admin)
+    SetPriceSource {
+        price_source: PriceSource,
+        price_fallback: PriceFallback,
+    },
+    /// Set the amount of the asset per unit of the fee denom for the fixed price source, None removes it (must be called by the admin)
+    SetFixedPrice {
+        asset_info: AssetInfo,
+        price: Option<Decimal>,
+    },
+    /// Set the swap operations used from the offer to the ask asset instead of swapping through the fee denom, None removes them (must be called by the admin)
+    SetSwapRoute {
+        offer_asset_info: AssetInfo,
+        ask_asset_info: AssetInfo,
+        operations: Option<Vec<SwapOperation>>,
+    },
+    /// Set the bounds in seconds of the timeout of outgoing packets, zero max means unbounded (must be called by the admin)
+    SetTimeoutBounds {
+        min_timeout: u64,
+        max_timeout: u64,
+    },
+    /// Set the default timeout in seconds of the packets sent on the channel instead of default_timeout, None removes it (must be called by the admin)
+    SetChannelTimeout {
+        channel_id: String,
+        timeout: Option<u64>,
+    },
+    /// Set the slippage of universal swaps in basis points, applied below the simulated amount (must be called by the admin)
+    SetSlippage {
+        default_bps: u16,
+        max_bps: u16,
+    },
+    /// Propose a new admin, which takes over once it accepts (must be called by the admin)
+    ProposeAdmin {
+        admin: String,
+    },
+    /// Become the admin (must be called by the proposed admin)
+    AcceptAdmin {},
+    /// Withdraw the admin proposal (must be called by the admin)
+    CancelAdminProposal {},
+    /// Set how many seconds sensitive changes wait before they can be executed (must be called by the admin).
+    /// The new delay goes through the current timelock itself
+    SetTimelockDelay {
+        delay: u64,
+    },
+    /// Apply a queued change whose timelock has expired (must be called by the admin)
+    ExecuteQueuedChange {
+        id: u64,
+    },
+    /// Drop a queued change (must be called by the admin)
+    CancelQueuedChange {
+        id: u64,
+    },
+    /// Cap how much of an ibc denom can go in and out of a channel over a rolling window (must be called by the admin).
+    /// Tokens that originated on this chain are capped under their local denom
+    SetRateLimit {
         channel_id: String,
         ibc_denom: String,
-        outstanding: Uint128,
-        total_sent: Option<Uint128>,
+        /// length of the rolling window in seconds. Changing it restarts the usage
+        window_seconds: u64,
+        /// in remote decimals, None means inflow is not limited
+        max_inflow: Option<Uint128>,
+        /// in remote decimals, None means outflow is not limited
+        max_outflow: Option<Uint128>,
+    },
+    /// Remove the rate limit of an ibc denom on a channel (must be called by the admin).
+    /// Rate limits apply right away: they only hold transfers back, so they can be tightened in an emergency
+    RemoveRateLimit {
+        channel_id: String,
+        ibc_denom: String,
+    },
+    /// Halt the traffic covered by the scope (must be called by the admin or the guardian)
+    Pause {
+        scope: PauseScope,
     },
+    /// Resume the traffic of a paused scope (must be called by the admin)
+    Unpause {
+        scope: PauseScope,
+    },
+    /// Set or clear the guardian, which can pause but not unpause (must be called by the admin)
+    SetGuardian {
+        guardian: Option<String>,
+    },
+    /// Pay a stuck fund out to its receiver (must be called by the receiver)
+    ClaimStuckFund {
+        id: u64,
+    },
+    /// Pay a stuck fund out to another address (must be called by the admin)
+    ResolveStuckFund {
+        id: u64,
+        recipient: String,
+    },
+    /// Sweep the accumulated fees to the token fee receiver and the relayer fee receiver
+    /// (must be called by the admin or one of the receivers)
+    CollectFees {},
+    /// Register a relayer, with the address its claimed fees are sent to. None pays it out to the relayer (must be called by the admin)
+    RegisterRelayer {
+        relayer: String,
+        payout: Option<String>,
+    },
+    /// Unregister a relayer. Fees it already earned stay claimable (must be called by the admin)
+    RemoveRelayer {
+        relayer: String,
+    },
+    /// Choose where the relayer fees earned by unregistered relayers go (must be called by the admin)
+    SetUnregisteredRelayerPolicy {
+        policy: UnregisteredRelayerPolicy,
+    },
+    /// Pay out the relayer fees the sender earned by delivering packets
+    ClaimRelayerFees {},
 }
 
 #[cw_serde]
@@ -99,20 +245,22 @@ pub struct DeletePairMsg {
     pub denom: String,
 }
 
-/// This is the message we accept via Receive
-// #[cw_serde]
-// pub struct TransferMsg {
-//     /// The local channel to send the packets on
-//     pub channel: String,
-//     /// The remote address to send to.
-//     /// Don't use HumanAddress as this will likely have a different Bech32 prefix than we use
-//     /// and cannot be validated locally
-//     pub remote_address: String,
-//     /// How long the packet lives in seconds. If not specified, use default_timeout
-//     pub timeout: Option<u64>,
-//     /// metadata of the transfer to suit the new fungible token transfer
-//     pub memo: Option<String>,
-// }
+/// This is the message we accept via Receive to escrow tokens that originated on this chain
+#[cw_serde]
+pub struct TransferMsg {
+    /// The local channel to send the packets on
+    pub channel: String,
+    /// The remote address to send to.
+    /// Don't use HumanAddress as this will likely have a different Bech32 prefix than we use
+    /// and cannot be validated locally
+    pub remote_address: String,
+    /// How long the packet lives in seconds. If not specified, use default_timeout
+    pub timeout: Option<u64>,
+    /// metadata of the transfer to suit the new fungible token transfer
+    pub memo: Option<String>,
+    /// When the packet times out, instead of timeout
+    pub packet_timeout: Option<PacketTimeout>,
+}
 
 /// This is the message we accept via Receive
 #[cw_serde]
@@ -126,6 +274,42 @@ pub struct TransferBackMsg {
     pub timeout: Option<u64>,
     /// metadata of the transfer to suit the new fungible token transfer
     pub memo: Option<String>,
+    /// When the packet times out, instead of timeout
+    pub packet_timeout: Option<PacketTimeout>,
+    /// local asset the sent tokens are swapped into before the transfer. The remote denom is then mapped to this asset
+    pub swap_to: Option<AssetInfo>,
+    /// least amount of swap_to the swap must give
+    pub min_out: Option<Uint128>,
+}
+
+/// Timeout of an outgoing packet. The seconds it lives must be within the bounds set by the admin
+#[cw_serde]
+pub enum PacketTimeout {
+    /// seconds from the current block time
+    Relative { seconds: u64 },
+    /// block time of the counterparty chain
+    Absolute { timestamp: Timestamp },
+    /// block height of the counterparty chain. Rejected, since only a timestamp can be checked against the bounds
+    Height { block: IbcTimeoutBlock },
+    /// whichever of the block height and the block time of the counterparty chain comes first
+    HeightOrTimestamp {
+        block: IbcTimeoutBlock,
+        timestamp: Timestamp,
+    },
+}
+
+/// This is the message we accept via Receive to send the tokens in several transfers.
+/// The amounts of the legs must add up to the sent amount
+#[cw_serde]
+pub struct BatchTransferBackMsg {
+    pub legs: Vec<TransferLeg>,
+}
+
+#[cw_serde]
+pub struct TransferLeg {
+    /// part of the sent amount this leg transfers, in local decimals
+    pub amount: Uint128,
+    pub msg: TransferBackMsg,
 }
 
 /// This is the message we accept via Receive
@@ -183,8 +367,203 @@ pub enum QueryMsg {
     PairMapping { key: String },
     #[returns(Vec<PairQuery>)]
     PairMappingsFromAssetInfo { asset_info: AssetInfo },
-    #[returns(Ratio)]
+    #[returns(TokenFeeSchedule)]
     GetTransferTokenFee { remote_token_denom: String },
+    /// List the funds that can still be recovered from a closed channel
+    #[returns(ListRecoverableFundsResponse)]
+    RecoverableFunds { channel_id: String },
+    /// List the outgoing packets on a channel that are still waiting for an ack or a timeout
+    #[returns(ListPendingTransfersResponse)]
+    PendingTransfers {
+        channel_id: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+        order: Option<u8>,
+    },
+    /// List the sensitive changes waiting for their timelock
+    #[returns(ListQueuedChangesResponse)]
+    QueuedChanges {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+        order: Option<u8>,
+    },
+    /// Show the rate limit of an ibc denom on a channel and its usage in the current window
+    #[returns(RateLimitResponse)]
+    RateLimit {
+        channel_id: String,
+        ibc_denom: String,
+    },
+    /// List the rate limits of a channel and their usage in the current window
+    #[returns(ListRateLimitsResponse)]
+    RateLimits {
+        channel_id: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+        order: Option<u8>,
+    },
+    /// List the funds left in this contract by failed payouts
+    #[returns(ListStuckFundsResponse)]
+    StuckFunds {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+        order: Option<u8>,
+    },
+    /// List the addresses and prefixes paying reduced fees
+    #[returns(ListFeeExemptionsResponse)]
+    FeeExemptions {
+        start_after: Option<FeeExemptionSubject>,
+        limit: Option<u32>,
+        order: Option<u8>,
+    },
+    /// List the changes of a token or relayer fee, with the previous value and the height of each change
+    #[returns(ListFeeHistoryResponse)]
+    FeeHistory {
+        fee: FeeKey,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+        order: Option<u8>,
+    },
+    /// Preview an incoming packet received on the channel: fees, mapping, swaps and what gets forwarded
+    #[returns(SimulateReceiveResponse)]
+    SimulateReceive {
+        dest_channel: String,
+        packet: Ics20Packet,
+    },
+    /// Preview sending the amount back to the remote chain with TransferToRemote or a cw20 Receive
+    #[returns(SimulateTransferResponse)]
+    SimulateTransfer {
+        sender: String,
+        msg: TransferBackMsg,
+        amount: Amount,
+    },
+    /// Show the fixed and the last known price of an asset in the fee denom
+    #[returns(PriceResponse)]
+    Price { asset_info: AssetInfo },
+    /// Show the swap operations used from the offer to the ask asset
+    #[returns(SwapRouteResponse)]
+    SwapRoute {
+        offer_asset_info: AssetInfo,
+        ask_asset_info: AssetInfo,
+    },
+    /// Show the fees accumulated since the last CollectFees
+    #[returns(AccumulatedFeesResponse)]
+    AccumulatedFees {},
+    /// List the registered relayers
+    #[returns(ListRelayersResponse)]
+    Relayers {
+        start_after: Option<String>,
+        limit: Option<u32>,
+        order: Option<u8>,
+    },
+    /// Show the relayer fees a relayer can claim
+    #[returns(RelayerBalancesResponse)]
+    RelayerBalances { relayer: String },
+}
+
+#[cw_serde]
+pub struct SimulateReceiveResponse {
+    pub fee_data: FeeData,
+    pub pair_mapping: PairQuery,
+    /// swaps from the received asset to the destination asset of the memo. Empty when nothing is swapped
+    pub swap_operations: Vec<SwapOperation>,
+    /// least amount of the destination asset the swaps may give after slippage, in local decimals
+    pub minimum_receive: Uint128,
+    /// mapping used to forward the tokens to the destination chain of the memo
+    pub destination_pair_mapping: Option<PairQuery>,
+    /// amount forwarded to the destination chain of the memo, in its decimals
+    pub remote_amount: Option<Uint128>,
+    /// why the memo could not be parsed. The tokens then stay with the receiver
+    pub memo_error: Option<String>,
+}
+
+#[cw_serde]
+pub struct SimulateTransferResponse {
+    pub fee_data: FeeData,
+    pub pair_mapping: PairQuery,
+    /// amount of the packet, in remote decimals
+    pub remote_amount: Uint128,
+}
+
+#[cw_serde]
+pub struct PriceResponse {
+    pub fixed: Option<Decimal>,
+    pub last_known: Option<Decimal>,
+}
+
+#[cw_serde]
+pub struct SwapRouteResponse {
+    pub operations: Vec<SwapOperation>,
+    /// false when the operations are the default route through the fee denom
+    pub registered: bool,
+}
+
+/// Query an oracle price source must answer
+#[cw_serde]
+pub enum OracleQueryMsg {
+    /// amount of quote per unit of base
+    Price { base: String, quote: AssetInfo },
+}
+
+#[cw_serde]
+pub struct OraclePriceResponse {
+    pub price: Decimal,
+}
+
+#[cw_serde]
+pub struct ListFeeHistoryResponse {
+    pub changes: Vec<FeeChange>,
+}
+
+#[cw_serde]
+pub struct ListFeeExemptionsResponse {
+    pub exemptions: Vec<FeeExemption>,
+}
+
+#[cw_serde]
+pub struct RelayerResponse {
+    pub relayer: Addr,
+    pub payout: Addr,
+}
+
+#[cw_serde]
+pub struct ListRelayersResponse {
+    pub relayers: Vec<RelayerResponse>,
+}
+
+#[cw_serde]
+pub struct RelayerBalancesResponse {
+    pub balances: Vec<Amount>,
+}
+
+#[cw_serde]
+pub struct AccumulatedFeesResponse {
+    pub token_fees: Vec<Amount>,
+    pub relayer_fees: Vec<Amount>,
+}
+
+#[cw_serde]
+pub struct QueuedChangeResponse {
+    pub id: u64,
+    pub change: QueuedChange,
+}
+
+#[cw_serde]
+pub struct ListQueuedChangesResponse {
+    pub changes: Vec<QueuedChangeResponse>,
+}
+
+#[cw_serde]
+pub struct RateLimitResponse {
+    pub ibc_denom: String,
+    pub limit: RateLimit,
+    /// amounts that went in and out during the rolling window ending now
+    pub inflow: Uint128,
+    pub outflow: Uint128,
+}
+
+#[cw_serde]
+pub struct ListRateLimitsResponse {
+    pub rate_limits: Vec<RateLimitResponse>,
 }
 
 #[cw_serde]
@@ -201,6 +580,10 @@ pub struct ChannelResponse {
     /// The total number of tokens that have been sent over this channel
     /// (even if many have been returned, so balance is low)
     pub total_sent: Vec<Amount>,
+    /// How many tokens originated on this chain are currently escrowed for this channel
+    pub forward_balances: Vec<Amount>,
+    /// timeout in seconds of the packets sent on this channel, if it overrides default_timeout
+    pub default_timeout: Option<u64>,
 }
 
 #[cw_serde]
@@ -214,6 +597,39 @@ pub struct ChannelWithKeyResponse {
     pub total_sent: Amount,
 }
 
+#[cw_serde]
+pub struct ListRecoverableFundsResponse {
+    pub funds: Vec<RecoverableFundResponse>,
+}
+
+#[cw_serde]
+pub struct RecoverableFundResponse {
+    pub ibc_denom: String,
+    pub fund: RecoverableFund,
+}
+
+#[cw_serde]
+pub struct ListPendingTransfersResponse {
+    pub transfers: Vec<PendingTransferResponse>,
+}
+
+#[cw_serde]
+pub struct PendingTransferResponse {
+    pub sequence: u64,
+    pub transfer: PendingTransfer,
+}
+
+#[cw_serde]
+pub struct ListStuckFundsResponse {
+    pub funds: Vec<StuckFundResponse>,
+}
+
+#[cw_serde]
+pub struct StuckFundResponse {
+    pub id: u64,
+    pub fund: StuckFund,
+}
+
 #[cw_serde]
 pub struct PortResponse {
     pub port_id: String,
@@ -230,12 +646,27 @@ pub struct ConfigResponse {
     pub relayer_fee_receiver: Addr,
     pub token_fees: Vec<TokenFee>,
     pub relayer_fees: Vec<RelayerFeeResponse>,
+    pub guardian: Option<Addr>,
+    pub paused: Vec<PauseScope>,
+    pub pending_admin: Option<Addr>,
+    pub timelock_delay: u64,
+    pub price_source: PriceSource,
+    pub price_fallback: PriceFallback,
+    pub unregistered_relayer_policy: UnregisteredRelayerPolicy,
+    pub default_slippage_bps: u16,
+    pub max_slippage_bps: u16,
+    pub min_timeout: u64,
+    pub max_timeout: u64,
 }
 
 #[cw_serde]
 pub struct RelayerFeeResponse {
     pub prefix: String,
     pub amount: Uint128,
+    /// None is the default of the network
+    pub remote_denom: Option<String>,
+    /// None is the fee denom
+    pub fee_asset: Option<AssetInfo>,
 }
 
 #[cw_serde]
@@ -1,49 +1,136 @@
+use std::fmt;
+
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Addr, IbcEndpoint, StdResult, Storage, Uint128};
+use cosmwasm_std::{Addr, Decimal, IbcEndpoint, StdResult, Storage, Timestamp, Uint128};
 use cw_controllers::Admin;
 use cw_storage_plus::{Index, IndexList, IndexedMap, Item, Map, MultiIndex};
-use oraiswap::{asset::AssetInfo, router::RouterController};
+use oraiswap::{
+    asset::AssetInfo,
+    router::{RouterController, SwapOperation},
+};
 
+use crate::msg::TransferBackMsg;
 use crate::ContractError;
+use cw20_ics20_msg::amount::Amount;
+use cw20_ics20_msg::receiver::DestinationInfo;
 
 pub const ADMIN: Admin = Admin::new("admin");
 
 pub const CONFIG: Item<Config> = Item::new("ics20_config_v1.0.2");
 
-// Used to pass info from the ibc_packet_receive to the reply handler
-pub const REPLY_ARGS: Item<ReplyArgs> = Item::new("reply_args_v2");
+/// Address proposed by the admin to take over. It becomes the admin once it accepts
+pub const PENDING_ADMIN: Item<Addr> = Item::new("pending_admin");
+
+/// Seconds a sensitive change waits in the queue before it can be executed. Zero or unset applies it right away
+pub const TIMELOCK_DELAY: Item<u64> = Item::new("timelock_delay");
+
+/// Sensitive changes waiting for their timelock, keyed by an increasing id
+pub const QUEUED_CHANGES: Map<u64, QueuedChange> = Map::new("queued_changes");
+
+/// Last id handed out to a queued change
+pub const QUEUED_CHANGE_COUNT: Item<u64> = Item::new("queued_change_count");
+
+// Used to pass info from the ibc_packet_receive to the reply handler, keyed by the reply nonce of the submessage
+pub const REPLY_ARGS: Map<u64, ReplyArgs> = Map::new("reply_args_v3");
+
+/// Last nonce handed out to a submessage that needs its own reply state
+pub const REPLY_NONCE: Item<u64> = Item::new("reply_nonce");
+
+/// Reply id of the last submessage of the packet being received. execute is rejected until it replies, except for pausing
+pub const RECEIVE_LOCK: Item<u64> = Item::new("receive_lock");
+
+/// Outgoing packets waiting for an ack or a timeout, keyed by (channel, sequence)
+pub const PENDING_TRANSFERS: Map<(&str, u64), PendingTransfer> = Map::new("pending_transfers");
+
+/// Outgoing packets whose sequence is not known yet, keyed by reply nonce.
+/// They are moved to PENDING_TRANSFERS once the send packet reply tells us the sequence.
+/// A forward whose reply has no sequence is left here for the admin to recover
+pub const UNSEQUENCED_TRANSFERS: Map<u64, PendingTransfer> = Map::new("unsequenced_transfers");
+
+/// Transfers waiting for the swap of the sent tokens, keyed by the reply nonce of the swap
+pub const SWAP_TRANSFERS: Map<u64, SwapTransfer> = Map::new("swap_transfers");
 
-pub const SINGLE_STEP_REPLY_ARGS: Item<ReplyArgs> = Item::new("single_step_reply_args_v2");
+/// Payouts sent in a submessage, kept until the reply tells whether they went through
+pub const PAYOUT_REPLY_ARGS: Map<u64, PayoutReplyArgs> = Map::new("payout_reply_args");
+
+/// Funds left in this contract by a failed payout, keyed by the reply nonce of the failed submessage
+pub const STUCK_FUNDS: Map<u64, StuckFund> = Map::new("stuck_funds");
 
 /// static info on one channel that doesn't change
 pub const CHANNEL_INFO: Map<&str, ChannelInfo> = Map::new("channel_info");
 
-// /// Forward channel state is used when LOCAL chain initiates ibc transfer to remote chain
-// pub const CHANNEL_FORWARD_STATE: Map<(&str, &str), ChannelState> =
-//     Map::new("channel_forward_state");
+/// seconds the packets sent on a channel live when the sender does not say, overriding the default timeout of the config
+pub const CHANNEL_TIMEOUTS: Map<&str, u64> = Map::new("channel_timeouts");
+
+/// Forward channel state is used when LOCAL chain initiates ibc transfer to remote chain.
+/// Key is (channel, local denom), cw20 tokens are prefixed with cw20:
+pub const CHANNEL_FORWARD_STATE: Map<(&str, &str), ChannelState> =
+    Map::new("channel_forward_state");
 
 /// Reverse channel state is used when REMOTE chain initiates ibc transfer to local chain
 pub const CHANNEL_REVERSE_STATE: Map<(&str, &str), ChannelState> =
     Map::new("channel_reverse_state");
 
-/// Reverse channel state is used when LOCAL chain initiates ibc transfer to remote chain
-// pub const CHANNEL_FORWARD_STATE: Map<(&str, &str), ChannelState> =
-// Map::new("channel_forward_state");
+/// Snapshot of the outstanding balances of a channel, taken when the channel is closed.
+/// Key is (channel, ibc_denom), or (channel, local denom) for the escrowed tokens. Only the admin can pay these funds out afterwards
+pub const CHANNEL_RECOVERABLE_FUNDS: Map<(&str, &str), RecoverableFund> =
+    Map::new("channel_recoverable_funds");
+
+/// Inflow and outflow caps of a (channel, ibc_denom) pair, set by the admin
+pub const RATE_LIMITS: Map<(&str, &str), RateLimit> = Map::new("rate_limits");
+
+/// Amounts that went through a rate limited (channel, ibc_denom) pair in the sub-windows of its rolling window
+pub const RATE_LIMIT_USAGE: Map<(&str, &str), RateLimitUsage> = Map::new("rate_limit_usage_v2");
+
+/// Number of sub-windows a rate limit window is tracked in. The usage also counts the sub-window the rolling
+/// window starts in, so no span of window_seconds carries more than the cap
+pub const RATE_LIMIT_SUB_WINDOWS: u64 = 10;
+
+/// Optional address that can pause the bridge besides the admin. It cannot unpause
+pub const GUARDIAN: Item<Addr> = Item::new("guardian");
+
+/// Scopes the bridge is currently paused on
+pub const PAUSED_SCOPES: Item<Vec<PauseScope>> = Item::new("paused_scopes");
 
 /// Every cw20 contract we allow to be sent is stored here, possibly with a gas_limit
 pub const ALLOW_LIST: Map<&Addr, AllowInfo> = Map::new("allow_list");
 
-pub const TOKEN_FEE: Map<&str, Ratio> = Map::new("token_fee");
+// token fee schedule, keyed by remote denom
+pub const TOKEN_FEE: Map<&str, TokenFeeSchedule> = Map::new("token_fee_v2");
+
+// relayer fee, keyed by (network prefix, remote denom). An empty remote denom holds the default of the network
+// decimals of relayer fee should always be 10^6 when it is set in ORAI, the default fee asset
+pub const RELAYER_FEE: Map<(&str, &str), RelayerFeeSchedule> = Map::new("relayer_fee_v2");
+
+// every change of a token or relayer fee, keyed by (string form of the fee key, change id)
+pub const FEE_HISTORY: Map<(&str, u64), FeeChange> = Map::new("fee_history");
+
+// id of the last recorded fee change
+pub const FEE_CHANGE_COUNT: Item<u64> = Item::new("fee_change_count");
 
-// relayer fee. This fee depends on the network type, not token type
-// decimals of relayer fee should always be 10^6 because we use ORAI as relayer fee
-pub const RELAYER_FEE: Map<&str, Uint128> = Map::new("relayer_fee");
+// addresses and prefixes paying reduced token and relayer fees, keyed by the string form of the subject
+pub const FEE_EXEMPTIONS: Map<&str, FeeExemption> = Map::new("fee_exemptions");
 
-// // accumulated token fee
-// pub const TOKEN_FEE_ACCUMULATOR: Map<&str, Uint128> = Map::new("token_fee_accumulator");
+// amount of an asset per unit of the fee denom, set by the admin, keyed by asset info
+pub const FIXED_PRICES: Map<&str, Decimal> = Map::new("fixed_prices");
 
-// // accumulated relayer fee
-// pub const RELAYER_FEE_ACCUMULATOR: Map<&str, Uint128> = Map::new("relayer_fee_accumulator");
+// amount of an asset per unit of the fee denom, as last returned by the price source, keyed by asset info
+pub const LAST_KNOWN_PRICES: Map<&str, Decimal> = Map::new("last_known_prices");
+
+// swap routes set by the admin, keyed by the offer and the ask asset info. Other pairs swap through the fee denom
+pub const SWAP_ROUTES: Map<(&str, &str), Vec<SwapOperation>> = Map::new("swap_routes");
+
+// relayers registered by the admin, with the address their fees are paid out to
+pub const RELAYERS: Map<&Addr, RelayerInfo> = Map::new("relayers");
+
+// relayer fees a relayer can claim with ClaimRelayerFees, keyed by (relayer, denom of the fee)
+pub const RELAYER_BALANCES: Map<(&Addr, &str), Uint128> = Map::new("relayer_balances");
+
+// accumulated token fee, keyed by the denom of the fee. Swept to the token fee receiver by CollectFees
+pub const TOKEN_FEE_ACCUMULATOR: Map<&str, Uint128> = Map::new("token_fee_accumulator");
+
+// accumulated relayer fee, keyed by the denom of the fee. Swept to the relayer fee receiver by CollectFees
+pub const RELAYER_FEE_ACCUMULATOR: Map<&str, Uint128> = Map::new("relayer_fee_accumulator");
 
 // MappingMetadataIndexex structs keeps a list of indexers
 pub struct MappingMetadataIndexex<'a> {
@@ -78,6 +165,275 @@ pub struct ChannelState {
     pub total_sent: Uint128,
 }
 
+/// Caps are in remote decimals, like the channel balances. None means that direction is not limited
+#[cw_serde]
+pub struct RateLimit {
+    /// length of the rolling window in seconds. The flows of the last window_seconds count towards the caps
+    pub window_seconds: u64,
+    pub max_inflow: Option<Uint128>,
+    pub max_outflow: Option<Uint128>,
+}
+
+impl RateLimit {
+    /// index of the sub-window `time` falls in
+    pub fn sub_window(&self, time: Timestamp) -> u64 {
+        let sub_window_seconds = self.window_seconds.div_ceil(RATE_LIMIT_SUB_WINDOWS).max(1);
+        time.seconds() / sub_window_seconds
+    }
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub struct RateLimitUsage {
+    /// oldest first, only the sub-windows that saw a flow are kept
+    pub sub_windows: Vec<SubWindowUsage>,
+}
+
+#[cw_serde]
+pub struct SubWindowUsage {
+    pub index: u64,
+    pub inflow: Uint128,
+    pub outflow: Uint128,
+}
+
+impl SubWindowUsage {
+    fn flow_mut(&mut self, direction: &FlowDirection) -> &mut Uint128 {
+        match direction {
+            FlowDirection::Inflow => &mut self.inflow,
+            FlowDirection::Outflow => &mut self.outflow,
+        }
+    }
+}
+
+impl RateLimitUsage {
+    /// the usage as seen at `now`, without the sub-windows that have left the rolling window
+    pub fn at(mut self, limit: &RateLimit, now: Timestamp) -> Self {
+        let oldest = limit.sub_window(now).saturating_sub(RATE_LIMIT_SUB_WINDOWS);
+        self.sub_windows
+            .retain(|sub_window| sub_window.index >= oldest);
+        self
+    }
+
+    pub fn inflow(&self) -> Uint128 {
+        self.sub_windows
+            .iter()
+            .map(|sub_window| sub_window.inflow)
+            .sum()
+    }
+
+    pub fn outflow(&self) -> Uint128 {
+        self.sub_windows
+            .iter()
+            .map(|sub_window| sub_window.outflow)
+            .sum()
+    }
+
+    fn total(&self, direction: &FlowDirection) -> Uint128 {
+        match direction {
+            FlowDirection::Inflow => self.inflow(),
+            FlowDirection::Outflow => self.outflow(),
+        }
+    }
+
+    /// sub-window `index`, appended if there is none yet. Block time only moves forward, so it is always the last one
+    fn sub_window_mut(&mut self, index: u64) -> &mut SubWindowUsage {
+        if self.sub_windows.last().map(|sub_window| sub_window.index) != Some(index) {
+            self.sub_windows.push(SubWindowUsage {
+                index,
+                inflow: Uint128::zero(),
+                outflow: Uint128::zero(),
+            });
+        }
+        self.sub_windows.last_mut().unwrap()
+    }
+}
+
+pub enum FlowDirection {
+    Inflow,
+    Outflow,
+}
+
+#[cw_serde]
+pub enum PauseScope {
+    /// every packet in and out
+    All {},
+    /// packets coming in from the remote chains
+    Inbound {},
+    /// packets sent to the remote chains
+    Outbound {},
+    Channel {
+        channel_id: String,
+    },
+    /// a single mapping, in form port/channel/denom. Tokens that originated on this chain use their local denom
+    IbcDenom {
+        ibc_denom: String,
+    },
+}
+
+impl PauseScope {
+    pub fn covers(&self, channel: &str, ibc_denom: &str, direction: &FlowDirection) -> bool {
+        match self {
+            PauseScope::All {} => true,
+            PauseScope::Inbound {} => matches!(direction, FlowDirection::Inflow),
+            PauseScope::Outbound {} => matches!(direction, FlowDirection::Outflow),
+            PauseScope::Channel { channel_id } => channel_id == channel,
+            PauseScope::IbcDenom { ibc_denom: denom } => denom == ibc_denom,
+        }
+    }
+}
+
+impl fmt::Display for PauseScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PauseScope::All {} => write!(f, "all"),
+            PauseScope::Inbound {} => write!(f, "inbound"),
+            PauseScope::Outbound {} => write!(f, "outbound"),
+            PauseScope::Channel { channel_id } => write!(f, "channel:{}", channel_id),
+            PauseScope::IbcDenom { ibc_denom } => write!(f, "ibc_denom:{}", ibc_denom),
+        }
+    }
+}
+
+#[cw_serde]
+pub enum FeeExemptionSubject {
+    /// sender of outgoing transfers or receiver of incoming packets on this chain
+    LocalAddress { address: String },
+    /// counterparty address on the remote chain
+    RemoteAddress { address: String },
+    /// every counterparty address with this bech32 prefix
+    RemotePrefix { prefix: String },
+}
+
+impl fmt::Display for FeeExemptionSubject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeeExemptionSubject::LocalAddress { address } => write!(f, "local:{}", address),
+            FeeExemptionSubject::RemoteAddress { address } => write!(f, "remote:{}", address),
+            FeeExemptionSubject::RemotePrefix { prefix } => write!(f, "prefix:{}", prefix),
+        }
+    }
+}
+
+#[cw_serde]
+pub struct FeeExemption {
+    pub subject: FeeExemptionSubject,
+    /// share of the token and relayer fees that is waived. None waives them entirely
+    pub discount: Option<Ratio>,
+}
+
+impl FeeExemption {
+    pub fn discount(&self) -> Decimal {
+        match &self.discount {
+            Some(ratio) => Decimal::from_ratio(ratio.nominator, ratio.denominator),
+            None => Decimal::one(),
+        }
+    }
+}
+
+/// Identifies one token or relayer fee entry
+#[cw_serde]
+pub enum FeeKey {
+    TokenFee {
+        token_denom: String,
+    },
+    RelayerFee {
+        prefix: String,
+        /// None is the default of the network
+        #[serde(default)]
+        remote_denom: Option<String>,
+    },
+}
+
+impl fmt::Display for FeeKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeeKey::TokenFee { token_denom } => write!(f, "token:{}", token_denom),
+            FeeKey::RelayerFee {
+                prefix,
+                remote_denom: None,
+            } => write!(f, "relayer:{}", prefix),
+            FeeKey::RelayerFee {
+                prefix,
+                remote_denom: Some(remote_denom),
+            } => write!(f, "relayer:{}:{}", prefix, remote_denom),
+        }
+    }
+}
+
+#[cw_serde]
+pub enum FeeValue {
+    TokenFee { schedule: TokenFeeSchedule },
+    RelayerFee { schedule: RelayerFeeSchedule },
+}
+
+#[cw_serde]
+pub struct FeeChange {
+    pub id: u64,
+    /// block height the change took effect at
+    pub height: u64,
+    pub key: FeeKey,
+    /// None if the fee was not set before
+    pub previous: Option<FeeValue>,
+    /// None if the fee was removed
+    pub current: Option<FeeValue>,
+}
+
+/// Changes that go through the timelock when one is set. Rate limits, pauses and fee exemptions apply right away:
+/// the first two only hold transfers back and are the emergency brakes, and an exemption only lowers a fee below
+/// the timelocked schedule
+#[cw_serde]
+pub enum SensitiveChange {
+    SwapRouterContract {
+        swap_router_contract: String,
+    },
+    TokenFeeReceiver {
+        fee_receiver: Addr,
+    },
+    RelayerFeeReceiver {
+        relayer_fee_receiver: Addr,
+    },
+    OverrideChannelBalance {
+        channel_id: String,
+        ibc_denom: String,
+        outstanding: Uint128,
+        total_sent: Option<Uint128>,
+    },
+    TimelockDelay {
+        delay: u64,
+    },
+    PriceSource {
+        price_source: PriceSource,
+        price_fallback: PriceFallback,
+    },
+    FixedPrice {
+        asset_info: AssetInfo,
+        price: Option<Decimal>,
+    },
+    SwapRoute {
+        offer_asset_info: AssetInfo,
+        ask_asset_info: AssetInfo,
+        operations: Option<Vec<SwapOperation>>,
+    },
+    SetFees {
+        token_fees: Vec<TokenFee>,
+        relayer_fees: Vec<RelayerFee>,
+    },
+    RemoveFees {
+        fees: Vec<FeeKey>,
+    },
+    ReplaceFees {
+        token_fees: Option<Vec<TokenFee>>,
+        relayer_fees: Option<Vec<RelayerFee>>,
+    },
+}
+
+#[cw_serde]
+pub struct QueuedChange {
+    pub change: SensitiveChange,
+    /// the change can be executed from this time on
+    pub eta: Timestamp,
+}
+
 #[cw_serde]
 pub struct Config {
     pub default_timeout: u64,
@@ -86,6 +442,79 @@ pub struct Config {
     pub swap_router_contract: RouterController,
     pub token_fee_receiver: Addr,
     pub relayer_fee_receiver: Addr,
+    /// where the relayer fee, set in fee denom, gets its price in the transferred asset
+    #[serde(default)]
+    pub price_source: PriceSource,
+    /// what to do when the price source has no price
+    #[serde(default)]
+    pub price_fallback: PriceFallback,
+    /// where the relayer fees earned by relayers that are not registered go
+    #[serde(default)]
+    pub unregistered_relayer_policy: UnregisteredRelayerPolicy,
+    /// slippage of a universal swap whose memo sets neither a slippage nor a minimum out, in basis points
+    #[serde(default)]
+    pub default_slippage_bps: u16,
+    /// highest slippage a memo may ask for. A memo minimum out below it is raised to it
+    #[serde(default)]
+    pub max_slippage_bps: u16,
+    /// least seconds an outgoing packet may live
+    #[serde(default)]
+    pub min_timeout: u64,
+    /// most seconds an outgoing packet may live, zero for no bound
+    #[serde(default)]
+    pub max_timeout: u64,
+}
+
+#[cw_serde]
+pub enum PriceSource {
+    /// simulate a swap from the fee denom on the swap router
+    Router {},
+    /// prices set by the admin with SetFixedPrice
+    Fixed {},
+    /// query the price from an oracle contract, see OracleQueryMsg
+    Oracle { contract: Addr },
+}
+
+#[cw_serde]
+pub enum PriceFallback {
+    /// charge no relayer fee
+    Zero {},
+    /// fail the transfer. An incoming packet gets a failed ack so the remote chain refunds its sender
+    Reject {},
+    /// use the last price the source returned
+    LastKnown {},
+}
+
+impl Default for PriceSource {
+    fn default() -> Self {
+        PriceSource::Router {}
+    }
+}
+
+impl Default for PriceFallback {
+    fn default() -> Self {
+        PriceFallback::Zero {}
+    }
+}
+
+#[cw_serde]
+pub enum UnregisteredRelayerPolicy {
+    /// the relayer earns its fees like a registered one, claimable to its own address
+    Credit {},
+    /// the fees go to the relayer fee receiver
+    Treasury {},
+}
+
+impl Default for UnregisteredRelayerPolicy {
+    fn default() -> Self {
+        UnregisteredRelayerPolicy::Credit {}
+    }
+}
+
+#[cw_serde]
+pub struct RelayerInfo {
+    /// address the claimed fees are sent to
+    pub payout: Addr,
 }
 
 #[cw_serde]
@@ -96,6 +525,9 @@ pub struct ChannelInfo {
     pub counterparty_endpoint: IbcEndpoint,
     /// the connection this exists on (you can use to query client/consensus info)
     pub connection_id: String,
+    /// set when the channel is closed. No more transfers are accepted on a closed channel
+    #[serde(default)]
+    pub closed: bool,
 }
 
 #[cw_serde]
@@ -107,15 +539,88 @@ pub struct AllowInfo {
 pub struct TokenFee {
     pub token_denom: String,
     pub ratio: Ratio,
+    /// charged on top of the ratio, in local asset units
+    pub flat: Option<Uint128>,
+    /// lower bound of the fee, in local asset units
+    pub min: Option<Uint128>,
+    /// upper bound of the fee, in local asset units
+    pub max: Option<Uint128>,
+}
+
+impl TokenFee {
+    pub fn schedule(&self) -> TokenFeeSchedule {
+        TokenFeeSchedule {
+            ratio: self.ratio.clone(),
+            flat: self.flat,
+            min: self.min,
+            max: self.max,
+        }
+    }
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub struct TokenFeeSchedule {
+    pub ratio: Ratio,
+    /// charged on top of the ratio, in local asset units
+    pub flat: Option<Uint128>,
+    /// lower bound of the fee, in local asset units
+    pub min: Option<Uint128>,
+    /// upper bound of the fee, in local asset units
+    pub max: Option<Uint128>,
+}
+
+impl TokenFeeSchedule {
+    /// a schedule has to have a ratio that can be applied and a minimum that is not above its maximum
+    pub fn validate(&self, token_denom: &str) -> Result<(), ContractError> {
+        let reason = if self.ratio.denominator == 0 {
+            "the ratio denominator is zero"
+        } else if matches!((self.min, self.max), (Some(min), Some(max)) if min > max) {
+            "the minimum is above the maximum"
+        } else {
+            return Ok(());
+        };
+        Err(ContractError::InvalidTokenFee {
+            token_denom: token_denom.to_string(),
+            reason: reason.to_string(),
+        })
+    }
 }
 
 #[cw_serde]
 pub struct RelayerFee {
     pub prefix: String,
     pub fee: Uint128,
+    /// remote denom the fee applies to. None sets the default of the network
+    #[serde(default)]
+    pub remote_denom: Option<String>,
+    /// asset the fee is set in. None is the fee denom
+    #[serde(default)]
+    pub fee_asset: Option<AssetInfo>,
+}
+
+impl RelayerFee {
+    pub fn remote_denom(&self) -> &str {
+        self.remote_denom.as_deref().unwrap_or_default()
+    }
+
+    pub fn schedule(&self) -> RelayerFeeSchedule {
+        RelayerFeeSchedule {
+            fee: self.fee,
+            fee_asset: self.fee_asset.clone(),
+        }
+    }
+}
+
+#[cw_serde]
+pub struct RelayerFeeSchedule {
+    pub fee: Uint128,
+    /// asset the fee is set in. None is the fee denom
+    pub fee_asset: Option<AssetInfo>,
 }
 
 #[cw_serde]
+#[derive(Default)]
 pub struct Ratio {
     pub nominator: u64,
     pub denominator: u64,
@@ -129,12 +634,113 @@ pub struct MappingMetadata {
     pub asset_info_decimals: u8,
 }
 
+#[cw_serde]
+pub struct RecoverableFund {
+    /// outstanding balance of the channel when it was closed, in remote decimals
+    pub outstanding: Uint128,
+    /// amount already paid out by the admin, in remote decimals
+    pub recovered: Uint128,
+    /// block height at which the channel was closed
+    pub closed_height: u64,
+    /// set for tokens of this chain escrowed on the channel, whose amounts are local
+    #[serde(default)]
+    pub escrowed: bool,
+}
+
+#[cw_serde]
+pub struct PendingTransfer {
+    /// channel the packet was sent on
+    pub src_channel_id: String,
+    /// local address refunded when the packet fails
+    pub local_sender: String,
+    /// local asset backing the packet, after fees
+    pub local_amount: Amount,
+    /// token fee charged on the local chain, in the local asset
+    pub token_fee: Uint128,
+    /// relayer fee charged on the local chain, in the local asset
+    pub relayer_fee: Uint128,
+    /// set when the relayer fee is still held for the relayer of the ack. A failed packet gives it to the relayer fee receiver
+    #[serde(default)]
+    pub relayer_fee_on_ack: bool,
+    /// denom of the packet in form port/channel/denom
+    pub ibc_denom: String,
+    /// amount of the packet, in remote decimals
+    pub remote_amount: Uint128,
+    /// pair mapping used when the packet was sent. None for tokens that originated on this chain,
+    /// in which case ibc_denom is the local denom and remote_amount the escrowed amount
+    pub mapping: Option<MappingMetadata>,
+    /// block time the packet was sent at. A refund takes its outflow back out of the rate limit sub-window of that time
+    #[serde(default)]
+    pub sent_at: Option<Timestamp>,
+}
+
+#[cw_serde]
+pub struct SwapTransfer {
+    pub sender: Addr,
+    /// tokens sent by the sender, refunded if the swap fails
+    pub offer: Amount,
+    /// asset the tokens are swapped into
+    pub ask_asset_info: AssetInfo,
+    /// transfer of the swapped tokens, without the swap
+    pub msg: TransferBackMsg,
+}
+
+#[cw_serde]
+pub struct PayoutReplyArgs {
+    pub receiver: String,
+    pub amount: Amount,
+}
+
+#[cw_serde]
+pub struct StuckFund {
+    /// address the funds were meant for
+    pub receiver: String,
+    /// denom of the stuck asset, cw20 tokens are prefixed with cw20:
+    pub asset: String,
+    pub amount: Uint128,
+    /// error returned by the failed payout
+    pub reason: String,
+    pub height: u64,
+    /// set once the fund is claimed by its receiver or resolved by the admin
+    pub paid_to: Option<String>,
+    pub paid_height: Option<u64>,
+}
+
 #[cw_serde]
 pub struct ReplyArgs {
     pub channel: String,
     pub local_receiver: String,
     pub denom: String,
     pub amount: Uint128,
+    /// least amount the swap may give
+    #[serde(default)]
+    pub minimum_receive: Uint128,
+    /// hook or forward of the swapped tokens, only sent once the swap has given them
+    #[serde(default)]
+    pub follow_up: Option<SwapFollowUp>,
+}
+
+#[cw_serde]
+pub struct SwapFollowUp {
+    /// local denom the swap gives, cw20 tokens are prefixed with cw20:
+    pub ask_denom: String,
+    /// sender of the received packet on the remote chain
+    pub remote_sender: String,
+    pub destination: DestinationInfo,
+    /// channel on Oraichain that received the packet
+    pub local_channel_id: String,
+    pub pair_mapping: Option<(String, MappingMetadata)>,
+}
+
+// default timeout in seconds of the packets sent on the channel
+pub fn channel_default_timeout(
+    storage: &dyn Storage,
+    channel: &str,
+    default_timeout: u64,
+) -> StdResult<u64> {
+    Ok(CHANNEL_TIMEOUTS
+        .may_load(storage, channel)?
+        .unwrap_or(default_timeout))
 }
 
 pub fn increase_channel_balance(
@@ -145,8 +751,8 @@ pub fn increase_channel_balance(
 ) -> Result<(), ContractError> {
     CHANNEL_REVERSE_STATE.update(storage, (channel, denom), |orig| -> StdResult<_> {
         let mut state = orig.unwrap_or_default();
-        state.outstanding += amount;
-        state.total_sent += amount;
+        state.outstanding = state.outstanding.checked_add(amount)?;
+        state.total_sent = state.total_sent.checked_add(amount)?;
         Ok(state)
     })?;
     Ok(())
@@ -199,6 +805,29 @@ pub fn override_channel_balance(
     Ok(())
 }
 
+// reverts `increase_channel_balance` when the receive fails after it, both outstanding and total_sent
+pub fn undo_increase_channel_balance(
+    storage: &mut dyn Storage,
+    channel: &str,
+    denom: &str,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    CHANNEL_REVERSE_STATE.update(
+        storage,
+        (channel, denom),
+        |orig| -> Result<_, ContractError> {
+            let mut cur = orig.ok_or(ContractError::NoSuchChannelState {
+                id: channel.to_string(),
+                denom: denom.to_string(),
+            })?;
+            cur.outstanding = cur.outstanding.checked_sub(amount)?;
+            cur.total_sent = cur.total_sent.checked_sub(amount)?;
+            Ok(cur)
+        },
+    )?;
+    Ok(())
+}
+
 // this is like increase, but it only "un-subtracts" (= adds) outstanding, not total_sent
 // calling `reduce_channel_balance` and then `undo_reduce_channel_balance` should leave state unchanged.
 pub fn undo_reduce_channel_balance(
@@ -215,6 +844,291 @@ pub fn undo_reduce_channel_balance(
     Ok(())
 }
 
+/// Sets the token fee of a remote denom, None removes it. The change is recorded in the fee history
+pub fn save_token_fee(
+    storage: &mut dyn Storage,
+    height: u64,
+    token_denom: &str,
+    schedule: Option<TokenFeeSchedule>,
+) -> Result<(), ContractError> {
+    let previous = TOKEN_FEE.may_load(storage, token_denom)?;
+    match &schedule {
+        Some(schedule) => {
+            schedule.validate(token_denom)?;
+            TOKEN_FEE.save(storage, token_denom, schedule)?
+        }
+        None => TOKEN_FEE.remove(storage, token_denom),
+    }
+    record_fee_change(
+        storage,
+        height,
+        FeeKey::TokenFee {
+            token_denom: token_denom.to_string(),
+        },
+        previous.map(|schedule| FeeValue::TokenFee { schedule }),
+        schedule.map(|schedule| FeeValue::TokenFee { schedule }),
+    )?;
+    Ok(())
+}
+
+/// Sets the relayer fee of a prefix and remote denom, empty for the network default. None removes it.
+/// The change is recorded in the fee history
+pub fn save_relayer_fee(
+    storage: &mut dyn Storage,
+    height: u64,
+    prefix: &str,
+    remote_denom: &str,
+    schedule: Option<RelayerFeeSchedule>,
+) -> StdResult<()> {
+    let previous = RELAYER_FEE.may_load(storage, (prefix, remote_denom))?;
+    match &schedule {
+        Some(schedule) => RELAYER_FEE.save(storage, (prefix, remote_denom), schedule)?,
+        None => RELAYER_FEE.remove(storage, (prefix, remote_denom)),
+    }
+    record_fee_change(
+        storage,
+        height,
+        FeeKey::RelayerFee {
+            prefix: prefix.to_string(),
+            remote_denom: Some(remote_denom.to_string()).filter(|denom| !denom.is_empty()),
+        },
+        previous.map(|schedule| FeeValue::RelayerFee { schedule }),
+        schedule.map(|schedule| FeeValue::RelayerFee { schedule }),
+    )
+}
+
+fn record_fee_change(
+    storage: &mut dyn Storage,
+    height: u64,
+    key: FeeKey,
+    previous: Option<FeeValue>,
+    current: Option<FeeValue>,
+) -> StdResult<()> {
+    // saving the same value again is not a change
+    if previous == current {
+        return Ok(());
+    }
+    let id = FEE_CHANGE_COUNT.may_load(storage)?.unwrap_or_default() + 1;
+    FEE_CHANGE_COUNT.save(storage, &id)?;
+    FEE_HISTORY.save(
+        storage,
+        (&key.to_string(), id),
+        &FeeChange {
+            id,
+            height,
+            key,
+            previous,
+            current,
+        },
+    )
+}
+
+pub fn accumulate_fee(
+    storage: &mut dyn Storage,
+    fee_accumulator: Map<&str, Uint128>,
+    fee: &Amount,
+) -> StdResult<()> {
+    if fee.is_empty() {
+        return Ok(());
+    }
+    fee_accumulator.update(storage, &fee.denom(), |orig| -> StdResult<_> {
+        Ok(orig.unwrap_or_default().checked_add(fee.amount())?)
+    })?;
+    Ok(())
+}
+
+/// Credits a relayer fee to the relayer that delivered the packet. Unregistered relayers follow the configured policy
+pub fn credit_relayer_fee(
+    storage: &mut dyn Storage,
+    relayer: &Addr,
+    fee: &Amount,
+) -> StdResult<()> {
+    if fee.is_empty() {
+        return Ok(());
+    }
+    let policy = CONFIG.load(storage)?.unregistered_relayer_policy;
+    if !RELAYERS.has(storage, relayer) && matches!(policy, UnregisteredRelayerPolicy::Treasury {}) {
+        return accumulate_fee(storage, RELAYER_FEE_ACCUMULATOR, fee);
+    }
+    RELAYER_BALANCES.update(storage, (relayer, &fee.denom()), |orig| -> StdResult<_> {
+        Ok(orig.unwrap_or_default().checked_add(fee.amount())?)
+    })?;
+    Ok(())
+}
+
+pub fn increase_channel_forward_balance(
+    storage: &mut dyn Storage,
+    channel: &str,
+    denom: &str, // should be local denom
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    CHANNEL_FORWARD_STATE.update(storage, (channel, denom), |orig| -> StdResult<_> {
+        let mut state = orig.unwrap_or_default();
+        state.outstanding += amount;
+        state.total_sent += amount;
+        Ok(state)
+    })?;
+    Ok(())
+}
+
+pub fn reduce_channel_forward_balance(
+    storage: &mut dyn Storage,
+    channel: &str,
+    denom: &str, // should be local denom
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    CHANNEL_FORWARD_STATE.update(
+        storage,
+        (channel, denom),
+        |orig| -> Result<_, ContractError> {
+            // we can only unescrow what has been sent over this channel
+            let mut cur = orig.ok_or(ContractError::NoSuchChannelState {
+                id: channel.to_string(),
+                denom: denom.to_string(),
+            })?;
+            cur.outstanding =
+                cur.outstanding
+                    .checked_sub(amount)
+                    .or(Err(ContractError::InsufficientFunds {
+                        id: channel.to_string(),
+                        denom: denom.to_string(),
+                    }))?;
+            Ok(cur)
+        },
+    )?;
+    Ok(())
+}
+
+// reverts `increase_channel_forward_balance` when the packet fails, both outstanding and total_sent
+pub fn undo_increase_channel_forward_balance(
+    storage: &mut dyn Storage,
+    channel: &str,
+    denom: &str,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    CHANNEL_FORWARD_STATE.update(
+        storage,
+        (channel, denom),
+        |orig| -> Result<_, ContractError> {
+            let mut cur = orig.ok_or(ContractError::NoSuchChannelState {
+                id: channel.to_string(),
+                denom: denom.to_string(),
+            })?;
+            cur.outstanding = cur.outstanding.checked_sub(amount)?;
+            cur.total_sent = cur.total_sent.checked_sub(amount)?;
+            Ok(cur)
+        },
+    )?;
+    Ok(())
+}
+
+pub fn assert_not_paused(
+    storage: &dyn Storage,
+    channel: &str,
+    ibc_denom: &str,
+    direction: FlowDirection,
+) -> Result<(), ContractError> {
+    let paused = PAUSED_SCOPES.may_load(storage)?.unwrap_or_default();
+    match paused
+        .into_iter()
+        .find(|scope| scope.covers(channel, ibc_denom, &direction))
+    {
+        Some(scope) => Err(ContractError::Paused {
+            scope: scope.to_string(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Returns the usage of the (channel, ibc_denom) pair with the amount added, erroring if it goes over the cap.
+/// Nothing is written, so callers can check before their first write. None if the pair has no rate limit
+pub fn check_rate_limited_flow(
+    storage: &dyn Storage,
+    now: Timestamp,
+    channel: &str,
+    ibc_denom: &str,
+    amount: Uint128,
+    direction: FlowDirection,
+) -> Result<Option<RateLimitUsage>, ContractError> {
+    let limit = match RATE_LIMITS.may_load(storage, (channel, ibc_denom))? {
+        Some(limit) => limit,
+        None => return Ok(None),
+    };
+    let mut usage = RATE_LIMIT_USAGE
+        .may_load(storage, (channel, ibc_denom))?
+        .unwrap_or_default()
+        .at(&limit, now);
+    let max = match direction {
+        FlowDirection::Inflow => limit.max_inflow,
+        FlowDirection::Outflow => limit.max_outflow,
+    };
+    if let Some(max) = max {
+        if usage.total(&direction).checked_add(amount)? > max {
+            return Err(ContractError::RateLimitExceeded {
+                id: channel.to_string(),
+                denom: ibc_denom.to_string(),
+            });
+        }
+    }
+    let used = usage
+        .sub_window_mut(limit.sub_window(now))
+        .flow_mut(&direction);
+    *used = used.checked_add(amount)?;
+    Ok(Some(usage))
+}
+
+/// Adds the amount to the usage of the (channel, ibc_denom) pair, erroring if it goes over the cap.
+/// Pairs without a rate limit are not tracked
+pub fn record_rate_limited_flow(
+    storage: &mut dyn Storage,
+    now: Timestamp,
+    channel: &str,
+    ibc_denom: &str,
+    amount: Uint128,
+    direction: FlowDirection,
+) -> Result<(), ContractError> {
+    if let Some(usage) =
+        check_rate_limited_flow(storage, now, channel, ibc_denom, amount, direction)?
+    {
+        RATE_LIMIT_USAGE.save(storage, (channel, ibc_denom), &usage)?;
+    }
+    Ok(())
+}
+
+/// Takes a refunded outflow back out of the sub-window it was sent in, if that sub-window is still tracked
+pub fn release_rate_limited_outflow(
+    storage: &mut dyn Storage,
+    sent_at: Timestamp,
+    channel: &str,
+    ibc_denom: &str,
+    amount: Uint128,
+) -> StdResult<()> {
+    let limit = match RATE_LIMITS.may_load(storage, (channel, ibc_denom))? {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+    let mut usage = match RATE_LIMIT_USAGE.may_load(storage, (channel, ibc_denom))? {
+        Some(usage) => usage,
+        None => return Ok(()),
+    };
+    let index = limit.sub_window(sent_at);
+    if let Some(sub_window) = usage
+        .sub_windows
+        .iter_mut()
+        .find(|sub_window| sub_window.index == index)
+    {
+        sub_window.outflow = sub_window.outflow.saturating_sub(amount);
+        RATE_LIMIT_USAGE.save(storage, (channel, ibc_denom), &usage)?;
+    }
+    Ok(())
+}
+
+pub fn next_reply_nonce(storage: &mut dyn Storage) -> StdResult<u64> {
+    let nonce = REPLY_NONCE.may_load(storage)?.unwrap_or_default() + 1;
+    REPLY_NONCE.save(storage, &nonce)?;
+    Ok(nonce)
+}
+
 pub fn get_key_ics20_ibc_denom(port_id: &str, channel_id: &str, denom: &str) -> String {
     format!("{}/{}/{}", port_id, channel_id, denom)
 }
@@ -1,14 +1,18 @@
 #![cfg(test)]
 
 use crate::contract::instantiate;
-use crate::ibc::{ibc_channel_connect, ibc_channel_open, ICS20_ORDERING, ICS20_VERSION};
+use crate::ibc::{
+    ibc_channel_connect, ibc_channel_open, ibc_packet_receive, reply, ICS20_ORDERING, ICS20_VERSION,
+};
 use crate::state::ChannelInfo;
 
 use cosmwasm_std::testing::{
     mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
 };
 use cosmwasm_std::{
-    DepsMut, IbcChannel, IbcChannelConnectMsg, IbcChannelOpenMsg, IbcEndpoint, OwnedDeps,
+    DepsMut, Env, IbcChannel, IbcChannelConnectMsg, IbcChannelOpenMsg, IbcEndpoint,
+    IbcPacketReceiveMsg, IbcReceiveResponse, OwnedDeps, Reply, ReplyOn, SubMsgResponse,
+    SubMsgResult,
 };
 
 use crate::msg::{AllowMsg, InitMsg};
@@ -42,6 +46,7 @@ pub fn mock_channel_info(channel_id: &str) -> ChannelInfo {
             channel_id: format!("{}5", channel_id),
         },
         connection_id: CONNECTION_ID.into(),
+        closed: false,
     }
 }
 
@@ -85,3 +90,29 @@ pub fn setup(
     }
     deps
 }
+
+/// Receives the packet, then replies to its last submessage like the chain would, which releases the receive lock
+pub fn receive_and_reply(
+    deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+    env: Env,
+    msg: IbcPacketReceiveMsg,
+) -> IbcReceiveResponse {
+    let res = ibc_packet_receive(deps.as_mut(), env.clone(), msg).unwrap();
+    if let Some(last) = res.messages.last() {
+        if last.reply_on == ReplyOn::Always {
+            reply(
+                deps.as_mut(),
+                env,
+                Reply {
+                    id: last.id,
+                    result: SubMsgResult::Ok(SubMsgResponse {
+                        events: vec![],
+                        data: None,
+                    }),
+                },
+            )
+            .unwrap();
+        }
+    }
+    res
+}
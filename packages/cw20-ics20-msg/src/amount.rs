@@ -62,11 +62,10 @@ impl Amount {
 
     /// convert the amount into u64
     pub fn u64_amount(&self) -> Result<u64, StdError> {
-        Ok(self
-            .amount()
+        self.amount()
             .u128()
             .try_into()
-            .map_err(|_| StdError::generic_err("error casting to u64 from u128".to_string()))?)
+            .map_err(|_| StdError::generic_err("error casting to u64 from u128".to_string()))
     }
 
     pub fn is_empty(&self) -> bool {
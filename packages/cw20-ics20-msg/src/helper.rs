@@ -15,7 +15,7 @@ pub fn get_prefix_decode_bech32(address: &str) -> StdResult<String> {
 
 pub fn parse_asset_info_denom(asset_info: AssetInfo) -> String {
     match asset_info {
-        AssetInfo::Token { contract_addr } => format!("cw20:{}", contract_addr.to_string()),
+        AssetInfo::Token { contract_addr } => format!("cw20:{}", contract_addr),
         AssetInfo::NativeToken { denom } => denom,
     }
 }
@@ -30,7 +30,7 @@ pub fn denom_to_asset_info(
     denom: &str,
 ) -> StdResult<AssetInfo> {
     let info = if querier
-        .query_wasm_smart::<TokenInfoResponse>(denom.clone(), &Cw20QueryMsg::TokenInfo {})
+        .query_wasm_smart::<TokenInfoResponse>(denom, &Cw20QueryMsg::TokenInfo {})
         .is_ok()
     {
         AssetInfo::Token {
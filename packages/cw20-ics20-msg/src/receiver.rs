@@ -1,17 +1,153 @@
 use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{from_slice, to_vec, Binary, Uint128};
+use thiserror::Error;
 
 use crate::helper::get_prefix_decode_bech32;
 
+/// slippage of 100%, the swap may give anything
+pub const MAX_SLIPPAGE_BPS: u16 = 10_000;
+
 #[cw_serde]
+#[derive(Default)]
 pub struct DestinationInfo {
     pub receiver: String,
     pub destination_channel: String,
     /// destination denom can be in cw20 form or ibc/<hash>
     pub destination_denom: String,
+    /// least amount of the destination denom the swap must give, at most the simulated amount. None accepts the simulated amount
+    pub min_out: Option<Uint128>,
+    /// how far below the simulated amount the swap may land, in basis points. None takes the default of the contract.
+    /// Above the max slippage of the contract, which is zero until the admin sets it, the memo is rejected
+    pub slippage_bps: Option<u16>,
+    /// local address refunded when the swap or the forward fails, instead of the packet receiver
+    pub refund_address: Option<String>,
+    /// contract called with the received tokens, after the swap if there is a destination denom.
+    /// The hook cannot call back into this contract, e.g. to transfer the tokens on: such a call fails the hook and refunds the tokens
+    pub hook: Option<ContractHook>,
+}
+
+#[cw_serde]
+pub struct ContractHook {
+    pub contract: String,
+    pub msg: Binary,
+}
+
+/// JSON memo. Each version is its own variant, so a memo reads like {"v1":{...}}
+#[cw_serde]
+pub enum Memo {
+    V1(MemoV1),
+}
+
+#[cw_serde]
+pub struct MemoV1 {
+    pub receiver: String,
+    #[serde(default)]
+    pub destination_channel: String,
+    #[serde(default)]
+    pub destination_denom: String,
+    #[serde(default)]
+    pub min_out: Option<Uint128>,
+    #[serde(default)]
+    pub slippage_bps: Option<u16>,
+    #[serde(default)]
+    pub refund_address: Option<String>,
+    #[serde(default)]
+    pub hook: Option<ContractHook>,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum MemoError {
+    #[error("Invalid memo: {0}")]
+    InvalidJson(String),
+
+    #[error("Memo has no receiver")]
+    MissingReceiver,
+
+    #[error("Memo has a destination channel but no destination denom")]
+    MissingDestinationDenom,
+
+    #[error("Memo minimum out must be above zero")]
+    ZeroMinOut,
+
+    #[error("Memo slippage must be at most {max} basis points")]
+    InvalidSlippage { max: u16 },
+
+    #[error("Memo cannot set both a minimum out and a slippage")]
+    MinOutAndSlippage,
+
+    #[error("Memo cannot both call a hook and forward to another channel")]
+    HookWithForward,
 }
 
 impl DestinationInfo {
+    /// Parses a JSON memo, or the legacy <destination-channel>/<receiver>:<denom> string otherwise.
+    /// Unlike from_str, a JSON memo that does not make sense is an error
+    pub fn parse(memo: &str) -> Result<Self, MemoError> {
+        if !memo.trim_start().starts_with('{') {
+            return Ok(Self::from_str(memo));
+        }
+        let Memo::V1(memo) = from_slice::<Memo>(memo.as_bytes())
+            .map_err(|err| MemoError::InvalidJson(err.to_string()))?;
+        if memo.receiver.is_empty() {
+            return Err(MemoError::MissingReceiver);
+        }
+        if !memo.destination_channel.is_empty() && memo.destination_denom.is_empty() {
+            return Err(MemoError::MissingDestinationDenom);
+        }
+        if memo.min_out == Some(Uint128::zero()) {
+            return Err(MemoError::ZeroMinOut);
+        }
+        if memo.slippage_bps > Some(MAX_SLIPPAGE_BPS) {
+            return Err(MemoError::InvalidSlippage {
+                max: MAX_SLIPPAGE_BPS,
+            });
+        }
+        if memo.min_out.is_some() && memo.slippage_bps.is_some() {
+            return Err(MemoError::MinOutAndSlippage);
+        }
+        if memo.hook.is_some() && !memo.destination_channel.is_empty() {
+            return Err(MemoError::HookWithForward);
+        }
+        Ok(Self {
+            receiver: memo.receiver,
+            destination_channel: memo.destination_channel,
+            destination_denom: memo.destination_denom,
+            min_out: memo.min_out,
+            slippage_bps: memo.slippage_bps,
+            refund_address: memo.refund_address,
+            hook: memo.hook,
+        })
+    }
+
+    /// JSON memo of the latest version, which parse reads back into the same destination
+    pub fn to_memo(&self) -> String {
+        let memo = Memo::V1(MemoV1 {
+            receiver: self.receiver.clone(),
+            destination_channel: self.destination_channel.clone(),
+            destination_denom: self.destination_denom.clone(),
+            min_out: self.min_out,
+            slippage_bps: self.slippage_bps,
+            refund_address: self.refund_address.clone(),
+            hook: self.hook.clone(),
+        });
+        // serializing plain strings and numbers cannot fail
+        String::from_utf8(to_vec(&memo).unwrap_or_default()).unwrap_or_default()
+    }
+
+    /// Legacy memo string. It cannot carry the fields added with the JSON memo
+    pub fn to_legacy_string(&self) -> String {
+        let mut memo = self.receiver.clone();
+        if !self.destination_channel.is_empty() {
+            memo = format!("{}/{}", self.destination_channel, memo);
+        }
+        if !self.destination_denom.is_empty() {
+            memo = format!("{}:{}", memo, self.destination_denom);
+        }
+        memo
+    }
+
     // destination string format: <destination-channel>/<receiver>:<denom>
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(value: &str) -> Self {
         let (destination, denom) = match value.split_once(':') {
             Some((destination, denom)) => (destination, denom),
@@ -27,6 +163,7 @@ impl DestinationInfo {
             receiver: receiver.to_string(),
             destination_channel: channel.to_string(),
             destination_denom: denom.to_string(),
+            ..Default::default()
         }
     }
 
@@ -62,6 +199,7 @@ impl DestinationInfo {
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn test_is_evm_based() {
     let d1 = DestinationInfo::from_str("cosmos14n3tx8s5ftzhlxvq0w5962v60vd82h30sythlz");
     assert_eq!(false, d1.is_receiver_evm_based().0);
@@ -84,6 +222,7 @@ fn test_is_evm_based() {
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn test_is_cosmos_based() {
     let d1 = DestinationInfo::from_str("foo");
     assert_eq!(false, d1.is_receiver_cosmos_based());
@@ -149,7 +288,8 @@ fn test_parse_destination_info() {
         DestinationInfo {
             receiver: "cosmos14n3tx8s5ftzhlxvq0w5962v60vd82h30sythlz".to_string(),
             destination_channel: "channel-15".to_string(),
-            destination_denom: "atom".to_string()
+            destination_denom: "atom".to_string(),
+            ..Default::default()
         }
     );
     // swap to orai then orai to usdt with 'to' as the receiver when swapping, then we're done
@@ -159,7 +299,8 @@ fn test_parse_destination_info() {
         DestinationInfo {
             receiver: "orai14n3tx8s5ftzhlxvq0w5962v60vd82h30rha573".to_string(),
             destination_channel: "".to_string(),
-            destination_denom: "usdt".to_string()
+            destination_denom: "usdt".to_string(),
+            ..Default::default()
         }
     );
     // this case returns an error (because it has channel but no destination denom)
@@ -169,7 +310,8 @@ fn test_parse_destination_info() {
         DestinationInfo {
             receiver: "cosmos14n3tx8s5ftzhlxvq0w5962v60vd82h30sythlz".to_string(),
             destination_channel: "channel-15".to_string(),
-            destination_denom: "".to_string()
+            destination_denom: "".to_string(),
+            ..Default::default()
         }
     );
     let d4 =
@@ -179,7 +321,8 @@ fn test_parse_destination_info() {
         DestinationInfo {
             receiver: "trx-mainnet0x73Ddc880916021EFC4754Cb42B53db6EAB1f9D64".to_string(),
             destination_channel: "".to_string(),
-            destination_denom: "usdt".to_string()
+            destination_denom: "usdt".to_string(),
+            ..Default::default()
         }
     );
 
@@ -189,7 +332,8 @@ fn test_parse_destination_info() {
         DestinationInfo {
             receiver: "orai14n3tx8s5ftzhlxvq0w5962v60vd82h30rha573".to_string(),
             destination_channel: "".to_string(),
-            destination_denom: "".to_string()
+            destination_denom: "".to_string(),
+            ..Default::default()
         }
     );
 
@@ -201,7 +345,8 @@ fn test_parse_destination_info() {
         DestinationInfo {
             receiver: "trx-mainnet0x73Ddc880916021EFC4754Cb42B53db6EAB1f9D64".to_string(),
             destination_channel: "channel-5".to_string(),
-            destination_denom: "usdt".to_string()
+            destination_denom: "usdt".to_string(),
+            ..Default::default()
         }
     );
     // ibc hash case
@@ -212,7 +357,8 @@ fn test_parse_destination_info() {
             receiver: "trx-mainnet0x73Ddc880916021EFC4754Cb42B53db6EAB1f9D64".to_string(),
             destination_channel: "channel-5".to_string(),
             destination_denom:
-                "ibc/A2E2EEC9057A4A1C2C0A6A4C78B0239118DF5F278830F50B4A6BDD7A66506B78".to_string()
+                "ibc/A2E2EEC9057A4A1C2C0A6A4C78B0239118DF5F278830F50B4A6BDD7A66506B78".to_string(),
+            ..Default::default()
         }
     );
     let d8 = DestinationInfo::from_str("channel-124/cosmos1g4h64yjt0fvzv5v2j8tyfnpe5kmnetejl67nlm:orai17l2zk3arrx0a0fyuneyx8raln68622a2lrsz8ph75u7gw9tgz3esayqryf");
@@ -223,6 +369,91 @@ fn test_parse_destination_info() {
             destination_channel: "channel-124".to_string(),
             destination_denom: "orai17l2zk3arrx0a0fyuneyx8raln68622a2lrsz8ph75u7gw9tgz3esayqryf"
                 .to_string(),
+            ..Default::default()
         }
     )
 }
+
+#[test]
+fn test_parse_json_memo() {
+    // the legacy string is still accepted
+    let legacy = "channel-15/cosmos14n3tx8s5ftzhlxvq0w5962v60vd82h30sythlz:atom";
+    assert_eq!(
+        DestinationInfo::parse(legacy).unwrap(),
+        DestinationInfo::from_str(legacy)
+    );
+    assert_eq!(
+        DestinationInfo::from_str(legacy).to_legacy_string(),
+        legacy.to_string()
+    );
+
+    let d1 = DestinationInfo::parse(
+        r#"{"v1":{"receiver":"cosmos14n3tx8s5ftzhlxvq0w5962v60vd82h30sythlz","destination_channel":"channel-15","destination_denom":"atom","min_out":"100","refund_address":"orai14n3tx8s5ftzhlxvq0w5962v60vd82h30rha573"}}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        d1,
+        DestinationInfo {
+            receiver: "cosmos14n3tx8s5ftzhlxvq0w5962v60vd82h30sythlz".to_string(),
+            destination_channel: "channel-15".to_string(),
+            destination_denom: "atom".to_string(),
+            min_out: Some(Uint128::from(100u128)),
+            slippage_bps: None,
+            refund_address: Some("orai14n3tx8s5ftzhlxvq0w5962v60vd82h30rha573".to_string()),
+            hook: None,
+        }
+    );
+    // round trip
+    assert_eq!(DestinationInfo::parse(&d1.to_memo()).unwrap(), d1);
+    let d2 = DestinationInfo {
+        receiver: "orai14n3tx8s5ftzhlxvq0w5962v60vd82h30rha573".to_string(),
+        hook: Some(ContractHook {
+            contract: "orai1contract".to_string(),
+            msg: Binary::from(br#"{"ping":{}}"#),
+        }),
+        ..Default::default()
+    };
+    assert_eq!(DestinationInfo::parse(&d2.to_memo()).unwrap(), d2);
+
+    // malformed json memos are errors instead of empty fields
+    assert!(matches!(
+        DestinationInfo::parse(r#"{"v1":{"receiver":}}"#),
+        Err(MemoError::InvalidJson(_))
+    ));
+    assert!(matches!(
+        DestinationInfo::parse(r#"{"v2":{"receiver":"foo"}}"#),
+        Err(MemoError::InvalidJson(_))
+    ));
+    assert_eq!(
+        DestinationInfo::parse(r#"{"v1":{"receiver":""}}"#),
+        Err(MemoError::MissingReceiver)
+    );
+    assert_eq!(
+        DestinationInfo::parse(r#"{"v1":{"receiver":"foo","destination_channel":"channel-15"}}"#),
+        Err(MemoError::MissingDestinationDenom)
+    );
+    assert_eq!(
+        DestinationInfo::parse(r#"{"v1":{"receiver":"foo","min_out":"0"}}"#),
+        Err(MemoError::ZeroMinOut)
+    );
+    assert_eq!(
+        DestinationInfo::parse(r#"{"v1":{"receiver":"foo","slippage_bps":50}}"#)
+            .unwrap()
+            .slippage_bps,
+        Some(50)
+    );
+    assert_eq!(
+        DestinationInfo::parse(r#"{"v1":{"receiver":"foo","slippage_bps":10001}}"#),
+        Err(MemoError::InvalidSlippage { max: 10_000 })
+    );
+    assert_eq!(
+        DestinationInfo::parse(r#"{"v1":{"receiver":"foo","min_out":"1","slippage_bps":50}}"#),
+        Err(MemoError::MinOutAndSlippage)
+    );
+    assert_eq!(
+        DestinationInfo::parse(
+            r#"{"v1":{"receiver":"foo","destination_channel":"channel-15","destination_denom":"atom","hook":{"contract":"bar","msg":"e30="}}}"#
+        ),
+        Err(MemoError::HookWithForward)
+    );
+}